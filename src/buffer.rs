@@ -18,13 +18,173 @@
 
 use crate::mint_types::{MintChar, MintCount};
 use regex::bytes::Regex;
+use std::any::Any;
+use std::fs;
+use std::io::{self, Read};
 
-pub trait Buffer {
+const READ_CHUNK_SIZE: usize = 65536;
+
+// Saved contents of a buffer, in a form only meaningful to the buffer
+// implementation that created it.
+pub type BufferSnapshot = Box<dyn Any + Send>;
+
+pub trait Buffer: Send {
     fn size(&self) -> MintCount;
+    // Bytes of memory allocated to hold the text.  Text mapped from a file
+    // rather than copied is not counted.
+    fn capacity(&self) -> MintCount;
     fn get(&self, offset: MintCount) -> Option<MintChar>;
     fn replace(&mut self, offset: MintCount, n: MintCount, replacement: &[MintChar]) -> bool;
     fn erase(&mut self, offset: MintCount, n: MintCount) -> bool;
     fn insert(&mut self, offset: MintCount, to_insert: &[MintChar]) -> bool;
+    // An empty buffer of the same kind.
+    fn new_empty(&self) -> Box<dyn Buffer>;
+
+    // Insert everything from "reader" at "offset", returning the number of
+    // characters inserted.  "size_hint" is the expected size, which
+    // implementations can use to allocate space up front.  If an error
+    // occurs, nothing is inserted.
+    fn insert_from(
+        &mut self,
+        offset: MintCount,
+        reader: &mut dyn Read,
+        _size_hint: MintCount,
+    ) -> io::Result<MintCount> {
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        let mut total: MintCount = 0;
+        loop {
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => return Ok(total),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.erase(offset, total);
+                    return Err(e);
+                }
+            };
+            if !self.insert(offset + total, &chunk[..n]) {
+                self.erase(offset, total);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "insert failed"));
+            }
+            total += n as MintCount;
+        }
+    }
+
+    // Call "f" with successive contiguous slices of the text between "start"
+    // and "end", along with the offset of each slice, last slice first if
+    // "reverse" is set.  Stops early if "f" returns false.
+    fn for_each_chunk(
+        &self,
+        start: MintCount,
+        end: MintCount,
+        reverse: bool,
+        f: &mut dyn FnMut(MintCount, &[MintChar]) -> bool,
+    ) {
+        let end = end.min(self.size());
+        let block = READ_CHUNK_SIZE as MintCount;
+        let mut chunk = Vec::with_capacity(READ_CHUNK_SIZE);
+        let mut pos = if reverse { end } else { start };
+        while if reverse { start < pos } else { pos < end } {
+            let (from, to) = if reverse {
+                (pos.saturating_sub(block).max(start), pos)
+            } else {
+                (pos, pos.saturating_add(block).min(end))
+            };
+            chunk.clear();
+            chunk.extend((from..to).filter_map(|i| self.get(i)));
+            if !f(from, &chunk) {
+                return;
+            }
+            pos = if reverse { from } else { to };
+        }
+    }
+
+    // Append the text between "start" and "end" to "out".
+    fn read_range(&self, start: MintCount, end: MintCount, out: &mut Vec<MintChar>) {
+        out.reserve(end.min(self.size()).saturating_sub(start) as usize);
+        self.for_each_chunk(start, end, false, &mut |_, chunk| {
+            out.extend_from_slice(chunk);
+            true
+        });
+    }
+
+    // Number of occurrences of "ch" between "start" and "end".
+    fn count_byte(&self, ch: MintChar, start: MintCount, end: MintCount) -> MintCount {
+        let mut count = 0;
+        self.for_each_chunk(start, end, false, &mut |_, chunk| {
+            count += memchr::memchr_iter(ch, chunk).count() as MintCount;
+            true
+        });
+        count
+    }
+
+    // Offset of the first "ch" between "start" and "end".
+    fn find_byte_forward(
+        &self,
+        ch: MintChar,
+        start: MintCount,
+        end: MintCount,
+    ) -> Option<MintCount> {
+        let mut found = None;
+        self.for_each_chunk(start, end, false, &mut |offset, chunk| {
+            found = memchr::memchr(ch, chunk).map(|i| offset + i as MintCount);
+            found.is_none()
+        });
+        found
+    }
+
+    // Offset of the last "ch" between "start" and "end".
+    fn find_byte_backward(
+        &self,
+        ch: MintChar,
+        start: MintCount,
+        end: MintCount,
+    ) -> Option<MintCount> {
+        let mut found = None;
+        self.for_each_chunk(start, end, true, &mut |offset, chunk| {
+            found = memchr::memrchr(ch, chunk).map(|i| offset + i as MintCount);
+            found.is_none()
+        });
+        found
+    }
+
+    // Save the current contents.  By default this is a copy of the text,
+    // but implementations may share unchanged text with the snapshot.
+    fn snapshot(&self) -> BufferSnapshot {
+        let mut text = Vec::new();
+        self.read_range(0, self.size(), &mut text);
+        Box::new(text)
+    }
+
+    // Replace the contents with a snapshot previously taken from this
+    // buffer.  Returns false if the snapshot can't be used.
+    fn restore(&mut self, snapshot: &BufferSnapshot) -> bool {
+        match snapshot.downcast_ref::<Vec<MintChar>>() {
+            Some(text) => self.replace(0, self.size(), text),
+            None => false,
+        }
+    }
+
+    // Release unused memory, returning the number of bytes freed.
+    fn compact(&mut self) -> MintCount {
+        0
+    }
+
+    // Replace the contents of an empty buffer with a mapping of "file".
+    // Returns false if the buffer is not empty, or cannot map files.
+    fn map_file(&mut self, _file: &fs::File) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    // Whether the text maps the file "file" is the metadata of, so that the
+    // file must not be rewritten while the buffer still does.
+    fn maps_file(&self, _file: &fs::Metadata) -> bool {
+        false
+    }
+
+    // Copy whatever text is still mapped into the buffer's own storage.
+    fn unmap_file(&mut self) {}
+
     fn find_forward(
         &self,
         regex: &Regex,
@@ -16,13 +16,23 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
-use crate::emacs_buffers::{with_buffers, with_current_buffer};
+use crate::coding::Coding;
+use crate::emacs_buffer::{EmacsBuffer, MARK_POINT};
+use crate::emacs_buffers::{lock_buffer, with_buffers, with_current_buffer};
 use crate::mint::{Mint, MintPrim, MintVar};
 use crate::mint_arg::MintArgList;
 use crate::mint_string::{self, get_int_value};
-use crate::mint_types::MintString;
+use crate::mint_types::{MintChar, MintCount, MintString};
 use std::fs;
-use std::io::Write;
+use std::io::{self, Read, Seek, SeekFrom, Take, Write};
+use std::time::SystemTime;
+
+fn file_mtime(file_name: &[u8]) -> Option<SystemTime> {
+    let fn_str = String::from_utf8_lossy(file_name);
+    fs::metadata(fn_str.as_ref())
+        .and_then(|m| m.modified())
+        .ok()
+}
 
 // #(ba,X,Y)
 // ---------
@@ -41,11 +51,11 @@ struct BaPrim;
 impl MintPrim for BaPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let whattodo = args[1].get_int_value(10);
-        let buf_num = with_buffers(|buffers| {
+        let buf_num = with_buffers(interp, |buffers| {
             if whattodo == 0 {
                 buffers.new_buffer()
             } else if whattodo < 0 || buffers.select_buffer(whattodo as u32) {
-                buffers.get_cur_buffer().borrow().get_buf_number()
+                lock_buffer(&buffers.get_cur_buffer()).get_buf_number()
             } else {
                 0
             }
@@ -63,7 +73,7 @@ struct IsPrim;
 impl MintPrim for IsPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let string = args[1].value();
-        let success = with_current_buffer(|buffer| buffer.insert_string(string));
+        let success = with_current_buffer(interp, |buffer| buffer.insert_string(string));
 
         if success && args.len() > 2 {
             interp.return_string(is_active, args[2].value());
@@ -76,18 +86,19 @@ impl MintPrim for IsPrim {
 // #(pm,X,Y)
 // -------
 // Push/pop mark.  If "X" is greater than zero, that many temporary marks
-// are stacked.  If "X" is less than zero, the absolute value of that many
-// permanent marks are stacked.  If "X" is zero, temporary marks are
-// unstacked.  All newly stacked marks are set to the current value of
-// point.
+// are stacked, up to 10.  If "X" is less than zero, the absolute value of
+// that many permanent marks are stacked.  If "X" is zero, temporary marks
+// are unstacked.  All newly stacked marks are set to the current value of
+// point.  There is no limit to how deeply temporary marks can be stacked.
 //
 // Returns: null if successful, "Y" in active mode if an error occurs (ie
-// "X" would case the mark stack to overflow).
+// there are no temporary marks to unstack, or too many temporary or
+// permanent marks are requested).
 struct PmPrim;
 impl MintPrim for PmPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let whattodo = args[1].get_int_value(10);
-        let ok = with_current_buffer(|buf| {
+        let ok = with_current_buffer(interp, |buf| {
             if whattodo > 0 {
                 buf.push_temp_marks(whattodo as u32)
             } else if whattodo == 0 {
@@ -142,7 +153,7 @@ impl MintPrim for SmPrim {
                 args[2].value()[0]
             };
 
-            with_current_buffer(|buf| buf.set_mark(user_mark[0], mark));
+            with_current_buffer(interp, |buf| buf.set_mark(user_mark[0], mark));
         }
         interp.return_null(is_active);
     }
@@ -159,7 +170,7 @@ impl MintPrim for SpPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let marks = args[1].value();
         if !marks.is_empty() {
-            with_current_buffer(|buf| buf.set_point_to_marks(marks));
+            with_current_buffer(interp, |buf| buf.set_point_to_marks(marks));
         }
         interp.return_null(is_active);
     }
@@ -175,7 +186,7 @@ impl MintPrim for DmPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let marks = args[1].value();
         if !marks.is_empty() {
-            with_current_buffer(|buf| buf.delete_to_marks(marks));
+            with_current_buffer(interp, |buf| buf.delete_to_marks(marks));
         }
         interp.return_null(is_active);
     }
@@ -193,7 +204,7 @@ impl MintPrim for RmPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let mark = args[1].value();
         if !mark.is_empty() {
-            let s = with_current_buffer(|buf| buf.read_to_mark(mark[0]));
+            let s = with_current_buffer(interp, |buf| buf.read_to_mark(mark[0]));
             interp.return_string(is_active, &s);
         } else {
             interp.return_string(true, args[2].value());
@@ -212,7 +223,7 @@ impl MintPrim for RcPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let mark = args[1].value();
         let count = if !mark.is_empty() {
-            with_current_buffer(|buf| buf.chars_to_mark(mark[0]))
+            with_current_buffer(interp, |buf| buf.chars_to_mark(mark[0]))
         } else {
             0
         };
@@ -230,7 +241,7 @@ impl MintPrim for MbPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let mark = args[1].value();
         let before = if !mark.is_empty() {
-            with_current_buffer(|buf| buf.mark_before_point(mark[0]))
+            with_current_buffer(interp, |buf| buf.mark_before_point(mark[0]))
         } else {
             false
         };
@@ -245,22 +256,221 @@ impl MintPrim for MbPrim {
     }
 }
 
-// #(rf,X)
+// #(am,X)
+// -------
+// Any modified.
+//
+// Returns: A list, separated by literal string "X", of the numbers of
+// the buffers that have been modified, or null if none have.
+struct AmPrim;
+impl MintPrim for AmPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let separator = args[1].value();
+        let entries: Vec<MintString> = with_buffers(interp, |buffers| buffers.modified_buffers())
+            .into_iter()
+            .map(|bufno| {
+                let mut s = Vec::new();
+                mint_string::append_num(&mut s, bufno as i32, 10);
+                s
+            })
+            .collect();
+        interp.return_string(is_active, &entries.join(&separator[..]));
+    }
+}
+
+// #(sn)
+// -----
+// Snapshot.  Save the contents of the current buffer, so they can be
+// restored later by #(sr,...).  Any previous snapshot of the buffer is
+// discarded.  Snapshots are cheap for "piece" buffers, and a copy of the
+// text otherwise.
+//
+// Returns: null
+struct SnPrim;
+impl MintPrim for SnPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, _args: &MintArgList) {
+        with_current_buffer(interp, |buf| buf.take_snapshot());
+        interp.return_null(is_active);
+    }
+}
+
+// #(sr,X)
 // -------
+// Snapshot restore.  Restore the current buffer's contents to those saved
+// by the last #(sn).  Point and marks are kept where they were, as far as
+// the restored text allows.  The snapshot is kept.
+//
+// Returns: null if successful, "X" in active mode if there is no snapshot
+// or the buffer is write protected.
+struct SrPrim;
+impl MintPrim for SrPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        if with_current_buffer(interp, |buf| buf.restore_snapshot()) {
+            interp.return_null(is_active);
+        } else {
+            interp.return_string(true, args[1].value());
+        }
+    }
+}
+
+// #(cb)
+// -----
+// Compact buffer.  Release memory no longer needed by the current buffer,
+// such as after large deletions.  Buffers are also compacted automatically
+// when they are emptied.
+//
+// Returns: The number of bytes freed.
+struct CbPrim;
+impl MintPrim for CbPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, _args: &MintArgList) {
+        let freed = with_current_buffer(interp, |buf| buf.compact());
+        interp.return_integer(is_active, freed as i32, 10);
+    }
+}
+
+// Open "file_name" positioned at "offset" for reading "len" bytes (or the
+// remainder of the file if None), where a negative offset counts back from
+// the end of the file.  Also returns the number of bytes expected, which
+// is only a guess for files that don't know their size, such as pipes and
+// those in /proc, so the remainder of those is read whatever it says.
+fn open_file_part(
+    file_name: &str,
+    offset: i64,
+    len: Option<u64>,
+) -> std::io::Result<(std::io::Take<fs::File>, u64)> {
+    let mut file = fs::File::open(file_name)?;
+    let size = file.metadata()?.len();
+    // Pipes can't seek, so aren't asked to unless they must.
+    let start = match offset {
+        0 => 0,
+        _ if offset < 0 => file
+            .seek(SeekFrom::End(offset))
+            .or_else(|_| file.seek(SeekFrom::Start(0)))?,
+        _ => file.seek(SeekFrom::Start(offset as u64))?,
+    };
+    let remaining = size.saturating_sub(start);
+    match len {
+        Some(len) => {
+            let len = len.min(remaining);
+            Ok((file.take(len), len))
+        }
+        None => Ok((file.take(u64::MAX), remaining)),
+    }
+}
+
+// Read all of "file_name" into "buf" at point, as #(rf,X) does.
+pub fn read_file(buf: &mut EmacsBuffer, file_name: &[MintChar]) -> io::Result<()> {
+    let (mut reader, size) = open_file_part(&String::from_utf8_lossy(file_name), 0, None)?;
+    let visiting = buf.size() == 0;
+    read_into_buffer(buf, &mut reader, size, visiting, file_name)
+}
+
+// Read "size" bytes from "reader" into "buf" at point.  If "visiting",
+// the buffer is empty and becomes a view of "file_name".
+fn read_into_buffer(
+    buf: &mut EmacsBuffer,
+    reader: &mut Take<fs::File>,
+    size: u64,
+    visiting: bool,
+    file_name: &[MintChar],
+) -> io::Result<()> {
+    let raw = buf.get_coding() == Coding::Raw;
+    if raw && visiting && buf.map_file(reader.get_ref())? {
+        // Buffer is now a view of the file
+    } else if raw {
+        buf.insert_from_reader(reader, size as MintCount)?;
+    } else {
+        let mut contents = Vec::with_capacity(size as usize);
+        reader.read_to_end(&mut contents)?;
+        let (text, coding) = buf.get_coding().decode(&contents);
+        buf.insert_string(&text);
+        // So the text is written back as it was read.
+        buf.set_coding(coding);
+    }
+    if visiting {
+        buf.set_file_name(file_name);
+        buf.set_file_mtime(file_mtime(file_name));
+        buf.set_auto_save_pending(false);
+    }
+    Ok(())
+}
+
+// #(rf,X,Y,Z)
+// -----------
 // Read file.  File given by literal string "X" is read into current
-// buffer.
+// buffer, converted according to the buffer's "en" coding.  An "auto"
+// coding is replaced by the one detected, and a "utf-8" one by "raw" if
+// the file isn't valid UTF-8, which is then read unchanged.  If the
+// buffer was empty, it becomes the buffer's visited file.
+// If "Y" or "Z" is non-null, only part of the file is read: "Y" is the
+// byte offset to start reading from (negative values count back from the
+// end of the file), and "Z" is the maximum number of bytes to read (null
+// reads to the end of the file).  A partial read never changes the
+// buffer's visited file.
+// Files with "raw" coding are streamed directly into the buffer, so
+// very large files can be read without holding a second copy.  Buffers
+// of type "mmap" visit such files by mapping them instead.
 //
 // Returns: null if successful, otherwise returns error message string.
 struct RfPrim;
 impl MintPrim for RfPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let fn_str = String::from_utf8_lossy(args[1].value());
+        let partial = !args[2].is_empty() || !args[3].is_empty();
+        let offset = args[2].get_int_value(10) as i64;
+        let len = if args[3].is_empty() {
+            None
+        } else {
+            Some(args[3].get_int_value(10).max(0) as u64)
+        };
 
-        match fs::read(&fn_str as &str) {
-            Ok(contents) => {
-                with_current_buffer(|buf| buf.insert_string(&contents));
-                interp.return_null(is_active);
+        let result = open_file_part(&fn_str, offset, len).and_then(|(mut reader, size)| {
+            with_current_buffer(interp, |buf| {
+                let visiting = buf.size() == 0 && !partial;
+                read_into_buffer(buf, &mut reader, size, visiting, args[1].value())
+            })
+        });
+
+        match result {
+            Ok(()) => interp.return_null(is_active),
+            Err(e) => {
+                let msg = format!("Error reading file: {}", e);
+                interp.return_string(is_active, &msg.into());
             }
+        }
+    }
+}
+
+// #(rv!,X)
+// --------
+// Revert buffer.  The current buffer's text is replaced by the contents of
+// the file given by literal string "X", or of the buffer's visited file if
+// "X" is null, converted according to the buffer's "en" coding.  Point,
+// topline and marks keep their offsets where these are still within the
+// new text.  The file becomes the buffer's visited file, and the modified
+// flag is cleared.  The buffer is unchanged if the file can't be read.
+//
+// Returns: null if successful, otherwise returns error message string.
+struct RvPrim;
+impl MintPrim for RvPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let file_name = if args[1].is_empty() {
+            with_current_buffer(interp, |buf| buf.get_file_name().clone())
+        } else {
+            args[1].value().clone()
+        };
+        let fn_str = String::from_utf8_lossy(&file_name);
+
+        let result = open_file_part(&fn_str, 0, None).and_then(|(mut reader, size)| {
+            with_current_buffer(interp, |buf| {
+                buf.reload(|buf| read_into_buffer(buf, &mut reader, size, true, &file_name))?;
+                buf.set_modified(false);
+                Ok(())
+            })
+        });
+
+        match result {
+            Ok(()) => interp.return_null(is_active),
             Err(e) => {
                 let msg = format!("Error reading file: {}", e);
                 interp.return_string(is_active, &msg.into());
@@ -269,22 +479,57 @@ impl MintPrim for RfPrim {
     }
 }
 
-// #(wf,X,Y)
-// ---------
+// #(wf,X,Y,Z)
+// -----------
 // Write file.  Write text between point and mark "Y" to file given by
-// literal string "X".
+// literal string "X", converted according to the buffer's "en" coding.
+// If "Y" is null, the whole buffer is written.  If "Z" is non-null, the
+// text is appended to the file rather than replacing it.  Writing the
+// whole buffer without appending makes "X" the buffer's visited file and
+// clears the modified flag.
 //
 // Returns: null if write is successful, otherwise error message string.
 struct WfPrim;
 impl MintPrim for WfPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let fn_str = String::from_utf8_lossy(args[1].value());
+        let mark = args[2].get_first_char();
+        let append = !args[3].is_empty();
 
-        let content = with_current_buffer(|buf| buf.read_to_mark_from(b']', 0));
-        match fs::File::create(&fn_str as &str) {
+        let (content, whole_buffer) = with_current_buffer(interp, |buf| {
+            let (text, whole_buffer) = match mark {
+                Some(mark) => {
+                    let text = buf.read_to_mark(mark);
+                    let whole_buffer = text.len() as u32 == buf.size();
+                    (text, whole_buffer)
+                }
+                None => (buf.read_to_mark_from(b']', 0), true),
+            };
+            (buf.get_coding().encode(&text), whole_buffer)
+        });
+        if !append {
+            with_buffers(interp, |buffers| buffers.unmap_file(&fn_str));
+        }
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(&fn_str as &str);
+        match file {
             Ok(mut file) => match file.write_all(content.as_slice()) {
                 Ok(_) => {
-                    with_current_buffer(|buf| buf.set_modified(false));
+                    if whole_buffer && !append {
+                        with_buffers(interp, |buffers| {
+                            let buf_rc = buffers.get_cur_buffer();
+                            let mut buf = lock_buffer(&buf_rc);
+                            fs::remove_file(buffers.auto_save_path(args[1].value())).ok();
+                            buf.set_file_name(args[1].value());
+                            buf.set_file_mtime(file_mtime(args[1].value()));
+                            buf.set_modified(false);
+                            buf.set_auto_save_pending(false);
+                        });
+                    }
                     interp.return_null(is_active);
                 }
                 Err(e) => {
@@ -300,6 +545,86 @@ impl MintPrim for WfPrim {
     }
 }
 
+// #(d?,A,B)
+// ---------
+// Disk changed?  Checks whether the file visited by the current buffer
+// has been modified or removed on disk since it was last read with
+// #(rf,...) or written with #(wf,...).  Save commands can use this to
+// warn before overwriting someone else's changes.
+//
+// Returns: "A" if the file has changed on disk, "B" otherwise.
+struct DkPrim;
+impl MintPrim for DkPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let changed = with_current_buffer(interp, |buf| match buf.get_file_mtime() {
+            Some(mtime) => file_mtime(buf.get_file_name()) != Some(mtime),
+            None => false,
+        });
+
+        let result = if changed {
+            args[1].value()
+        } else {
+            args[2].value()
+        };
+        interp.return_string(is_active, result);
+    }
+}
+
+// #(av,X,Y)
+// ---------
+// Auto-save.  If "X" is null, every modified buffer that is visiting a
+// file is written to its auto-save file "#name#" immediately.  Otherwise
+// modified buffers are auto-saved every "X" seconds while waiting for
+// input (zero disables), and "Y" is the directory to write auto-save
+// files to.  If "Y" is null, auto-save files are written alongside the
+// visited file.  An auto-save made while waiting that fails says so on
+// the message line.
+//
+// Returns: The number of buffers written if "X" is null, otherwise null.
+struct AvPrim;
+impl MintPrim for AvPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        if args[1].is_empty() {
+            let (saved, _) = with_buffers(interp, |buffers| buffers.auto_save_all());
+            interp.return_integer(is_active, saved as i32, 10);
+        } else {
+            let interval = args[1].get_int_value(10).max(0) as u32;
+            with_buffers(interp, |buffers| {
+                buffers.set_auto_save(interval, args[2].value())
+            });
+            interp.return_null(is_active);
+        }
+    }
+}
+
+// #(ar,X)
+// -------
+// Auto-save recover.  The auto-save file for the file given by literal
+// string "X" is read into the current buffer, which is then marked as
+// visiting "X" and modified.
+//
+// Returns: null if successful, otherwise returns error message string.
+struct ArPrim;
+impl MintPrim for ArPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let path = with_buffers(interp, |buffers| buffers.auto_save_path(args[1].value()));
+
+        match fs::read(&path) {
+            Ok(contents) => {
+                with_current_buffer(interp, |buf| {
+                    buf.set_file_name(args[1].value());
+                    buf.insert_string(&contents);
+                });
+                interp.return_null(is_active);
+            }
+            Err(e) => {
+                let msg = format!("Error reading auto-save file: {}", e);
+                interp.return_string(is_active, &msg.into());
+            }
+        }
+    }
+}
+
 // #(pb)
 // -----
 // Print contents of current buffer to stderr.
@@ -308,7 +633,7 @@ impl MintPrim for WfPrim {
 struct PbPrim;
 impl MintPrim for PbPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, _args: &MintArgList) {
-        with_current_buffer(|buf| {
+        with_current_buffer(interp, |buf| {
             eprintln!("Buffer number: {}", buf.get_buf_number());
             eprintln!("===== CONTENTS =====");
             let content = buf.read_to_mark_from(b']', 0);
@@ -341,10 +666,10 @@ impl MintPrim for BiPrim {
             let mark_char = mark[0];
 
             // Get text from source buffer
-            let text = with_buffers(|buffers| {
-                let cur_buf_num = buffers.get_cur_buffer().borrow().get_buf_number();
+            let text = with_buffers(interp, |buffers| {
+                let cur_buf_num = lock_buffer(&buffers.get_cur_buffer()).get_buf_number();
                 if buffers.select_buffer(buf_num) {
-                    let text = buffers.get_cur_buffer().borrow().read_to_mark(mark_char);
+                    let text = lock_buffer(&buffers.get_cur_buffer()).read_to_mark(mark_char);
                     buffers.select_buffer(cur_buf_num);
                     Some(text)
                 } else {
@@ -354,7 +679,7 @@ impl MintPrim for BiPrim {
 
             // Insert into current buffer
             if let Some(text) = text {
-                success = with_current_buffer(|buf| buf.insert_string(&text));
+                success = with_current_buffer(interp, |buf| buf.insert_string(&text));
             }
         }
 
@@ -417,7 +742,7 @@ impl MintPrim for LpPrim {
         let is_plain = args[3].value().is_empty();
         let fold_case = !args[4].value().is_empty();
 
-        let success = with_buffers(|buffers| {
+        let success = with_buffers(interp, |buffers| {
             if is_plain {
                 buffers.set_search_string(pattern, fold_case)
             } else {
@@ -468,7 +793,7 @@ impl MintPrim for LkPrim {
         let success_str = args[5].value();
         let failure_str = args[6].value();
 
-        let found = with_buffers(|buffers| buffers.search(mark1, mark2, mark3, mark4));
+        let found = with_buffers(interp, |buffers| buffers.search(mark1, mark2, mark3, mark4));
 
         if found {
             interp.return_string(is_active, success_str);
@@ -478,12 +803,51 @@ impl MintPrim for LkPrim {
     }
 }
 
-// #(tr,X,Y)
-// ---------
+// #(hm,X)
+// -------
+// Highlight matches.  If "X" is non-null, every match of the string set
+// by #(lp,...) is highlighted where the current buffer is displayed, until
+// #(hm) with "X" null turns highlighting off again.  Changing the search
+// string later doesn't change what is highlighted.
+//
+// Returns: null
+struct HmPrim;
+impl MintPrim for HmPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let on = !args[1].is_empty();
+        with_buffers(interp, |buffers| buffers.highlight_search(on));
+        interp.return_null(is_active);
+    }
+}
+
+// Expand "a-z" style ranges in a #(tr,...) character set.  A "-" at the
+// start or end of the set, or in a descending range, is taken literally.
+fn expand_ranges(set: &[u8]) -> MintString {
+    let mut result = MintString::new();
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' && set[i] <= set[i + 2] {
+            result.extend(set[i]..=set[i + 2]);
+            i += 3;
+        } else {
+            result.push(set[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+// #(tr,X,Y,Z)
+// -----------
 // Translate.  Translates from point to mark "X" using string "Y" as a
 // translation character set.  Each character is read from the buffer, and
 // if the ordinal value is less than the length of "Y", then it is replaced
 // with this character.
+// If "Z" is non-null, "Y" and "Z" are instead sets of characters in the
+// style of tr(1): each character in "Y" is replaced by the corresponding
+// character in "Z", and both may contain ranges such as "a-z".  If "Z" is
+// shorter than "Y", its last character is repeated.  For example,
+// #(tr,],a-zA-Z,n-za-mN-ZA-M) applies rot13.
 //
 // Returns: null
 struct TrPrim;
@@ -492,17 +856,48 @@ impl MintPrim for TrPrim {
         let mark = args[1].value();
         let trstr = args[2].value();
 
+        let (from_str, to_str) = if args[3].is_empty() {
+            let half = trstr.len() / 2;
+            (trstr[..half].to_vec(), trstr[half..].to_vec())
+        } else {
+            let from_str = expand_ranges(trstr);
+            let mut to_str = expand_ranges(args[3].value());
+            if let Some(&last) = to_str.last() {
+                to_str.resize(to_str.len().max(from_str.len()), last);
+            }
+            (from_str, to_str)
+        };
+
         if !mark.is_empty() {
-            with_current_buffer(|buf| buf.translate(mark[0], trstr));
+            with_current_buffer(interp, |buf| buf.translate(mark[0], &from_str, &to_str));
         }
         interp.return_null(is_active);
     }
 }
 
+// #(tb,X,Y)
+// ---------
+// Tabify.  Convert the leading whitespace of each line from point to mark
+// "X" to as many tabs as possible followed by spaces, using the buffer's
+// tab width.  If "Y" is non-null, leading tabs are instead expanded to
+// spaces.  The width of the whitespace is unchanged.
+//
+// Returns: The number of lines changed.
+struct TbPrim;
+impl MintPrim for TbPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let changed = match args[1].get_first_char() {
+            Some(mark) => with_current_buffer(interp, |buf| buf.tabify(mark, args[2].is_empty())),
+            None => 0,
+        };
+        interp.return_integer(is_active, changed as i32, 10);
+    }
+}
+
 struct ClVar;
 impl MintVar for ClVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        with_current_buffer(|buf| {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
             let line_no = buf.get_point_line() + 1;
             let mut s = MintString::new();
             mint_string::append_num(&mut s, line_no as i32, 10);
@@ -510,9 +905,9 @@ impl MintVar for ClVar {
         })
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let line_no = get_int_value(val, 10);
-        with_current_buffer(|buf| {
+        with_current_buffer(interp, |buf| {
             buf.set_point_line(std::cmp::max(0, line_no - 1) as u32);
         });
     }
@@ -520,8 +915,8 @@ impl MintVar for ClVar {
 
 struct CsVar;
 impl MintVar for CsVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        with_current_buffer(|buf| {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
             let col_no = buf.get_column() + 1;
             let mut s = Vec::new();
             mint_string::append_num(&mut s, col_no as i32, 10);
@@ -529,10 +924,10 @@ impl MintVar for CsVar {
         })
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let col_no = get_int_value(val, 10);
         if col_no > 0 {
-            with_current_buffer(|buf| {
+            with_current_buffer(interp, |buf| {
                 buf.set_column(col_no as u32 - 1);
             });
         }
@@ -541,8 +936,8 @@ impl MintVar for CsVar {
 
 struct MbVar;
 impl MintVar for MbVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        with_current_buffer(|buf| {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
             let mut s = Vec::new();
             let mod_flag = if buf.is_modified() { 1 } else { 0 };
             let wp_flag = if buf.is_write_protected() { 2 } else { 0 };
@@ -551,9 +946,9 @@ impl MintVar for MbVar {
         })
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let flags = get_int_value(val, 10);
-        with_current_buffer(|buf| {
+        with_current_buffer(interp, |buf| {
             buf.set_modified((flags & 1) != 0);
             buf.set_write_protected((flags & 2) != 0);
         });
@@ -562,8 +957,8 @@ impl MintVar for MbVar {
 
 struct NlVar;
 impl MintVar for NlVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        with_current_buffer(|buf| {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
             let newline_count = buf.count_newlines_total() as i32;
             let mut s = Vec::new();
             mint_string::append_num(&mut s, newline_count + 1, 10);
@@ -578,8 +973,8 @@ impl MintVar for NlVar {
 
 struct PbVar;
 impl MintVar for PbVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        with_current_buffer(|buf| {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
             let point_line = buf.get_point_line() as i32;
             let newline_count = buf.count_newlines_total() as i32;
             let mut s = Vec::new();
@@ -595,8 +990,8 @@ impl MintVar for PbVar {
 
 struct RsVar;
 impl MintVar for RsVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        with_current_buffer(|buf| {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
             let get_point_row = buf.get_point_row() as i32;
             let mut s = Vec::new();
             mint_string::append_num(&mut s, get_point_row, 10);
@@ -604,8 +999,8 @@ impl MintVar for RsVar {
         })
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
-        with_current_buffer(|buf| {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        with_current_buffer(interp, |buf| {
             buf.set_point_row(get_int_value(val, 10) as u32);
         });
     }
@@ -613,8 +1008,8 @@ impl MintVar for RsVar {
 
 struct TcVar;
 impl MintVar for TcVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        with_current_buffer(|buf| {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
             let tab_width = buf.get_tab_width() as i32;
             let mut s = Vec::new();
             mint_string::append_num(&mut s, tab_width, 10);
@@ -622,13 +1017,150 @@ impl MintVar for TcVar {
         })
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
-        with_current_buffer(|buf| {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        with_current_buffer(interp, |buf| {
             buf.set_tab_width(get_int_value(val, 10) as u32);
         });
     }
 }
 
+// en
+// --
+// Get/set the coding used to read and write files in the current buffer.
+// One of "raw" (bytes unchanged, the default), "utf-8", "latin-1", or
+// "auto" to detect UTF-8 or Latin-1 on the next #(rf,...).  Unknown
+// names are ignored.  Only buffers with a coding other than "raw" show
+// UTF-8 as characters, with wide ones two columns across; raw buffers
+// show bytes above 127 in hex, as they may not be text at all.
+struct EnVar;
+impl MintVar for EnVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| buf.get_coding().name().to_vec())
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        if let Some(coding) = Coding::from_name(val) {
+            with_current_buffer(interp, |buf| buf.set_coding(coding));
+        }
+    }
+}
+
+// hx
+// --
+// Get/set hex display for the current buffer.  When non-zero, control
+// characters are shown as "\x1B" rather than "^[", like bytes above 0x7F.
+struct HxVar;
+impl MintVar for HxVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = with_current_buffer(interp, |buf| buf.get_hex_display());
+        if val { b"1".to_vec() } else { b"0".to_vec() }
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        let flag = get_int_value(val, 10) != 0;
+        with_current_buffer(interp, |buf| buf.set_hex_display(flag));
+    }
+}
+
+// lc
+// --
+// Get/set the left column of the current buffer's window, the first
+// column of text displayed.  Redisplay scrolls sideways by itself to keep
+// point visible.
+struct LcVar;
+impl MintVar for LcVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
+            let mut s = Vec::new();
+            mint_string::append_num(&mut s, buf.get_left_column() as i32, 10);
+            s
+        })
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        with_current_buffer(interp, |buf| {
+            buf.set_left_column(get_int_value(val, 10).max(0) as MintCount);
+        });
+    }
+}
+
+// po
+// --
+// Get/set point as a byte offset from the start of the current buffer.
+// Offsets past the end of the buffer move point to the end.
+struct PoVar;
+impl MintVar for PoVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
+            let mut s = Vec::new();
+            mint_string::append_num(&mut s, buf.get_mark_position(MARK_POINT) as i32, 10);
+            s
+        })
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        with_current_buffer(interp, |buf| {
+            buf.set_point_position(get_int_value(val, 10).max(0) as MintCount);
+        });
+    }
+}
+
+// bz
+// --
+// Get the size of the current buffer in bytes.
+struct BzVar;
+impl MintVar for BzVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
+            let mut s = Vec::new();
+            mint_string::append_num(&mut s, buf.size() as i32, 10);
+            s
+        })
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Value can't be set
+    }
+}
+
+// bm
+// --
+// Get the bytes of memory allocated to hold the current buffer's text,
+// which is at least its size (see bz) unless the buffer maps its file.
+struct BmVar;
+impl MintVar for BmVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_current_buffer(interp, |buf| {
+            let mut s = Vec::new();
+            mint_string::append_num(&mut s, buf.capacity() as i32, 10);
+            s
+        })
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Value can't be set
+    }
+}
+
+// bt
+// --
+// Get/set the type of buffer created by #(ba,...).  One of "gap" (the
+// usual gap buffer), "mmap", which visits raw files by mapping them
+// rather than reading them, so very large files can be viewed without
+// copying, "rope", which keeps edits anywhere in large buffers cheap, or
+// "piece", a piece table whose contents can be snapshotted cheaply.
+// Unknown names are ignored.
+struct BtVar;
+impl MintVar for BtVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        with_buffers(interp, |buffers| buffers.get_buffer_type().clone())
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        with_buffers(interp, |buffers| buffers.set_buffer_type(val));
+    }
+}
+
 pub fn register_buf_prims(interp: &mut Mint) {
     interp.add_prim(b"ba".to_vec(), Box::new(BaPrim));
     interp.add_prim(b"is".to_vec(), Box::new(IsPrim));
@@ -639,20 +1171,46 @@ pub fn register_buf_prims(interp: &mut Mint) {
     interp.add_prim(b"rm".to_vec(), Box::new(RmPrim));
     interp.add_prim(b"rc".to_vec(), Box::new(RcPrim));
     interp.add_prim(b"mb".to_vec(), Box::new(MbPrim));
+    interp.add_prim(b"am".to_vec(), Box::new(AmPrim));
+    interp.add_prim(b"cb".to_vec(), Box::new(CbPrim));
+    interp.add_prim(b"sn".to_vec(), Box::new(SnPrim));
+    interp.add_prim(b"sr".to_vec(), Box::new(SrPrim));
     interp.add_prim(b"rf".to_vec(), Box::new(RfPrim));
+    interp.add_prim(b"rv!".to_vec(), Box::new(RvPrim));
     interp.add_prim(b"wf".to_vec(), Box::new(WfPrim));
+    interp.add_prim(b"d?".to_vec(), Box::new(DkPrim));
+    interp.add_prim(b"av".to_vec(), Box::new(AvPrim));
+    interp.add_prim(b"ar".to_vec(), Box::new(ArPrim));
     interp.add_prim(b"tr".to_vec(), Box::new(TrPrim));
+    interp.add_prim(b"tb".to_vec(), Box::new(TbPrim));
     interp.add_prim(b"bi".to_vec(), Box::new(BiPrim));
     interp.add_prim(b"pb".to_vec(), Box::new(PbPrim));
     interp.add_prim(b"st".to_vec(), Box::new(StPrim));
     interp.add_prim(b"lp".to_vec(), Box::new(LpPrim));
     interp.add_prim(b"l?".to_vec(), Box::new(LkPrim));
+    interp.add_prim(b"hm".to_vec(), Box::new(HmPrim));
 
+    interp.add_var(b"bm".to_vec(), Box::new(BmVar));
+    interp.add_var(b"bt".to_vec(), Box::new(BtVar));
+    interp.add_var(b"bz".to_vec(), Box::new(BzVar));
     interp.add_var(b"cl".to_vec(), Box::new(ClVar));
     interp.add_var(b"cs".to_vec(), Box::new(CsVar));
+    interp.add_var(b"en".to_vec(), Box::new(EnVar));
+    interp.add_var(b"hx".to_vec(), Box::new(HxVar));
+    interp.add_var(b"lc".to_vec(), Box::new(LcVar));
     interp.add_var(b"mb".to_vec(), Box::new(MbVar));
     interp.add_var(b"nl".to_vec(), Box::new(NlVar));
     interp.add_var(b"pb".to_vec(), Box::new(PbVar));
+    interp.add_var(b"po".to_vec(), Box::new(PoVar));
     interp.add_var(b"rs".to_vec(), Box::new(RsVar));
     interp.add_var(b"tc".to_vec(), Box::new(TcVar));
+
+    // Classes for #(ph)
+    interp.add_prim_class(
+        b"change",
+        &[
+            b"is", b"dm", b"bi", b"tr", b"tb", b"rf", b"rv!", b"ar", b"sr",
+        ],
+    );
+    interp.add_prim_class(b"file", &[b"rf", b"wf", b"rv!", b"ar", b"av"]);
 }
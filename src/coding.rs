@@ -0,0 +1,141 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+use crate::mint_types::{MintChar, MintString};
+
+// Character encoding applied when reading files into, and writing files
+// from, buffers.  Buffer text is held as UTF-8 for the Utf8 and Latin1
+// codings, and as the raw file bytes for Raw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coding {
+    Raw,
+    Utf8,
+    Latin1,
+    Auto,
+}
+
+impl Coding {
+    pub fn from_name(name: &[MintChar]) -> Option<Self> {
+        match name.to_ascii_lowercase().as_slice() {
+            b"raw" | b"binary" => Some(Coding::Raw),
+            b"utf-8" | b"utf8" => Some(Coding::Utf8),
+            b"latin-1" | b"latin1" | b"iso-8859-1" => Some(Coding::Latin1),
+            b"auto" => Some(Coding::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static [MintChar] {
+        match self {
+            Coding::Raw => b"raw",
+            Coding::Utf8 => b"utf-8",
+            Coding::Latin1 => b"latin-1",
+            Coding::Auto => b"auto",
+        }
+    }
+
+    // Work out what Auto means for "bytes".  Anything that is valid UTF-8
+    // is taken to be UTF-8, otherwise Latin-1 is assumed.  UTF-8 that
+    // isn't valid is taken as Raw, so no bytes are lost to replacement
+    // characters.
+    pub fn detect(&self, bytes: &[MintChar]) -> Coding {
+        let is_utf8 = || std::str::from_utf8(bytes).is_ok();
+        match self {
+            Coding::Auto if is_utf8() => Coding::Utf8,
+            Coding::Auto => Coding::Latin1,
+            Coding::Utf8 if !is_utf8() => Coding::Raw,
+            other => *other,
+        }
+    }
+
+    // Convert file contents to buffer text.  Returns the text and the
+    // coding actually used, which differs from self for Auto, and for
+    // UTF-8 that isn't valid.
+    pub fn decode(&self, bytes: &[MintChar]) -> (MintString, Coding) {
+        let coding = self.detect(bytes);
+        let text = match coding {
+            Coding::Latin1 => {
+                let mut text = MintString::with_capacity(bytes.len());
+                for &b in bytes {
+                    let mut utf8 = [0u8; 2];
+                    text.extend_from_slice((b as char).encode_utf8(&mut utf8).as_bytes());
+                }
+                text
+            }
+            _ => bytes.to_vec(),
+        };
+        (text, coding)
+    }
+
+    // Convert buffer text back to file contents.  Characters that cannot
+    // be represented in Latin-1 are written as '?'.
+    pub fn encode(&self, text: &[MintChar]) -> MintString {
+        match self {
+            Coding::Latin1 => String::from_utf8_lossy(text)
+                .chars()
+                .map(|ch| if (ch as u32) < 0x100 { ch as u8 } else { b'?' })
+                .collect(),
+            _ => text.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coding_names_round_trip() {
+        for coding in [Coding::Raw, Coding::Utf8, Coding::Latin1, Coding::Auto] {
+            assert_eq!(Some(coding), Coding::from_name(coding.name()));
+        }
+        assert_eq!(Some(Coding::Utf8), Coding::from_name(b"UTF8"));
+        assert_eq!(None, Coding::from_name(b"ebcdic"));
+    }
+
+    #[test]
+    fn coding_auto_detect() {
+        assert_eq!(Coding::Utf8, Coding::Auto.detect("caf\u{e9}".as_bytes()));
+        assert_eq!(Coding::Latin1, Coding::Auto.detect(b"caf\xe9"));
+        assert_eq!(Coding::Raw, Coding::Raw.detect(b"caf\xe9"));
+        assert_eq!(Coding::Raw, Coding::Utf8.detect(b"caf\xe9"));
+    }
+
+    #[test]
+    fn coding_latin1_round_trip() {
+        let (text, coding) = Coding::Auto.decode(b"caf\xe9");
+        assert_eq!(Coding::Latin1, coding);
+        assert_eq!("caf\u{e9}".as_bytes(), text.as_slice());
+        assert_eq!(b"caf\xe9".to_vec(), coding.encode(&text));
+        assert_eq!(b"?".to_vec(), Coding::Latin1.encode("\u{20ac}".as_bytes()));
+    }
+
+    #[test]
+    fn coding_invalid_utf8_is_unchanged() {
+        let (text, coding) = Coding::Utf8.decode(b"caf\xe9");
+        assert_eq!(Coding::Raw, coding);
+        assert_eq!(b"caf\xe9".to_vec(), text);
+    }
+
+    #[test]
+    fn coding_raw_is_unchanged() {
+        let bytes = b"\x00\x80\xff".to_vec();
+        assert_eq!(bytes, Coding::Raw.decode(&bytes).0);
+        assert_eq!(bytes, Coding::Raw.encode(&bytes));
+    }
+}
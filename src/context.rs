@@ -0,0 +1,91 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* What the primitives work on besides the interpreter itself. */
+
+// Each Mint owns one of these, so two editors, or two tests, never see
+// each other's buffers or screen.  The primitives reach it through the
+// interpreter they are given: with_buffers and with_current_buffer in
+// emacs_buffers, with_window and friends in emacs_window, with_layout in
+// window_layout, and the #(bg) jobs through worker.
+
+use crate::emacs_buffers::EmacsBuffers;
+use crate::emacs_window::EmacsWindow;
+use crate::keyboard_macro::KeyboardMacro;
+use crate::mint_types::{MintCount, MintString};
+use crate::subprocess::Process;
+use crate::wakeup::Waker;
+use crate::window_layout::Layout;
+use crate::worker::Worker;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+
+pub struct Context {
+    // Set by init_buffers and init_window, until then there are none.
+    pub buffers: RefCell<Option<EmacsBuffers>>,
+    pub window: RefCell<Option<Box<dyn EmacsWindow>>>,
+    pub layout: RefCell<Layout>,
+    // Keys queued by push_key, as #(pu) and pasting do, to be read ahead
+    // of anything typed.
+    pub pending_keys: RefCell<VecDeque<MintString>>,
+    // Keys recorded and being replayed by #(kr) and #(kx).
+    pub keyboard_macro: RefCell<KeyboardMacro>,
+    // Pitch #(bl) uses when it isn't given one.
+    pub bell_pitch: Cell<i32>,
+    // Processes run in buffers, keyed by buffer number.
+    pub processes: RefCell<HashMap<MintCount, Process>>,
+    // Thread doing #(bg) jobs, started for the first of them.
+    pub worker: RefCell<Option<Worker>>,
+    // Ends a wait for a key when either of those has something.
+    pub waker: Waker,
+    // Files this editor holds locks on; see file_lock.
+    pub held_locks: RefCell<Vec<PathBuf>>,
+    // The directory "sd" was set to, if it has been.
+    pub swap_dir: RefCell<Option<PathBuf>>,
+    // What the terminal showed before the display took it over.
+    pub original_screen: RefCell<MintString>,
+    // Set once the display going away has been read as "Close".
+    pub closed: Cell<bool>,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Context {
+            buffers: RefCell::new(None),
+            window: RefCell::new(None),
+            layout: RefCell::new(Layout::new()),
+            pending_keys: RefCell::new(VecDeque::new()),
+            keyboard_macro: RefCell::new(KeyboardMacro::default()),
+            bell_pitch: Cell::new(440),
+            processes: RefCell::new(HashMap::new()),
+            worker: RefCell::new(None),
+            waker: Waker::new(),
+            held_locks: RefCell::new(Vec::new()),
+            swap_dir: RefCell::new(None),
+            original_screen: RefCell::new(Vec::new()),
+            closed: Cell::new(false),
+        }
+    }
+}
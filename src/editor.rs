@@ -0,0 +1,190 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* The whole editor: interpreter, buffers and window, for embedding. */
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::emacs_buffer;
+use crate::emacs_buffers::{self, with_buffers, with_current_buffer};
+use crate::emacs_window::{self, EmacsWindow};
+use crate::file_lock;
+use crate::mint::Mint;
+use crate::mint_types::{MintChar, MintCount, MintString};
+use crate::recovery;
+use crate::{buffer, gap_buffer};
+use crate::{bufprim, frmprim, libprim, mthprim, strprim, sysprim, varprim, winprim};
+
+fn gap_buffer_factory() -> Box<dyn buffer::Buffer> {
+    Box::new(gap_buffer::GapBuffer::with_default_size())
+}
+
+// Buffers and the window belong to the interpreter, so each Editor has
+// its own.  Dropping it frees them.
+pub struct Editor {
+    interp: Mint,
+    // With recovery kept, when the recovery library was last written and
+    // how many form changes there had been.
+    recovery: Option<(Instant, u64)>,
+}
+
+// How often the recovery library is written, if forms have changed.
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+impl Editor {
+    // An editor showing its buffers on "window", that starts by running
+    // "script".  "args" and "envp" are what the sysprim primitives report
+    // as the command line and environment.
+    pub fn new(
+        window: Box<dyn EmacsWindow>,
+        script: &[MintChar],
+        args: &[String],
+        envp: &[(String, String)],
+    ) -> Self {
+        let mut interp = Mint::with_initial_string(script);
+        emacs_buffers::init_buffers(&interp, gap_buffer_factory);
+        emacs_window::init_window(&interp, window);
+        bufprim::register_buf_prims(&mut interp);
+        winprim::register_win_prims(&mut interp);
+        mthprim::register_mth_prims(&mut interp);
+        libprim::register_lib_prims(&mut interp);
+        frmprim::register_frm_prims(&mut interp);
+        strprim::register_str_prims(&mut interp);
+        sysprim::register_sys_prims(&mut interp, args, envp);
+        varprim::register_var_prims(&mut interp);
+
+        Editor {
+            interp,
+            recovery: None,
+        }
+    }
+
+    // The interpreter, for adding primitives and variables of your own.
+    pub fn interp(&mut self) -> &mut Mint {
+        &mut self.interp
+    }
+
+    // Scan until the active string is used up.  Once the starting script
+    // is done, each step runs the default string, which reads a key and
+    // does what it is bound to.
+    pub fn step(&mut self) {
+        self.interp.scan();
+    }
+
+    // Run the editor until #(hl), returning the code it gave.
+    pub fn run(&mut self) -> i32 {
+        loop {
+            self.interp.scan();
+            if let Some(code) = self.interp.halted() {
+                return code;
+            }
+            self.save_recovery_if_due();
+        }
+    }
+
+    // Have run() write the changed forms to the recovery library every so
+    // often.
+    pub fn keep_recovery(&mut self) {
+        self.recovery = Some((Instant::now(), self.interp.form_changes()));
+    }
+
+    fn save_recovery_if_due(&mut self) {
+        let Some((saved, changes)) = self.recovery else {
+            return;
+        };
+        if saved.elapsed() >= RECOVERY_INTERVAL && self.interp.form_changes() != changes {
+            recovery::save(&self.interp).ok();
+            self.keep_recovery();
+        }
+    }
+
+    // The code #(hl) gave, once it has halted the editor.
+    pub fn halted(&self) -> Option<i32> {
+        self.interp.halted()
+    }
+
+    // Type "key", a key name as #(it) returns it, and step until it has
+    // been read and acted on.  Stops early if a step reads no keys, in
+    // which case the key is left to be read later.
+    pub fn feed_key(&mut self, key: &str) {
+        emacs_window::push_key(&self.interp, key.as_bytes());
+        loop {
+            let pending = emacs_window::pending_keys(&self.interp);
+            if pending == 0 {
+                break;
+            }
+            self.step();
+            if emacs_window::pending_keys(&self.interp) == pending {
+                break;
+            }
+        }
+    }
+
+    // Read "file_name" into a new buffer, which becomes the current one.
+    // Returns the buffer's number.
+    pub fn open_file(&mut self, file_name: &str) -> io::Result<MintCount> {
+        std::fs::metadata(file_name)?;
+        let bufno = with_buffers(&self.interp, |buffers| buffers.new_buffer());
+        with_current_buffer(&self.interp, |buf| {
+            bufprim::read_file(buf, file_name.as_bytes())
+        })?;
+        Ok(bufno)
+    }
+
+    // As open_file, except that a file that doesn't exist yet gives an
+    // empty buffer, which writes it when saved.
+    pub fn visit_file(&mut self, file_name: &str) -> io::Result<MintCount> {
+        match self.open_file(file_name) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let bufno = with_buffers(&self.interp, |buffers| buffers.new_buffer());
+                with_current_buffer(&self.interp, |buf| buf.set_file_name(file_name.as_bytes()));
+                Ok(bufno)
+            }
+            result => result,
+        }
+    }
+
+    // Select buffer "bufno".  Returns false if there is no such buffer.
+    pub fn select_buffer(&mut self, bufno: MintCount) -> bool {
+        with_buffers(&self.interp, |buffers| buffers.select_buffer(bufno))
+    }
+
+    // Move point to the start of line "line" of the current buffer,
+    // counting from 1.
+    pub fn goto_line(&mut self, line: MintCount) {
+        with_current_buffer(&self.interp, |buf| {
+            buf.set_point_line(line.saturating_sub(1))
+        });
+    }
+
+    // All the text of the current buffer.
+    pub fn buffer_text(&self) -> MintString {
+        with_current_buffer(&self.interp, |buf| {
+            buf.read_to_mark_from(emacs_buffer::MARK_EOB, 0)
+        })
+    }
+}
+
+impl Drop for Editor {
+    fn drop(&mut self) {
+        file_lock::unlock_all(&self.interp);
+        emacs_window::free_window(&self.interp);
+        emacs_buffers::free_buffers(&self.interp);
+    }
+}
@@ -16,10 +16,16 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
-use crate::buffer::Buffer;
+use crate::buffer::{Buffer, BufferSnapshot};
+use crate::coding::Coding;
 use crate::mint_types::{MintChar, MintCount, MintString};
 use regex::bytes::Regex;
+use std::cell::Cell;
 use std::cmp::{max, min};
+use std::fs;
+use std::io::{self, Read};
+use std::time::SystemTime;
+use unicode_width::UnicodeWidthChar;
 
 pub const EOLCHAR: MintChar = b'\n';
 
@@ -42,8 +48,53 @@ pub const MARK_PREV_NBLANK: MintChar = b'{';
 pub const MARK_NEXT_NBLANK: MintChar = b'}';
 pub const MARK_POINT: MintChar = b'.';
 pub const MARK_TOPLINE: MintChar = b'!';
+// Where output from the buffer's process goes.
+pub const MARK_PROCESS: MintChar = b'&';
+
+// How bytes that aren't printable ASCII are displayed: "^@" style for
+// control characters and DEL, and "\x9B" style for bytes above 0x7F, so
+// binary data is shown unambiguously.  Tabs are expanded by the caller.
+pub fn byte_repr(ch: MintChar) -> Option<String> {
+    match ch {
+        0x00..=0x1f => Some(format!("^{}", (ch + b'@') as char)),
+        0x7f => Some("^?".to_string()),
+        0x80..=0xff => Some(format!("\\x{:02X}", ch)),
+        _ => None,
+    }
+}
+
+// Length of the UTF-8 sequence that starts with "lead", or None if "lead"
+// can't start one.
+pub fn utf8_len(lead: MintChar) -> Option<usize> {
+    match lead {
+        0xc2..=0xdf => Some(2),
+        0xe0..=0xef => Some(3),
+        0xf0..=0xf4 => Some(4),
+        _ => None,
+    }
+}
+
+// The character at the start of "bytes" and its length, if "bytes" starts
+// with a complete multi-byte UTF-8 sequence for a printable character.
+pub fn utf8_char(bytes: &[MintChar]) -> Option<(char, usize)> {
+    let len = utf8_len(*bytes.first()?)?;
+    let s = std::str::from_utf8(bytes.get(..len)?).ok()?;
+    s.chars()
+        .next()
+        .filter(|ch| !ch.is_control())
+        .map(|ch| (ch, len))
+}
 
-const MAX_MARKS: usize = 50;
+// Display settings a buffer has of its own, used rather than the
+// window's where they are set.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DisplayOverrides {
+    pub fore: Option<i32>,
+    pub back: Option<i32>,
+    pub ctrl_fore: Option<i32>,
+    pub wsp_fore: Option<i32>,
+    pub show_wsp: Option<bool>,
+}
 
 pub struct EmacsBuffer {
     wp: bool,
@@ -52,16 +103,33 @@ pub struct EmacsBuffer {
     topline: MintCount,
     leftcol: MintCount,
     tab_width: MintCount,
+    // Show control characters as "\x1B" rather than "^[".
+    hex_display: bool,
     temp_mark_base: usize,
     temp_mark_last: usize,
     perm_mark_count: usize,
-    marks_sp: usize,
+    // Positions of the permanent marks followed by the temporary marks for
+    // each level of #(pm,...), growing as more are pushed.
     marks: Vec<MintCount>,
+    process_mark: MintCount,
     mark_stack: Vec<usize>,
     point_line: MintCount,
     topline_line: MintCount,
     count_newlines: MintCount,
     bufno: MintCount,
+    file_name: MintString,
+    file_mtime: Option<SystemTime>,
+    coding: Coding,
+    auto_save_pending: bool,
+    // Most recently computed (bol, position, column) on a line, so column
+    // motion along long lines doesn't rescan from the start of the line.
+    column_cache: Cell<Option<(MintCount, MintCount, MintCount)>>,
+    change_tick: u64,
+    changed_span: Option<(MintCount, MintCount)>,
+    snapshot: Option<BufferSnapshot>,
+    // Pattern whose matches are highlighted on screen, if any.
+    highlight: Option<Regex>,
+    display: DisplayOverrides,
     text: Box<dyn Buffer>,
 }
 
@@ -74,16 +142,27 @@ impl EmacsBuffer {
             topline: 0,
             leftcol: 0,
             tab_width: 8,
+            hex_display: false,
             temp_mark_base: 1,
             temp_mark_last: 1,
             perm_mark_count: 1,
-            marks_sp: 0,
-            marks: vec![0; MAX_MARKS],
-            mark_stack: vec![0; MAX_MARKS],
+            marks: vec![0; 1],
+            process_mark: 0,
+            mark_stack: Vec::new(),
             point_line: 0,
             topline_line: 0,
             count_newlines: 0,
             bufno,
+            file_name: MintString::new(),
+            file_mtime: None,
+            coding: Coding::Raw,
+            auto_save_pending: false,
+            column_cache: Cell::new(None),
+            change_tick: 0,
+            changed_span: None,
+            snapshot: None,
+            highlight: None,
+            display: DisplayOverrides::default(),
             text,
         }
     }
@@ -104,6 +183,86 @@ impl EmacsBuffer {
         self.modified = ismodified;
     }
 
+    // Record that "deleted" characters at "pos" were replaced by
+    // "inserted" characters.
+    fn mark_modified(&mut self, pos: MintCount, deleted: MintCount, inserted: MintCount) {
+        self.modified = true;
+        self.auto_save_pending = true;
+        self.column_cache.set(None);
+        self.change_tick += 1;
+
+        // Shift the existing span to account for the change, then extend it
+        // to cover the changed text.
+        let shift = |p: MintCount| {
+            if p > pos {
+                p.saturating_sub(deleted).max(pos) + inserted
+            } else {
+                p
+            }
+        };
+        let end = pos + inserted;
+        self.changed_span = Some(match self.changed_span {
+            Some((start, old_end)) => (min(shift(start), pos), max(shift(old_end), end)),
+            None => (pos, end),
+        });
+    }
+
+    // Incremented on every change to the buffer's text.
+    pub fn get_change_tick(&self) -> u64 {
+        self.change_tick
+    }
+
+    // Span of text changed since the last call, in current positions.
+    // Deletions are recorded as an empty span where the text used to be.
+    pub fn take_changed_span(&mut self) -> Option<(MintCount, MintCount)> {
+        self.changed_span.take()
+    }
+
+    pub fn get_file_name(&self) -> &MintString {
+        &self.file_name
+    }
+
+    pub fn set_file_name(&mut self, file_name: &[MintChar]) {
+        self.file_name = file_name.to_vec();
+    }
+
+    /// Modification time of the visited file when it was last read or
+    /// written, if known.
+    pub fn get_file_mtime(&self) -> Option<SystemTime> {
+        self.file_mtime
+    }
+
+    pub fn set_file_mtime(&mut self, mtime: Option<SystemTime>) {
+        self.file_mtime = mtime;
+    }
+
+    pub fn get_coding(&self) -> Coding {
+        self.coding
+    }
+
+    // Whether the text is held as UTF-8, so multi-byte sequences are
+    // displayed as single characters rather than byte by byte.  Buffers
+    // are raw until their coding is set, so this is something each one
+    // opts in to.
+    pub fn is_utf8(&self) -> bool {
+        matches!(self.coding, Coding::Utf8 | Coding::Latin1)
+    }
+
+    pub fn set_coding(&mut self, coding: Coding) {
+        self.coding = coding;
+        self.column_cache.set(None);
+    }
+
+    /// True if the buffer has been changed since it was last auto-saved
+    /// or written.
+    pub fn is_auto_save_pending(&self) -> bool {
+        self.auto_save_pending
+    }
+
+    pub fn set_auto_save_pending(&mut self, pending: bool) {
+        self.auto_save_pending = pending;
+    }
+
     pub fn insert_string(&mut self, s: &MintString) -> bool {
         if self.wp {
             return false;
@@ -119,33 +278,268 @@ impl EmacsBuffer {
         self.point += s.len() as MintCount;
         self.point_line += newline_count;
         self.count_newlines += newline_count;
-        self.modified = true;
+        self.mark_modified(self.point - s.len() as MintCount, 0, s.len() as MintCount);
 
         true
     }
 
+    // Insert "s" at the process mark, moving the mark past it.  Point
+    // stays with the text around it, unless it was at the mark, when it
+    // moves past the insertion too.
+    pub fn insert_output(&mut self, s: &MintString) -> bool {
+        let (point, point_line) = (self.point, self.point_line);
+        let pos = self.process_mark;
+        self.point = pos;
+        self.point_line = self.count_newlines(0, pos);
+        if !self.insert_string(s) {
+            (self.point, self.point_line) = (point, point_line);
+            return false;
+        }
+        self.process_mark = self.point;
+        if point > pos {
+            let newline_count = s.iter().filter(|&&ch| ch == EOLCHAR).count() as MintCount;
+            self.point = point + s.len() as MintCount;
+            self.point_line = point_line + newline_count;
+        } else if point < pos {
+            (self.point, self.point_line) = (point, point_line);
+        }
+        true
+    }
+
+    pub fn insert_from_reader(
+        &mut self,
+        reader: &mut dyn Read,
+        size_hint: MintCount,
+    ) -> io::Result<MintCount> {
+        if self.wp {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "buffer is write protected",
+            ));
+        }
+
+        let n = self.text.insert_from(self.point, reader, size_hint)?;
+        self.text_inserted(n);
+        Ok(n)
+    }
+
+    // Visit "file" by mapping it, rather than copying it into the buffer.
+    // Returns false if the buffer is not empty, or its text can't be mapped.
+    pub fn map_file(&mut self, file: &fs::File) -> io::Result<bool> {
+        if self.wp {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "buffer is write protected",
+            ));
+        }
+        if self.size() != 0 || !self.text.map_file(file)? {
+            return Ok(false);
+        }
+        self.text_inserted(self.size());
+        Ok(true)
+    }
+
+    pub fn maps_file(&self, file: &fs::Metadata) -> bool {
+        self.text.maps_file(file)
+    }
+
+    pub fn unmap_file(&mut self) {
+        self.text.unmap_file();
+    }
+
+    // Update point, marks and line counts after "n" characters have been
+    // inserted into the text at point.
+    fn text_inserted(&mut self, n: MintCount) {
+        let newline_count = self.count_newlines(self.point, self.point + n);
+
+        self.adjust_marks_ins(n);
+        self.point += n;
+        self.point_line += newline_count;
+        self.count_newlines += newline_count;
+        if n > 0 {
+            self.mark_modified(self.point - n, 0, n);
+        }
+    }
+
+    // Replace "deleted" characters at "start" with "replacement", keeping
+    // point and marks after the replaced text in the same place relative
+    // to it.  Point and marks within it are moved to its start.
+    fn replace_range(
+        &mut self,
+        start: MintCount,
+        deleted: MintCount,
+        replacement: &[MintChar],
+    ) -> bool {
+        let end = start + deleted;
+        let old_newlines = self.count_newlines(start, end);
+        if !self.text.replace(start, deleted, replacement) {
+            return false;
+        }
+        let inserted = replacement.len() as MintCount;
+        let new_newlines = replacement.iter().filter(|&&ch| ch == EOLCHAR).count() as MintCount;
+        let shift = |p: MintCount| {
+            if p >= end {
+                p - deleted + inserted
+            } else {
+                p.min(start)
+            }
+        };
+
+        for mark in self.marks.iter_mut() {
+            *mark = shift(*mark);
+        }
+        self.process_mark = shift(self.process_mark);
+        let point_moved = self.point > start && self.point < end;
+        self.point = shift(self.point);
+        self.topline = shift(self.topline);
+        self.count_newlines = self.count_newlines + new_newlines - old_newlines;
+        if point_moved {
+            self.point_line = self.count_newlines(0, self.point);
+        } else if self.point >= start + inserted && self.point > start {
+            self.point_line = self.point_line + new_newlines - old_newlines;
+        }
+        self.mark_modified(start, deleted, inserted);
+        true
+    }
+
+    // Convert the leading whitespace of each line between point and "mark"
+    // to tabs and spaces if "to_tabs" is set, or to spaces only otherwise,
+    // keeping its width.  Returns the number of lines changed.
+    pub fn tabify(&mut self, mark: MintChar, to_tabs: bool) -> MintCount {
+        if self.wp || self.tab_width == 0 {
+            return 0;
+        }
+        let mark_pos = self.get_mark_position(mark);
+        let mut pos = self.find_bol(min(mark_pos, self.point));
+        let mut end = max(mark_pos, self.point);
+        let mut changed = 0;
+
+        while pos < end {
+            let mut ws_end = pos;
+            while let Some(ch) = self.text.get(ws_end)
+                && (ch == b' ' || ch == b'\t')
+            {
+                ws_end += 1;
+            }
+            let width = self.count_columns(pos, ws_end);
+            let mut replacement = Vec::new();
+            if to_tabs {
+                replacement.resize((width / self.tab_width) as usize, b'\t');
+            }
+            replacement.resize(
+                replacement.len()
+                    + (width - replacement.len() as MintCount * self.tab_width) as usize,
+                b' ',
+            );
+
+            let mut old = Vec::new();
+            self.text.read_range(pos, ws_end, &mut old);
+            if old != replacement && self.replace_range(pos, ws_end - pos, &replacement) {
+                end = end + replacement.len() as MintCount - (ws_end - pos);
+                changed += 1;
+            }
+
+            let eol = self.find_eol(pos);
+            if eol >= self.size() {
+                break;
+            }
+            pos = eol + 1;
+        }
+        changed
+    }
+
+    pub fn take_snapshot(&mut self) {
+        self.snapshot = Some(self.text.snapshot());
+    }
+
+    // Restore the text saved by take_snapshot(), keeping point and marks
+    // where they were as far as possible.  The snapshot is kept, so it
+    // can be restored again.
+    pub fn restore_snapshot(&mut self) -> bool {
+        if self.wp {
+            return false;
+        }
+        let old_size = self.size();
+        let restored = match &self.snapshot {
+            Some(snapshot) => self.text.restore(snapshot),
+            None => false,
+        };
+        if !restored {
+            return false;
+        }
+
+        let size = self.size();
+        for mark in self.marks.iter_mut() {
+            *mark = (*mark).min(size);
+        }
+        self.process_mark = self.process_mark.min(size);
+        self.point = self.point.min(size);
+        self.topline = self.find_bol(self.topline.min(size));
+        self.count_newlines = self.count_newlines(0, size);
+        self.point_line = self.count_newlines(0, self.point);
+        self.topline_line = self.count_newlines(0, self.topline);
+        self.mark_modified(0, old_size, size);
+        true
+    }
+
+    // Replace the whole text with whatever "load" inserts into an empty
+    // buffer, taking the visited file and coding it gives that buffer too.
+    // Point, topline and marks keep their offsets where these are still
+    // within the new text.  If "load" fails, the buffer is left unchanged.
+    pub fn reload<F>(&mut self, load: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Self) -> io::Result<()>,
+    {
+        if self.wp {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "buffer is write protected",
+            ));
+        }
+        let mut scratch = EmacsBuffer::new(self.bufno, self.text.new_empty());
+        scratch.coding = self.coding;
+        scratch.file_name = self.file_name.clone();
+        scratch.file_mtime = self.file_mtime;
+        load(&mut scratch)?;
+
+        let old_size = self.size();
+        std::mem::swap(&mut self.text, &mut scratch.text);
+        self.coding = scratch.coding;
+        self.file_name = scratch.file_name;
+        self.file_mtime = scratch.file_mtime;
+        let size = self.size();
+        self.mark_modified(0, old_size, size);
+        self.auto_save_pending = scratch.auto_save_pending;
+        for mark in self.marks.iter_mut() {
+            *mark = (*mark).min(size);
+        }
+        self.process_mark = self.process_mark.min(size);
+        self.point = self.point.min(size);
+        self.topline = self.find_bol(self.topline.min(size));
+        self.count_newlines = self.count_newlines(0, size);
+        self.point_line = self.count_newlines(0, self.point);
+        self.topline_line = self.count_newlines(0, self.topline);
+        Ok(())
+    }
+
     pub fn push_temp_marks(&mut self, n: MintCount) -> bool {
         let n = n as usize;
-        if (self.temp_mark_last + n) <= MAX_MARKS {
-            self.mark_stack[self.marks_sp] = self.temp_mark_base;
-            self.marks_sp += 1;
-            self.temp_mark_base = self.temp_mark_last;
-            self.temp_mark_last = self.temp_mark_base + n;
-
-            for i in 0..n {
-                self.marks[self.temp_mark_base + i] = self.point;
-            }
-            true
-        } else {
-            false
+        // Only marks 0 to 9 can be named, so more would never be used.
+        if n > MARK_MAX_TEMP {
+            return false;
         }
+        self.mark_stack.push(self.temp_mark_base);
+        self.temp_mark_base = self.temp_mark_last;
+        self.temp_mark_last = self.temp_mark_base + n;
+        self.marks.resize(self.temp_mark_last, self.point);
+        true
     }
 
     pub fn pop_temp_marks(&mut self) -> bool {
-        if self.marks_sp > 0 {
+        if let Some(base) = self.mark_stack.pop() {
             self.temp_mark_last = self.temp_mark_base;
-            self.marks_sp -= 1;
-            self.temp_mark_base = self.mark_stack[self.marks_sp];
+            self.temp_mark_base = base;
+            self.marks.truncate(self.temp_mark_last);
             true
         } else {
             false
@@ -158,7 +552,8 @@ impl EmacsBuffer {
             self.perm_mark_count = n;
             self.temp_mark_base = n;
             self.temp_mark_last = n;
-            self.marks_sp = 0;
+            self.marks.resize(n, 0);
+            self.mark_stack.clear();
             true
         } else {
             false
@@ -206,11 +601,21 @@ impl EmacsBuffer {
             self.point_line -= newline_count;
         }
         self.count_newlines -= newline_count;
-        self.modified = true;
+        self.mark_modified(min_pos, delete_len, 0);
+
+        // Emptying a buffer is how libraries discard its contents, so give
+        // back any memory it grew to hold.
+        if self.text.size() == 0 {
+            self.text.compact();
+        }
 
         true
     }
 
+    pub fn compact(&mut self) -> MintCount {
+        self.text.compact()
+    }
+
     pub fn read_to_mark(&self, mark: MintChar) -> MintString {
         self.read_to_mark_from(mark, self.point)
     }
@@ -224,28 +629,27 @@ impl EmacsBuffer {
         let max_pos = max(from_pos, to_pos);
 
         let mut result = Vec::new();
-        for i in min_pos..max_pos {
-            if let Some(ch) = self.text.get(i) {
-                result.push(ch);
-            }
-        }
+        self.text.read_range(min_pos, max_pos, &mut result);
         result
     }
 
-    pub fn translate(&mut self, mark: MintChar, trstr: &MintString) -> bool {
-        if self.wp || trstr.len() < 2 {
+    // Replace each character between point and "mark" that appears in
+    // "from_str" with the character at the same index in "to_str".
+    pub fn translate(
+        &mut self,
+        mark: MintChar,
+        from_str: &[MintChar],
+        to_str: &[MintChar],
+    ) -> bool {
+        if self.wp || from_str.is_empty() {
             return false;
         }
 
-        let half = trstr.len() / 2;
-        let from_str = &trstr[0..half];
-        let to_str = &trstr[half..];
-
         let mark_pos = self.get_mark_position(mark);
         let min_pos = min(mark_pos, self.point);
         let max_pos = max(mark_pos, self.point);
 
-        let mut changed = false;
+        let mut changed = None;
         for pos in min_pos..max_pos {
             if let Some(ch) = self.text.get(pos)
                 && let Some(idx) = from_str.iter().position(|&c| c == ch)
@@ -253,14 +657,14 @@ impl EmacsBuffer {
             {
                 let replacement = vec![to_str[idx]];
                 self.text.replace(pos, 1, &replacement);
-                changed = true;
+                changed = Some(changed.map_or((pos, pos + 1), |(start, _)| (start, pos + 1)));
             }
         }
 
-        if changed {
-            self.modified = true;
+        if let Some((start, end)) = changed {
+            self.mark_modified(start, end - start, end - start);
         }
-        changed
+        changed.is_some()
     }
 
     pub fn chars_to_mark(&self, mark: MintChar) -> MintCount {
@@ -282,9 +686,18 @@ impl EmacsBuffer {
         self.text.size() as MintCount
     }
 
+    pub fn capacity(&self) -> MintCount {
+        self.text.capacity()
+    }
+
     pub fn set_mark_position(&mut self, mark: MintChar, position: MintCount) -> bool {
         let adjusted_pos = min(self.text.size() as MintCount, position);
 
+        if mark == MARK_PROCESS {
+            self.process_mark = adjusted_pos;
+            return true;
+        }
+
         if mark >= MARK_FIRST_TEMP {
             let temp_markno = (mark - MARK_FIRST_TEMP) as usize;
             if (self.temp_mark_base + temp_markno) < self.temp_mark_last {
@@ -314,6 +727,7 @@ impl EmacsBuffer {
             MARK_BOB => 0,
             MARK_EOB => self.text.size() as MintCount,
             MARK_TOPLINE => self.topline,
+            MARK_PROCESS => self.process_mark,
             MARK_BOL => self.find_bol(frompos),
             MARK_EOL => self.find_eol(frompos),
             MARK_PREV_CHAR => {
@@ -379,87 +793,195 @@ impl EmacsBuffer {
 
     pub fn get_column(&self) -> MintCount {
         let bol = self.find_bol(self.point);
-        self.count_columns(bol, self.point)
+        let (from, from_col) = match self.column_cache.get() {
+            Some((cached_bol, pos, col)) if cached_bol == bol && pos <= self.point => (pos, col),
+            _ => (bol, 0),
+        };
+        let col = from_col + self.count_columns_from(from_col, from, self.point);
+        self.column_cache.set(Some((bol, self.point, col)));
+        col
     }
 
     pub fn set_column(&mut self, col: MintCount) {
         let bol = self.find_bol(self.point);
         let eol = self.find_eol(self.point);
-        let mut cur_col = 0;
-        let mut pos = bol;
+        let (mut pos, mut cur_col) = match self.column_cache.get() {
+            Some((cached_bol, pos, cached_col)) if cached_bol == bol && cached_col <= col => {
+                (pos, cached_col)
+            }
+            _ => (bol, 0),
+        };
 
         while pos < eol && cur_col < col {
-            if let Some(ch) = self.text.get(pos) {
-                cur_col += self.char_width(cur_col, ch);
-                pos += 1;
-            } else {
+            let (width, len) = self.char_width_at(cur_col, pos, eol);
+            if len == 0 {
                 break;
             }
+            cur_col += width;
+            pos += len as MintCount;
         }
         self.point = pos;
+        self.column_cache.set(Some((bol, pos, cur_col)));
     }
 
     pub fn count_newlines(&self, from: MintCount, to: MintCount) -> MintCount {
-        let mut count = 0;
-        for i in from..to {
-            if let Some(ch) = self.text.get(i)
-                && ch == EOLCHAR
-            {
-                count += 1;
+        self.text.count_byte(EOLCHAR, from, to)
+    }
+
+    pub fn count_columns(&self, from: MintCount, to: MintCount) -> MintCount {
+        self.count_columns_from(0, from, to)
+    }
+
+    // Columns taken by the text from "from" to "to", when "from" is at
+    // column "start_col".
+    fn count_columns_from(
+        &self,
+        start_col: MintCount,
+        from: MintCount,
+        to: MintCount,
+    ) -> MintCount {
+        let mut col = start_col;
+        if self.is_utf8() {
+            let mut pos = from;
+            while pos < to {
+                let (width, len) = self.char_width_at(col, pos, to);
+                if len == 0 {
+                    break;
+                }
+                col += width;
+                pos += len as MintCount;
             }
+        } else {
+            self.text.for_each_chunk(from, to, false, &mut |_, chunk| {
+                for &ch in chunk {
+                    col += self.char_width(col, ch);
+                }
+                true
+            });
         }
-        count
+        col - start_col
     }
 
-    pub fn count_columns(&self, from: MintCount, to: MintCount) -> MintCount {
-        let mut col = 0;
-        for i in from..to {
-            if let Some(ch) = self.text.get(i) {
-                col += self.char_width(col, ch);
+    // Columns taken by the character at "pos", which must be before "end",
+    // and its length in bytes.  Zero length means "pos" is past the text.
+    fn char_width_at(
+        &self,
+        cur_col: MintCount,
+        pos: MintCount,
+        end: MintCount,
+    ) -> (MintCount, usize) {
+        let Some(lead) = self.text.get(pos) else {
+            return (0, 0);
+        };
+        let mut bytes = [lead; 4];
+        let mut len = 1;
+        if self.is_utf8()
+            && let Some(seq_len) = utf8_len(lead)
+        {
+            while len < seq_len && pos + (len as MintCount) < end {
+                match self.text.get(pos + len as MintCount) {
+                    Some(ch) => bytes[len] = ch,
+                    None => break,
+                }
+                len += 1;
             }
         }
-        col
+        self.display_width(cur_col, &bytes[..len])
     }
 
     pub fn get_left_column(&self) -> MintCount {
         self.leftcol
     }
 
+    pub fn set_left_column(&mut self, col: MintCount) {
+        self.leftcol = col;
+    }
+
     pub fn set_tab_width(&mut self, n: MintCount) {
         self.tab_width = n;
+        self.column_cache.set(None);
     }
 
     pub fn get_tab_width(&self) -> MintCount {
         self.tab_width
     }
 
+    pub fn set_hex_display(&mut self, flag: bool) {
+        self.hex_display = flag;
+        self.column_cache.set(None);
+    }
+
+    pub fn get_hex_display(&self) -> bool {
+        self.hex_display
+    }
+
+    pub fn display(&self) -> &DisplayOverrides {
+        &self.display
+    }
+
+    pub fn display_mut(&mut self) -> &mut DisplayOverrides {
+        &mut self.display
+    }
+
+    // How "ch" is shown in this buffer, or None if it is shown as itself.
+    // Like byte_repr, except control characters are shown in hex when
+    // hex display is on.
+    pub fn display_repr(&self, ch: MintChar) -> Option<String> {
+        if self.hex_display && matches!(ch, 0x00..=0x1f | 0x7f) {
+            Some(format!("\\x{:02X}", ch))
+        } else {
+            byte_repr(ch)
+        }
+    }
+
     pub fn char_width(&self, cur_col: MintCount, ch: MintChar) -> MintCount {
         if ch == b'\t' {
             self.tab_width - (cur_col % self.tab_width)
-        } else if !(32..127).contains(&ch) {
-            2
         } else {
-            1
+            self.display_repr(ch)
+                .map_or(1, |repr| repr.len() as MintCount)
+        }
+    }
+
+    // Columns taken by the character at the start of "bytes" when it is
+    // displayed at "cur_col", and its length in bytes.  In UTF-8 buffers a
+    // multi-byte character takes its Unicode width: two cells for wide
+    // (CJK) characters, none for combining marks.
+    pub fn display_width(&self, cur_col: MintCount, bytes: &[MintChar]) -> (MintCount, usize) {
+        if self.is_utf8()
+            && let Some((ch, len)) = utf8_char(bytes)
+        {
+            (ch.width().unwrap_or(1) as MintCount, len)
+        } else {
+            (self.char_width(cur_col, bytes[0]), 1)
         }
     }
 
     pub fn force_point_in_window(
         &mut self,
         li: MintCount,
-        _co: MintCount,
+        co: MintCount,
         tp: MintCount,
         bp: MintCount,
     ) {
+        // Scroll sideways to bring point back to the middle of the window
+        // if it has gone off either edge.
+        let col = self.get_column();
+        if co > 0 && (col < self.leftcol || col >= self.leftcol + co) {
+            self.leftcol = col.saturating_sub(co / 2);
+        }
+
         let tl = li * tp / 100;
         if self.point_line <= tl {
             self.topline = 0;
             self.topline_line = 0;
         } else {
             let bl = li * bp / 100;
-            if self.point_line >= self.count_newlines - bl {
+            if self.point_line >= self.count_newlines.saturating_sub(bl) {
+                // A buffer shorter than the window is shown from the top.
                 let size = self.text.size() as MintCount;
                 self.topline = self.backward_lines(self.find_bol(size), li - 1);
-                self.topline_line = self.count_newlines - (li - 1);
+                self.topline_line = self.count_newlines.saturating_sub(li - 1);
             } else if self.point_line < (self.topline_line + tl) {
                 let blines = (self.topline_line + tl) - self.point_line;
                 self.topline = self.backward_lines(self.topline, blines);
@@ -487,12 +1009,22 @@ impl EmacsBuffer {
         self.point_line - self.topline_line
     }
 
+    // Move point to the character shown at "row" and "col" of the window,
+    // or as near to it as the text allows.
+    pub fn set_point_to_screen(&mut self, row: MintCount, col: MintCount) {
+        self.set_point_line(self.topline_line + row);
+        self.set_column(self.leftcol + col);
+    }
+
     fn adjust_marks_ins(&mut self, n: MintCount) {
-        for i in 0..MAX_MARKS {
-            if self.marks[i] > self.point {
-                self.marks[i] += n;
+        for mark in self.marks.iter_mut() {
+            if *mark > self.point {
+                *mark += n;
             }
         }
+        if self.process_mark > self.point {
+            self.process_mark += n;
+        }
         self.topline = if self.topline > self.point {
             self.topline + n
         } else {
@@ -501,41 +1033,30 @@ impl EmacsBuffer {
     }
 
     fn adjust_marks_del(&mut self, n: MintCount) {
-        for i in 0..MAX_MARKS {
-            if self.marks[i] > self.point {
-                self.marks[i] = self.marks[i].saturating_sub(n);
+        for mark in self.marks.iter_mut() {
+            if *mark > self.point {
+                *mark = mark.saturating_sub(n);
             }
         }
+        if self.process_mark > self.point {
+            self.process_mark = self.process_mark.saturating_sub(n).max(self.point);
+        }
         if self.topline > self.point {
             self.topline = self.topline.saturating_sub(n);
         }
     }
 
     fn find_bol(&self, frompos: MintCount) -> MintCount {
-        let mut pos = frompos;
-        while pos > 0 {
-            pos -= 1;
-            if let Some(ch) = self.text.get(pos)
-                && ch == EOLCHAR
-            {
-                return pos + 1;
-            }
-        }
-        0
+        self.text
+            .find_byte_backward(EOLCHAR, 0, frompos)
+            .map_or(0, |pos| pos + 1)
     }
 
     fn find_eol(&self, frompos: MintCount) -> MintCount {
         let size = self.text.size() as MintCount;
-        let mut pos = frompos;
-        while pos < size {
-            if let Some(ch) = self.text.get(pos)
-                && ch == EOLCHAR
-            {
-                return pos;
-            }
-            pos += 1;
-        }
-        size
+        self.text
+            .find_byte_forward(EOLCHAR, frompos, size)
+            .unwrap_or(size)
     }
 
     fn find_prev_blank(&self, frompos: MintCount) -> MintCount {
@@ -615,6 +1136,12 @@ impl EmacsBuffer {
         self.point_line = self.count_newlines(0, self.point);
     }
 
+    // Move point to byte offset "pos", or the end of the buffer if past it.
+    pub fn set_point_position(&mut self, pos: MintCount) {
+        self.point = min(self.text.size() as MintCount, pos);
+        self.point_line = self.count_newlines(0, self.point);
+    }
+
     pub fn set_point_to_marks(&mut self, marks: &MintString) {
         for &mark in marks {
             self.set_point_to_mark(mark);
@@ -630,6 +1157,28 @@ impl EmacsBuffer {
         self.text.find_forward(regex, start, end)
     }
 
+    pub fn set_highlight(&mut self, regex: Option<Regex>) {
+        self.highlight = regex;
+    }
+
+    // Spans of the text from "from" to "to" that match the highlight
+    // pattern, in order.  Empty matches are left out.
+    pub fn highlight_spans(&self, from: MintCount, to: MintCount) -> Vec<(MintCount, MintCount)> {
+        let mut spans = Vec::new();
+        if let Some(regex) = &self.highlight {
+            let mut pos = from;
+            while pos < to
+                && let Some((start, end)) = self.find_forward(regex, pos, to)
+            {
+                if end > start {
+                    spans.push((start, end));
+                }
+                pos = max(end, start + 1);
+            }
+        }
+        spans
+    }
+
     pub fn find_backward(
         &self,
         regex: &Regex,
@@ -639,3 +1188,203 @@ impl EmacsBuffer {
         self.text.find_backward(regex, start, end)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gap_buffer::GapBuffer;
+
+    fn new_buffer(text: &str) -> EmacsBuffer {
+        let mut buf = EmacsBuffer::new(1, Box::new(GapBuffer::with_default_size()));
+        buf.insert_string(&text.as_bytes().to_vec());
+        buf.take_changed_span();
+        buf
+    }
+
+    #[test]
+    fn failed_reload_leaves_buffer_alone() {
+        let mut buf = new_buffer("one\ntwo\n");
+        buf.set_point_position(5);
+        let result = buf.reload(|buf| {
+            buf.insert_string(&b"partly read".to_vec());
+            Err(io::Error::other("read failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(b"one\ntwo\n".to_vec(), buf.read(0, buf.size()));
+        assert_eq!(5, buf.point);
+        assert!(
+            buf.reload(|buf| {
+                buf.insert_string(&b"new".to_vec());
+                Ok(())
+            })
+            .is_ok()
+        );
+        assert_eq!(b"new".to_vec(), buf.read(0, buf.size()));
+        assert_eq!(3, buf.point);
+    }
+
+    #[test]
+    fn highlight_match_spans() {
+        let mut buf = new_buffer("abcab\nxab");
+        let size = buf.size();
+        assert!(buf.highlight_spans(0, size).is_empty());
+        buf.set_highlight(Some(Regex::new("ab").unwrap()));
+        assert_eq!(vec![(0, 2), (3, 5), (7, 9)], buf.highlight_spans(0, size));
+        assert_eq!(vec![(3, 5)], buf.highlight_spans(1, 5));
+        buf.set_highlight(Some(Regex::new("x*").unwrap()));
+        assert_eq!(vec![(6, 7)], buf.highlight_spans(0, size));
+    }
+
+    #[test]
+    fn point_from_screen_position() {
+        let mut buf = new_buffer("one\n\ttwo\nthree");
+        buf.set_point_to_mark(MARK_BOB);
+        buf.set_point_to_screen(1, 9);
+        assert_eq!(6, buf.get_mark_position(MARK_POINT));
+        buf.set_point_to_screen(1, 3);
+        assert_eq!(5, buf.get_mark_position(MARK_POINT));
+        buf.set_point_to_screen(0, 40);
+        assert_eq!(3, buf.get_mark_position(MARK_POINT));
+        buf.set_point_to_screen(9, 1);
+        assert_eq!(10, buf.get_mark_position(MARK_POINT));
+    }
+
+    #[test]
+    fn force_point_in_window_scrolls_sideways() {
+        let mut buf = new_buffer(&format!("{}\nshort", "x".repeat(100)));
+        buf.set_point_to_mark(MARK_BOB);
+        buf.set_column(85);
+        buf.force_point_in_window(1, 40, 0, 0);
+        assert_eq!(65, buf.get_left_column());
+        buf.set_column(70);
+        buf.force_point_in_window(1, 40, 0, 0);
+        assert_eq!(65, buf.get_left_column());
+        buf.set_point_to_mark(MARK_EOB);
+        buf.force_point_in_window(1, 40, 0, 0);
+        assert_eq!(0, buf.get_left_column());
+    }
+
+    #[test]
+    fn changed_span_tracks_edits() {
+        let mut buf = new_buffer("0123456789");
+        let tick = buf.get_change_tick();
+        assert_eq!(None, buf.take_changed_span());
+
+        buf.set_point_to_mark(MARK_BOB);
+        buf.set_point_to_marks(&b">>>>>>".to_vec());
+        buf.insert_string(&b"ab".to_vec());
+        assert_eq!(Some((6, 8)), buf.take_changed_span());
+        assert_eq!(None, buf.take_changed_span());
+
+        // Insert then delete before it; the span shifts left.
+        buf.insert_string(&b"xyz".to_vec());
+        buf.set_point_to_mark(MARK_BOB);
+        buf.delete_to_marks(&b">>".to_vec());
+        assert_eq!(Some((0, 9)), buf.take_changed_span());
+        assert_eq!(tick + 4, buf.get_change_tick());
+    }
+
+    #[test]
+    fn output_goes_in_at_process_mark() {
+        let mut buf = new_buffer("$ ");
+        buf.set_mark_position(MARK_PROCESS, 2);
+        buf.insert_output(&b"ls\n".to_vec());
+        assert_eq!(5, buf.get_mark_position(MARK_PROCESS));
+        assert_eq!(5, buf.get_mark_position(MARK_POINT));
+        assert_eq!(1, buf.get_point_line());
+
+        // Typed input after the mark is kept after the output.
+        buf.insert_string(&b"pwd".to_vec());
+        buf.insert_output(&b"a b\n$ ".to_vec());
+        assert_eq!(b"$ ls\na b\n$ pwd".to_vec(), buf.read(0, buf.size()));
+        assert_eq!(11, buf.get_mark_position(MARK_PROCESS));
+        assert_eq!(14, buf.get_mark_position(MARK_POINT));
+        assert_eq!(2, buf.get_point_line());
+
+        // Deleting text before the mark moves it back.
+        buf.set_point_to_mark(MARK_BOB);
+        buf.delete_to_marks(&b">>".to_vec());
+        assert_eq!(9, buf.get_mark_position(MARK_PROCESS));
+        assert_eq!(0, buf.get_point_line());
+    }
+
+    #[test]
+    fn byte_repr_and_width() {
+        let buf = new_buffer("");
+        assert_eq!(Some("^@".to_string()), byte_repr(0));
+        assert_eq!(Some("^?".to_string()), byte_repr(0x7f));
+        assert_eq!(Some("\\x9B".to_string()), byte_repr(0x9b));
+        assert_eq!(None, byte_repr(b'a'));
+        assert_eq!(2, buf.char_width(0, 0));
+        assert_eq!(4, buf.char_width(0, 0xff));
+        assert_eq!(1, buf.char_width(0, b'~'));
+    }
+
+    #[test]
+    fn hex_display_of_control_characters() {
+        let mut buf = new_buffer("a\x1b\x7f");
+        assert_eq!(Some("^[".to_string()), buf.display_repr(0x1b));
+        assert_eq!(5, buf.count_columns(0, 3));
+        buf.set_hex_display(true);
+        assert_eq!(Some("\\x1B".to_string()), buf.display_repr(0x1b));
+        assert_eq!(Some("\\x7F".to_string()), buf.display_repr(0x7f));
+        assert_eq!(None, buf.display_repr(b'a'));
+        assert_eq!(9, buf.count_columns(0, 3));
+    }
+
+    #[test]
+    fn utf8_columns() {
+        let mut buf = new_buffer("caf\u{e9} \u{20ac}!");
+        let size = buf.size();
+        assert_eq!(25, buf.count_columns(0, size));
+        buf.set_coding(Coding::Utf8);
+        assert_eq!(7, buf.count_columns(0, size));
+        assert_eq!(Some(('\u{20ac}', 3)), utf8_char("\u{20ac}!".as_bytes()));
+        assert_eq!(None, utf8_char(b"\xe2\x82"));
+        assert_eq!(None, utf8_char(b"a"));
+        assert_eq!(None, utf8_char("\u{85}".as_bytes()));
+    }
+
+    #[test]
+    fn wide_and_combining_columns() {
+        // Two CJK characters, then "e" with a combining acute accent
+        let mut buf = new_buffer("\u{65e5}\u{672c}e\u{301}x");
+        buf.set_coding(Coding::Utf8);
+        let size = buf.size();
+        assert_eq!(6, buf.count_columns(0, size));
+        assert_eq!((2, 3), buf.display_width(0, "\u{65e5}".as_bytes()));
+        assert_eq!((0, 2), buf.display_width(5, "\u{301}".as_bytes()));
+        buf.set_point_to_mark(MARK_BOB);
+        buf.set_column(3);
+        assert_eq!(4, buf.get_column());
+        assert_eq!("e\u{301}x".as_bytes().to_vec(), buf.read_to_mark(MARK_EOB));
+    }
+
+    #[test]
+    fn tabify_leading_whitespace() {
+        let mut buf = new_buffer("        a\n\t  b  \n   \tc\n");
+        buf.set_tab_width(4);
+        buf.set_point_to_mark(MARK_BOB);
+        buf.set_mark(b'@', MARK_EOB);
+        assert_eq!(2, buf.tabify(b'@', true));
+        assert_eq!(
+            b"\t\ta\n\t  b  \n\tc\n".to_vec(),
+            buf.read_to_mark(MARK_EOB)
+        );
+        assert_eq!(0, buf.tabify(b'@', true));
+        assert_eq!(3, buf.tabify(b'@', false));
+        assert_eq!(
+            b"        a\n      b  \n    c\n".to_vec(),
+            buf.read_to_mark(MARK_EOB)
+        );
+        assert_eq!(buf.size(), buf.get_mark_position(b'@'));
+    }
+
+    #[test]
+    fn changed_span_for_deletion_is_empty() {
+        let mut buf = new_buffer("0123456789");
+        buf.set_point_to_mark(MARK_EOB);
+        buf.delete_to_marks(&b"<<<".to_vec());
+        assert_eq!(Some((7, 7)), buf.take_changed_span());
+    }
+}
@@ -18,58 +18,213 @@
 
 use crate::buffer::Buffer;
 use crate::emacs_buffer::EmacsBuffer;
+use crate::gap_buffer::GapBuffer;
+use crate::mint::Mint;
 use crate::mint_types::{MintChar, MintCount, MintString};
+use crate::mmap_buffer::MmapBuffer;
+use crate::piece_table_buffer::PieceTableBuffer;
+use crate::rope_buffer::RopeBuffer;
+use crate::sysprim::replace_file;
 use regex::bytes::{Regex, RegexBuilder};
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, Instant};
 
-static S_BUFNO: AtomicUsize = AtomicUsize::new(1);
+pub type BufferFactory = fn() -> Box<dyn Buffer>;
+
+// Named buffer implementations that can be selected for new buffers.
+fn buffer_type_factory(name: &[MintChar]) -> Option<BufferFactory> {
+    match name {
+        b"gap" => Some(|| Box::new(GapBuffer::with_default_size())),
+        b"mmap" => Some(|| Box::new(MmapBuffer::new())),
+        b"piece" => Some(|| Box::new(PieceTableBuffer::new())),
+        b"rope" => Some(|| Box::new(RopeBuffer::new())),
+        _ => None,
+    }
+}
 
 pub struct EmacsBuffers {
-    buffer_factory: fn() -> Box<dyn Buffer>,
-    current_buffer: Rc<RefCell<EmacsBuffer>>,
-    buffers: HashMap<MintCount, Rc<RefCell<EmacsBuffer>>>,
+    buffer_factory: BufferFactory,
+    buffer_type: MintString,
+    current_buffer: Arc<Mutex<EmacsBuffer>>,
+    buffers: HashMap<MintCount, Arc<Mutex<EmacsBuffer>>>,
+    // Number for the next buffer made.
+    next_bufno: MintCount,
     regex: Option<Regex>,
+    auto_save_interval: MintCount,
+    auto_save_dir: MintString,
+    last_auto_save: Instant,
 }
 
 impl EmacsBuffers {
-    pub fn new(factory: fn() -> Box<dyn Buffer>) -> Self {
-        let bufno = S_BUFNO.fetch_add(1, Ordering::SeqCst) as MintCount;
-        let init_buffer = Rc::new(RefCell::new(EmacsBuffer::new(bufno, factory())));
+    pub fn new(factory: BufferFactory) -> Self {
+        let bufno = 1;
+        let init_buffer = Arc::new(Mutex::new(EmacsBuffer::new(bufno, factory())));
         let mut buffers = HashMap::new();
-        buffers.insert(bufno, Rc::clone(&init_buffer));
+        buffers.insert(bufno, Arc::clone(&init_buffer));
         Self {
             buffer_factory: factory,
-            current_buffer: Rc::clone(&init_buffer),
+            buffer_type: MintString::new(),
+            current_buffer: Arc::clone(&init_buffer),
             buffers,
+            next_bufno: bufno + 1,
             regex: None,
+            auto_save_interval: 0,
+            auto_save_dir: MintString::new(),
+            last_auto_save: Instant::now(),
         }
     }
 
-    pub fn get_cur_buffer(&self) -> Rc<RefCell<EmacsBuffer>> {
-        Rc::clone(&self.current_buffer)
+    pub fn get_cur_buffer(&self) -> Arc<Mutex<EmacsBuffer>> {
+        Arc::clone(&self.current_buffer)
     }
 
     pub fn new_buffer(&mut self) -> MintCount {
         let new_buffer = (self.buffer_factory)();
-        let bufno = S_BUFNO.fetch_add(1, Ordering::SeqCst) as MintCount;
-        self.current_buffer = Rc::new(RefCell::new(EmacsBuffer::new(bufno, new_buffer)));
-        let bufno = self.current_buffer.borrow().get_buf_number();
-        self.buffers.insert(bufno, Rc::clone(&self.current_buffer));
+        let bufno = self.next_bufno;
+        self.next_bufno += 1;
+        self.current_buffer = Arc::new(Mutex::new(EmacsBuffer::new(bufno, new_buffer)));
+        let bufno = lock_buffer(&self.current_buffer).get_buf_number();
+        self.buffers.insert(bufno, Arc::clone(&self.current_buffer));
         bufno
     }
 
+    pub fn get_buffer(&self, bufno: MintCount) -> Option<Arc<Mutex<EmacsBuffer>>> {
+        self.buffers.get(&bufno).map(Arc::clone)
+    }
+
     pub fn select_buffer(&mut self, bufno: MintCount) -> bool {
         if let Some(buf) = self.buffers.get(&bufno) {
-            self.current_buffer = Rc::clone(buf);
+            self.current_buffer = Arc::clone(buf);
             true
         } else {
             false
         }
     }
 
+    // Numbers of the buffers that have been modified, in order.
+    pub fn modified_buffers(&self) -> Vec<MintCount> {
+        let mut bufnos: Vec<MintCount> = self
+            .buffers
+            .iter()
+            .filter(|(_, buf)| lock_buffer(buf).is_modified())
+            .map(|(&bufno, _)| bufno)
+            .collect();
+        bufnos.sort_unstable();
+        bufnos
+    }
+
+    // Copy into buffers' own storage any text they map from "file_name",
+    // so that it can be rewritten.  A mapped file that changed under its
+    // buffer would change its text, or take it away.  Changes made by
+    // other programs can still do that.
+    pub fn unmap_file(&self, file_name: &str) {
+        let Ok(metadata) = fs::metadata(file_name) else {
+            return;
+        };
+        for buf in self.buffers.values() {
+            let mut buf = lock_buffer(buf);
+            if buf.maps_file(&metadata) {
+                buf.unmap_file();
+            }
+        }
+    }
+
+    // Select the named buffer implementation for buffers created from now
+    // on.  Returns false if there is no such implementation.
+    pub fn set_buffer_type(&mut self, name: &[MintChar]) -> bool {
+        match buffer_type_factory(name) {
+            Some(factory) => {
+                self.buffer_factory = factory;
+                self.buffer_type = name.to_vec();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Name of the implementation used for new buffers, or null if it is
+    // still the one the buffers were initialised with.
+    pub fn get_buffer_type(&self) -> &MintString {
+        &self.buffer_type
+    }
+
+    pub fn set_auto_save(&mut self, interval: MintCount, dir: &MintString) {
+        self.auto_save_interval = interval;
+        self.auto_save_dir = dir.clone();
+        self.last_auto_save = Instant::now();
+    }
+
+    pub fn get_auto_save_interval(&self) -> MintCount {
+        self.auto_save_interval
+    }
+
+    // Auto-save file for "file_name" is "#name#", either alongside the
+    // file, or in the auto-save directory if one has been set.
+    pub fn auto_save_path(&self, file_name: &MintString) -> PathBuf {
+        let file_str = String::from_utf8_lossy(file_name);
+        let path = Path::new(file_str.as_ref());
+        let base = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let save_name = format!("#{}#", base);
+        if self.auto_save_dir.is_empty() {
+            path.with_file_name(save_name)
+        } else {
+            let dir_str = String::from_utf8_lossy(&self.auto_save_dir);
+            Path::new(dir_str.as_ref()).join(save_name)
+        }
+    }
+
+    // How long until auto-saving is next due, or None if it is off.
+    pub fn auto_save_wait(&self) -> Option<Duration> {
+        if self.auto_save_interval == 0 {
+            return None;
+        }
+        let interval = Duration::from_secs(self.auto_save_interval as u64);
+        Some(interval.saturating_sub(self.last_auto_save.elapsed()))
+    }
+
+    pub fn auto_save_if_due(&mut self) -> (MintCount, Option<String>) {
+        if self.auto_save_wait() == Some(Duration::ZERO) {
+            self.auto_save_all()
+        } else {
+            (0, None)
+        }
+    }
+
+    // Write each modified buffer visiting a file to its auto-save file.
+    // Returns how many were written, and why the first that couldn't be
+    // wasn't.
+    pub fn auto_save_all(&mut self) -> (MintCount, Option<String>) {
+        self.last_auto_save = Instant::now();
+        let mut saved = 0;
+        let mut failed = None;
+        for buf_rc in self.buffers.values() {
+            let mut buf = lock_buffer(buf_rc);
+            if !buf.is_modified() || !buf.is_auto_save_pending() || buf.get_file_name().is_empty() {
+                continue;
+            }
+            let path = self.auto_save_path(buf.get_file_name());
+            let content = buf.read_to_mark_from(crate::emacs_buffer::MARK_EOB, 0);
+            match replace_file(&path, &content) {
+                Ok(_) => {
+                    buf.set_auto_save_pending(false);
+                    saved += 1;
+                }
+                Err(e) => {
+                    failed.get_or_insert_with(|| {
+                        format!("Auto-save to {} failed: {}", path.display(), e)
+                    });
+                }
+            }
+        }
+        (saved, failed)
+    }
+
     pub fn set_search_string(&mut self, s: &MintString, fold_case: bool) -> bool {
         if s.is_empty() {
             self.regex = None;
@@ -114,8 +269,15 @@ impl EmacsBuffers {
         }
     }
 
+    // Highlight matches of the search pattern in the current buffer, or
+    // stop highlighting if "on" is false.
+    pub fn highlight_search(&self, on: bool) {
+        let regex = if on { self.regex.clone() } else { None };
+        lock_buffer(&self.current_buffer).set_highlight(regex);
+    }
+
     pub fn search(&self, ss: MintChar, se: MintChar, ms: MintChar, me: MintChar) -> bool {
-        let mut buf = self.current_buffer.borrow_mut();
+        let mut buf = lock_buffer(&self.current_buffer);
 
         if self.regex.is_none() {
             if cfg!(debug_assertions) {
@@ -207,39 +369,40 @@ impl EmacsBuffers {
     }
 }
 
-// FIXME: This should not be thread local.
-thread_local! {
-    static EMACS_BUFFERS: RefCell<Option<EmacsBuffers>> = const { RefCell::new(None) };
+pub fn init_buffers(interp: &Mint, buffer_factory: BufferFactory) {
+    *interp.context().buffers.borrow_mut() = Some(EmacsBuffers::new(buffer_factory));
 }
 
-pub fn init_buffers(buffer_factory: fn() -> Box<dyn Buffer>) {
-    EMACS_BUFFERS.with(|buffers| {
-        *buffers.borrow_mut() = Some(EmacsBuffers::new(buffer_factory));
-    });
+pub fn free_buffers(interp: &Mint) {
+    *interp.context().buffers.borrow_mut() = None;
 }
 
-pub fn free_buffers() {
-    EMACS_BUFFERS.with(|buffers| {
-        *buffers.borrow_mut() = None;
-    });
-    S_BUFNO.store(1, Ordering::SeqCst);
+// Lock "buf".  Buffers are only locked by the thread the interpreter is
+// on, so if it is locked already, that is further up the same thread, and
+// waiting would never end.  That panics instead, as borrowing it twice did
+// when buffers were in a RefCell.
+pub fn lock_buffer(buf: &Mutex<EmacsBuffer>) -> MutexGuard<'_, EmacsBuffer> {
+    match buf.try_lock() {
+        Ok(guard) => guard,
+        Err(TryLockError::WouldBlock) => panic!("buffer already in use"),
+        Err(TryLockError::Poisoned(e)) => panic!("{}", e),
+    }
 }
 
-pub fn with_buffers<F, R>(f: F) -> R
+pub fn with_buffers<F, R>(interp: &Mint, f: F) -> R
 where
     F: FnOnce(&mut EmacsBuffers) -> R,
 {
-    // buffers.borrow_mut().as_mut().unwrap()
-    EMACS_BUFFERS.with(|buffers| f(buffers.borrow_mut().as_mut().unwrap()))
+    f(interp.context().buffers.borrow_mut().as_mut().unwrap())
 }
 
-pub fn with_current_buffer<F, R>(f: F) -> R
+pub fn with_current_buffer<F, R>(interp: &Mint, f: F) -> R
 where
     F: FnOnce(&mut EmacsBuffer) -> R,
 {
-    with_buffers(|buffers| {
+    with_buffers(interp, |buffers| {
         let buf_rc = buffers.get_cur_buffer();
-        let mut buf = buf_rc.borrow_mut();
+        let mut buf = lock_buffer(&buf_rc);
         f(&mut buf)
     })
 }
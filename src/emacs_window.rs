@@ -16,23 +16,351 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
-use std::cell::RefCell;
+use std::cmp::min;
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::Duration;
 
-use crate::emacs_buffer::EmacsBuffer;
-use crate::mint_types::{MintCount, MintString};
+use unicode_width::UnicodeWidthChar;
 
-pub trait EmacsWindow {
+use crate::emacs_buffer::{EmacsBuffer, utf8_char};
+use crate::keyboard_macro;
+use crate::mint::Mint;
+use crate::mint_types::{MintChar, MintCount, MintString};
+use crate::window_layout::{self, Rect};
+
+// Mode line shown beneath each window, with left-aligned, centred and
+// right-aligned fields.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModeLine {
+    pub left: MintString,
+    pub centre: MintString,
+    pub right: MintString,
+}
+
+impl ModeLine {
+    // Lay the fields out across exactly "width" columns.  When they don't
+    // all fit, the left field is kept in preference to the right, and the
+    // right in preference to the centre.
+    pub fn render(&self, width: usize) -> MintString {
+        let (left, left_w) = fit_cells(&self.left, width);
+        let (right, right_w) = fit_cells(&self.right, width - left_w);
+        let room = width - left_w - right_w;
+        let (centre, centre_w) = fit_cells(&self.centre, room);
+        let start = ((width - centre_w) / 2).clamp(left_w, left_w + room - centre_w);
+
+        let mut line = left;
+        line.push_str(&" ".repeat(start - left_w));
+        line.push_str(&centre);
+        line.push_str(&" ".repeat(width - right_w - start - centre_w));
+        line.push_str(&right);
+        line.into_bytes()
+    }
+}
+
+// The longest leading part of "field" that fits in "cells" columns, as
+// UTF-8, and the columns it takes.  Valid UTF-8 sequences are kept, other
+// bytes are taken as Latin-1 and control characters shown as spaces.
+pub fn fit_cells(field: &[MintChar], cells: usize) -> (String, usize) {
+    let mut text = String::new();
+    let mut used = 0;
+    let mut idx = 0;
+    while idx < field.len() {
+        let (ch, len) = utf8_char(&field[idx..]).unwrap_or((field[idx] as char, 1));
+        let ch = if ch.is_control() { ' ' } else { ch };
+        let width = ch.width().unwrap_or(1);
+        if used + width > cells {
+            break;
+        }
+        text.push(ch);
+        used += width;
+        idx += len;
+    }
+    (text, used)
+}
+
+// A line as drawn on the screen, as runs of text in the same foreground
+// and background colours.
+pub type DrawnLine = Vec<(i32, i32, String)>;
+
+// How buffer text is coloured and marked when it is laid out.
+pub struct LineStyle<'a> {
+    pub fore: i32,
+    pub back: i32,
+    pub wsp_fore: i32,
+    pub ctrl_fore: i32,
+    pub show_wsp: bool,
+    pub eol_glyph: &'a [MintChar],
+    pub eob_glyph: &'a [MintChar],
+}
+
+impl LineStyle<'_> {
+    // This style with the display settings "buf" has of its own.
+    pub fn for_buffer(self, buf: &EmacsBuffer) -> Self {
+        let own = buf.display();
+        Self {
+            fore: own.fore.unwrap_or(self.fore),
+            back: own.back.unwrap_or(self.back),
+            wsp_fore: own.wsp_fore.unwrap_or(self.wsp_fore),
+            ctrl_fore: own.ctrl_fore.unwrap_or(self.ctrl_fore),
+            show_wsp: own.show_wsp.unwrap_or(self.show_wsp),
+            ..self
+        }
+    }
+}
+
+// The lines for the panes on a screen of "screen_lines", given that
+// "requested" were asked for: all but the message line if 0, and at
+// least one.
+pub fn fit_edit_lines(requested: MintCount, screen_lines: MintCount) -> MintCount {
+    let most = screen_lines.saturating_sub(1).max(1);
+    if requested == 0 {
+        most
+    } else {
+        requested.min(most)
+    }
+}
+
+// Lay out a line of "buf" as it will appear in a pane "cols" wide.
+pub fn render_line(
+    buf: &EmacsBuffer,
+    bol: MintCount,
+    eol: MintCount,
+    cols: u16,
+    style: &LineStyle,
+) -> DrawnLine {
+    let leftcol = buf.get_left_column();
+    let utf8 = buf.is_utf8();
+    let (fore, back) = (style.fore, style.back);
+    let mut line = DrawnLine::new();
+
+    let text = buf.read(bol, eol);
+    let line_len = text.len();
+    let line_text = &text[..];
+
+    // Find the last non-space/non-tab character index, for trailing whitespace display.
+    let mut nwsp_idx = line_len;
+    for (idx, &ch) in line_text.iter().enumerate().rev() {
+        if ch != b'\t' && ch != b' ' {
+            nwsp_idx = idx + 1;
+            break;
+        }
+    }
+
+    let mut cur_col = 0i32;
+    let mut char_idx = 0;
+
+    // Advance past left-scroll column without writing.
+    while cur_col < leftcol as i32 && char_idx < line_len {
+        let (width, len) = buf.display_width(cur_col as MintCount, &line_text[char_idx..]);
+        cur_col += width as i32;
+        char_idx += len;
+    }
+
+    // Blank the part of a tab or wide character that crosses leftcol.
+    let overhang = min(cur_col, leftcol as i32 + cols as i32) - leftcol as i32;
+    if overhang > 0 {
+        push_run(&mut line, fore, back, &" ".repeat(overhang as usize));
+    }
+
+    // Matches of the highlight pattern are shown in reverse video.
+    let spans = buf.highlight_spans(bol, eol);
+    let highlighted = |idx: usize| {
+        let pos = bol + idx as MintCount;
+        spans
+            .iter()
+            .any(|&(start, end)| (start..end).contains(&pos))
+    };
+
+    // Write visible characters.
+    while cur_col < (leftcol as i32 + cols as i32) && char_idx < line_len {
+        let ch = line_text[char_idx];
+        let (fore, back) = if highlighted(char_idx) {
+            (back, fore)
+        } else {
+            (fore, back)
+        };
+        char_idx += 1;
+
+        if ch == b'\t' {
+            let mut tabw = buf.char_width(cur_col as MintCount, ch) as i32;
+            tabw = min(tabw, leftcol as i32 + cols as i32 - cur_col);
+
+            if style.show_wsp && char_idx > nwsp_idx {
+                push_run(&mut line, style.wsp_fore, back, &"·".repeat(tabw as usize));
+            } else {
+                push_run(&mut line, fore, back, &" ".repeat(tabw as usize));
+            }
+            cur_col += tabw;
+        } else if utf8 && let Some((uch, len)) = utf8_char(&line_text[char_idx - 1..]) {
+            // Multi-byte character: wide ones take two cells, combining marks none.
+            let bytes = &line_text[char_idx - 1..];
+            let width = buf.display_width(cur_col as MintCount, bytes).0 as i32;
+            if cur_col + width > leftcol as i32 + cols as i32 {
+                break;
+            }
+            push_run(&mut line, fore, back, uch.encode_utf8(&mut [0; 4]));
+            char_idx += len - 1;
+            cur_col += width;
+        } else if let Some(repr) = buf.display_repr(ch) {
+            // Control character or high byte — display as ^X or \xNN.
+            let room = (leftcol as i32 + cols as i32 - cur_col) as usize;
+            let shown = &repr[..repr.len().min(room)];
+            push_run(&mut line, style.ctrl_fore, back, shown);
+            cur_col += shown.len() as i32;
+        } else if ch == b' ' {
+            if style.show_wsp && char_idx > nwsp_idx {
+                push_run(&mut line, style.wsp_fore, back, "·");
+            } else {
+                push_run(&mut line, fore, back, " ");
+            }
+            cur_col += 1;
+        } else {
+            push_run(&mut line, fore, back, (ch as char).encode_utf8(&mut [0; 4]));
+            cur_col += 1;
+        }
+    }
+
+    // Mark the end of the line, unless it is off the screen.
+    if char_idx == line_len && cur_col >= leftcol as i32 {
+        let room = (leftcol as i32 + cols as i32 - cur_col) as usize;
+        let (glyph, width) = fit_cells(style.eol_glyph, room);
+        if width > 0 {
+            push_run(&mut line, style.wsp_fore, back, &glyph);
+            cur_col += width as i32;
+        }
+    }
+
+    // Clear remainder of line, leaving any pane to the right alone.
+    let remaining = leftcol as i32 + cols as i32 - cur_col;
+    if remaining > 0 {
+        push_run(&mut line, fore, back, &" ".repeat(remaining as usize));
+    }
+    line
+}
+
+// A screen line past the end of the buffer.
+pub fn eob_line(cols: u16, style: &LineStyle) -> DrawnLine {
+    let mut line = DrawnLine::new();
+    let cols = cols as usize;
+    let (glyph, width) = fit_cells(style.eob_glyph, cols);
+    if width > 0 {
+        push_run(&mut line, style.wsp_fore, style.back, &glyph);
+    }
+    if width < cols {
+        push_run(&mut line, style.fore, style.back, &" ".repeat(cols - width));
+    }
+    line
+}
+
+// Add "text" to the end of "line", in the given colours.
+fn push_run(line: &mut DrawnLine, fore: i32, back: i32, text: &str) {
+    match line.last_mut() {
+        Some((f, b, run)) if *f == fore && *b == back => run.push_str(text),
+        _ => line.push((fore, back, text.to_string())),
+    }
+}
+
+// Counts of the work done by redisplay, to measure what it costs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub redisplays: u64,
+    pub lines: u64,
+    pub cells: u64,
+    pub flush_time: Duration,
+}
+
+// What a mouse button did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseAction {
+    Down,
+    Up,
+    Drag,
+}
+
+// Something read from the keyboard, mouse or terminal.  #(it) turns these
+// into the key names the macros see.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    // A key, by name, e.g. "x", "C-x" or "M-Left Arrow".
+    Key(MintString),
+    // The screen has changed size.
+    Resize,
+    // Button 1 (left), 2 (middle) or 3 (right), at a screen position.
+    Mouse {
+        action: MouseAction,
+        button: u8,
+        row: MintCount,
+        col: MintCount,
+    },
+    Wheel {
+        up: bool,
+    },
+    // Text pasted into the terminal in one go.
+    Paste(MintString),
+    // The window gaining or losing the focus.
+    Focus(bool),
+    // The window was closed, or the terminal went away.
+    Close,
+    // Nothing arrived in time.
+    Timeout,
+    // Something arrived that means nothing here.
+    Unknown,
+}
+
+// A window goes with the interpreter drawing on it, which can be moved to
+// another thread.
+pub trait EmacsWindow: Send {
+    // What drives the screen, such as "curses" or "gui".
+    fn backend(&self) -> &'static str;
     fn get_columns(&self) -> MintCount;
-    fn get_lines(&self) -> MintCount;
+    // Lines on the screen, including the message line at the bottom.
+    fn get_screen_lines(&self) -> MintCount;
+    // Lines at the top of the screen for the panes: as many as asked for
+    // by set_edit_lines, or all but the message line.  Lines left over
+    // between the panes and the message line are blank.
+    fn get_edit_lines(&self) -> MintCount;
+    // Ask for "lines" for the panes, or all there is room for if 0.
+    fn set_edit_lines(&mut self, lines: MintCount);
 
-    fn redisplay(&mut self, buf: &mut EmacsBuffer, force: bool);
+    // Bracket a redisplay of the whole screen, so terminals that can will
+    // show it all at once rather than as it is drawn.
+    fn begin_update(&mut self);
+    fn end_update(&mut self);
+    // Display "buf" in the text area of a pane, leaving the cursor at point.
+    fn redisplay(&mut self, buf: &mut EmacsBuffer, area: &Rect, force: bool);
+    fn get_render_stats(&self) -> RenderStats;
+    fn reset_render_stats(&mut self);
     fn overwrite(&mut self, s: &MintString);
     fn gotoxy(&mut self, x: i32, y: i32);
     fn key_waiting(&self) -> bool;
-    fn get_input(&mut self, millisec: MintCount) -> MintString;
+    fn get_input(&mut self, millisec: MintCount) -> InputEvent;
+    // The descriptor keys are read from, for the editor to sleep on along
+    // with everything else it waits for, or None if get_input has to do
+    // the waiting.
+    fn input_fd(&self) -> Option<i32>;
+    // Report the key read as "code" by the name "name" from now on.
+    // Returns false if "code" means nothing to this display.
+    fn decode_key(&mut self, code: &MintString, name: &MintString) -> bool;
 
     fn announce(&mut self, left: &MintString, right: &MintString);
-    fn announce_win(&mut self, left: &MintString, right: &MintString);
+    // Write a pane's mode line in "area", truncated to fit, in the mode
+    // line colours, leaving the cursor where it was.
+    fn mode_line(&mut self, area: &Rect, line: &ModeLine);
+
+    // Put "text" on the system clipboard, if the terminal allows it.
+    fn set_clipboard(&mut self, text: &MintString);
+
+    // Give the terminal back and stop until continued, then take it again,
+    // repainting all of it on the next redisplay.  Returns false if this
+    // display can't be suspended.
+    fn suspend(&mut self) -> bool;
+    // What the terminal showed before this display took it over, or null
+    // if it couldn't be read.
+    fn original_screen(&self) -> MintString {
+        MintString::new()
+    }
 
     fn audible_bell(&mut self, freq: MintCount, millisec: MintCount);
     fn visual_bell(&mut self, millisec: MintCount);
@@ -43,11 +371,21 @@ pub trait EmacsWindow {
     fn get_back_colour(&self) -> i32;
     fn set_ctrl_fore_colour(&mut self, colour: i32);
     fn get_ctrl_fore_colour(&self) -> i32;
+    fn set_mode_fore_colour(&mut self, colour: i32);
+    fn get_mode_fore_colour(&self) -> i32;
+    fn set_mode_back_colour(&mut self, colour: i32);
+    fn get_mode_back_colour(&self) -> i32;
 
     fn set_whitespace_display(&mut self, flag: bool);
     fn get_whitespace_display(&self) -> bool;
     fn set_whitespace_colour(&mut self, colour: i32);
     fn get_whitespace_colour(&self) -> i32;
+    // Text shown, in the whitespace colour, on screen lines past the end of
+    // the buffer and after the end of each line.  Null for none.
+    fn set_eob_indicator(&mut self, glyph: &MintString);
+    fn get_eob_indicator(&self) -> MintString;
+    fn set_eol_indicator(&mut self, glyph: &MintString);
+    fn get_eol_indicator(&self) -> MintString;
 
     fn get_bot_scroll_percent(&self) -> MintCount;
     fn set_bot_scroll_percent(&mut self, perc: MintCount);
@@ -55,34 +393,287 @@ pub trait EmacsWindow {
     fn set_top_scroll_percent(&mut self, perc: MintCount);
 }
 
-// FIXME: This should not be thread local.
-thread_local! {
-    static EMACS_WINDOW: RefCell<Option<Box<dyn EmacsWindow>>> = RefCell::new(None);
+// Pitch #(bl) uses when it isn't given one.  Negative means a visual bell.
+pub fn set_bell_pitch(interp: &Mint, pitch: i32) {
+    interp.context().bell_pitch.set(pitch);
 }
 
-pub fn init_window(w: Box<dyn EmacsWindow>) {
-    EMACS_WINDOW.with(|window| {
-        *window.borrow_mut() = Some(w);
-    });
+pub fn bell_pitch(interp: &Mint) -> i32 {
+    interp.context().bell_pitch.get()
 }
 
-pub fn free_window() {
-    EMACS_WINDOW.with(|window| {
-        *window.borrow_mut() = None;
-    });
+// Sound "freq" Hz for "millisec" without waiting for it to finish.  This
+// only works where there is a speaker we can drive: through a Linux
+// virtual console, or with Beep() on Windows.  Returns false if no tone
+// was played, so the caller can fall back to the terminal bell.
+#[cfg(target_os = "linux")]
+pub fn play_tone(freq: MintCount, millisec: MintCount) -> bool {
+    // From linux/kd.h.  The argument is the tone's period in timer ticks
+    // in the low word and its length in milliseconds in the high word.
+    const KDMKTONE: u64 = 0x4B30;
+    const CLOCK_TICK_RATE: MintCount = 1_193_180;
+
+    if freq == 0 || millisec == 0 {
+        return false;
+    }
+    let ticks = (CLOCK_TICK_RATE / freq).min(0xffff);
+    if ticks == 0 {
+        return false;
+    }
+    let arg = (millisec.min(0xffff) << 16) | ticks;
+    // SAFETY: KDMKTONE takes its argument by value and touches no memory;
+    // on anything but a virtual console it just fails.
+    unsafe { libc::ioctl(libc::STDOUT_FILENO, KDMKTONE as _, arg as libc::c_ulong) == 0 }
 }
 
-pub fn with_window<F, R>(f: F) -> R
+#[cfg(windows)]
+pub fn play_tone(freq: MintCount, millisec: MintCount) -> bool {
+    use windows_sys::Win32::System::Diagnostics::Debug::Beep;
+
+    if !(37..=32767).contains(&freq) || millisec == 0 {
+        return false;
+    }
+    // Beep() doesn't return until the tone is over.
+    std::thread::spawn(move || unsafe { Beep(freq, millisec) });
+    true
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn play_tone(_freq: MintCount, _millisec: MintCount) -> bool {
+    false
+}
+
+// What the terminal shows before the display takes it over, for
+// env.SCREEN.  Displays call this before switching to the alternate
+// screen; when the contents can't be read it is null.
+pub fn capture_screen() -> MintString {
+    read_terminal_screen().unwrap_or_default()
+}
+
+// What the terminal showed before the window of "interp" took it over.
+pub fn original_screen(interp: &Mint) -> MintString {
+    interp.context().original_screen.borrow().clone()
+}
+
+// Terminals can't generally be asked what they show, so this only works
+// inside tmux, or on a Linux virtual console through /dev/vcsa.
+fn read_terminal_screen() -> Option<MintString> {
+    if env::var_os("TMUX").is_some() {
+        // Our own pane, rather than whichever one is active.
+        let pane = env::var_os("TMUX_PANE")?;
+        let out = Command::new("tmux")
+            .arg("capture-pane")
+            .arg("-p")
+            .arg("-t")
+            .arg(pane)
+            .output()
+            .ok()?;
+        return out.status.success().then_some(out.stdout);
+    }
+
+    let tty = fs::read_link("/proc/self/fd/0").ok()?;
+    let console: u32 = tty.to_str()?.strip_prefix("/dev/tty")?.parse().ok()?;
+    let vcsa = fs::read(format!("/dev/vcsa{}", console)).ok()?;
+
+    // Header of rows, columns and cursor position, then character and
+    // attribute pairs for each cell.
+    let (rows, cols) = (*vcsa.first()? as usize, *vcsa.get(1)? as usize);
+    let cells = vcsa.get(4..4 + rows * cols * 2)?;
+    let mut text = Vec::new();
+    for row in cells.chunks(cols * 2) {
+        let line: MintString = row.iter().step_by(2).copied().collect();
+        let len = line.iter().rposition(|&ch| ch != b' ').map_or(0, |n| n + 1);
+        text.extend_from_slice(&line[..len]);
+        text.push(b'\n');
+    }
+    Some(text)
+}
+
+pub fn init_window(interp: &Mint, w: Box<dyn EmacsWindow>) {
+    *interp.context().original_screen.borrow_mut() = w.original_screen();
+    *interp.context().window.borrow_mut() = Some(w);
+    window_layout::reset_layout(interp);
+}
+
+pub fn free_window(interp: &Mint) {
+    *interp.context().window.borrow_mut() = None;
+    interp.context().pending_keys.borrow_mut().clear();
+}
+
+pub fn with_window<F, R>(interp: &Mint, f: F) -> R
 where
     F: FnOnce(&mut dyn EmacsWindow) -> R,
 {
-    EMACS_WINDOW.with(|window| {
-        let mut window_ref = window.borrow_mut();
-        let win = window_ref.as_deref_mut().unwrap();
-        f(win)
-    })
+    let mut window_ref = interp.context().window.borrow_mut();
+    let win = window_ref.as_deref_mut().unwrap();
+    f(win)
+}
+
+// How long to wait after an Escape for the key it may prefix.  Terminals
+// send Alt-x as ESC followed by x, so a key arriving this soon after an
+// Escape is reported as "M-x" rather than as two keys.
+pub const ESC_TIMEOUT_MS: MintCount = 25;
+
+// Key name for "key" pressed with Alt (Meta) held, e.g. "M-x" or
+// "M-Left Arrow".
+pub fn meta_key(key: &[MintChar]) -> MintString {
+    let mut name = b"M-".to_vec();
+    name.extend_from_slice(key);
+    name
+}
+
+// Key name for typing "ch".  Characters that mean something in MINT
+// syntax have names of their own.
+pub fn char_key(ch: char) -> MintString {
+    match ch {
+        ',' => b"Comma".to_vec(),
+        '(' => b"LPar".to_vec(),
+        ')' => b"RPar".to_vec(),
+        ch if (ch as u32) < 0x100 => vec![ch as u8],
+        ch => ch.to_string().into_bytes(),
+    }
+}
+
+// Name of "key" with "C-" and "S-" prefixes for the modifiers held.
+pub fn modified_key(ctrl: bool, shift: bool, key: &str) -> MintString {
+    let ctrl = if ctrl { "C-" } else { "" };
+    let shift = if shift { "S-" } else { "" };
+    format!("{}{}{}", ctrl, shift, key).into_bytes()
+}
+
+// DEC reverse screen mode on and off.  A visual bell turns it on and
+// turns it off again once the flash has lasted as long as was asked for.
+pub const FLASH_ON: &str = "\x1b[?5h";
+pub const FLASH_OFF: &str = "\x1b[?5l";
+
+// OSC 52 escape sequence asking the terminal to put "text" on the
+// clipboard.  This goes wherever the terminal is, so works over SSH.
+pub fn clipboard_sequence(text: &[MintChar]) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text))
+}
+
+fn base64(data: &[MintChar]) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bits =
+            group.iter().fold(0u32, |acc, &b| acc << 8 | b as u32) << (8 * (3 - group.len()));
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(DIGITS[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
 }
 
-pub fn key_waiting() -> bool {
-    with_window(|w| w.key_waiting())
+// Queue "key" to be read ahead of anything typed, or of a keyboard macro
+// being replayed.  Keys read from the queue aren't recorded in a macro.
+pub fn push_key(interp: &Mint, key: &[MintChar]) {
+    interp
+        .context()
+        .pending_keys
+        .borrow_mut()
+        .push_back(key.to_vec());
+}
+
+pub fn pending_keys(interp: &Mint) -> usize {
+    interp.context().pending_keys.borrow().len()
+}
+
+// The next key queued by push_key, if there is one.
+pub fn pop_key(interp: &Mint) -> Option<MintString> {
+    interp.context().pending_keys.borrow_mut().pop_front()
+}
+
+// The next input, from the keys queued by push_key first, otherwise from
+// the window, waiting up to "millisec" for it.
+pub fn get_input(interp: &Mint, millisec: MintCount) -> InputEvent {
+    match pop_key(interp) {
+        Some(key) => InputEvent::Key(key),
+        None => with_window(interp, |w| w.get_input(millisec)),
+    }
+}
+
+// Keys pushed or being replayed count, so the screen isn't redrawn
+// between them.
+pub fn key_waiting(interp: &Mint) -> bool {
+    pending_keys(interp) > 0
+        || keyboard_macro::replaying(interp)
+        || with_window(interp, |w| w.key_waiting())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode_line(left: &str, centre: &str, right: &str) -> ModeLine {
+        ModeLine {
+            left: left.as_bytes().to_vec(),
+            centre: centre.as_bytes().to_vec(),
+            right: right.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn mode_line_render() {
+        let line = mode_line("abc", "mid", "xyz");
+        assert_eq!(b"abc    mid    xyz".to_vec(), line.render(17));
+        assert_eq!(b"abcmidxyz".to_vec(), line.render(9));
+        assert_eq!(b"abcmixyz".to_vec(), line.render(8));
+        assert_eq!(b"abcxy".to_vec(), line.render(5));
+        assert_eq!(b"ab".to_vec(), line.render(2));
+        assert_eq!(b"  mid   ".to_vec(), mode_line("", "mid", "").render(8));
+        assert_eq!(
+            b"abcdefmid".to_vec(),
+            mode_line("abcdef", "mid", "").render(9)
+        );
+        let wide = mode_line("\u{65e5}\u{672c}", "", "x");
+        assert_eq!("\u{65e5}\u{672c}".as_bytes().to_vec(), wide.render(4));
+        assert_eq!("\u{65e5}x".as_bytes().to_vec(), wide.render(3));
+        assert_eq!("\u{65e5}\u{672c} x".as_bytes().to_vec(), wide.render(6));
+    }
+
+    #[test]
+    fn clipboard_sequence_encoding() {
+        assert_eq!("\x1b]52;c;\x07", clipboard_sequence(b""));
+        assert_eq!("\x1b]52;c;Zg==\x07", clipboard_sequence(b"f"));
+        assert_eq!("\x1b]52;c;Zm8=\x07", clipboard_sequence(b"fo"));
+        assert_eq!("\x1b]52;c;Zm9vYmFy\x07", clipboard_sequence(b"foobar"));
+    }
+
+    #[test]
+    fn buffer_display_overrides_style() {
+        let text = Box::new(crate::gap_buffer::GapBuffer::with_default_size());
+        let mut buf = EmacsBuffer::new(1, text);
+        buf.insert_string(&b"x ".to_vec());
+        buf.display_mut().back = Some(4);
+        buf.display_mut().show_wsp = Some(true);
+        let style = LineStyle {
+            fore: 7,
+            back: 0,
+            wsp_fore: 8,
+            ctrl_fore: 11,
+            show_wsp: false,
+            eol_glyph: b"",
+            eob_glyph: b"",
+        };
+        let line = render_line(&buf, 0, 2, 4, &style.for_buffer(&buf));
+        assert_eq!(
+            vec![
+                (7, 4, "x".to_string()),
+                (8, 4, "·".to_string()),
+                (7, 4, "  ".to_string())
+            ],
+            line
+        );
+    }
+
+    #[test]
+    fn meta_key_names() {
+        assert_eq!(b"M-x".to_vec(), meta_key(b"x"));
+        assert_eq!(b"M-Left Arrow".to_vec(), meta_key(b"Left Arrow"));
+    }
 }
@@ -0,0 +1,239 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* No window at all, for running scripts: #(ow) writes to standard output. */
+
+// Announcements go to standard error, so that they can be told from the
+// output.  There is no keyboard, so reading a key finds the window closed.
+
+use std::io::{self, Write};
+
+use crate::emacs_buffer::EmacsBuffer;
+use crate::emacs_window::{EmacsWindow, InputEvent, ModeLine, RenderStats, fit_edit_lines};
+use crate::mint_types::{MintCount, MintString};
+use crate::window_layout::Rect;
+
+pub struct EmacsWindowBatch {
+    columns: MintCount,
+    lines: MintCount,
+    fore: i32,
+    back: i32,
+    wsp_fore: i32,
+    show_wsp: bool,
+    eob_glyph: MintString,
+    eol_glyph: MintString,
+    ctrl_fore: i32,
+    mode_fore: i32,
+    mode_back: i32,
+    bot_scroll_percent: MintCount,
+    top_scroll_percent: MintCount,
+    // Lines asked for by set_edit_lines, or 0 for all there is room for.
+    edit_lines: MintCount,
+}
+
+impl EmacsWindowBatch {
+    pub fn new(cols: MintCount, lines: MintCount) -> Self {
+        EmacsWindowBatch {
+            columns: cols,
+            lines,
+            fore: 7,
+            back: 0,
+            wsp_fore: 6,
+            show_wsp: false,
+            eob_glyph: MintString::new(),
+            eol_glyph: MintString::new(),
+            ctrl_fore: 2,
+            mode_fore: 0,
+            mode_back: 7,
+            bot_scroll_percent: 90,
+            top_scroll_percent: 10,
+            edit_lines: 0,
+        }
+    }
+}
+
+impl Drop for EmacsWindowBatch {
+    fn drop(&mut self) {
+        io::stdout().flush().ok();
+    }
+}
+
+impl EmacsWindow for EmacsWindowBatch {
+    fn backend(&self) -> &'static str {
+        "batch"
+    }
+
+    fn get_columns(&self) -> MintCount {
+        self.columns
+    }
+
+    fn get_screen_lines(&self) -> MintCount {
+        self.lines
+    }
+
+    fn get_edit_lines(&self) -> MintCount {
+        fit_edit_lines(self.edit_lines, self.get_screen_lines())
+    }
+
+    fn set_edit_lines(&mut self, lines: MintCount) {
+        self.edit_lines = lines;
+    }
+
+    fn begin_update(&mut self) {}
+
+    fn end_update(&mut self) {}
+
+    fn redisplay(&mut self, _buf: &mut EmacsBuffer, _area: &Rect, _force: bool) {}
+
+    fn get_render_stats(&self) -> RenderStats {
+        RenderStats::default()
+    }
+
+    fn reset_render_stats(&mut self) {}
+
+    fn overwrite(&mut self, s: &MintString) {
+        io::stdout().write_all(s).ok();
+    }
+
+    fn gotoxy(&mut self, _x: i32, _y: i32) {}
+
+    fn key_waiting(&self) -> bool {
+        false
+    }
+
+    fn get_input(&mut self, _millisec: MintCount) -> InputEvent {
+        InputEvent::Close
+    }
+
+    fn input_fd(&self) -> Option<i32> {
+        None
+    }
+
+    fn decode_key(&mut self, _code: &MintString, _name: &MintString) -> bool {
+        false
+    }
+
+    fn announce(&mut self, left: &MintString, right: &MintString) {
+        if !left.is_empty() || !right.is_empty() {
+            let mut stderr = io::stderr();
+            stderr.write_all(left).ok();
+            stderr.write_all(right).ok();
+            stderr.write_all(b"\n").ok();
+        }
+    }
+
+    fn mode_line(&mut self, _area: &Rect, _line: &ModeLine) {}
+
+    fn set_clipboard(&mut self, _text: &MintString) {}
+
+    fn suspend(&mut self) -> bool {
+        false
+    }
+
+    fn audible_bell(&mut self, _freq: MintCount, _millisec: MintCount) {}
+
+    fn visual_bell(&mut self, _millisec: MintCount) {}
+
+    fn set_fore_colour(&mut self, colour: i32) {
+        self.fore = colour;
+    }
+
+    fn get_fore_colour(&self) -> i32 {
+        self.fore
+    }
+
+    fn set_back_colour(&mut self, colour: i32) {
+        self.back = colour;
+    }
+
+    fn get_back_colour(&self) -> i32 {
+        self.back
+    }
+
+    fn set_ctrl_fore_colour(&mut self, colour: i32) {
+        self.ctrl_fore = colour;
+    }
+
+    fn get_ctrl_fore_colour(&self) -> i32 {
+        self.ctrl_fore
+    }
+
+    fn set_mode_fore_colour(&mut self, colour: i32) {
+        self.mode_fore = colour;
+    }
+
+    fn get_mode_fore_colour(&self) -> i32 {
+        self.mode_fore
+    }
+
+    fn set_mode_back_colour(&mut self, colour: i32) {
+        self.mode_back = colour;
+    }
+
+    fn get_mode_back_colour(&self) -> i32 {
+        self.mode_back
+    }
+
+    fn set_whitespace_display(&mut self, flag: bool) {
+        self.show_wsp = flag;
+    }
+
+    fn get_whitespace_display(&self) -> bool {
+        self.show_wsp
+    }
+
+    fn set_whitespace_colour(&mut self, colour: i32) {
+        self.wsp_fore = colour;
+    }
+
+    fn get_whitespace_colour(&self) -> i32 {
+        self.wsp_fore
+    }
+
+    fn set_eob_indicator(&mut self, glyph: &MintString) {
+        self.eob_glyph = glyph.clone();
+    }
+
+    fn get_eob_indicator(&self) -> MintString {
+        self.eob_glyph.clone()
+    }
+
+    fn set_eol_indicator(&mut self, glyph: &MintString) {
+        self.eol_glyph = glyph.clone();
+    }
+
+    fn get_eol_indicator(&self) -> MintString {
+        self.eol_glyph.clone()
+    }
+
+    fn get_bot_scroll_percent(&self) -> MintCount {
+        self.bot_scroll_percent
+    }
+
+    fn set_bot_scroll_percent(&mut self, perc: MintCount) {
+        self.bot_scroll_percent = perc;
+    }
+
+    fn get_top_scroll_percent(&self) -> MintCount {
+        self.top_scroll_percent
+    }
+
+    fn set_top_scroll_percent(&mut self, perc: MintCount) {
+        self.top_scroll_percent = perc;
+    }
+}
@@ -17,24 +17,54 @@
  */
 
 use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::io::{self, BufWriter, IsTerminal, Write};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crossterm::{
     cursor,
-    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers,
+        KeyboardEnhancementFlags, MouseButton, MouseEvent, MouseEventKind,
+    },
     execute, queue,
     style::{Color, Colors, Print, SetColors},
     terminal::{self, ClearType},
 };
 
 use crate::emacs_buffer::EmacsBuffer;
-use crate::emacs_window::EmacsWindow;
+use crate::emacs_window::{
+    self, DrawnLine, ESC_TIMEOUT_MS, EmacsWindow, FLASH_OFF, FLASH_ON, InputEvent, LineStyle,
+    ModeLine, MouseAction, RenderStats, eob_line, meta_key, modified_key, render_line,
+};
 use crate::mint_types::{MintCount, MintString};
+use crate::signals;
+use crate::window_layout::Rect;
+
+// The Windows console reports keys with their modifiers, rather than the
+// bytes a terminal sends for them.  Alt keys don't arrive as Escape and a
+// key, and AltGr arrives as Ctrl and Alt held together.
+const CONSOLE_KEYS: bool = cfg!(target_os = "windows");
 
 pub struct EmacsWindowCrossterm {
     writer: BufWriter<io::Stdout>,
     is_tty: bool,
+    // Set when the terminal has been resized, so the next redisplay
+    // repaints the whole screen.
+    resized: bool,
+    // Set when the terminal reports modifiers unambiguously (the kitty
+    // keyboard protocol), so Escape never needs to be joined to a key.
+    enhanced_keys: bool,
+    // Event read while checking for a key after Escape, returned next.
+    pending: Option<Event>,
+    // Names given to keys from MINT, keyed by the name they'd have otherwise.
+    key_names: HashMap<MintString, MintString>,
+    // Lines last drawn in the panes, keyed by screen position, so that
+    // redisplay only sends the lines that have changed.
+    drawn: HashMap<(u16, u16), DrawnLine>,
+    stats: RenderStats,
+    // When the screen flash from a visual bell should end.
+    bell_until: Option<Instant>,
     overwriting: bool,
     ovy: u16,
     ovx: u16,
@@ -42,9 +72,16 @@ pub struct EmacsWindowCrossterm {
     back: i32,
     wsp_fore: i32,
     show_wsp: bool,
+    eob_glyph: MintString,
+    eol_glyph: MintString,
     ctrl_fore: i32,
+    mode_fore: i32,
+    mode_back: i32,
     bot_scroll_percent: MintCount,
     top_scroll_percent: MintCount,
+    // Lines asked for by set_edit_lines, or 0 for all there is room for.
+    edit_lines: MintCount,
+    original_screen: MintString,
 }
 
 impl Default for EmacsWindowCrossterm {
@@ -56,22 +93,16 @@ impl Default for EmacsWindowCrossterm {
 impl EmacsWindowCrossterm {
     pub fn new() -> Self {
         let is_tty = io::stdout().is_terminal();
-        let mut writer = BufWriter::new(io::stdout());
-
-        if is_tty {
-            terminal::enable_raw_mode().expect("failed to enable raw mode");
-            execute!(
-                writer,
-                terminal::EnterAlternateScreen,
-                terminal::Clear(ClearType::All),
-                cursor::Hide,
-            )
-            .expect("failed to initialise terminal");
-        }
-
-        Self {
-            writer,
+        let mut window = Self {
+            writer: BufWriter::new(io::stdout()),
             is_tty,
+            resized: false,
+            enhanced_keys: false,
+            pending: None,
+            key_names: HashMap::new(),
+            drawn: HashMap::new(),
+            stats: RenderStats::default(),
+            bell_until: None,
             overwriting: false,
             ovy: 0,
             ovx: 0,
@@ -79,128 +110,213 @@ impl EmacsWindowCrossterm {
             back: 0,
             wsp_fore: 15,
             show_wsp: false,
+            eob_glyph: MintString::new(),
+            eol_glyph: MintString::new(),
             ctrl_fore: 11,
+            mode_fore: 0,
+            mode_back: 7,
             bot_scroll_percent: 0,
             top_scroll_percent: 0,
+            edit_lines: 0,
+            original_screen: MintString::new(),
+        };
+
+        if is_tty {
+            window.original_screen = emacs_window::capture_screen();
+            window.take_terminal();
+            window.enhanced_keys = terminal::supports_keyboard_enhancement().unwrap_or(false);
+            window.push_key_flags();
         }
+        window
     }
 
-    fn term_size(&self) -> (u16, u16) {
-        terminal::size().unwrap_or((80, 27))
+    // Raw mode on the alternate screen, with mouse and paste reports.
+    fn take_terminal(&mut self) {
+        terminal::enable_raw_mode().expect("failed to enable raw mode");
+        execute!(
+            self.writer,
+            terminal::EnterAlternateScreen,
+            event::EnableMouseCapture,
+            event::EnableBracketedPaste,
+            terminal::Clear(ClearType::All),
+            cursor::Hide,
+        )
+        .expect("failed to initialise terminal");
     }
 
-    /// Queue a colour change, applying it only when it differs from the previous call.
-    /// Callers must flush the writer at appropriate points (end of redisplay, announce, etc.).
-    fn queue_colours(&mut self, fore: i32, back: i32) {
-        queue!(
+    fn push_key_flags(&mut self) {
+        if self.enhanced_keys {
+            let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES;
+            execute!(self.writer, event::PushKeyboardEnhancementFlags(flags)).ok();
+        }
+    }
+
+    // Put the terminal back as it was before we took it.
+    fn give_terminal_back(&mut self) {
+        if self.enhanced_keys {
+            execute!(self.writer, event::PopKeyboardEnhancementFlags).ok();
+        }
+        if self.bell_until.take().is_some() {
+            execute!(self.writer, Print(FLASH_OFF)).ok();
+        }
+        execute!(
             self.writer,
-            SetColors(Colors::new(ansi_colour(fore), ansi_colour(back)))
+            cursor::Show,
+            event::DisableBracketedPaste,
+            event::DisableMouseCapture,
+            terminal::LeaveAlternateScreen,
         )
         .ok();
+        terminal::disable_raw_mode().ok();
     }
 
-    fn write_line(&mut self, buf: &EmacsBuffer, bol: MintCount, eol: MintCount) {
-        let (cols, _) = self.term_size();
-        let leftcol = buf.get_left_column();
-
-        let text = buf.read_to_mark_from(crate::emacs_buffer::MARK_EOB, bol);
-        let line_len = min((eol - bol) as usize, text.len());
-        let line_text = &text[..line_len];
-
-        // Find the last non-space/non-tab character index, for trailing whitespace display.
-        let mut nwsp_idx = line_len;
-        for (idx, &ch) in line_text.iter().enumerate().rev() {
-            if ch != b'\t' && ch != b' ' {
-                nwsp_idx = idx + 1;
-                break;
+    // After a plain Escape, take a key that follows within ESC_TIMEOUT_MS
+    // as Alt-modified, since that is how terminals send Alt keys.
+    fn escape_prefix(&mut self) -> MintString {
+        let timeout = Duration::from_millis(ESC_TIMEOUT_MS as u64);
+        if event::poll(timeout).unwrap_or(false) {
+            match event::read() {
+                Ok(Event::Key(ke)) if ke.kind == KeyEventKind::Press => {
+                    return meta_key(&map_key_event(ke, CONSOLE_KEYS));
+                }
+                Ok(event) => self.pending = Some(event),
+                Err(_) => {}
             }
         }
+        b"Escape".to_vec()
+    }
 
-        let mut cur_col = 0i32;
-        let mut char_idx = 0;
+    // Turn off the visual bell flash if it has run its course.
+    fn end_bell_if_due(&mut self) {
+        if let Some(until) = self.bell_until
+            && Instant::now() >= until
+        {
+            queue!(self.writer, Print(FLASH_OFF)).ok();
+            self.writer.flush().ok();
+            self.bell_until = None;
+        }
+    }
 
-        // Advance past left-scroll column without writing.
-        while cur_col < leftcol as i32 && char_idx < line_len {
-            let ch = line_text[char_idx];
-            cur_col += buf.char_width(cur_col as MintCount, ch) as i32;
-            char_idx += 1;
+    // Wait up to "timeout" for an event.  A visual bell that is due to end
+    // within the wait is ended on time rather than when the wait is over.
+    fn poll_input(&mut self, timeout: Duration) -> bool {
+        self.end_bell_if_due();
+        if let Some(until) = self.bell_until {
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining < timeout {
+                if event::poll(remaining).unwrap_or(false) {
+                    return true;
+                }
+                self.end_bell_if_due();
+                return event::poll(timeout - remaining).unwrap_or(false);
+            }
         }
+        event::poll(timeout).unwrap_or(false)
+    }
 
-        // Write visible characters.
-        while cur_col < (leftcol as i32 + cols as i32) && char_idx < line_len {
-            let ch = line_text[char_idx];
-            char_idx += 1;
+    fn timed_flush(&mut self) {
+        let start = Instant::now();
+        self.writer.flush().ok();
+        self.stats.flush_time += start.elapsed();
+    }
 
-            if ch == b'\t' {
-                let mut tabw = buf.char_width(cur_col as MintCount, ch) as i32;
-                tabw = min(tabw, leftcol as i32 + cols as i32 - cur_col);
+    fn term_size(&self) -> (u16, u16) {
+        terminal::size().unwrap_or((80, 27))
+    }
 
-                if self.show_wsp && char_idx > nwsp_idx {
-                    self.queue_colours(self.wsp_fore, self.back);
-                    for _ in 0..tabw {
-                        queue!(self.writer, Print('·')).ok();
-                    }
-                } else {
-                    self.queue_colours(self.fore, self.back);
-                    for _ in 0..tabw {
-                        queue!(self.writer, Print(' ')).ok();
-                    }
-                }
-                cur_col += tabw;
-            } else if ch < 0x20 {
-                // Control character — display as ^X.
-                self.queue_colours(self.ctrl_fore, self.back);
-                queue!(self.writer, Print((ch + b'@') as char)).ok();
-                cur_col += 1;
-            } else if ch == b' ' {
-                if self.show_wsp && char_idx > nwsp_idx {
-                    self.queue_colours(self.wsp_fore, self.back);
-                    queue!(self.writer, Print('·')).ok();
-                } else {
-                    self.queue_colours(self.fore, self.back);
-                    queue!(self.writer, Print(' ')).ok();
-                }
-                cur_col += 1;
-            } else {
-                self.queue_colours(self.fore, self.back);
-                queue!(self.writer, Print(ch as char)).ok();
-                cur_col += 1;
-            }
+    /// Queue a colour change, applying it only when it differs from the previous call.
+    /// Callers must flush the writer at appropriate points (end of redisplay, announce, etc.).
+    fn queue_colours(&mut self, fore: i32, back: i32) {
+        queue!(
+            self.writer,
+            SetColors(Colors::new(ansi_colour(fore), ansi_colour(back)))
+        )
+        .ok();
+    }
+
+    fn line_style(&self) -> LineStyle<'_> {
+        LineStyle {
+            fore: self.fore,
+            back: self.back,
+            wsp_fore: self.wsp_fore,
+            ctrl_fore: self.ctrl_fore,
+            show_wsp: self.show_wsp,
+            eol_glyph: &self.eol_glyph,
+            eob_glyph: &self.eob_glyph,
         }
+    }
 
-        // Clear remainder of line.
-        if cur_col < (leftcol as i32 + cols as i32) {
-            self.queue_colours(self.fore, self.back);
-            queue!(self.writer, terminal::Clear(ClearType::UntilNewLine)).ok();
+    // Write a line at "pos" unless the same line was drawn there last time.
+    fn draw_line(&mut self, pos: (u16, u16), line: DrawnLine) {
+        if self.drawn.get(&pos) == Some(&line) {
+            return;
         }
+        self.stats.lines += 1;
+        let cells: usize = line.iter().map(|(_, _, text)| text.chars().count()).sum();
+        self.stats.cells += cells as u64;
+        queue!(self.writer, cursor::MoveTo(pos.0, pos.1)).ok();
+        for (fore, back, text) in &line {
+            self.queue_colours(*fore, *back);
+            queue!(self.writer, Print(text)).ok();
+        }
+        self.drawn.insert(pos, line);
     }
 }
 
 impl EmacsWindow for EmacsWindowCrossterm {
+    fn backend(&self) -> &'static str {
+        "crossterm"
+    }
+
     fn get_columns(&self) -> MintCount {
         self.term_size().0 as MintCount
     }
 
-    fn get_lines(&self) -> MintCount {
-        // Reserve 3 rows: editing area uses (rows - 2) lines,
-        // then the mode line and the message/prompt line.
-        self.term_size().1.saturating_sub(3) as MintCount
+    fn get_screen_lines(&self) -> MintCount {
+        self.term_size().1 as MintCount
+    }
+
+    fn get_edit_lines(&self) -> MintCount {
+        emacs_window::fit_edit_lines(self.edit_lines, self.get_screen_lines())
+    }
+
+    fn set_edit_lines(&mut self, lines: MintCount) {
+        self.edit_lines = lines;
+        self.resized = true;
+    }
+
+    fn begin_update(&mut self) {
+        // DEC private mode 2026; terminals without it ignore the request.
+        if self.is_tty {
+            queue!(self.writer, terminal::BeginSynchronizedUpdate).ok();
+        }
+    }
+
+    fn end_update(&mut self) {
+        if self.is_tty {
+            queue!(self.writer, terminal::EndSynchronizedUpdate).ok();
+            self.timed_flush();
+        }
     }
 
-    fn redisplay(&mut self, buf: &mut EmacsBuffer, force: bool) {
+    fn redisplay(&mut self, buf: &mut EmacsBuffer, area: &Rect, force: bool) {
         if !self.is_tty {
             return;
         }
 
+        self.end_bell_if_due();
         self.overwriting = false;
+        self.stats.redisplays += 1;
 
-        let (cols, rows) = self.term_size();
-        let edit_rows = rows.saturating_sub(2);
+        let (left, top) = (area.col as u16, area.row as u16);
+        let (cols, edit_rows) = (area.cols as u16, area.rows as u16);
 
         queue!(self.writer, cursor::Hide).ok();
 
-        if force {
+        if force || self.resized {
             queue!(self.writer, terminal::Clear(ClearType::All)).ok();
+            self.resized = false;
+            self.drawn.clear();
         }
 
         buf.force_point_in_window(
@@ -215,24 +331,41 @@ impl EmacsWindow for EmacsWindowCrossterm {
         let screen_line = buf.count_newlines(curline, point);
         let screen_col = buf.get_column() as i32 - buf.get_left_column() as i32;
 
+        // Once the end of the buffer is reached, lines stop advancing.
+        let mut prev_eol = None;
         for i in 0..edit_rows {
-            queue!(self.writer, cursor::MoveTo(0, i)).ok();
             let eol = buf.get_mark_position_from(crate::emacs_buffer::MARK_EOL, curline);
-            self.write_line(buf, curline, eol);
+            let line = if prev_eol == Some(curline) {
+                eob_line(cols, &self.line_style().for_buffer(buf))
+            } else {
+                render_line(buf, curline, eol, cols, &self.line_style().for_buffer(buf))
+            };
+            self.draw_line((left, top + i), line);
+            prev_eol = Some(eol);
             curline = buf.get_mark_position_from(crate::emacs_buffer::MARK_NEXT_CHAR, eol);
         }
 
         queue!(
             self.writer,
-            cursor::MoveTo(screen_col as u16, screen_line as u16),
+            cursor::MoveTo(left + screen_col as u16, top + screen_line as u16),
             cursor::Show,
         )
         .ok();
-        self.writer.flush().ok();
+        self.timed_flush();
+    }
+
+    fn get_render_stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    fn reset_render_stats(&mut self) {
+        self.stats = RenderStats::default();
     }
 
     fn overwrite(&mut self, s: &MintString) {
         if self.is_tty {
+            // Whatever this covers has to be drawn again.
+            self.drawn.clear();
             if !self.overwriting {
                 self.overwriting = true;
                 self.ovy = 0;
@@ -275,11 +408,23 @@ impl EmacsWindow for EmacsWindowCrossterm {
         }
     }
 
+    fn input_fd(&self) -> Option<i32> {
+        // Crossterm reads standard input when it is a terminal.  While a
+        // visual bell shows, waits are left to poll_input, which knows
+        // when the bell ends.
+        let usable = cfg!(unix)
+            && self.is_tty
+            && io::stdin().is_terminal()
+            && self.pending.is_none()
+            && self.bell_until.is_none();
+        usable.then_some(0)
+    }
+
     fn key_waiting(&self) -> bool {
-        event::poll(Duration::ZERO).unwrap_or(false)
+        self.pending.is_some() || event::poll(Duration::ZERO).unwrap_or(false)
     }
 
-    fn get_input(&mut self, millisec: MintCount) -> MintString {
+    fn get_input(&mut self, millisec: MintCount) -> InputEvent {
         if self.is_tty {
             let timeout = if millisec < 10 {
                 Duration::ZERO
@@ -287,25 +432,45 @@ impl EmacsWindow for EmacsWindowCrossterm {
                 Duration::from_millis(millisec as u64)
             };
 
-            match event::poll(timeout) {
-                Ok(true) => match event::read() {
-                    Ok(Event::Key(ke)) => map_key_event(ke),
-                    _ => b"Unknown".to_vec(),
-                },
-                _ => b"Timeout".to_vec(),
-            }
+            let event = match self.pending.take() {
+                Some(event) => Ok(event),
+                None if self.poll_input(timeout) => event::read(),
+                None => return InputEvent::Timeout,
+            };
+            let key = match event {
+                Ok(Event::Key(ke)) if is_escape(&ke) && !self.enhanced_keys && !CONSOLE_KEYS => {
+                    self.escape_prefix()
+                }
+                Ok(Event::Key(ke)) => map_key_event(ke, CONSOLE_KEYS),
+                Ok(Event::Mouse(me)) => return map_mouse_event(me),
+                Ok(Event::Resize(_, _)) => {
+                    self.resized = true;
+                    return InputEvent::Resize;
+                }
+                Ok(Event::Paste(text)) => return InputEvent::Paste(text.into_bytes()),
+                Ok(Event::FocusGained) => return InputEvent::Focus(true),
+                Ok(Event::FocusLost) => return InputEvent::Focus(false),
+                Err(_) => return InputEvent::Unknown,
+            };
+            InputEvent::Key(self.key_names.get(&key).cloned().unwrap_or(key))
         } else if millisec > 0 {
             let mut buf = [0u8; 1];
             if io::stdin().read(&mut buf).ok().unwrap_or(0) > 0 {
-                vec![buf[0]]
+                InputEvent::Key(vec![buf[0]])
             } else {
-                b"Timeout".to_vec()
+                InputEvent::Timeout
             }
         } else {
-            b"Timeout".to_vec()
+            InputEvent::Timeout
         }
     }
 
+    fn decode_key(&mut self, code: &MintString, name: &MintString) -> bool {
+        // Crossterm has no key codes of its own, so keys are known by name.
+        self.key_names.insert(code.clone(), name.clone());
+        true
+    }
+
     fn announce(&mut self, left: &MintString, right: &MintString) {
         if self.is_tty {
             let (cols, rows) = self.term_size();
@@ -346,59 +511,64 @@ impl EmacsWindow for EmacsWindowCrossterm {
         }
     }
 
-    fn announce_win(&mut self, left: &MintString, right: &MintString) {
-        if self.is_tty {
-            let (cols, rows) = self.term_size();
-            let n = min(left.len(), cols as usize - 1);
+    fn mode_line(&mut self, area: &Rect, line: &ModeLine) {
+        if self.is_tty && area.rows > 0 {
+            let text = line.render(area.cols as usize);
+            let (row, col) = (area.row as u16, area.col as u16);
+            self.drawn.retain(|&(_, drawn_row), _| drawn_row != row);
 
             // Save cursor position, write to mode line, then restore.
             let (saved_x, saved_y) = crossterm::cursor::position().unwrap_or((0, 0));
 
-            self.queue_colours(self.fore, self.back);
-            queue!(self.writer, cursor::MoveTo(0, rows - 2)).ok();
-
-            for &ch in left.iter().take(n) {
-                if ch == b'\n' {
-                    queue!(self.writer, Print('\r')).ok();
-                }
-                queue!(self.writer, Print(ch as char)).ok();
-            }
-
-            let m = min(right.len(), cols as usize - n);
-            for &ch in right.iter().take(m) {
-                if ch == b'\n' {
-                    queue!(self.writer, Print('\r')).ok();
-                }
-                queue!(self.writer, Print(ch as char)).ok();
-            }
-
-            if (n + m) < cols as usize {
-                queue!(self.writer, terminal::Clear(ClearType::UntilNewLine)).ok();
-            }
+            self.queue_colours(self.mode_fore, self.mode_back);
+            queue!(self.writer, cursor::MoveTo(col, row)).ok();
+            queue!(self.writer, Print(String::from_utf8_lossy(&text))).ok();
 
             queue!(self.writer, cursor::MoveTo(saved_x, saved_y)).ok();
             self.writer.flush().ok();
         }
     }
 
-    fn audible_bell(&mut self, _freq: MintCount, _millisec: MintCount) {
+    fn set_clipboard(&mut self, text: &MintString) {
+        if self.is_tty {
+            let sequence = emacs_window::clipboard_sequence(text);
+            queue!(self.writer, Print(sequence)).ok();
+            self.writer.flush().ok();
+        }
+    }
+
+    fn suspend(&mut self) -> bool {
+        if !self.is_tty || !signals::can_stop() {
+            return false;
+        }
+        self.give_terminal_back();
+        signals::stop();
+        self.take_terminal();
+        self.push_key_flags();
+        self.resized = true;
+        true
+    }
+
+    fn original_screen(&self) -> MintString {
+        self.original_screen.clone()
+    }
+
+    fn audible_bell(&mut self, freq: MintCount, millisec: MintCount) {
+        if emacs_window::play_tone(freq, millisec) {
+            return;
+        }
         // Crossterm has no beep primitive — emit the ASCII BEL character.
         queue!(self.writer, Print('\x07')).ok();
         self.writer.flush().ok();
     }
 
-    fn visual_bell(&mut self, _millisec: MintCount) {
-        // Brief colour inversion to simulate a flash.
+    fn visual_bell(&mut self, millisec: MintCount) {
+        // Invert the screen now; it is put back by whichever of input or
+        // redisplay next notices the time is up.
         if self.is_tty {
-            queue!(
-                self.writer,
-                SetColors(Colors::new(ansi_colour(self.back), ansi_colour(self.fore)))
-            )
-            .ok();
-            self.writer.flush().ok();
-            std::thread::sleep(Duration::from_millis(50));
-            self.queue_colours(self.fore, self.back);
+            queue!(self.writer, Print(FLASH_ON)).ok();
             self.writer.flush().ok();
+            self.bell_until = Some(Instant::now() + Duration::from_millis(millisec as u64));
         }
     }
 
@@ -426,6 +596,22 @@ impl EmacsWindow for EmacsWindowCrossterm {
         self.ctrl_fore
     }
 
+    fn set_mode_fore_colour(&mut self, colour: i32) {
+        self.mode_fore = colour;
+    }
+
+    fn get_mode_fore_colour(&self) -> i32 {
+        self.mode_fore
+    }
+
+    fn set_mode_back_colour(&mut self, colour: i32) {
+        self.mode_back = colour;
+    }
+
+    fn get_mode_back_colour(&self) -> i32 {
+        self.mode_back
+    }
+
     fn set_whitespace_display(&mut self, flag: bool) {
         self.show_wsp = flag;
     }
@@ -442,6 +628,22 @@ impl EmacsWindow for EmacsWindowCrossterm {
         self.wsp_fore
     }
 
+    fn set_eob_indicator(&mut self, glyph: &MintString) {
+        self.eob_glyph = glyph.clone();
+    }
+
+    fn get_eob_indicator(&self) -> MintString {
+        self.eob_glyph.clone()
+    }
+
+    fn set_eol_indicator(&mut self, glyph: &MintString) {
+        self.eol_glyph = glyph.clone();
+    }
+
+    fn get_eol_indicator(&self) -> MintString {
+        self.eol_glyph.clone()
+    }
+
     fn get_bot_scroll_percent(&self) -> MintCount {
         self.bot_scroll_percent
     }
@@ -462,13 +664,7 @@ impl EmacsWindow for EmacsWindowCrossterm {
 impl Drop for EmacsWindowCrossterm {
     fn drop(&mut self) {
         if self.is_tty {
-            execute!(
-                self.writer,
-                cursor::Show,
-                terminal::LeaveAlternateScreen,
-            )
-            .ok();
-            terminal::disable_raw_mode().ok();
+            self.give_terminal_back();
         }
     }
 }
@@ -505,7 +701,8 @@ fn ansi_colour(colour: i32) -> Color {
 
 /// Translate a crossterm `KeyEvent` into the `MintString` token that the
 /// editor expects (matching the key names used in the ncurses implementation).
-fn map_key_event(ke: KeyEvent) -> MintString {
+/// With `altgr`, characters typed with Ctrl and Alt held are AltGr characters.
+fn map_key_event(ke: KeyEvent, altgr: bool) -> MintString {
     // Ignore key-release and key-repeat events emitted by some terminals
     // in the "enhanced keyboard" mode.
     if ke.kind != KeyEventKind::Press {
@@ -514,8 +711,15 @@ fn map_key_event(ke: KeyEvent) -> MintString {
 
     let ctrl = ke.modifiers.contains(KeyModifiers::CONTROL);
     let shift = ke.modifiers.contains(KeyModifiers::SHIFT);
+    let alt = ke.modifiers.contains(KeyModifiers::ALT);
+
+    // AltGr characters, such as '@' or '{' on a German keyboard, are typed
+    // as they are.  Letters and digits with Ctrl and Alt are still M-C- keys.
+    let altgr =
+        altgr && ctrl && alt && matches!(ke.code, KeyCode::Char(c) if !c.is_ascii_alphanumeric());
+    let (ctrl, alt) = if altgr { (false, false) } else { (ctrl, alt) };
 
-    match ke.code {
+    let name = match ke.code {
         // Control characters
         KeyCode::Char('@') if ctrl => b"C-@".to_vec(),
         KeyCode::Char(c) if ctrl => format!("C-{}", c.to_ascii_lowercase()).into_bytes(),
@@ -531,26 +735,124 @@ fn map_key_event(ke: KeyEvent) -> MintString {
         // Named keys
         KeyCode::Backspace => b"Back Space".to_vec(),
         KeyCode::Tab | KeyCode::BackTab => b"Tab".to_vec(),
+        KeyCode::Enter if ke.state.contains(KeyEventState::KEYPAD) => b"Enter".to_vec(),
         KeyCode::Enter => b"Return".to_vec(),
         KeyCode::Esc => b"Escape".to_vec(),
-        KeyCode::Delete => b"Del".to_vec(),
-        KeyCode::Insert => b"Ins".to_vec(),
-        KeyCode::Up => b"Up Arrow".to_vec(),
-        KeyCode::Down => b"Down Arrow".to_vec(),
-        KeyCode::Left => b"Left Arrow".to_vec(),
-        KeyCode::Right => b"Right Arrow".to_vec(),
-        KeyCode::Home => b"Home".to_vec(),
-        KeyCode::End => b"End".to_vec(),
-        KeyCode::PageUp => b"Pg Up".to_vec(),
-        KeyCode::PageDown => b"Pg Dn".to_vec(),
-
-        // Function keys (shifted variants use S-Fn naming)
-        KeyCode::F(n) if shift => format!("S-F{}", n).into_bytes(),
-        KeyCode::F(n) => format!("F{}", n).into_bytes(),
-
-        _ => b"Unknown".to_vec(),
+
+        // Navigation keys, with "C-" and "S-" for Ctrl and Shift
+        KeyCode::Delete => modified_key(ctrl, shift, "Del"),
+        KeyCode::Insert => modified_key(ctrl, shift, "Ins"),
+        KeyCode::Up => modified_key(ctrl, shift, "Up Arrow"),
+        KeyCode::Down => modified_key(ctrl, shift, "Down Arrow"),
+        KeyCode::Left => modified_key(ctrl, shift, "Left Arrow"),
+        KeyCode::Right => modified_key(ctrl, shift, "Right Arrow"),
+        KeyCode::Home => modified_key(ctrl, shift, "Home"),
+        KeyCode::End => modified_key(ctrl, shift, "End"),
+        KeyCode::PageUp => modified_key(ctrl, shift, "Pg Up"),
+        KeyCode::PageDown => modified_key(ctrl, shift, "Pg Dn"),
+
+        // Function keys F1 to F24 and beyond, modified the same way
+        KeyCode::F(n) => modified_key(ctrl, shift, &format!("F{}", n)),
+
+        _ => return b"Unknown".to_vec(),
+    };
+
+    // Keypad keys are only told apart with the kitty keyboard protocol.
+    let name = if ke.state.contains(KeyEventState::KEYPAD) {
+        [b"KP-".as_slice(), &name].concat()
+    } else {
+        name
+    };
+    if alt { meta_key(&name) } else { name }
+}
+
+/// Whether `ke` is a press of Escape with no modifiers.
+fn is_escape(ke: &KeyEvent) -> bool {
+    ke.code == KeyCode::Esc && ke.modifiers.is_empty() && ke.kind == KeyEventKind::Press
+}
+
+/// Translate a crossterm `MouseEvent` into an input event.
+fn map_mouse_event(me: MouseEvent) -> InputEvent {
+    let (row, col) = (me.row as MintCount, me.column as MintCount);
+    let mouse = |action, b: MouseButton| InputEvent::Mouse {
+        action,
+        button: match b {
+            MouseButton::Left => 1,
+            MouseButton::Middle => 2,
+            MouseButton::Right => 3,
+        },
+        row,
+        col,
+    };
+
+    match me.kind {
+        MouseEventKind::Down(b) => mouse(MouseAction::Down, b),
+        MouseEventKind::Up(b) => mouse(MouseAction::Up, b),
+        MouseEventKind::Drag(b) => mouse(MouseAction::Drag, b),
+        MouseEventKind::ScrollUp => InputEvent::Wheel { up: true },
+        MouseEventKind::ScrollDown => InputEvent::Wheel { up: false },
+        _ => InputEvent::Unknown,
     }
 }
 
 // Bring Read into scope for the non-tty stdin fallback in get_input.
 use std::io::Read;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn console_keys_are_decoded() {
+        // As the Windows console reports them.
+        let cases = [
+            (key(KeyCode::Char('x'), KeyModifiers::CONTROL), "C-x"),
+            (key(KeyCode::Char('x'), KeyModifiers::ALT), "M-x"),
+            (key(KeyCode::Char('X'), KeyModifiers::SHIFT), "X"),
+            (key(KeyCode::Char(','), KeyModifiers::ALT), "M-Comma"),
+            (key(KeyCode::Char('@'), KeyModifiers::CONTROL), "C-@"),
+            (key(KeyCode::Esc, KeyModifiers::NONE), "Escape"),
+            (key(KeyCode::Enter, KeyModifiers::NONE), "Return"),
+            (key(KeyCode::Backspace, KeyModifiers::NONE), "Back Space"),
+            (key(KeyCode::F(5), KeyModifiers::CONTROL), "C-F5"),
+            (key(KeyCode::Up, KeyModifiers::SHIFT), "S-Up Arrow"),
+            (key(KeyCode::Left, KeyModifiers::ALT), "M-Left Arrow"),
+        ];
+        for (ke, name) in cases {
+            assert_eq!(name.as_bytes(), map_key_event(ke, true), "{:?}", ke);
+        }
+    }
+
+    #[test]
+    fn altgr_characters_are_typed() {
+        let altgr = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        assert_eq!(
+            b"@".to_vec(),
+            map_key_event(key(KeyCode::Char('@'), altgr), true)
+        );
+        assert_eq!(
+            b"RPar".to_vec(),
+            map_key_event(key(KeyCode::Char(')'), altgr), true)
+        );
+        assert_eq!(
+            b"M-C-x".to_vec(),
+            map_key_event(key(KeyCode::Char('x'), altgr), true)
+        );
+        // A terminal means Ctrl and Alt when it says so.
+        assert_eq!(
+            b"M-C-@".to_vec(),
+            map_key_event(key(KeyCode::Char('@'), altgr), false)
+        );
+    }
+
+    #[test]
+    fn key_releases_are_ignored() {
+        let mut ke = key(KeyCode::Char('a'), KeyModifiers::NONE);
+        ke.kind = KeyEventKind::Release;
+        assert_eq!(b"Unknown".to_vec(), map_key_event(ke, true));
+    }
+}
@@ -16,13 +16,19 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
-use crate::emacs_buffer::EmacsBuffer;
-use crate::emacs_window::EmacsWindow;
+use crate::emacs_buffer::{EmacsBuffer, utf8_char};
+use crate::emacs_window::{
+    self, ESC_TIMEOUT_MS, EmacsWindow, FLASH_OFF, FLASH_ON, InputEvent, ModeLine, MouseAction,
+    RenderStats, fit_cells, meta_key,
+};
 use crate::mint_types::{MintCount, MintString};
+use crate::signals;
+use crate::window_layout::Rect;
 use ncurses::*;
 use std::cmp::{max, min};
 use std::collections::HashMap;
 use std::io::IsTerminal;
+use std::time::{Duration, Instant};
 
 pub struct EmacsWindowCurses {
     win: WINDOW,
@@ -35,20 +41,68 @@ pub struct EmacsWindowCurses {
     back: i32,
     wsp_fore: i32,
     show_wsp: bool,
+    eob_glyph: MintString,
+    eol_glyph: MintString,
     ctrl_fore: i32,
+    mode_fore: i32,
+    mode_back: i32,
     old_fore: i32,
     old_back: i32,
     decode_key: HashMap<i32, MintString>,
+    stats: RenderStats,
+    // When the screen flash from a visual bell should end.
+    bell_until: Option<Instant>,
     bot_scroll_percent: MintCount,
     top_scroll_percent: MintCount,
+    // Lines asked for by set_edit_lines, or 0 for all there is room for.
+    edit_lines: MintCount,
+    original_screen: MintString,
 }
 
+// SAFETY: "win" belongs to curses, which keeps the rest of its state in
+// globals of its own.  Curses can't be used from two threads at once, but
+// nothing here shares the window: it is only ever used by whichever thread
+// has the interpreter that owns it.
+unsafe impl Send for EmacsWindowCurses {}
+
 impl Default for EmacsWindowCurses {
     fn default() -> Self {
         Self::new()
     }
 }
 
+// Extended key capabilities for modified cursor keys are named for the key
+// with a digit for the modifiers held, e.g. "kLFT5" for Ctrl-Left.  Curses
+// gives these codes above KEY_MAX when it starts.
+const EXT_KEYS: [(&str, &str); 10] = [
+    ("kUP", "Up Arrow"),
+    ("kDN", "Down Arrow"),
+    ("kLFT", "Left Arrow"),
+    ("kRIT", "Right Arrow"),
+    ("kHOM", "Home"),
+    ("kEND", "End"),
+    ("kPRV", "Pg Up"),
+    ("kNXT", "Pg Dn"),
+    ("kIC", "Ins"),
+    ("kDC", "Del"),
+];
+const EXT_MODIFIERS: [(&str, &str); 6] = [
+    ("2", "S-"),
+    ("3", "M-"),
+    ("4", "M-S-"),
+    ("5", "C-"),
+    ("6", "C-S-"),
+    ("7", "M-C-"),
+];
+
+// Key name for an extended key capability, e.g. "C-Left Arrow" for "kLFT5".
+fn ext_key_name(cap: &str) -> Option<MintString> {
+    let (base, mods) = cap.split_at(cap.len().checked_sub(1)?);
+    let (_, key) = EXT_KEYS.iter().find(|(name, _)| *name == base)?;
+    let (_, prefix) = EXT_MODIFIERS.iter().find(|(digit, _)| *digit == mods)?;
+    Some(format!("{}{}", prefix, key).into_bytes())
+}
+
 fn key_fn(n: u8) -> i32 {
     // The comment in ncurses.h says:
     /* Function keys.  Space for 64 */
@@ -61,7 +115,11 @@ impl EmacsWindowCurses {
     pub fn new() -> Self {
         let is_tty = std::io::stdout().is_terminal();
 
-        let (win, has_colours) = if is_tty {
+        let (win, has_colours, original_screen) = if is_tty {
+            let original_screen = emacs_window::capture_screen();
+            // Curses takes the encoding of what it draws from the locale,
+            // which is "C" until asked for the user's.
+            let _ = setlocale(LcCategory::all, "");
             let win = initscr();
             let has_colours = has_colors();
 
@@ -74,6 +132,7 @@ impl EmacsWindowCurses {
             nl();
             intrflush(win, false);
             keypad(win, true);
+            set_escdelay(ESC_TIMEOUT_MS as i32);
 
             idlok(win, true);
             idcok(win, true);
@@ -85,11 +144,15 @@ impl EmacsWindowCurses {
             let lines = getmaxy(win);
             wsetscrreg(win, 0, lines - 3);
 
+            // Report presses and releases separately rather than clicks.
+            mousemask(ALL_MOUSE_EVENTS as mmask_t, None);
+            mouseinterval(0);
+
             werase(win);
 
-            (win, has_colours)
+            (win, has_colours, original_screen)
         } else {
-            (std::ptr::null_mut(), false)
+            (std::ptr::null_mut(), false, MintString::new())
         };
 
         let mut decode_key = HashMap::new();
@@ -135,6 +198,8 @@ impl EmacsWindowCurses {
         decode_key.insert(key_fn(10), b"F10".to_vec());
         decode_key.insert(key_fn(11), b"F11".to_vec());
         decode_key.insert(key_fn(12), b"F12".to_vec());
+        // xterm style terminals send shifted F1-F12 as F13-F24, with Ctrl
+        // as F25-F36, Ctrl-Shift as F37-F48 and Alt as F49-F60
         decode_key.insert(key_fn(13), b"S-F1".to_vec());
         decode_key.insert(key_fn(14), b"S-F2".to_vec());
         decode_key.insert(key_fn(15), b"S-F3".to_vec());
@@ -147,11 +212,42 @@ impl EmacsWindowCurses {
         decode_key.insert(key_fn(22), b"S-F10".to_vec());
         decode_key.insert(key_fn(23), b"S-F11".to_vec());
         decode_key.insert(key_fn(24), b"S-F12".to_vec());
+        for n in 1..=12u8 {
+            decode_key.insert(key_fn(n + 24), format!("C-F{}", n).into_bytes());
+            decode_key.insert(key_fn(n + 36), format!("C-S-F{}", n).into_bytes());
+            decode_key.insert(key_fn(n + 48), format!("M-F{}", n).into_bytes());
+        }
         decode_key.insert(KEY_DC, b"Del".to_vec());
         decode_key.insert(KEY_IC, b"Ins".to_vec());
         decode_key.insert(KEY_NPAGE, b"Pg Dn".to_vec());
         decode_key.insert(KEY_PPAGE, b"Pg Up".to_vec());
         decode_key.insert(KEY_END, b"End".to_vec());
+        decode_key.insert(KEY_SR, b"S-Up Arrow".to_vec());
+        decode_key.insert(KEY_SF, b"S-Down Arrow".to_vec());
+        decode_key.insert(KEY_SLEFT, b"S-Left Arrow".to_vec());
+        decode_key.insert(KEY_SRIGHT, b"S-Right Arrow".to_vec());
+        decode_key.insert(KEY_SHOME, b"S-Home".to_vec());
+        decode_key.insert(KEY_SEND, b"S-End".to_vec());
+        decode_key.insert(KEY_SPREVIOUS, b"S-Pg Up".to_vec());
+        decode_key.insert(KEY_SNEXT, b"S-Pg Dn".to_vec());
+        decode_key.insert(KEY_SIC, b"S-Ins".to_vec());
+        decode_key.insert(KEY_SDC, b"S-Del".to_vec());
+
+        // Keypad keys that curses can tell from the main keys
+        decode_key.insert(KEY_ENTER, b"KP-Enter".to_vec());
+        decode_key.insert(KEY_A1, b"KP-Home".to_vec());
+        decode_key.insert(KEY_A3, b"KP-Pg Up".to_vec());
+        decode_key.insert(KEY_B2, b"KP-5".to_vec());
+        decode_key.insert(KEY_C1, b"KP-End".to_vec());
+        decode_key.insert(KEY_C3, b"KP-Pg Dn".to_vec());
+
+        if !win.is_null() {
+            for code in KEY_MAX + 1..KEY_MAX + 512 {
+                if let Some(key) = keyname(code).as_deref().and_then(ext_key_name) {
+                    decode_key.insert(code, key);
+                }
+            }
+        }
 
         let mut window = Self {
             win,
@@ -164,12 +260,20 @@ impl EmacsWindowCurses {
             back: 0,
             wsp_fore: 15,
             show_wsp: false,
+            eob_glyph: MintString::new(),
+            eol_glyph: MintString::new(),
             ctrl_fore: 11,
+            mode_fore: 0,
+            mode_back: 7,
             old_fore: -1,
             old_back: -1,
             decode_key,
+            stats: RenderStats::default(),
+            bell_until: None,
             bot_scroll_percent: 0,
             top_scroll_percent: 0,
+            edit_lines: 0,
+            original_screen,
         };
 
         if !win.is_null() {
@@ -179,13 +283,100 @@ impl EmacsWindowCurses {
         window
     }
 
-    fn write_line(&mut self, buf: &EmacsBuffer, bol: MintCount, eol: MintCount) {
-        let cols = getmaxx(self.win);
+    fn decode(&self, ch: i32) -> MintString {
+        self.decode_key
+            .get(&ch)
+            .cloned()
+            .unwrap_or_else(|| b"Unknown".to_vec())
+    }
+
+    // Curses has no way to send arbitrary escape sequences, so write them
+    // straight to the terminal.
+    fn send_sequence(&self, seq: &str) {
+        use std::io::Write;
+        let mut out = std::io::stdout();
+        let _ = out.write_all(seq.as_bytes());
+        let _ = out.flush();
+    }
+
+    fn end_bell(&mut self) {
+        if self.bell_until.take().is_some() {
+            self.send_sequence(FLASH_OFF);
+        }
+    }
+
+    // Turn off the visual bell flash if it has run its course.
+    fn end_bell_if_due(&mut self) {
+        if self.bell_until.is_some_and(|until| Instant::now() >= until) {
+            self.end_bell();
+        }
+    }
+
+    // Read a key, waiting at most "millisec" for one.  A visual bell that
+    // is due to end within the wait is ended on time rather than when the
+    // wait is over.
+    fn read_key(&mut self, millisec: MintCount) -> i32 {
+        self.end_bell_if_due();
+        if millisec < 10 {
+            nodelay(self.win, true);
+            wtimeout(self.win, 0);
+            return wgetch(self.win);
+        }
+
+        nodelay(self.win, false);
+        if let Some(until) = self.bell_until {
+            let remaining = until.saturating_duration_since(Instant::now()).as_millis() as i32;
+            if remaining < millisec as i32 {
+                wtimeout(self.win, remaining);
+                let ch = wgetch(self.win);
+                if ch != ERR {
+                    return ch;
+                }
+                self.end_bell();
+                wtimeout(self.win, millisec as i32 - remaining);
+                return wgetch(self.win);
+            }
+        }
+        wtimeout(self.win, millisec as i32);
+        wgetch(self.win)
+    }
+
+    // Terminals send Alt-x as ESC followed by x, so a key arriving within
+    // ESC_TIMEOUT_MS of an Escape is reported as Alt-modified.
+    fn escape_prefix(&mut self) -> MintString {
+        nodelay(self.win, false);
+        wtimeout(self.win, ESC_TIMEOUT_MS as i32);
+        let ch = wgetch(self.win);
+        if ch == ERR {
+            b"Escape".to_vec()
+        } else if ch == KEY_MOUSE || ch == KEY_RESIZE {
+            ungetch(ch);
+            b"Escape".to_vec()
+        } else {
+            meta_key(&self.decode(ch))
+        }
+    }
+
+    // A screen line past the end of the buffer
+    fn write_eob_line(&mut self, cols: i32) {
+        let (glyph, width) = fit_cells(&self.eob_glyph, cols as usize);
+        if width > 0 {
+            self.set_curses_attributes(self.wsp_fore, self.back);
+            let _ = waddstr(self.win, &glyph);
+        }
+        self.set_curses_attributes(self.fore, self.back);
+        for _ in width as i32..cols {
+            waddch(self.win, b' ' as chtype);
+        }
+    }
+
+    fn write_line(&mut self, buf: &EmacsBuffer, bol: MintCount, eol: MintCount, cols: i32) {
         let leftcol = buf.get_left_column();
+        let utf8 = buf.is_utf8();
 
-        let text = buf.read_to_mark_from(crate::emacs_buffer::MARK_EOB, bol);
-        let line_len = min((eol - bol) as usize, text.len());
-        let line_text = &text[..line_len];
+        let text = buf.read(bol, eol);
+        let line_len = text.len();
+        let line_text = &text[..];
 
         // Find the last non-space character
         let mut nwsp_idx = line_len;
@@ -201,14 +392,34 @@ impl EmacsWindowCurses {
 
         // Skip to leftcol
         while cur_col < leftcol as i32 && char_idx < line_len {
-            let ch = line_text[char_idx];
-            cur_col += buf.char_width(cur_col as MintCount, ch) as i32;
-            char_idx += 1;
+            let (width, len) = buf.display_width(cur_col as MintCount, &line_text[char_idx..]);
+            cur_col += width as i32;
+            char_idx += len;
+        }
+
+        // Blank the part of a tab or wide character that crosses leftcol
+        self.set_curses_attributes(self.fore, self.back);
+        for _ in leftcol as i32..min(cur_col, leftcol as i32 + cols) {
+            waddch(self.win, b' ' as chtype);
         }
 
+        // Matches of the highlight pattern are shown in reverse video
+        let spans = buf.highlight_spans(bol, eol);
+        let highlighted = |idx: usize| {
+            let pos = bol + idx as MintCount;
+            spans
+                .iter()
+                .any(|&(start, end)| (start..end).contains(&pos))
+        };
+
         // Write visible characters
         while cur_col < (leftcol as i32 + cols) && char_idx < line_len {
             let ch = line_text[char_idx];
+            let (fore, back) = if highlighted(char_idx) {
+                (self.back, self.fore)
+            } else {
+                (self.fore, self.back)
+            };
             char_idx += 1;
 
             if ch == 0x09 {
@@ -216,10 +427,10 @@ impl EmacsWindowCurses {
                 tabw = min(tabw, leftcol as i32 + cols - cur_col);
 
                 let display_ch = if self.show_wsp && char_idx > nwsp_idx {
-                    self.set_curses_attributes(self.wsp_fore, self.back);
+                    self.set_curses_attributes(self.wsp_fore, back);
                     ACS_BULLET()
                 } else {
-                    self.set_curses_attributes(self.fore, self.back);
+                    self.set_curses_attributes(fore, back);
                     b' ' as chtype
                 };
 
@@ -227,31 +438,107 @@ impl EmacsWindowCurses {
                     waddch(self.win, display_ch);
                 }
                 cur_col += tabw;
-            } else if ch < 0x20 {
-                self.set_curses_attributes(self.ctrl_fore, self.back);
-                waddch(self.win, (ch + b'@') as chtype);
-                cur_col += 1;
+            } else if utf8 && let Some((uch, len)) = utf8_char(&line_text[char_idx - 1..]) {
+                // Wide characters take two cells, combining marks none
+                let width = buf
+                    .display_width(cur_col as MintCount, &line_text[char_idx - 1..])
+                    .0;
+                if cur_col + width as i32 > leftcol as i32 + cols {
+                    break;
+                }
+                self.set_curses_attributes(fore, back);
+                let _ = waddstr(self.win, uch.encode_utf8(&mut [0; 4]));
+                char_idx += len - 1;
+                cur_col += width as i32;
+            } else if let Some(repr) = buf.display_repr(ch) {
+                self.set_curses_attributes(self.ctrl_fore, back);
+                for &repr_ch in repr.as_bytes() {
+                    if cur_col >= leftcol as i32 + cols {
+                        break;
+                    }
+                    waddch(self.win, repr_ch as chtype);
+                    cur_col += 1;
+                }
             } else if ch == 0x20 {
                 let display_ch = if self.show_wsp && char_idx > nwsp_idx {
-                    self.set_curses_attributes(self.wsp_fore, self.back);
+                    self.set_curses_attributes(self.wsp_fore, back);
                     ACS_BULLET()
                 } else {
-                    self.set_curses_attributes(self.fore, self.back);
+                    self.set_curses_attributes(fore, back);
                     b' ' as chtype
                 };
                 waddch(self.win, display_ch);
                 cur_col += 1;
             } else {
-                self.set_curses_attributes(self.fore, self.back);
+                self.set_curses_attributes(fore, back);
                 waddch(self.win, ch as chtype);
                 cur_col += 1;
             }
         }
 
+        // Mark the end of the line, unless it is off the screen
+        if char_idx == line_len && cur_col >= leftcol as i32 {
+            let room = (leftcol as i32 + cols - cur_col) as usize;
+            let (glyph, width) = fit_cells(&self.eol_glyph, room);
+            if width > 0 {
+                self.set_curses_attributes(self.wsp_fore, self.back);
+                let _ = waddstr(self.win, &glyph);
+                cur_col += width as i32;
+            }
+        }
+
+        // Clear the rest of the line, leaving any pane to the right alone.
         if cur_col < (leftcol as i32 + cols) {
             self.set_curses_attributes(self.fore, self.back);
-            wclrtoeol(self.win);
+            for _ in cur_col..(leftcol as i32 + cols) {
+                waddch(self.win, b' ' as chtype);
+            }
+        }
+    }
+
+    // Translate the pending mouse event into an input event.
+    fn decode_mouse(&self) -> InputEvent {
+        let mut ev = MEVENT {
+            id: 0,
+            x: 0,
+            y: 0,
+            z: 0,
+            bstate: 0,
+        };
+        if getmouse(&mut ev) != OK {
+            return InputEvent::Unknown;
+        }
+
+        let state = ev.bstate as mmask_t;
+        let has = |mask: i32| state & mask as mmask_t != 0;
+        let (row, col) = (ev.y as MintCount, ev.x as MintCount);
+        if has(BUTTON4_PRESSED) {
+            return InputEvent::Wheel { up: true };
+        }
+        if has(BUTTON5_PRESSED) {
+            return InputEvent::Wheel { up: false };
         }
+        let buttons = [
+            (1, BUTTON1_PRESSED, BUTTON1_RELEASED),
+            (2, BUTTON2_PRESSED, BUTTON2_RELEASED),
+            (3, BUTTON3_PRESSED, BUTTON3_RELEASED),
+        ];
+        for (button, pressed, released) in buttons {
+            let action = if has(pressed) {
+                MouseAction::Down
+            } else if has(released) {
+                MouseAction::Up
+            } else {
+                continue;
+            };
+            return InputEvent::Mouse {
+                action,
+                button,
+                row,
+                col,
+            };
+        }
+        InputEvent::Unknown
     }
 
     fn set_curses_attributes(&mut self, fo: i32, ba: i32) {
@@ -290,6 +577,10 @@ impl EmacsWindowCurses {
 }
 
 impl EmacsWindow for EmacsWindowCurses {
+    fn backend(&self) -> &'static str {
+        "curses"
+    }
+
     fn get_columns(&self) -> MintCount {
         if !self.win.is_null() {
             getmaxx(self.win) as MintCount
@@ -298,28 +589,53 @@ impl EmacsWindow for EmacsWindowCurses {
         }
     }
 
-    fn get_lines(&self) -> MintCount {
+    fn get_screen_lines(&self) -> MintCount {
         if !self.win.is_null() {
-            (getmaxy(self.win) - 3) as MintCount
+            getmaxy(self.win) as MintCount
         } else {
-            24
+            27
         }
     }
 
-    fn redisplay(&mut self, buf: &mut EmacsBuffer, force: bool) {
+    fn get_edit_lines(&self) -> MintCount {
+        emacs_window::fit_edit_lines(self.edit_lines, self.get_screen_lines())
+    }
+
+    fn set_edit_lines(&mut self, lines: MintCount) {
+        self.edit_lines = lines;
         if !self.win.is_null() {
+            werase(self.win);
+        }
+    }
+
+    fn begin_update(&mut self) {
+        // Curses already sends the changes together when it refreshes.
+    }
+
+    fn end_update(&mut self) {
+        if !self.win.is_null() {
+            let start = Instant::now();
+            wrefresh(self.win);
+            self.stats.flush_time += start.elapsed();
+        }
+    }
+
+    fn redisplay(&mut self, buf: &mut EmacsBuffer, area: &Rect, force: bool) {
+        if !self.win.is_null() {
+            self.end_bell_if_due();
             self.overwriting = false;
+            self.stats.redisplays += 1;
 
             if force {
                 touchwin(self.win);
             }
 
-            let lines = getmaxy(self.win);
-            let cols = getmaxx(self.win);
+            let (left, top) = (area.col as i32, area.row as i32);
+            let (cols, lines) = (area.cols as i32, area.rows as i32);
 
             buf.force_point_in_window(
-                (lines - 2) as MintCount,
-                cols as MintCount,
+                area.rows,
+                area.cols,
                 self.top_scroll_percent,
                 self.bot_scroll_percent,
             );
@@ -329,17 +645,56 @@ impl EmacsWindow for EmacsWindowCurses {
             let screen_line = buf.count_newlines(curline, point);
             let screen_col = buf.get_column() as i32 - buf.get_left_column() as i32;
 
-            for i in 0..(lines - 2) {
-                wmove(self.win, i, 0);
+            // The buffer's own display settings are used while it is drawn.
+            let window_style = (
+                self.fore,
+                self.back,
+                self.ctrl_fore,
+                self.wsp_fore,
+                self.show_wsp,
+            );
+            let own = buf.display();
+            self.fore = own.fore.unwrap_or(self.fore);
+            self.back = own.back.unwrap_or(self.back);
+            self.ctrl_fore = own.ctrl_fore.unwrap_or(self.ctrl_fore);
+            self.wsp_fore = own.wsp_fore.unwrap_or(self.wsp_fore);
+            self.show_wsp = own.show_wsp.unwrap_or(self.show_wsp);
+
+            // Once the end of the buffer is reached, lines stop advancing
+            let mut prev_eol = None;
+            for i in 0..lines {
+                wmove(self.win, top + i, left);
                 let eol = buf.get_mark_position_from(crate::emacs_buffer::MARK_EOL, curline);
-                self.write_line(buf, curline, eol);
+                if prev_eol == Some(curline) {
+                    self.write_eob_line(cols);
+                } else {
+                    self.write_line(buf, curline, eol, cols);
+                }
+                self.stats.lines += 1;
+                self.stats.cells += cols as u64;
+                prev_eol = Some(eol);
                 curline = buf.get_mark_position_from(crate::emacs_buffer::MARK_NEXT_CHAR, eol);
             }
-
-            wmove(self.win, screen_line as i32, screen_col);
+            (
+                self.fore,
+                self.back,
+                self.ctrl_fore,
+                self.wsp_fore,
+                self.show_wsp,
+            ) = window_style;
+
+            wmove(self.win, top + screen_line as i32, left + screen_col);
         }
     }
 
+    fn get_render_stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    fn reset_render_stats(&mut self) {
+        self.stats = RenderStats::default();
+    }
+
     fn overwrite(&mut self, s: &MintString) {
         if !self.win.is_null() {
             if !self.overwriting {
@@ -381,53 +736,74 @@ impl EmacsWindow for EmacsWindowCurses {
         }
     }
 
+    fn input_fd(&self) -> Option<i32> {
+        // Curses reads standard input.  While a visual bell shows, it is
+        // left to read_key, which knows when the bell ends.
+        if self.win.is_null() || self.bell_until.is_some() {
+            None
+        } else {
+            Some(0)
+        }
+    }
+
     fn key_waiting(&self) -> bool {
         if !self.win.is_null() {
-            #[cfg(not(target_os = "windows"))]
-            {
-                nodelay(self.win, true);
-                wtimeout(self.win, 0);
-                let ch = wgetch(self.win);
-                if ch != ERR {
-                    ungetch(ch);
-                    return true;
-                }
+            nodelay(self.win, true);
+            wtimeout(self.win, 0);
+            let ch = wgetch(self.win);
+            if ch != ERR {
+                ungetch(ch);
+                return true;
             }
         }
         false
     }
 
-    fn get_input(&mut self, millisec: MintCount) -> MintString {
+    fn get_input(&mut self, millisec: MintCount) -> InputEvent {
         if !self.win.is_null() {
-            if millisec < 10 {
-                nodelay(self.win, true);
-                wtimeout(self.win, 0);
-            } else {
-                nodelay(self.win, false);
-                wtimeout(self.win, millisec as i32);
-            }
-
-            let ch = wgetch(self.win);
+            let ch = self.read_key(millisec);
 
             if ch == ERR {
-                b"Timeout".to_vec()
+                InputEvent::Timeout
+            } else if ch == KEY_RESIZE {
+                // Curses has already updated the screen size, so just
+                // repaint everything on the next refresh.
+                wsetscrreg(self.win, 0, getmaxy(self.win) - 3);
+                clearok(self.win, true);
+                InputEvent::Resize
+            } else if ch == KEY_MOUSE {
+                self.decode_mouse()
+            } else if ch == 0x1B {
+                InputEvent::Key(self.escape_prefix())
             } else {
-                self.decode_key
-                    .get(&ch)
-                    .cloned()
-                    .unwrap_or_else(|| b"Unknown".to_vec())
+                InputEvent::Key(self.decode(ch))
             }
         } else if millisec > 0 {
             use std::io::{self, Read};
             let mut buffer = [0u8; 1];
             if io::stdin().read(&mut buffer).ok().unwrap_or(0) > 0 {
-                vec![buffer[0]]
+                InputEvent::Key(vec![buffer[0]])
             } else {
-                b"Timeout".to_vec()
+                InputEvent::Timeout
             }
         } else {
-            b"Timeout".to_vec()
+            InputEvent::Timeout
+        }
+    }
+
+    fn decode_key(&mut self, code: &MintString, name: &MintString) -> bool {
+        // Either the number curses reads for the key, or the name of
+        // the keys to be renamed.
+        if let Some(ch) = std::str::from_utf8(code).ok().and_then(|s| s.parse().ok()) {
+            self.decode_key.insert(ch, name.clone());
+            return true;
+        }
+        let mut found = false;
+        for key in self.decode_key.values_mut().filter(|key| *key == code) {
+            *key = name.clone();
+            found = true;
         }
+        found
     }
 
     fn announce(&mut self, left: &MintString, right: &MintString) {
@@ -466,39 +842,53 @@ impl EmacsWindow for EmacsWindowCurses {
         }
     }
 
-    fn announce_win(&mut self, left: &MintString, right: &MintString) {
-        if !self.win.is_null() {
-            let cols = getmaxx(self.win);
-            let lines = getmaxy(self.win);
-            let n = min(left.len(), (cols - 1) as usize);
+    fn mode_line(&mut self, area: &Rect, line: &ModeLine) {
+        if !self.win.is_null() && area.rows > 0 {
+            let text = line.render(area.cols as usize);
 
-            self.set_curses_attributes(self.fore, self.back);
+            self.set_curses_attributes(self.mode_fore, self.mode_back);
 
             let mut y = 0;
             let mut x = 0;
             getyx(self.win, &mut y, &mut x);
 
-            wmove(self.win, lines - 2, 0);
+            wmove(self.win, area.row as i32, area.col as i32);
+            let _ = waddstr(self.win, &String::from_utf8_lossy(&text));
 
-            for &ch in left.iter().take(n) {
-                waddch(self.win, ch as chtype);
-            }
-
-            let m = min(right.len(), (cols - n as i32) as usize);
-            for &ch in right.iter().take(m) {
-                waddch(self.win, ch as chtype);
-            }
+            // Shown by the next refresh, which for a redisplay is end_update.
+            wmove(self.win, y, x);
+        }
+    }
 
-            if (n + m) < cols as usize {
-                wclrtoeol(self.win);
-            }
+    fn set_clipboard(&mut self, text: &MintString) {
+        // Xterm compatible terminals take this without output.
+        if !self.win.is_null() {
+            self.send_sequence(&emacs_window::clipboard_sequence(text));
+        }
+    }
 
-            wmove(self.win, y, x);
-            refresh();
+    fn suspend(&mut self) -> bool {
+        if self.win.is_null() || !signals::can_stop() {
+            return false;
         }
+        self.end_bell();
+        endwin();
+        signals::stop();
+        // The next refresh takes the terminal back, and it may have
+        // changed size while we were stopped.
+        wsetscrreg(self.win, 0, getmaxy(self.win) - 3);
+        clearok(self.win, true);
+        true
+    }
+
+    fn original_screen(&self) -> MintString {
+        self.original_screen.clone()
     }
 
-    fn audible_bell(&mut self, _freq: MintCount, _millisec: MintCount) {
+    fn audible_bell(&mut self, freq: MintCount, millisec: MintCount) {
+        if emacs_window::play_tone(freq, millisec) {
+            return;
+        }
         if !self.win.is_null() {
             beep();
         } else {
@@ -506,9 +896,12 @@ impl EmacsWindow for EmacsWindowCurses {
         }
     }
 
-    fn visual_bell(&mut self, _millisec: MintCount) {
+    fn visual_bell(&mut self, millisec: MintCount) {
+        // Invert the screen now; it is put back by whichever of input or
+        // redisplay next notices the time is up.
         if !self.win.is_null() {
-            flash();
+            self.send_sequence(FLASH_ON);
+            self.bell_until = Some(Instant::now() + Duration::from_millis(millisec as u64));
         }
     }
 
@@ -536,6 +929,22 @@ impl EmacsWindow for EmacsWindowCurses {
         self.ctrl_fore
     }
 
+    fn set_mode_fore_colour(&mut self, colour: i32) {
+        self.mode_fore = colour;
+    }
+
+    fn get_mode_fore_colour(&self) -> i32 {
+        self.mode_fore
+    }
+
+    fn set_mode_back_colour(&mut self, colour: i32) {
+        self.mode_back = colour;
+    }
+
+    fn get_mode_back_colour(&self) -> i32 {
+        self.mode_back
+    }
+
     fn set_whitespace_display(&mut self, flag: bool) {
         self.show_wsp = flag;
     }
@@ -552,6 +961,22 @@ impl EmacsWindow for EmacsWindowCurses {
         self.wsp_fore
     }
 
+    fn set_eob_indicator(&mut self, glyph: &MintString) {
+        self.eob_glyph = glyph.clone();
+    }
+
+    fn get_eob_indicator(&self) -> MintString {
+        self.eob_glyph.clone()
+    }
+
+    fn set_eol_indicator(&mut self, glyph: &MintString) {
+        self.eol_glyph = glyph.clone();
+    }
+
+    fn get_eol_indicator(&self) -> MintString {
+        self.eol_glyph.clone()
+    }
+
     fn get_bot_scroll_percent(&self) -> MintCount {
         self.bot_scroll_percent
     }
@@ -572,6 +997,7 @@ impl EmacsWindow for EmacsWindowCurses {
 impl Drop for EmacsWindowCurses {
     fn drop(&mut self) {
         if !self.win.is_null() {
+            self.end_bell();
             endwin();
         }
     }
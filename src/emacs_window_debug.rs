@@ -17,8 +17,9 @@
  */
 
 use crate::emacs_buffer::EmacsBuffer;
-use crate::emacs_window::EmacsWindow;
+use crate::emacs_window::{EmacsWindow, InputEvent, ModeLine, RenderStats, fit_edit_lines};
 use crate::mint_types::{MintChar, MintCount, MintString};
+use crate::window_layout::Rect;
 
 pub struct EmacsWindowDebug {
     columns: MintCount,
@@ -27,9 +28,15 @@ pub struct EmacsWindowDebug {
     back: i32,
     wsp_fore: i32,
     show_wsp: bool,
+    eob_glyph: MintString,
+    eol_glyph: MintString,
     ctrl_fore: i32,
+    mode_fore: i32,
+    mode_back: i32,
     bot_scroll_percent: MintCount,
     top_scroll_percent: MintCount,
+    // Lines asked for by set_edit_lines, or 0 for all there is room for.
+    edit_lines: MintCount,
 }
 
 fn to_s(s: &[MintChar]) -> String {
@@ -45,24 +52,58 @@ impl EmacsWindowDebug {
             back: 0,
             wsp_fore: 6,
             show_wsp: false,
+            eob_glyph: MintString::new(),
+            eol_glyph: MintString::new(),
             ctrl_fore: 2,
+            mode_fore: 0,
+            mode_back: 7,
             bot_scroll_percent: 90,
             top_scroll_percent: 10,
+            edit_lines: 0,
         }
     }
 }
 
 impl EmacsWindow for EmacsWindowDebug {
+    fn backend(&self) -> &'static str {
+        "debug"
+    }
+
     fn get_columns(&self) -> MintCount {
         self.columns
     }
 
-    fn get_lines(&self) -> MintCount {
+    fn get_screen_lines(&self) -> MintCount {
         self.lines
     }
 
-    fn redisplay(&mut self, _buf: &mut EmacsBuffer, force: bool) {
-        println!("Redisplay(force={})", force);
+    fn get_edit_lines(&self) -> MintCount {
+        fit_edit_lines(self.edit_lines, self.get_screen_lines())
+    }
+
+    fn set_edit_lines(&mut self, lines: MintCount) {
+        println!("set_edit_lines({})", lines);
+        self.edit_lines = lines;
+    }
+
+    fn begin_update(&mut self) {
+        println!("begin_update()");
+    }
+
+    fn end_update(&mut self) {
+        println!("end_update()");
+    }
+
+    fn redisplay(&mut self, _buf: &mut EmacsBuffer, area: &Rect, force: bool) {
+        println!("Redisplay({:?}, force={})", area, force);
+    }
+
+    fn get_render_stats(&self) -> RenderStats {
+        RenderStats::default()
+    }
+
+    fn reset_render_stats(&mut self) {
+        println!("reset_render_stats()");
     }
 
     fn overwrite(&mut self, s: &MintString) {
@@ -78,17 +119,39 @@ impl EmacsWindow for EmacsWindowDebug {
         false
     }
 
-    fn get_input(&mut self, millisec: MintCount) -> MintString {
+    fn get_input(&mut self, millisec: MintCount) -> InputEvent {
         println!("get_input({})", millisec);
-        b"Timeout".to_vec()
+        InputEvent::Timeout
+    }
+
+    fn input_fd(&self) -> Option<i32> {
+        None
+    }
+
+    fn decode_key(&mut self, code: &MintString, name: &MintString) -> bool {
+        println!("decode_key({:?}, {:?})", to_s(code), to_s(name));
+        true
     }
 
     fn announce(&mut self, left: &MintString, right: &MintString) {
         println!("ann    |{:?}| |{:?}|", to_s(left), to_s(right));
     }
 
-    fn announce_win(&mut self, left: &MintString, right: &MintString) {
-        println!("annw   |{:?}| |{:?}|", to_s(left), to_s(right));
+    fn mode_line(&mut self, area: &Rect, line: &ModeLine) {
+        println!(
+            "mode   {:?} |{:?}|",
+            area,
+            to_s(&line.render(area.cols as usize))
+        );
+    }
+
+    fn set_clipboard(&mut self, text: &MintString) {
+        println!("set_clipboard({:?})", to_s(text));
+    }
+
+    fn suspend(&mut self) -> bool {
+        println!("suspend()");
+        false
     }
 
     fn audible_bell(&mut self, freq: MintCount, millisec: MintCount) {
@@ -126,6 +189,24 @@ impl EmacsWindow for EmacsWindowDebug {
         self.ctrl_fore
     }
 
+    fn set_mode_fore_colour(&mut self, colour: i32) {
+        println!("set_mode_fore_colour({})", colour);
+        self.mode_fore = colour;
+    }
+
+    fn get_mode_fore_colour(&self) -> i32 {
+        self.mode_fore
+    }
+
+    fn set_mode_back_colour(&mut self, colour: i32) {
+        println!("set_mode_back_colour({})", colour);
+        self.mode_back = colour;
+    }
+
+    fn get_mode_back_colour(&self) -> i32 {
+        self.mode_back
+    }
+
     fn set_whitespace_display(&mut self, flag: bool) {
         println!("set_whitespace_display({})", flag);
         self.show_wsp = flag;
@@ -144,6 +225,22 @@ impl EmacsWindow for EmacsWindowDebug {
         self.wsp_fore
     }
 
+    fn set_eob_indicator(&mut self, glyph: &MintString) {
+        self.eob_glyph = glyph.clone();
+    }
+
+    fn get_eob_indicator(&self) -> MintString {
+        self.eob_glyph.clone()
+    }
+
+    fn set_eol_indicator(&mut self, glyph: &MintString) {
+        self.eol_glyph = glyph.clone();
+    }
+
+    fn get_eol_indicator(&self) -> MintString {
+        self.eol_glyph.clone()
+    }
+
     fn get_bot_scroll_percent(&self) -> MintCount {
         self.bot_scroll_percent
     }
@@ -0,0 +1,953 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* A window of its own, showing the screen as a grid of character cells. */
+
+// The screen is kept as a grid of cells, each a character and its
+// colours, and drawn into a pixel buffer with glyphs from the system's
+// monospace font whenever the editor is about to wait for a key.  Keys
+// are named as the terminal backends name them.
+
+use std::cmp::{max, min};
+use std::collections::{HashMap, VecDeque};
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use fontdue::{Font, FontSettings, Metrics};
+use minifb::{InputCallback, Key, MouseButton, MouseMode, ScaleMode, Window, WindowOptions};
+use unicode_width::UnicodeWidthChar;
+
+use crate::emacs_buffer::{self, EmacsBuffer};
+use crate::emacs_window::{
+    self, DrawnLine, EmacsWindow, InputEvent, LineStyle, ModeLine, MouseAction, RenderStats,
+    char_key, eob_line, meta_key, modified_key, render_line,
+};
+use crate::mint_types::{MintCount, MintString};
+use crate::window_layout::Rect;
+
+const FONT_SIZE: f32 = 16.0;
+
+// How often to look for input while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// The sixteen DOS colours, as 0RGB.
+const PALETTE: [u32; 16] = [
+    0x000000, 0x0000aa, 0x00aa00, 0x00aaaa, 0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa, 0x555555,
+    0x5555ff, 0x55ff55, 0x55ffff, 0xff5555, 0xff55ff, 0xffff55, 0xffffff,
+];
+
+// What is shown in one cell of the grid.  The cell after a double width
+// character holds '\0', and is covered by it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    ch: char,
+    fore: i32,
+    back: i32,
+}
+
+// The monospace font, and the glyphs drawn from it so far.
+struct Glyphs {
+    font: Font,
+    cell_width: usize,
+    cell_height: usize,
+    ascent: i32,
+    cache: HashMap<char, (Metrics, Vec<u8>)>,
+}
+
+impl Glyphs {
+    // The system's usual monospace font.
+    fn system_monospace() -> Result<Self, String> {
+        let mut db = fontdb::Database::new();
+        db.load_system_fonts();
+        let query = fontdb::Query {
+            families: &[fontdb::Family::Monospace],
+            ..fontdb::Query::default()
+        };
+        // fontdb's idea of the monospace family is a guess, so fall back on
+        // any upright monospaced face.
+        let id = db
+            .query(&query)
+            .or_else(|| {
+                db.faces()
+                    .find(|face| {
+                        face.monospaced
+                            && face.style == fontdb::Style::Normal
+                            && face.weight == fontdb::Weight::NORMAL
+                    })
+                    .map(|face| face.id)
+            })
+            .ok_or("no monospace font found")?;
+        let font = db
+            .with_face_data(id, |data, index| {
+                let settings = FontSettings {
+                    collection_index: index,
+                    ..FontSettings::default()
+                };
+                Font::from_bytes(data, settings)
+            })
+            .ok_or("monospace font can't be read")??;
+
+        let line = font
+            .horizontal_line_metrics(FONT_SIZE)
+            .ok_or("monospace font has no line metrics")?;
+        let cell_width = font.metrics('M', FONT_SIZE).advance_width.ceil() as usize;
+        Ok(Glyphs {
+            font,
+            cell_width: max(cell_width, 1),
+            cell_height: max(line.new_line_size.ceil() as usize, 1),
+            ascent: line.ascent.ceil() as i32,
+            cache: HashMap::new(),
+        })
+    }
+
+    fn glyph(&mut self, ch: char) -> &(Metrics, Vec<u8>) {
+        let font = &self.font;
+        self.cache
+            .entry(ch)
+            .or_insert_with(|| font.rasterize(ch, FONT_SIZE))
+    }
+}
+
+// Keys typed in the window, as names, from minifb's input callback.  The
+// callback is given both the keys pressed and the text they make, so
+// keys that type text are taken from the text, unless Ctrl or Alt is
+// held, and all others from the key.
+#[derive(Default)]
+struct KeyQueue {
+    events: Arc<Mutex<VecDeque<InputEvent>>>,
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+}
+
+impl InputCallback for KeyQueue {
+    fn add_char(&mut self, uni_char: u32) {
+        if self.ctrl || self.alt {
+            return;
+        }
+        if let Some(ch) = char::from_u32(uni_char)
+            && !ch.is_control()
+        {
+            let key = InputEvent::Key(char_key(ch));
+            self.events.lock().unwrap().push_back(key);
+        }
+    }
+
+    fn set_key_state(&mut self, key: Key, state: bool) {
+        match key {
+            Key::LeftCtrl | Key::RightCtrl => self.ctrl = state,
+            Key::LeftAlt | Key::RightAlt => self.alt = state,
+            Key::LeftShift | Key::RightShift => self.shift = state,
+            _ if state => {
+                if let Some(name) = map_key(key, self.ctrl, self.shift, self.alt) {
+                    self.events.lock().unwrap().push_back(InputEvent::Key(name));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct EmacsWindowGui {
+    window: ThreadBound<Window>,
+    glyphs: Glyphs,
+    // The window's contents, in 0RGB, as big as the window.
+    pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    cols: usize,
+    rows: usize,
+    cursor: (usize, usize),
+    // Set when the grid has changed since the window was last drawn.
+    dirty: bool,
+    events: Arc<Mutex<VecDeque<InputEvent>>>,
+    // Names given to keys from MINT, keyed by the name they'd have otherwise.
+    key_names: HashMap<MintString, MintString>,
+    // Mouse buttons held, and the cell the mouse was last seen over.
+    buttons: [bool; 3],
+    mouse_cell: (usize, usize),
+    stats: RenderStats,
+    // When the screen flash from a visual bell should end.
+    bell_until: Option<Instant>,
+    overwriting: bool,
+    ovy: usize,
+    ovx: usize,
+    fore: i32,
+    back: i32,
+    wsp_fore: i32,
+    show_wsp: bool,
+    eob_glyph: MintString,
+    eol_glyph: MintString,
+    ctrl_fore: i32,
+    mode_fore: i32,
+    mode_back: i32,
+    bot_scroll_percent: MintCount,
+    top_scroll_percent: MintCount,
+    // Lines asked for by set_edit_lines, or 0 for all there is room for.
+    edit_lines: MintCount,
+}
+
+// A value that may only be used, or dropped, on the thread that made it,
+// as windows must be on some systems.  It can be moved to another thread
+// along with the interpreter, but using it there panics, and dropping it
+// there leaks it, so nothing of it is ever touched off its own thread.
+struct ThreadBound<T> {
+    value: ManuallyDrop<T>,
+    thread: ThreadId,
+}
+
+// SAFETY: the value is only reached through deref and drop, and those
+// check that they are on the thread the value was made on.
+unsafe impl<T> Send for ThreadBound<T> {}
+
+impl<T> ThreadBound<T> {
+    fn new(value: T) -> Self {
+        ThreadBound {
+            value: ManuallyDrop::new(value),
+            thread: thread::current().id(),
+        }
+    }
+
+    fn on_thread(&self) -> bool {
+        thread::current().id() == self.thread
+    }
+}
+
+impl<T> Deref for ThreadBound<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        assert!(
+            self.on_thread(),
+            "window used off the thread that opened it"
+        );
+        &self.value
+    }
+}
+
+impl<T> DerefMut for ThreadBound<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        assert!(
+            self.on_thread(),
+            "window used off the thread that opened it"
+        );
+        &mut self.value
+    }
+}
+
+impl<T> Drop for ThreadBound<T> {
+    fn drop(&mut self) {
+        if self.on_thread() {
+            // SAFETY: the value is never used again.
+            unsafe { ManuallyDrop::drop(&mut self.value) };
+        }
+    }
+}
+
+impl EmacsWindowGui {
+    // Open a window "cols" by "lines" cells.  Fails if there is no display
+    // to open it on, or no monospace font to draw with.
+    pub fn new(cols: usize, lines: usize) -> Result<Self, String> {
+        let glyphs = Glyphs::system_monospace()?;
+        let (width, height) = (cols * glyphs.cell_width, lines * glyphs.cell_height);
+        let options = WindowOptions {
+            resize: true,
+            scale_mode: ScaleMode::UpperLeft,
+            ..WindowOptions::default()
+        };
+        let mut window =
+            Window::new("Freemacs", width, height, options).map_err(|e| e.to_string())?;
+        // Input is polled at our own pace, so drawing needn't be held back.
+        window.set_target_fps(0);
+
+        let queue = KeyQueue::default();
+        let events = Arc::clone(&queue.events);
+        window.set_input_callback(Box::new(queue));
+
+        let blank = Cell {
+            ch: ' ',
+            fore: 15,
+            back: 0,
+        };
+        Ok(Self {
+            window: ThreadBound::new(window),
+            glyphs,
+            pixels: vec![0; width * height],
+            width,
+            height,
+            cells: vec![blank; cols * lines],
+            cols,
+            rows: lines,
+            cursor: (0, 0),
+            dirty: true,
+            events,
+            key_names: HashMap::new(),
+            buttons: [false; 3],
+            mouse_cell: (0, 0),
+            stats: RenderStats::default(),
+            bell_until: None,
+            overwriting: false,
+            ovy: 0,
+            ovx: 0,
+            fore: 15,
+            back: 0,
+            wsp_fore: 15,
+            show_wsp: false,
+            eob_glyph: MintString::new(),
+            eol_glyph: MintString::new(),
+            ctrl_fore: 11,
+            mode_fore: 0,
+            mode_back: 7,
+            bot_scroll_percent: 0,
+            top_scroll_percent: 0,
+            edit_lines: 0,
+        })
+    }
+
+    fn line_style(&self) -> LineStyle<'_> {
+        LineStyle {
+            fore: self.fore,
+            back: self.back,
+            wsp_fore: self.wsp_fore,
+            ctrl_fore: self.ctrl_fore,
+            show_wsp: self.show_wsp,
+            eol_glyph: &self.eol_glyph,
+            eob_glyph: &self.eob_glyph,
+        }
+    }
+
+    fn clear(&mut self) {
+        let blank = Cell {
+            ch: ' ',
+            fore: self.fore,
+            back: self.back,
+        };
+        self.cells.fill(blank);
+        self.dirty = true;
+    }
+
+    // Write "text" from row "y", column "x" on, clipped at the right edge.
+    // Returns the column after it.
+    fn put(&mut self, x: usize, y: usize, text: &str, fore: i32, back: i32) -> usize {
+        let mut x = x;
+        if y >= self.rows {
+            return x;
+        }
+        for ch in text.chars() {
+            let width = ch.width().unwrap_or(1);
+            if width == 0 {
+                continue;
+            }
+            if x + width > self.cols {
+                break;
+            }
+            let row = y * self.cols;
+            self.cells[row + x] = Cell { ch, fore, back };
+            if width == 2 {
+                self.cells[row + x + 1] = Cell {
+                    ch: '\0',
+                    fore,
+                    back,
+                };
+            }
+            x += width;
+        }
+        self.dirty = true;
+        x
+    }
+
+    // Write a line at "pos" unless it is already there.
+    fn draw_line(&mut self, pos: (usize, usize), line: DrawnLine) {
+        if pos.0 >= self.cols || pos.1 >= self.rows {
+            return;
+        }
+        let start = pos.1 * self.cols + pos.0;
+        let before = self.cells[start..start + self.cols - pos.0].to_vec();
+        let mut x = pos.0;
+        for (fore, back, text) in &line {
+            x = self.put(x, pos.1, text, *fore, *back);
+        }
+        if self.cells[start..start + x - pos.0] != before[..x - pos.0] {
+            self.stats.lines += 1;
+            self.stats.cells += (x - pos.0) as u64;
+        }
+    }
+
+    // Follow the window's size, reporting a change in the grid.
+    fn check_size(&mut self) {
+        let (width, height) = self.window.get_size();
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+        let cols = max(width / self.glyphs.cell_width, 1);
+        let rows = max(height / self.glyphs.cell_height, 1);
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width * height];
+        if (cols, rows) != (self.cols, self.rows) {
+            self.cols = cols;
+            self.rows = rows;
+            self.cells = vec![
+                Cell {
+                    ch: ' ',
+                    fore: self.fore,
+                    back: self.back
+                };
+                cols * rows
+            ];
+            self.cursor = (min(self.cursor.0, cols - 1), min(self.cursor.1, rows - 1));
+            self.events.lock().unwrap().push_back(InputEvent::Resize);
+        }
+        self.dirty = true;
+    }
+
+    // Queue mouse presses, releases, drags and wheel turns.
+    fn check_mouse(&mut self) {
+        if let Some((_, dy)) = self.window.get_scroll_wheel() {
+            let wheel = InputEvent::Wheel { up: dy > 0.0 };
+            self.events.lock().unwrap().push_back(wheel);
+        }
+        let Some((x, y)) = self.window.get_mouse_pos(MouseMode::Discard) else {
+            return;
+        };
+        let col = min(x as usize / self.glyphs.cell_width, self.cols - 1);
+        let row = min(y as usize / self.glyphs.cell_height, self.rows - 1);
+        let moved = (col, row) != self.mouse_cell;
+        self.mouse_cell = (col, row);
+
+        let buttons = [MouseButton::Left, MouseButton::Middle, MouseButton::Right];
+        for (idx, button) in buttons.into_iter().enumerate() {
+            let down = self.window.get_mouse_down(button);
+            let action = match (self.buttons[idx], down) {
+                (false, true) => MouseAction::Down,
+                (true, false) => MouseAction::Up,
+                (true, true) if moved => MouseAction::Drag,
+                _ => continue,
+            };
+            self.buttons[idx] = down;
+            self.events.lock().unwrap().push_back(InputEvent::Mouse {
+                action,
+                button: idx as u8 + 1,
+                row: row as MintCount,
+                col: col as MintCount,
+            });
+        }
+    }
+
+    // Draw the grid, with the cursor, and show it in the window.  This
+    // also reads the window's events.
+    fn present(&mut self) {
+        if self.bell_until.is_some_and(|until| Instant::now() >= until) {
+            self.bell_until = None;
+            self.dirty = true;
+        }
+        if !self.dirty {
+            self.window.update();
+            return;
+        }
+        self.pixels.fill(PALETTE[(self.back & 0x0f) as usize]);
+        for y in 0..self.rows {
+            for x in 0..self.cols {
+                let cell = self.cells[y * self.cols + x];
+                if cell.ch != '\0' {
+                    let cursor = (x, y) == self.cursor;
+                    self.draw_cell(x, y, cell, cursor);
+                }
+            }
+        }
+        // A visual bell shows the screen in reverse.
+        if self.bell_until.is_some() {
+            for pixel in self.pixels.iter_mut() {
+                *pixel ^= 0xffffff;
+            }
+        }
+        self.window
+            .update_with_buffer(&self.pixels, self.width, self.height)
+            .ok();
+        self.dirty = false;
+    }
+
+    // Draw "cell" at column "x", row "y", in reverse for the cursor.
+    fn draw_cell(&mut self, x: usize, y: usize, cell: Cell, cursor: bool) {
+        let (cell_width, cell_height) = (self.glyphs.cell_width, self.glyphs.cell_height);
+        let (fore, back) = if cursor {
+            (cell.back, cell.fore)
+        } else {
+            (cell.fore, cell.back)
+        };
+        let fore = PALETTE[(fore & 0x0f) as usize];
+        let back = PALETTE[(back & 0x0f) as usize];
+        let (left, top) = (x * cell_width, y * cell_height);
+        let width = cell.ch.width().unwrap_or(1) * cell_width;
+
+        for py in top..min(top + cell_height, self.height) {
+            let row = py * self.width;
+            let right = min(left + width, self.width);
+            self.pixels[row + left..row + right].fill(back);
+        }
+        if cell.ch == ' ' {
+            return;
+        }
+
+        let ascent = self.glyphs.ascent;
+        let (metrics, bitmap) = self.glyphs.glyph(cell.ch);
+        let gx = left as i32 + metrics.xmin;
+        let gy = top as i32 + ascent - metrics.height as i32 - metrics.ymin;
+        for (idx, &coverage) in bitmap.iter().enumerate() {
+            let px = gx + (idx % metrics.width) as i32;
+            let py = gy + (idx / metrics.width) as i32;
+            if coverage == 0 || px < 0 || py < 0 {
+                continue;
+            }
+            let (px, py) = (px as usize, py as usize);
+            if px < self.width && py < self.height {
+                self.pixels[py * self.width + px] = blend(fore, back, coverage);
+            }
+        }
+    }
+}
+
+impl EmacsWindow for EmacsWindowGui {
+    fn backend(&self) -> &'static str {
+        "gui"
+    }
+
+    fn get_columns(&self) -> MintCount {
+        self.cols as MintCount
+    }
+
+    fn get_screen_lines(&self) -> MintCount {
+        self.rows as MintCount
+    }
+
+    fn get_edit_lines(&self) -> MintCount {
+        emacs_window::fit_edit_lines(self.edit_lines, self.get_screen_lines())
+    }
+
+    fn set_edit_lines(&mut self, lines: MintCount) {
+        self.edit_lines = lines;
+        self.clear();
+    }
+
+    fn begin_update(&mut self) {}
+
+    fn end_update(&mut self) {
+        self.present();
+    }
+
+    fn redisplay(&mut self, buf: &mut EmacsBuffer, area: &Rect, force: bool) {
+        self.overwriting = false;
+        self.stats.redisplays += 1;
+
+        let (left, top) = (area.col as usize, area.row as usize);
+        let (cols, edit_rows) = (area.cols as u16, area.rows as usize);
+        if force {
+            self.clear();
+        }
+
+        buf.force_point_in_window(
+            edit_rows as MintCount,
+            cols as MintCount,
+            self.top_scroll_percent,
+            self.bot_scroll_percent,
+        );
+
+        let mut curline = buf.get_mark_position(emacs_buffer::MARK_TOPLINE);
+        let point = buf.get_mark_position(emacs_buffer::MARK_POINT);
+        let screen_line = buf.count_newlines(curline, point) as usize;
+        let screen_col = max(0, buf.get_column() as i32 - buf.get_left_column() as i32) as usize;
+
+        // Once the end of the buffer is reached, lines stop advancing.
+        let mut prev_eol = None;
+        for i in 0..min(edit_rows, self.rows.saturating_sub(top)) {
+            let eol = buf.get_mark_position_from(emacs_buffer::MARK_EOL, curline);
+            let line = if prev_eol == Some(curline) {
+                eob_line(cols, &self.line_style().for_buffer(buf))
+            } else {
+                render_line(buf, curline, eol, cols, &self.line_style().for_buffer(buf))
+            };
+            self.draw_line((left, top + i), line);
+            prev_eol = Some(eol);
+            curline = buf.get_mark_position_from(emacs_buffer::MARK_NEXT_CHAR, eol);
+        }
+
+        self.cursor = (
+            min(left + screen_col, self.cols - 1),
+            min(top + screen_line, self.rows - 1),
+        );
+        self.dirty = true;
+    }
+
+    fn get_render_stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    fn reset_render_stats(&mut self) {
+        self.stats = RenderStats::default();
+    }
+
+    fn overwrite(&mut self, s: &MintString) {
+        if !self.overwriting {
+            self.overwriting = true;
+            self.ovy = 0;
+            self.ovx = 0;
+        }
+        for &ch in s.iter() {
+            if ch == b'\n' {
+                self.ovx = 0;
+                self.ovy = min(self.ovy + 1, self.rows - 1);
+                continue;
+            }
+            let text = (ch as char).to_string();
+            self.ovx = self.put(self.ovx, self.ovy, &text, self.fore, self.back);
+            if self.ovx >= self.cols {
+                self.ovx = 0;
+                self.ovy = min(self.ovy + 1, self.rows - 1);
+            }
+        }
+        self.cursor = (self.ovx, self.ovy);
+    }
+
+    fn gotoxy(&mut self, x: i32, y: i32) {
+        self.overwriting = true;
+        self.ovx = max(0, min(x, self.cols as i32 - 1)) as usize;
+        self.ovy = max(0, min(y, self.rows as i32 - 1)) as usize;
+        self.cursor = (self.ovx, self.ovy);
+        self.dirty = true;
+    }
+
+    fn key_waiting(&self) -> bool {
+        // Events are only read when the window is drawn, which happens
+        // before any wait for a key.
+        !self.events.lock().unwrap().is_empty()
+    }
+
+    fn get_input(&mut self, millisec: MintCount) -> InputEvent {
+        let deadline = Instant::now() + Duration::from_millis(millisec as u64);
+        loop {
+            self.present();
+            if !self.window.is_open() {
+                return InputEvent::Close;
+            }
+            self.check_size();
+            self.check_mouse();
+
+            match self.events.lock().unwrap().pop_front() {
+                Some(InputEvent::Key(key)) => {
+                    return InputEvent::Key(self.key_names.get(&key).cloned().unwrap_or(key));
+                }
+                Some(event) => return event,
+                None => {}
+            }
+            let now = Instant::now();
+            if millisec < 10 || now >= deadline {
+                return InputEvent::Timeout;
+            }
+            thread::sleep(min(POLL_INTERVAL, deadline - now));
+        }
+    }
+
+    fn input_fd(&self) -> Option<i32> {
+        None
+    }
+
+    fn decode_key(&mut self, code: &MintString, name: &MintString) -> bool {
+        // There are no key codes here either, so keys are known by name.
+        self.key_names.insert(code.clone(), name.clone());
+        true
+    }
+
+    fn announce(&mut self, left: &MintString, right: &MintString) {
+        let y = self.rows - 1;
+        let start = y * self.cols;
+        self.cells[start..start + self.cols].fill(Cell {
+            ch: ' ',
+            fore: self.fore,
+            back: self.back,
+        });
+
+        let n = min(left.len(), self.cols - 1);
+        let left: String = left[..n].iter().map(|&ch| ch as char).collect();
+        let x = self.put(0, y, &left, self.fore, self.back);
+        let m = min(right.len(), self.cols.saturating_sub(n + 1));
+        let right: String = right[..m].iter().map(|&ch| ch as char).collect();
+        self.put(x, y, &right, self.fore, self.back);
+        self.cursor = (x, y);
+        self.present();
+    }
+
+    fn mode_line(&mut self, area: &Rect, line: &ModeLine) {
+        if area.rows > 0 {
+            let text = line.render(area.cols as usize);
+            let text = String::from_utf8_lossy(&text);
+            let (row, col) = (area.row as usize, area.col as usize);
+            self.put(col, row, &text, self.mode_fore, self.mode_back);
+        }
+    }
+
+    fn set_clipboard(&mut self, _text: &MintString) {
+        // minifb has no access to the clipboard.
+    }
+
+    fn suspend(&mut self) -> bool {
+        // The window is ours, so there's no terminal to give back.
+        false
+    }
+
+    fn audible_bell(&mut self, freq: MintCount, millisec: MintCount) {
+        // With no speaker to drive, flash instead.
+        if !emacs_window::play_tone(freq, millisec) {
+            self.visual_bell(millisec);
+        }
+    }
+
+    fn visual_bell(&mut self, millisec: MintCount) {
+        self.bell_until = Some(Instant::now() + Duration::from_millis(millisec as u64));
+        self.dirty = true;
+        self.present();
+    }
+
+    fn set_fore_colour(&mut self, colour: i32) {
+        self.fore = colour;
+    }
+
+    fn get_fore_colour(&self) -> i32 {
+        self.fore
+    }
+
+    fn set_back_colour(&mut self, colour: i32) {
+        self.back = colour;
+    }
+
+    fn get_back_colour(&self) -> i32 {
+        self.back
+    }
+
+    fn set_ctrl_fore_colour(&mut self, colour: i32) {
+        self.ctrl_fore = colour;
+    }
+
+    fn get_ctrl_fore_colour(&self) -> i32 {
+        self.ctrl_fore
+    }
+
+    fn set_mode_fore_colour(&mut self, colour: i32) {
+        self.mode_fore = colour;
+    }
+
+    fn get_mode_fore_colour(&self) -> i32 {
+        self.mode_fore
+    }
+
+    fn set_mode_back_colour(&mut self, colour: i32) {
+        self.mode_back = colour;
+    }
+
+    fn get_mode_back_colour(&self) -> i32 {
+        self.mode_back
+    }
+
+    fn set_whitespace_display(&mut self, flag: bool) {
+        self.show_wsp = flag;
+    }
+
+    fn get_whitespace_display(&self) -> bool {
+        self.show_wsp
+    }
+
+    fn set_whitespace_colour(&mut self, colour: i32) {
+        self.wsp_fore = colour;
+    }
+
+    fn get_whitespace_colour(&self) -> i32 {
+        self.wsp_fore
+    }
+
+    fn set_eob_indicator(&mut self, glyph: &MintString) {
+        self.eob_glyph = glyph.clone();
+    }
+
+    fn get_eob_indicator(&self) -> MintString {
+        self.eob_glyph.clone()
+    }
+
+    fn set_eol_indicator(&mut self, glyph: &MintString) {
+        self.eol_glyph = glyph.clone();
+    }
+
+    fn get_eol_indicator(&self) -> MintString {
+        self.eol_glyph.clone()
+    }
+
+    fn get_bot_scroll_percent(&self) -> MintCount {
+        self.bot_scroll_percent
+    }
+
+    fn set_bot_scroll_percent(&mut self, perc: MintCount) {
+        self.bot_scroll_percent = perc;
+    }
+
+    fn get_top_scroll_percent(&self) -> MintCount {
+        self.top_scroll_percent
+    }
+
+    fn set_top_scroll_percent(&mut self, perc: MintCount) {
+        self.top_scroll_percent = perc;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+// "fore" drawn over "back" with "coverage" out of 255.
+fn blend(fore: u32, back: u32, coverage: u8) -> u32 {
+    let coverage = coverage as u32;
+    let channel = |shift: u32| {
+        let f = (fore >> shift) & 0xff;
+        let b = (back >> shift) & 0xff;
+        ((f * coverage + b * (255 - coverage)) / 255) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+// The character "key" types on a US keyboard, if it types one.
+fn key_char(key: Key, shift: bool) -> Option<char> {
+    let code = key as u8;
+    let (plain, shifted) = match key {
+        _ if code <= Key::Key9 as u8 => {
+            let digit = (b'0' + code) as char;
+            (digit, b")!@#$%^&*("[code as usize] as char)
+        }
+        _ if (Key::A as u8..=Key::Z as u8).contains(&code) => {
+            let letter = (b'a' + code - Key::A as u8) as char;
+            (letter, letter.to_ascii_uppercase())
+        }
+        Key::Apostrophe => ('\'', '"'),
+        Key::Backquote => ('`', '~'),
+        Key::Backslash => ('\\', '|'),
+        Key::Comma => (',', '<'),
+        Key::Equal => ('=', '+'),
+        Key::LeftBracket => ('[', '{'),
+        Key::Minus => ('-', '_'),
+        Key::Period => ('.', '>'),
+        Key::RightBracket => (']', '}'),
+        Key::Semicolon => (';', ':'),
+        Key::Slash => ('/', '?'),
+        Key::Space => (' ', ' '),
+        _ => return None,
+    };
+    Some(if shift { shifted } else { plain })
+}
+
+// Name of "key" pressed with the modifiers given.  Keys that type text
+// are only named here when Ctrl or Alt is held.
+fn map_key(key: Key, ctrl: bool, shift: bool, alt: bool) -> Option<MintString> {
+    let name = match key {
+        Key::Backspace => b"Back Space".to_vec(),
+        Key::Tab => b"Tab".to_vec(),
+        Key::Enter => b"Return".to_vec(),
+        Key::NumPadEnter => b"Enter".to_vec(),
+        Key::Escape => b"Escape".to_vec(),
+
+        // Navigation keys, with "C-" and "S-" for Ctrl and Shift
+        Key::Delete => modified_key(ctrl, shift, "Del"),
+        Key::Insert => modified_key(ctrl, shift, "Ins"),
+        Key::Up => modified_key(ctrl, shift, "Up Arrow"),
+        Key::Down => modified_key(ctrl, shift, "Down Arrow"),
+        Key::Left => modified_key(ctrl, shift, "Left Arrow"),
+        Key::Right => modified_key(ctrl, shift, "Right Arrow"),
+        Key::Home => modified_key(ctrl, shift, "Home"),
+        Key::End => modified_key(ctrl, shift, "End"),
+        Key::PageUp => modified_key(ctrl, shift, "Pg Up"),
+        Key::PageDown => modified_key(ctrl, shift, "Pg Dn"),
+
+        _ if (Key::F1 as u8..=Key::F15 as u8).contains(&(key as u8)) => {
+            let n = key as u8 - Key::F1 as u8 + 1;
+            modified_key(ctrl, shift, &format!("F{}", n))
+        }
+
+        _ => match key_char(key, shift) {
+            Some(' ') if ctrl => b"C-@".to_vec(),
+            Some(ch) if ctrl => format!("C-{}", ch.to_ascii_lowercase()).into_bytes(),
+            Some(ch) if alt => char_key(ch),
+            _ => return None,
+        },
+    };
+    Some(if alt { meta_key(&name) } else { name })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    #[test]
+    fn thread_bound_values_stay_on_their_thread() {
+        let shared = Rc::new(());
+        let bound = ThreadBound::new(Rc::clone(&shared));
+        assert_eq!(2, Rc::strong_count(&bound));
+        let moved = thread::spawn(move || {
+            let used = panic::catch_unwind(AssertUnwindSafe(|| Rc::strong_count(&bound)));
+            // Dropped here, which leaks it rather than touch the Rc.
+            used.is_err()
+        });
+        assert!(moved.join().unwrap());
+        assert_eq!(2, Rc::strong_count(&shared));
+        drop(ThreadBound::new(Rc::clone(&shared)));
+        assert_eq!(2, Rc::strong_count(&shared));
+    }
+
+    #[test]
+    fn keys_are_named_as_in_a_terminal() {
+        assert_eq!(Some(b"C-x".to_vec()), map_key(Key::X, true, false, false));
+        assert_eq!(
+            Some(b"M-Comma".to_vec()),
+            map_key(Key::Comma, false, false, true)
+        );
+        assert_eq!(Some(b"M-X".to_vec()), map_key(Key::X, false, true, true));
+        assert_eq!(Some(b"C-@".to_vec()), map_key(Key::Key2, true, true, false));
+        assert_eq!(
+            Some(b"S-Up Arrow".to_vec()),
+            map_key(Key::Up, false, true, false)
+        );
+        assert_eq!(
+            Some(b"F12".to_vec()),
+            map_key(Key::F12, false, false, false)
+        );
+        assert_eq!(
+            Some(b"Back Space".to_vec()),
+            map_key(Key::Backspace, false, false, false)
+        );
+        // Plain text comes from add_char instead.
+        assert_eq!(None, map_key(Key::X, false, false, false));
+        assert_eq!(b"LPar".to_vec(), char_key('('));
+    }
+
+    #[test]
+    fn blend_mixes_by_coverage() {
+        assert_eq!(0xffffff, blend(0xffffff, 0x000000, 255));
+        assert_eq!(0x0000aa, blend(0xffffff, 0x0000aa, 0));
+        assert_eq!(0x7f7f7f, blend(0xffffff, 0x000000, 127));
+    }
+}
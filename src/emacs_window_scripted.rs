@@ -0,0 +1,531 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Headless window for tests: keys come from a script, output goes to a log. */
+
+use std::cmp::min;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::emacs_buffer::{self, EmacsBuffer, utf8_char};
+use crate::emacs_window::{EmacsWindow, InputEvent, ModeLine, RenderStats, fit_cells, meta_key};
+use crate::emacs_window_debug::EmacsWindowDebug;
+use crate::mint_types::{MintChar, MintCount, MintString};
+use crate::window_layout::Rect;
+
+// What the window has been asked to show, one entry per call.  Shared so
+// it can still be read once the window has been handed to init_window.
+pub type ScriptLog = Arc<Mutex<Vec<String>>>;
+
+// The text the window would show, without colours.  Each cell holds what
+// is drawn there: the second cell of a wide character is empty, and
+// combining marks go in with the character they combine with.
+pub struct Screen {
+    rows: Vec<Vec<String>>,
+    cursor: (usize, usize),
+    // Whether the screen is flashed by a visual bell.
+    flashing: bool,
+}
+
+pub type SharedScreen = Arc<Mutex<Screen>>;
+
+impl Screen {
+    fn new(cols: MintCount, lines: MintCount) -> Self {
+        Screen {
+            rows: vec![vec![" ".to_string(); cols as usize]; lines as usize],
+            cursor: (0, 0),
+            flashing: false,
+        }
+    }
+
+    fn clear(&mut self) {
+        for row in self.rows.iter_mut() {
+            row.fill(" ".to_string());
+        }
+    }
+
+    // Put "cells" on "row" from "col", dropping any that don't fit.
+    fn put(&mut self, row: usize, col: usize, cells: Vec<String>) {
+        if let Some(line) = self.rows.get_mut(row) {
+            for (cell, text) in line.iter_mut().skip(col).zip(cells) {
+                *cell = text;
+            }
+        }
+    }
+
+    // Each row as text, with trailing blanks removed.
+    pub fn lines(&self) -> Vec<String> {
+        self.rows
+            .iter()
+            .map(|row| row.concat().trim_end().to_string())
+            .collect()
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    // The screen as text, one line per row, followed by the cursor
+    // position as "cursor ROW COL", and "flashing" while a visual bell is.
+    pub fn snapshot(&self) -> String {
+        let mut text = String::new();
+        for line in self.lines() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text.push_str(&format!("cursor {} {}", self.cursor.0, self.cursor.1));
+        if self.flashing {
+            text.push_str("\nflashing");
+        }
+        text
+    }
+}
+
+// Cells for "text", with wide characters taking two.
+fn text_cells(text: &str) -> Vec<String> {
+    let mut cells: Vec<String> = Vec::new();
+    for ch in text.chars() {
+        match ch.width().unwrap_or(0) {
+            0 => match cells.last_mut() {
+                Some(cell) => cell.push(ch),
+                None => cells.push(ch.to_string()),
+            },
+            width => {
+                cells.push(ch.to_string());
+                cells.extend((1..width).map(|_| String::new()));
+            }
+        }
+    }
+    cells
+}
+
+pub struct EmacsWindowScripted {
+    debug: EmacsWindowDebug,
+    input: VecDeque<InputEvent>,
+    // Names given to keys by #(kd), keyed by the name they'd have otherwise.
+    key_names: HashMap<MintString, MintString>,
+    log: ScriptLog,
+    screen: SharedScreen,
+    overwriting: bool,
+    stats: RenderStats,
+    // How much longer the visual bell flashes, in milliseconds.
+    bell_left: MintCount,
+}
+
+fn to_s(s: &[MintChar]) -> String {
+    String::from_utf8_lossy(s).to_string()
+}
+
+impl EmacsWindowScripted {
+    pub fn new(cols: MintCount, lines: MintCount) -> Self {
+        // Scroll like the terminal windows do until told otherwise.
+        let mut debug = EmacsWindowDebug::new(cols, lines);
+        debug.set_bot_scroll_percent(0);
+        debug.set_top_scroll_percent(0);
+        EmacsWindowScripted {
+            debug,
+            input: VecDeque::new(),
+            key_names: HashMap::new(),
+            log: Arc::new(Mutex::new(Vec::new())),
+            screen: Arc::new(Mutex::new(Screen::new(cols, lines))),
+            overwriting: false,
+            stats: RenderStats::default(),
+            bell_left: 0,
+        }
+    }
+
+    // Queue keys for get_input to return, in order.  Once they run out
+    // every read times out.
+    pub fn with_keys(mut self, keys: &[&str]) -> Self {
+        self.input.extend(
+            keys.iter()
+                .map(|key| InputEvent::Key(key.as_bytes().to_vec())),
+        );
+        self
+    }
+
+    // Queue input events for get_input to return, as with_keys.
+    pub fn with_events(mut self, events: Vec<InputEvent>) -> Self {
+        self.input.extend(events);
+        self
+    }
+
+    pub fn log(&self) -> ScriptLog {
+        self.log.clone()
+    }
+
+    pub fn screen(&self) -> SharedScreen {
+        self.screen.clone()
+    }
+
+    fn record(&self, entry: String) {
+        self.log.lock().unwrap().push(entry);
+    }
+
+    // Cells for a line of "buf" as it appears in a pane "cols" wide.
+    fn render_line(
+        &self,
+        buf: &EmacsBuffer,
+        bol: MintCount,
+        eol: MintCount,
+        cols: usize,
+    ) -> Vec<String> {
+        let leftcol = buf.get_left_column() as usize;
+        let show_wsp = buf
+            .display()
+            .show_wsp
+            .unwrap_or_else(|| self.debug.get_whitespace_display());
+        let line = buf.read(bol, eol);
+        let trailing = line
+            .iter()
+            .rposition(|&ch| ch != b' ' && ch != b'\t')
+            .map_or(0, |n| n + 1);
+
+        let mut cells: Vec<String> = Vec::new();
+        let mut idx = 0;
+        while idx < line.len() && cells.len() < leftcol + cols {
+            let (width, len) = buf.display_width(cells.len() as MintCount, &line[idx..]);
+            let ch = line[idx];
+            if ch == b'\t' || ch == b' ' {
+                let blank = if show_wsp && idx >= trailing {
+                    "·"
+                } else {
+                    " "
+                };
+                cells.extend((0..width).map(|_| blank.to_string()));
+            } else if buf.is_utf8()
+                && let Some((uch, _)) = utf8_char(&line[idx..])
+            {
+                cells.extend(text_cells(&uch.to_string()));
+            } else if let Some(repr) = buf.display_repr(ch) {
+                cells.extend(repr.chars().map(String::from));
+            } else {
+                cells.push((ch as char).to_string());
+            }
+            idx += len;
+        }
+        if idx == line.len() && cells.len() < leftcol + cols {
+            let room = leftcol + cols - cells.len();
+            let (glyph, _) = fit_cells(&self.debug.get_eol_indicator(), room);
+            cells.extend(text_cells(&glyph));
+        }
+
+        let mut shown: Vec<String> = cells.into_iter().skip(leftcol).take(cols).collect();
+        if shown.first().is_some_and(|cell| cell.is_empty()) {
+            shown[0] = " ".to_string();
+        }
+        shown.resize(cols, " ".to_string());
+        shown
+    }
+}
+
+impl EmacsWindow for EmacsWindowScripted {
+    fn backend(&self) -> &'static str {
+        "scripted"
+    }
+
+    fn get_columns(&self) -> MintCount {
+        self.debug.get_columns()
+    }
+
+    fn get_screen_lines(&self) -> MintCount {
+        self.debug.get_screen_lines()
+    }
+
+    fn get_edit_lines(&self) -> MintCount {
+        self.debug.get_edit_lines()
+    }
+
+    fn set_edit_lines(&mut self, lines: MintCount) {
+        self.debug.set_edit_lines(lines);
+        self.screen.lock().unwrap().clear();
+    }
+
+    fn begin_update(&mut self) {
+        self.record("begin update".to_string());
+    }
+
+    fn end_update(&mut self) {
+        self.record("end update".to_string());
+    }
+
+    fn redisplay(&mut self, buf: &mut EmacsBuffer, area: &Rect, force: bool) {
+        self.stats.redisplays += 1;
+        self.overwriting = false;
+        self.record(format!(
+            "redisplay {},{} {}x{} force={}",
+            area.row, area.col, area.cols, area.rows, force
+        ));
+
+        let mut screen = self.screen.lock().unwrap();
+        if force {
+            screen.clear();
+        }
+
+        buf.force_point_in_window(
+            area.rows,
+            area.cols,
+            self.debug.get_top_scroll_percent(),
+            self.debug.get_bot_scroll_percent(),
+        );
+        let mut curline = buf.get_mark_position(emacs_buffer::MARK_TOPLINE);
+        let point = buf.get_mark_position(emacs_buffer::MARK_POINT);
+        let screen_line = buf.count_newlines(curline, point);
+        let screen_col = buf.get_column().saturating_sub(buf.get_left_column());
+
+        let (row, col, cols) = (area.row as usize, area.col as usize, area.cols as usize);
+        let mut prev_eol = None;
+        for i in 0..area.rows as usize {
+            let eol = buf.get_mark_position_from(emacs_buffer::MARK_EOL, curline);
+            let cells = if prev_eol == Some(curline) {
+                let (glyph, _) = fit_cells(&self.debug.get_eob_indicator(), cols);
+                let mut cells = text_cells(&glyph);
+                cells.resize(cols, " ".to_string());
+                cells
+            } else {
+                self.render_line(buf, curline, eol, cols)
+            };
+            screen.put(row + i, col, cells);
+            prev_eol = Some(eol);
+            curline = buf.get_mark_position_from(emacs_buffer::MARK_NEXT_CHAR, eol);
+        }
+        screen.cursor = (row + screen_line as usize, col + screen_col as usize);
+    }
+
+    fn get_render_stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    fn reset_render_stats(&mut self) {
+        self.stats = RenderStats::default();
+    }
+
+    fn overwrite(&mut self, s: &MintString) {
+        self.record(format!("overwrite {}", to_s(s)));
+
+        let (cols, rows) = (
+            self.get_columns() as usize,
+            self.get_screen_lines() as usize,
+        );
+        let mut screen = self.screen.lock().unwrap();
+        if !self.overwriting {
+            self.overwriting = true;
+            screen.cursor = (0, 0);
+        }
+        for &ch in s.iter() {
+            let (row, col) = screen.cursor;
+            if ch == b'\n' {
+                screen.cursor = (min(row + 1, rows - 1), 0);
+                continue;
+            }
+            screen.put(row, col, vec![(ch as char).to_string()]);
+            screen.cursor = if col + 1 >= cols {
+                (min(row + 1, rows - 1), 0)
+            } else {
+                (row, col + 1)
+            };
+        }
+    }
+
+    fn gotoxy(&mut self, x: i32, y: i32) {
+        self.record(format!("gotoxy {} {}", x, y));
+
+        let (cols, rows) = (self.get_columns() as i32, self.get_screen_lines() as i32);
+        self.overwriting = true;
+        self.screen.lock().unwrap().cursor =
+            (y.clamp(0, rows - 1) as usize, x.clamp(0, cols - 1) as usize);
+    }
+
+    fn key_waiting(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    fn get_input(&mut self, millisec: MintCount) -> InputEvent {
+        // Queued input is there at once.  Otherwise the whole wait passes,
+        // and a visual bell ends if it has lasted long enough.
+        let Some(event) = self.input.pop_front() else {
+            self.bell_left = self.bell_left.saturating_sub(millisec);
+            self.screen.lock().unwrap().flashing = self.bell_left > 0;
+            return InputEvent::Timeout;
+        };
+        // Queued keys all arrive at once, so an Escape with a key after it
+        // is joined to it, as a terminal's would be.
+        let event = match (event, self.input.front()) {
+            (InputEvent::Key(key), Some(InputEvent::Key(next))) if key == b"Escape" => {
+                let name = meta_key(next);
+                self.input.pop_front();
+                InputEvent::Key(name)
+            }
+            (event, _) => event,
+        };
+        match event {
+            InputEvent::Key(key) => {
+                InputEvent::Key(self.key_names.get(&key).cloned().unwrap_or(key))
+            }
+            event => event,
+        }
+    }
+
+    fn input_fd(&self) -> Option<i32> {
+        None
+    }
+
+    fn decode_key(&mut self, code: &MintString, name: &MintString) -> bool {
+        self.key_names.insert(code.clone(), name.clone());
+        true
+    }
+
+    fn announce(&mut self, left: &MintString, right: &MintString) {
+        self.record(format!("announce {}|{}", to_s(left), to_s(right)));
+
+        let (cols, rows) = (
+            self.get_columns() as usize,
+            self.get_screen_lines() as usize,
+        );
+        let n = min(left.len(), cols - 1);
+        let m = min(right.len(), cols.saturating_sub(n + 1));
+        let mut cells: Vec<String> = left[..n]
+            .iter()
+            .chain(&right[..m])
+            .map(|&ch| (ch as char).to_string())
+            .collect();
+        cells.resize(cols, " ".to_string());
+
+        let mut screen = self.screen.lock().unwrap();
+        screen.put(rows - 1, 0, cells);
+        screen.cursor = (rows - 1, n);
+    }
+
+    fn mode_line(&mut self, area: &Rect, line: &ModeLine) {
+        let text = line.render(area.cols as usize);
+        self.record(format!("mode {} {}", area.row, to_s(&text)));
+
+        let cells = text_cells(&to_s(&text));
+        let mut screen = self.screen.lock().unwrap();
+        screen.put(area.row as usize, area.col as usize, cells);
+    }
+
+    fn set_clipboard(&mut self, text: &MintString) {
+        self.record(format!("clipboard {}", to_s(text)));
+    }
+
+    fn suspend(&mut self) -> bool {
+        self.record("suspend".to_string());
+        true
+    }
+
+    fn audible_bell(&mut self, freq: MintCount, millisec: MintCount) {
+        self.record(format!("bell {} {}", freq, millisec));
+    }
+
+    fn visual_bell(&mut self, millisec: MintCount) {
+        self.record(format!("flash {}", millisec));
+        self.bell_left = millisec;
+        self.screen.lock().unwrap().flashing = millisec > 0;
+    }
+
+    fn set_fore_colour(&mut self, colour: i32) {
+        self.debug.set_fore_colour(colour);
+    }
+
+    fn get_fore_colour(&self) -> i32 {
+        self.debug.get_fore_colour()
+    }
+
+    fn set_back_colour(&mut self, colour: i32) {
+        self.debug.set_back_colour(colour);
+    }
+
+    fn get_back_colour(&self) -> i32 {
+        self.debug.get_back_colour()
+    }
+
+    fn set_ctrl_fore_colour(&mut self, colour: i32) {
+        self.debug.set_ctrl_fore_colour(colour);
+    }
+
+    fn get_ctrl_fore_colour(&self) -> i32 {
+        self.debug.get_ctrl_fore_colour()
+    }
+
+    fn set_mode_fore_colour(&mut self, colour: i32) {
+        self.debug.set_mode_fore_colour(colour);
+    }
+
+    fn get_mode_fore_colour(&self) -> i32 {
+        self.debug.get_mode_fore_colour()
+    }
+
+    fn set_mode_back_colour(&mut self, colour: i32) {
+        self.debug.set_mode_back_colour(colour);
+    }
+
+    fn get_mode_back_colour(&self) -> i32 {
+        self.debug.get_mode_back_colour()
+    }
+
+    fn set_whitespace_display(&mut self, flag: bool) {
+        self.debug.set_whitespace_display(flag);
+    }
+
+    fn get_whitespace_display(&self) -> bool {
+        self.debug.get_whitespace_display()
+    }
+
+    fn set_whitespace_colour(&mut self, colour: i32) {
+        self.debug.set_whitespace_colour(colour);
+    }
+
+    fn get_whitespace_colour(&self) -> i32 {
+        self.debug.get_whitespace_colour()
+    }
+
+    fn set_eob_indicator(&mut self, glyph: &MintString) {
+        self.debug.set_eob_indicator(glyph);
+    }
+
+    fn get_eob_indicator(&self) -> MintString {
+        self.debug.get_eob_indicator()
+    }
+
+    fn set_eol_indicator(&mut self, glyph: &MintString) {
+        self.debug.set_eol_indicator(glyph);
+    }
+
+    fn get_eol_indicator(&self) -> MintString {
+        self.debug.get_eol_indicator()
+    }
+
+    fn get_bot_scroll_percent(&self) -> MintCount {
+        self.debug.get_bot_scroll_percent()
+    }
+
+    fn set_bot_scroll_percent(&mut self, perc: MintCount) {
+        self.debug.set_bot_scroll_percent(perc);
+    }
+
+    fn get_top_scroll_percent(&self) -> MintCount {
+        self.debug.get_top_scroll_percent()
+    }
+
+    fn set_top_scroll_percent(&mut self, perc: MintCount) {
+        self.debug.set_top_scroll_percent(perc);
+    }
+}
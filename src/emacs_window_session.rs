@@ -0,0 +1,737 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Session recording and playback, wrapping another window. */
+
+// A session log is in the style of an asciinema cast: a header line, then
+// one line per event of the form
+//
+//     [seconds, "code", "data"]
+//
+// Input is recorded as "i" with each key returned by get_input, "e" with
+// any other input event, such as "resize" or "mouse down 1 4 10", and "w"
+// with the answer to key_waiting, as all of them steer what the editor
+// does next.  Everything sent to the screen is recorded too, so
+// a session can be compared with its replay, but only input is played
+// back: feeding the same keys to the same macros repeats the session.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::emacs_buffer::EmacsBuffer;
+use crate::emacs_window::{EmacsWindow, InputEvent, ModeLine, MouseAction, RenderStats};
+use crate::mint_types::{MintCount, MintString};
+use crate::window_layout::Rect;
+
+const KEY: &str = "i";
+const KEY_WAITING: &str = "w";
+const EVENT: &str = "e";
+
+// Quote "data" as a JSON string.  Bytes that aren't UTF-8 are written
+// as "\xNN", which JSON doesn't have, so that they are played back as
+// they were recorded.
+fn quote(data: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for chunk in data.utf8_chunks() {
+        for ch in chunk.valid().chars() {
+            match ch {
+                '"' => s.push_str("\\\""),
+                '\\' => s.push_str("\\\\"),
+                '\n' => s.push_str("\\n"),
+                '\r' => s.push_str("\\r"),
+                '\t' => s.push_str("\\t"),
+                ch if ch.is_control() => s.push_str(&format!("\\u{:04x}", ch as u32)),
+                ch => s.push(ch),
+            }
+        }
+        for byte in chunk.invalid() {
+            s.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    s.push('"');
+    s
+}
+
+// Read back a string written by quote, returning it and the rest of "s".
+fn unquote(s: &str) -> Option<(MintString, &str)> {
+    let mut chars = s.strip_prefix('"')?.char_indices();
+    let mut text = MintString::new();
+    let push = |text: &mut MintString, ch: char| {
+        text.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes())
+    };
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '"' => return Some((text, &s[i + 2..])),
+            '\\' => match chars.next()?.1 {
+                'n' => push(&mut text, '\n'),
+                'r' => push(&mut text, '\r'),
+                't' => push(&mut text, '\t'),
+                'u' => {
+                    let hex: String = (0..4).filter_map(|_| chars.next()).map(|c| c.1).collect();
+                    push(
+                        &mut text,
+                        char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?,
+                    );
+                }
+                'x' => {
+                    let hex: String = (0..2).filter_map(|_| chars.next()).map(|c| c.1).collect();
+                    text.push(u8::from_str_radix(&hex, 16).ok()?);
+                }
+                ch => push(&mut text, ch),
+            },
+            ch => push(&mut text, ch),
+        }
+    }
+    None
+}
+
+fn format_event(time: Duration, code: &str, data: &[u8]) -> String {
+    format!("[{:.6}, \"{}\", {}]", time.as_secs_f64(), code, quote(data))
+}
+
+// Split an event line into its time, code and data.
+fn parse_event(line: &str) -> Option<(Duration, String, MintString)> {
+    let (time, rest) = line.strip_prefix('[')?.split_once(',')?;
+    let time = Duration::from_secs_f64(time.trim().parse().ok()?);
+    let (code, rest) = unquote(rest.trim_start())?;
+    let (data, _) = unquote(rest.trim_start().strip_prefix(',')?.trim_start())?;
+    Some((time, String::from_utf8(code).ok()?, data))
+}
+
+// The code and data an input event is recorded as.
+fn format_input(event: &InputEvent) -> (&'static str, MintString) {
+    let text = match event {
+        InputEvent::Key(key) => return (KEY, key.clone()),
+        InputEvent::Resize => "resize".to_string(),
+        InputEvent::Mouse {
+            action,
+            button,
+            row,
+            col,
+        } => {
+            let action = match action {
+                MouseAction::Down => "down",
+                MouseAction::Up => "up",
+                MouseAction::Drag => "drag",
+            };
+            format!("mouse {} {} {} {}", action, button, row, col)
+        }
+        InputEvent::Wheel { up } => format!("wheel {}", if *up { "up" } else { "down" }),
+        InputEvent::Paste(text) => {
+            let mut data = b"paste ".to_vec();
+            data.extend_from_slice(text);
+            return (EVENT, data);
+        }
+        InputEvent::Focus(gained) => format!("focus {}", if *gained { "in" } else { "out" }),
+        InputEvent::Close => "close".to_string(),
+        InputEvent::Timeout => "timeout".to_string(),
+        InputEvent::Unknown => "unknown".to_string(),
+    };
+    (EVENT, text.into_bytes())
+}
+
+// The input event recorded as "code" and "data".
+fn parse_input(code: &str, data: &[u8]) -> InputEvent {
+    if code == KEY {
+        return InputEvent::Key(data.to_vec());
+    }
+    if let Some(text) = data.strip_prefix(b"paste ") {
+        return InputEvent::Paste(text.to_vec());
+    }
+    let text = String::from_utf8_lossy(data);
+    let words: Vec<&str> = text.split_whitespace().collect();
+    match words[..] {
+        ["resize"] => InputEvent::Resize,
+        ["mouse", action, button, row, col] => {
+            let action = match action {
+                "down" => MouseAction::Down,
+                "up" => MouseAction::Up,
+                "drag" => MouseAction::Drag,
+                _ => return InputEvent::Unknown,
+            };
+            match (button.parse(), row.parse(), col.parse()) {
+                (Ok(button), Ok(row), Ok(col)) => InputEvent::Mouse {
+                    action,
+                    button,
+                    row,
+                    col,
+                },
+                _ => InputEvent::Unknown,
+            }
+        }
+        ["wheel", dir] => InputEvent::Wheel { up: dir == "up" },
+        ["focus", dir] => InputEvent::Focus(dir == "in"),
+        ["close"] => InputEvent::Close,
+        ["timeout"] => InputEvent::Timeout,
+        _ => InputEvent::Unknown,
+    }
+}
+
+// Records a session to "log" while passing everything on to "window".
+pub struct EmacsWindowRecorder {
+    window: Box<dyn EmacsWindow>,
+    log: RefCell<Box<dyn Write + Send>>,
+    start: Instant,
+}
+
+impl EmacsWindowRecorder {
+    pub fn new(window: Box<dyn EmacsWindow>, mut log: Box<dyn Write + Send>) -> Self {
+        let _ = writeln!(
+            log,
+            "{{\"version\": 2, \"width\": {}, \"height\": {}}}",
+            window.get_columns(),
+            window.get_screen_lines()
+        );
+        EmacsWindowRecorder {
+            window,
+            log: RefCell::new(log),
+            start: Instant::now(),
+        }
+    }
+
+    fn record(&self, code: &str, data: &[u8]) {
+        let line = format_event(self.start.elapsed(), code, data);
+        let mut log = self.log.borrow_mut();
+        let _ = writeln!(log, "{}", line);
+        // Input is flushed as it comes, so a session that ends in a crash
+        // can still be replayed up to it.
+        if code == KEY || code == KEY_WAITING || code == EVENT {
+            let _ = log.flush();
+        }
+    }
+}
+
+impl EmacsWindow for EmacsWindowRecorder {
+    fn backend(&self) -> &'static str {
+        self.window.backend()
+    }
+
+    fn get_columns(&self) -> MintCount {
+        self.window.get_columns()
+    }
+
+    fn get_screen_lines(&self) -> MintCount {
+        self.window.get_screen_lines()
+    }
+
+    fn get_edit_lines(&self) -> MintCount {
+        self.window.get_edit_lines()
+    }
+
+    fn set_edit_lines(&mut self, lines: MintCount) {
+        self.window.set_edit_lines(lines);
+    }
+
+    fn begin_update(&mut self) {
+        self.window.begin_update();
+    }
+
+    fn end_update(&mut self) {
+        self.window.end_update();
+    }
+
+    fn redisplay(&mut self, buf: &mut EmacsBuffer, area: &Rect, force: bool) {
+        let data = format!(
+            "{} {} {} {} {} {} {}",
+            area.row,
+            area.col,
+            area.rows,
+            area.cols,
+            buf.get_point_line(),
+            buf.get_column(),
+            force
+        );
+        self.record("d", data.as_bytes());
+        self.window.redisplay(buf, area, force);
+    }
+
+    fn get_render_stats(&self) -> RenderStats {
+        self.window.get_render_stats()
+    }
+
+    fn reset_render_stats(&mut self) {
+        self.window.reset_render_stats();
+    }
+
+    fn overwrite(&mut self, s: &MintString) {
+        self.record("o", s);
+        self.window.overwrite(s);
+    }
+
+    fn gotoxy(&mut self, x: i32, y: i32) {
+        self.record("g", format!("{} {}", x, y).as_bytes());
+        self.window.gotoxy(x, y);
+    }
+
+    fn key_waiting(&self) -> bool {
+        let waiting = self.window.key_waiting();
+        self.record(KEY_WAITING, if waiting { b"1" } else { b"0" });
+        waiting
+    }
+
+    fn get_input(&mut self, millisec: MintCount) -> InputEvent {
+        let event = self.window.get_input(millisec);
+        let (code, data) = format_input(&event);
+        self.record(code, &data);
+        event
+    }
+
+    // Every wait is left to get_input, so each key the macros read is
+    // recorded once, just as playback hands them back.
+    fn input_fd(&self) -> Option<i32> {
+        None
+    }
+
+    fn decode_key(&mut self, code: &MintString, name: &MintString) -> bool {
+        self.window.decode_key(code, name)
+    }
+
+    fn announce(&mut self, left: &MintString, right: &MintString) {
+        let mut data = left.clone();
+        data.push(b'\n');
+        data.extend_from_slice(right);
+        self.record("a", &data);
+        self.window.announce(left, right);
+    }
+
+    fn mode_line(&mut self, area: &Rect, line: &ModeLine) {
+        self.record("m", &line.render(area.cols as usize));
+        self.window.mode_line(area, line);
+    }
+
+    fn set_clipboard(&mut self, text: &MintString) {
+        self.record("c", text);
+        self.window.set_clipboard(text);
+    }
+
+    fn suspend(&mut self) -> bool {
+        self.window.suspend()
+    }
+
+    fn original_screen(&self) -> MintString {
+        self.window.original_screen()
+    }
+
+    fn audible_bell(&mut self, freq: MintCount, millisec: MintCount) {
+        self.record("b", format!("{} {}", freq, millisec).as_bytes());
+        self.window.audible_bell(freq, millisec);
+    }
+
+    fn visual_bell(&mut self, millisec: MintCount) {
+        self.record("f", millisec.to_string().as_bytes());
+        self.window.visual_bell(millisec);
+    }
+
+    fn set_fore_colour(&mut self, colour: i32) {
+        self.window.set_fore_colour(colour);
+    }
+
+    fn get_fore_colour(&self) -> i32 {
+        self.window.get_fore_colour()
+    }
+
+    fn set_back_colour(&mut self, colour: i32) {
+        self.window.set_back_colour(colour);
+    }
+
+    fn get_back_colour(&self) -> i32 {
+        self.window.get_back_colour()
+    }
+
+    fn set_ctrl_fore_colour(&mut self, colour: i32) {
+        self.window.set_ctrl_fore_colour(colour);
+    }
+
+    fn get_ctrl_fore_colour(&self) -> i32 {
+        self.window.get_ctrl_fore_colour()
+    }
+
+    fn set_mode_fore_colour(&mut self, colour: i32) {
+        self.window.set_mode_fore_colour(colour);
+    }
+
+    fn get_mode_fore_colour(&self) -> i32 {
+        self.window.get_mode_fore_colour()
+    }
+
+    fn set_mode_back_colour(&mut self, colour: i32) {
+        self.window.set_mode_back_colour(colour);
+    }
+
+    fn get_mode_back_colour(&self) -> i32 {
+        self.window.get_mode_back_colour()
+    }
+
+    fn set_whitespace_display(&mut self, flag: bool) {
+        self.window.set_whitespace_display(flag);
+    }
+
+    fn get_whitespace_display(&self) -> bool {
+        self.window.get_whitespace_display()
+    }
+
+    fn set_whitespace_colour(&mut self, colour: i32) {
+        self.window.set_whitespace_colour(colour);
+    }
+
+    fn get_whitespace_colour(&self) -> i32 {
+        self.window.get_whitespace_colour()
+    }
+
+    fn set_eob_indicator(&mut self, glyph: &MintString) {
+        self.window.set_eob_indicator(glyph);
+    }
+
+    fn get_eob_indicator(&self) -> MintString {
+        self.window.get_eob_indicator()
+    }
+
+    fn set_eol_indicator(&mut self, glyph: &MintString) {
+        self.window.set_eol_indicator(glyph);
+    }
+
+    fn get_eol_indicator(&self) -> MintString {
+        self.window.get_eol_indicator()
+    }
+
+    fn get_bot_scroll_percent(&self) -> MintCount {
+        self.window.get_bot_scroll_percent()
+    }
+
+    fn set_bot_scroll_percent(&mut self, perc: MintCount) {
+        self.window.set_bot_scroll_percent(perc);
+    }
+
+    fn get_top_scroll_percent(&self) -> MintCount {
+        self.window.get_top_scroll_percent()
+    }
+
+    fn set_top_scroll_percent(&mut self, perc: MintCount) {
+        self.window.set_top_scroll_percent(perc);
+    }
+}
+
+// Plays back the input from a recorded session, at the pace it was
+// recorded, with output going to "window".  Once the recording runs out,
+// or no longer matches what the editor asks for, input comes from
+// "window" as usual.
+pub struct EmacsWindowPlayback {
+    window: Box<dyn EmacsWindow>,
+    input: RefCell<VecDeque<(Duration, String, MintString)>>,
+    start: Instant,
+}
+
+impl EmacsWindowPlayback {
+    pub fn new(window: Box<dyn EmacsWindow>, log: &str) -> Self {
+        let input = log
+            .lines()
+            .filter_map(parse_event)
+            .filter(|(_, code, _)| code == KEY || code == KEY_WAITING || code == EVENT)
+            .collect();
+        EmacsWindowPlayback {
+            window,
+            input: RefCell::new(input),
+            start: Instant::now(),
+        }
+    }
+
+    // The code and data of the next input if it is one of "codes",
+    // waiting until it is due.
+    fn next_input(&self, codes: &[&str]) -> Option<(String, MintString)> {
+        let mut input = self.input.borrow_mut();
+        match input.front() {
+            Some((_, next, _)) if codes.contains(&next.as_str()) => {}
+            _ => {
+                input.clear();
+                return None;
+            }
+        }
+        let (time, code, data) = input.pop_front()?;
+        if let Some(wait) = time.checked_sub(self.start.elapsed()) {
+            thread::sleep(wait);
+        }
+        Some((code, data))
+    }
+}
+
+impl EmacsWindow for EmacsWindowPlayback {
+    fn backend(&self) -> &'static str {
+        self.window.backend()
+    }
+
+    fn get_columns(&self) -> MintCount {
+        self.window.get_columns()
+    }
+
+    fn get_screen_lines(&self) -> MintCount {
+        self.window.get_screen_lines()
+    }
+
+    fn get_edit_lines(&self) -> MintCount {
+        self.window.get_edit_lines()
+    }
+
+    fn set_edit_lines(&mut self, lines: MintCount) {
+        self.window.set_edit_lines(lines);
+    }
+
+    fn begin_update(&mut self) {
+        self.window.begin_update();
+    }
+
+    fn end_update(&mut self) {
+        self.window.end_update();
+    }
+
+    fn redisplay(&mut self, buf: &mut EmacsBuffer, area: &Rect, force: bool) {
+        self.window.redisplay(buf, area, force);
+    }
+
+    fn get_render_stats(&self) -> RenderStats {
+        self.window.get_render_stats()
+    }
+
+    fn reset_render_stats(&mut self) {
+        self.window.reset_render_stats();
+    }
+
+    fn overwrite(&mut self, s: &MintString) {
+        self.window.overwrite(s);
+    }
+
+    fn gotoxy(&mut self, x: i32, y: i32) {
+        self.window.gotoxy(x, y);
+    }
+
+    fn key_waiting(&self) -> bool {
+        match self.next_input(&[KEY_WAITING]) {
+            Some((_, waiting)) => waiting == b"1",
+            None => self.window.key_waiting(),
+        }
+    }
+
+    fn get_input(&mut self, millisec: MintCount) -> InputEvent {
+        match self.next_input(&[KEY, EVENT]) {
+            Some((code, data)) => parse_input(&code, &data),
+            None => self.window.get_input(millisec),
+        }
+    }
+
+    fn input_fd(&self) -> Option<i32> {
+        None
+    }
+
+    fn decode_key(&mut self, code: &MintString, name: &MintString) -> bool {
+        self.window.decode_key(code, name)
+    }
+
+    fn announce(&mut self, left: &MintString, right: &MintString) {
+        self.window.announce(left, right);
+    }
+
+    fn mode_line(&mut self, area: &Rect, line: &ModeLine) {
+        self.window.mode_line(area, line);
+    }
+
+    fn set_clipboard(&mut self, text: &MintString) {
+        self.window.set_clipboard(text);
+    }
+
+    fn suspend(&mut self) -> bool {
+        self.window.suspend()
+    }
+
+    fn original_screen(&self) -> MintString {
+        self.window.original_screen()
+    }
+
+    fn audible_bell(&mut self, freq: MintCount, millisec: MintCount) {
+        self.window.audible_bell(freq, millisec);
+    }
+
+    fn visual_bell(&mut self, millisec: MintCount) {
+        self.window.visual_bell(millisec);
+    }
+
+    fn set_fore_colour(&mut self, colour: i32) {
+        self.window.set_fore_colour(colour);
+    }
+
+    fn get_fore_colour(&self) -> i32 {
+        self.window.get_fore_colour()
+    }
+
+    fn set_back_colour(&mut self, colour: i32) {
+        self.window.set_back_colour(colour);
+    }
+
+    fn get_back_colour(&self) -> i32 {
+        self.window.get_back_colour()
+    }
+
+    fn set_ctrl_fore_colour(&mut self, colour: i32) {
+        self.window.set_ctrl_fore_colour(colour);
+    }
+
+    fn get_ctrl_fore_colour(&self) -> i32 {
+        self.window.get_ctrl_fore_colour()
+    }
+
+    fn set_mode_fore_colour(&mut self, colour: i32) {
+        self.window.set_mode_fore_colour(colour);
+    }
+
+    fn get_mode_fore_colour(&self) -> i32 {
+        self.window.get_mode_fore_colour()
+    }
+
+    fn set_mode_back_colour(&mut self, colour: i32) {
+        self.window.set_mode_back_colour(colour);
+    }
+
+    fn get_mode_back_colour(&self) -> i32 {
+        self.window.get_mode_back_colour()
+    }
+
+    fn set_whitespace_display(&mut self, flag: bool) {
+        self.window.set_whitespace_display(flag);
+    }
+
+    fn get_whitespace_display(&self) -> bool {
+        self.window.get_whitespace_display()
+    }
+
+    fn set_whitespace_colour(&mut self, colour: i32) {
+        self.window.set_whitespace_colour(colour);
+    }
+
+    fn get_whitespace_colour(&self) -> i32 {
+        self.window.get_whitespace_colour()
+    }
+
+    fn set_eob_indicator(&mut self, glyph: &MintString) {
+        self.window.set_eob_indicator(glyph);
+    }
+
+    fn get_eob_indicator(&self) -> MintString {
+        self.window.get_eob_indicator()
+    }
+
+    fn set_eol_indicator(&mut self, glyph: &MintString) {
+        self.window.set_eol_indicator(glyph);
+    }
+
+    fn get_eol_indicator(&self) -> MintString {
+        self.window.get_eol_indicator()
+    }
+
+    fn get_bot_scroll_percent(&self) -> MintCount {
+        self.window.get_bot_scroll_percent()
+    }
+
+    fn set_bot_scroll_percent(&mut self, perc: MintCount) {
+        self.window.set_bot_scroll_percent(perc);
+    }
+
+    fn get_top_scroll_percent(&self) -> MintCount {
+        self.window.get_top_scroll_percent()
+    }
+
+    fn set_top_scroll_percent(&mut self, perc: MintCount) {
+        self.window.set_top_scroll_percent(perc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emacs_window_scripted::EmacsWindowScripted;
+
+    #[test]
+    fn event_round_trip() {
+        let line = format_event(Duration::from_millis(1500), KEY, b"a \"b\"\\\n\x01");
+        assert_eq!(r#"[1.500000, "i", "a \"b\"\\\n\u0001"]"#, line);
+        let (time, code, data) = parse_event(&line).unwrap();
+        assert_eq!(Duration::from_millis(1500), time);
+        assert_eq!(KEY, code);
+        assert_eq!(b"a \"b\"\\\n\x01".to_vec(), data);
+    }
+
+    #[test]
+    fn event_round_trip_keeps_bytes_that_are_not_utf8() {
+        let bytes = b"caf\xe9 \xc3\xa9 \xc3";
+        let line = format_event(Duration::ZERO, EVENT, bytes);
+        assert_eq!(r#"[0.000000, "e", "caf\xe9 é \xc3"]"#, line);
+        assert_eq!(bytes.to_vec(), parse_event(&line).unwrap().2);
+    }
+
+    #[test]
+    fn playback_feeds_recorded_input() {
+        let log = concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 25}\n",
+            "[0.000000, \"w\", \"1\"]\n",
+            "[0.000000, \"i\", \"C-x\"]\n",
+            "[0.000000, \"o\", \"ignored\"]\n",
+            "[0.000000, \"e\", \"timeout\"]\n",
+            "[0.000000, \"e\", \"paste a b\"]\n",
+        );
+        let window = EmacsWindowScripted::new(80, 25).with_keys(&["live"]);
+        let mut playback = EmacsWindowPlayback::new(Box::new(window), log);
+        assert!(playback.key_waiting());
+        assert_eq!(InputEvent::Key(b"C-x".to_vec()), playback.get_input(0));
+        assert_eq!(InputEvent::Timeout, playback.get_input(0));
+        assert_eq!(InputEvent::Paste(b"a b".to_vec()), playback.get_input(0));
+        assert_eq!(InputEvent::Key(b"live".to_vec()), playback.get_input(0));
+    }
+
+    #[test]
+    fn input_events_round_trip() {
+        let events = [
+            InputEvent::Key(b"M-Left Arrow".to_vec()),
+            InputEvent::Resize,
+            InputEvent::Mouse {
+                action: MouseAction::Drag,
+                button: 1,
+                row: 4,
+                col: 10,
+            },
+            InputEvent::Wheel { up: false },
+            InputEvent::Paste(b" two\nlines ".to_vec()),
+            InputEvent::Focus(true),
+            InputEvent::Close,
+            InputEvent::Timeout,
+            InputEvent::Unknown,
+        ];
+        for event in events {
+            let (code, data) = format_input(&event);
+            assert_eq!(event, parse_input(code, &data));
+        }
+        assert_eq!(
+            (EVENT, b"mouse drag 1 4 10".to_vec()),
+            format_input(&InputEvent::Mouse {
+                action: MouseAction::Drag,
+                button: 1,
+                row: 4,
+                col: 10,
+            })
+        );
+    }
+}
@@ -0,0 +1,823 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* A terminal at the other end of a transport, such as a socket. */
+
+// The terminal libraries only drive the terminal the process was started
+// on, so this writes xterm escape sequences and decodes the keys it is
+// sent itself.  Anything that understands them will do at the other end:
+// telnet, or socat with the local terminal in raw mode.
+
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::emacs_buffer::{self, EmacsBuffer};
+use crate::emacs_window::{
+    self, DrawnLine, ESC_TIMEOUT_MS, EmacsWindow, FLASH_OFF, FLASH_ON, InputEvent, LineStyle,
+    ModeLine, MouseAction, RenderStats, char_key, eob_line, meta_key, modified_key, render_line,
+};
+use crate::mint_types::{MintCount, MintString};
+use crate::transport::{Input, Transport};
+use crate::window_layout::Rect;
+
+// Alternate screen, SGR mouse reports and a clear screen to start with,
+// and the reverse to finish.
+const START: &str = "\x1b[?1049h\x1b[?1000h\x1b[?1002h\x1b[?1006h\x1b[2J";
+const FINISH: &str = "\x1b[?1006l\x1b[?1002l\x1b[?1000l\x1b[0m\x1b[?25h\x1b[?1049l";
+
+// How long to wait for a telnet client to say how big its window is.
+const SIZE_WAIT: Duration = Duration::from_millis(250);
+
+// What a run of input bytes amounts to so far.
+#[derive(Debug, PartialEq, Eq)]
+enum Decoded {
+    Key(MintString),
+    // Input other than a key, such as a mouse report.
+    Event(InputEvent),
+    // The bytes so far could be the start of a longer sequence.
+    More,
+}
+
+pub struct EmacsWindowStream {
+    transport: Transport,
+    // Escape sequences waiting to be sent.
+    out: String,
+    cols: u16,
+    rows: u16,
+    // Set when the terminal has been resized, so the next redisplay
+    // repaints the whole screen.
+    resized: bool,
+    // Names given to keys from MINT, keyed by the name they'd have otherwise.
+    key_names: HashMap<MintString, MintString>,
+    // Lines last drawn in the panes, keyed by screen position, so that
+    // redisplay only sends the lines that have changed.
+    drawn: HashMap<(u16, u16), DrawnLine>,
+    // Where the cursor was last put.
+    cursor: (u16, u16),
+    stats: RenderStats,
+    // When the screen flash from a visual bell should end.
+    bell_until: Option<Instant>,
+    overwriting: bool,
+    ovy: u16,
+    ovx: u16,
+    fore: i32,
+    back: i32,
+    wsp_fore: i32,
+    show_wsp: bool,
+    eob_glyph: MintString,
+    eol_glyph: MintString,
+    ctrl_fore: i32,
+    mode_fore: i32,
+    mode_back: i32,
+    bot_scroll_percent: MintCount,
+    top_scroll_percent: MintCount,
+    // Lines asked for by set_edit_lines, or 0 for all there is room for.
+    edit_lines: MintCount,
+}
+
+impl EmacsWindowStream {
+    // A terminal "cols" by "lines" unless it says otherwise.
+    pub fn new(transport: Transport, cols: u16, lines: u16) -> Self {
+        let mut window = Self {
+            transport,
+            out: START.to_string(),
+            cols,
+            rows: lines,
+            resized: false,
+            key_names: HashMap::new(),
+            drawn: HashMap::new(),
+            cursor: (0, 0),
+            stats: RenderStats::default(),
+            bell_until: None,
+            overwriting: false,
+            ovy: 0,
+            ovx: 0,
+            fore: 15,
+            back: 0,
+            wsp_fore: 15,
+            show_wsp: false,
+            eob_glyph: MintString::new(),
+            eol_glyph: MintString::new(),
+            ctrl_fore: 11,
+            mode_fore: 0,
+            mode_back: 7,
+            bot_scroll_percent: 0,
+            top_scroll_percent: 0,
+            edit_lines: 0,
+        };
+        window.wait_for_size();
+        window.flush();
+        window
+    }
+
+    // Give a telnet client a moment to report its window size before the
+    // first screen is drawn.  Keys typed meanwhile are kept.
+    fn wait_for_size(&mut self) {
+        let deadline = Instant::now() + SIZE_WAIT;
+        let mut typed = Vec::new();
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match self.transport.read(deadline - now) {
+                Some(Input::Size(cols, rows)) => {
+                    self.set_size(cols, rows);
+                    break;
+                }
+                Some(input) => typed.push(input),
+                None => break,
+            }
+        }
+        for input in typed.into_iter().rev() {
+            self.transport.unread(input);
+        }
+    }
+
+    fn set_size(&mut self, cols: u16, rows: u16) {
+        self.cols = max(cols, 1);
+        self.rows = max(rows, 1);
+        self.resized = true;
+    }
+
+    fn flush(&mut self) {
+        let start = Instant::now();
+        self.transport.write_all(self.out.as_bytes()).ok();
+        self.transport.flush().ok();
+        self.out.clear();
+        self.stats.flush_time += start.elapsed();
+    }
+
+    fn move_to(&mut self, x: u16, y: u16) {
+        write!(self.out, "\x1b[{};{}H", y + 1, x + 1).ok();
+        self.cursor = (x, y);
+    }
+
+    fn set_colours(&mut self, fore: i32, back: i32) {
+        let (fore, back) = (sgr_colour(fore, 30), sgr_colour(back, 40));
+        write!(self.out, "\x1b[0;{};{}m", fore, back).ok();
+    }
+
+    fn line_style(&self) -> LineStyle<'_> {
+        LineStyle {
+            fore: self.fore,
+            back: self.back,
+            wsp_fore: self.wsp_fore,
+            ctrl_fore: self.ctrl_fore,
+            show_wsp: self.show_wsp,
+            eol_glyph: &self.eol_glyph,
+            eob_glyph: &self.eob_glyph,
+        }
+    }
+
+    // Write a line at "pos" unless the same line was drawn there last time.
+    fn draw_line(&mut self, pos: (u16, u16), line: DrawnLine) {
+        if self.drawn.get(&pos) == Some(&line) {
+            return;
+        }
+        self.stats.lines += 1;
+        let cells: usize = line.iter().map(|(_, _, text)| text.chars().count()).sum();
+        self.stats.cells += cells as u64;
+        self.move_to(pos.0, pos.1);
+        for (fore, back, text) in &line {
+            self.set_colours(*fore, *back);
+            self.out.push_str(text);
+        }
+        self.drawn.insert(pos, line);
+    }
+
+    // Turn off the visual bell flash if it has run its course.
+    fn end_bell_if_due(&mut self) {
+        if let Some(until) = self.bell_until
+            && Instant::now() >= until
+        {
+            self.out.push_str(FLASH_OFF);
+            self.flush();
+            self.bell_until = None;
+        }
+    }
+
+    // Wait up to "timeout" for input.  A visual bell that is due to end
+    // within the wait is ended on time rather than when the wait is over.
+    fn read_input(&mut self, timeout: Duration) -> Option<Input> {
+        self.end_bell_if_due();
+        if let Some(until) = self.bell_until {
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining < timeout {
+                if let Some(input) = self.transport.read(remaining) {
+                    return Some(input);
+                }
+                self.end_bell_if_due();
+                return self.transport.read(timeout - remaining);
+            }
+        }
+        self.transport.read(timeout)
+    }
+
+    // Read bytes starting with "first" until they make a key or other
+    // event.  A sequence cut short by a pause is taken as Escape, or as an
+    // Alt key.
+    fn read_event(&mut self, first: u8) -> InputEvent {
+        let timeout = Duration::from_millis(ESC_TIMEOUT_MS as u64);
+        let mut bytes = vec![first];
+        loop {
+            match decode_key(&bytes) {
+                Decoded::Key(key) => return InputEvent::Key(key),
+                Decoded::Event(event) => return event,
+                Decoded::More => {}
+            }
+            match self.transport.read(timeout) {
+                Some(Input::Byte(byte)) => bytes.push(byte),
+                other => {
+                    if let Some(input) = other {
+                        self.transport.unread(input);
+                    }
+                    return match bytes[..] {
+                        [0x1b, byte] => InputEvent::Key(meta_key(&ascii_key(byte))),
+                        [0x1b, ..] => InputEvent::Key(b"Escape".to_vec()),
+                        _ => InputEvent::Unknown,
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl EmacsWindow for EmacsWindowStream {
+    fn backend(&self) -> &'static str {
+        "stream"
+    }
+
+    fn get_columns(&self) -> MintCount {
+        self.cols as MintCount
+    }
+
+    fn get_screen_lines(&self) -> MintCount {
+        self.rows as MintCount
+    }
+
+    fn get_edit_lines(&self) -> MintCount {
+        emacs_window::fit_edit_lines(self.edit_lines, self.get_screen_lines())
+    }
+
+    fn set_edit_lines(&mut self, lines: MintCount) {
+        self.edit_lines = lines;
+        self.resized = true;
+    }
+
+    fn begin_update(&mut self) {
+        // DEC private mode 2026; terminals without it ignore the request.
+        self.out.push_str("\x1b[?2026h");
+    }
+
+    fn end_update(&mut self) {
+        self.out.push_str("\x1b[?2026l");
+        self.flush();
+    }
+
+    fn redisplay(&mut self, buf: &mut EmacsBuffer, area: &Rect, force: bool) {
+        self.end_bell_if_due();
+        self.overwriting = false;
+        self.stats.redisplays += 1;
+
+        let (left, top) = (area.col as u16, area.row as u16);
+        let (cols, edit_rows) = (area.cols as u16, area.rows as u16);
+
+        self.out.push_str("\x1b[?25l");
+        if force || self.resized {
+            self.set_colours(self.fore, self.back);
+            self.out.push_str("\x1b[2J");
+            self.resized = false;
+            self.drawn.clear();
+        }
+
+        buf.force_point_in_window(
+            edit_rows as MintCount,
+            cols as MintCount,
+            self.top_scroll_percent,
+            self.bot_scroll_percent,
+        );
+
+        let mut curline = buf.get_mark_position(emacs_buffer::MARK_TOPLINE);
+        let point = buf.get_mark_position(emacs_buffer::MARK_POINT);
+        let screen_line = buf.count_newlines(curline, point);
+        let screen_col = buf.get_column() as i32 - buf.get_left_column() as i32;
+
+        // Once the end of the buffer is reached, lines stop advancing.
+        let mut prev_eol = None;
+        for i in 0..edit_rows {
+            let eol = buf.get_mark_position_from(emacs_buffer::MARK_EOL, curline);
+            let line = if prev_eol == Some(curline) {
+                eob_line(cols, &self.line_style().for_buffer(buf))
+            } else {
+                render_line(buf, curline, eol, cols, &self.line_style().for_buffer(buf))
+            };
+            self.draw_line((left, top + i), line);
+            prev_eol = Some(eol);
+            curline = buf.get_mark_position_from(emacs_buffer::MARK_NEXT_CHAR, eol);
+        }
+
+        self.move_to(left + max(screen_col, 0) as u16, top + screen_line as u16);
+        self.out.push_str("\x1b[?25h");
+        self.flush();
+    }
+
+    fn get_render_stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    fn reset_render_stats(&mut self) {
+        self.stats = RenderStats::default();
+    }
+
+    fn overwrite(&mut self, s: &MintString) {
+        // Whatever this covers has to be drawn again.
+        self.drawn.clear();
+        if !self.overwriting {
+            self.overwriting = true;
+            self.ovy = 0;
+            self.ovx = 0;
+        }
+
+        self.set_colours(self.fore, self.back);
+        self.move_to(self.ovx, self.ovy);
+        for &ch in s.iter() {
+            if ch == b'\n' {
+                self.ovx = 0;
+                self.ovy = min(self.ovy + 1, self.rows - 1);
+                self.move_to(self.ovx, self.ovy);
+                continue;
+            }
+            self.out.push(ch as char);
+            self.ovx += 1;
+            if self.ovx >= self.cols {
+                self.ovx = 0;
+                self.ovy = min(self.ovy + 1, self.rows - 1);
+                self.move_to(self.ovx, self.ovy);
+            }
+        }
+        self.cursor = (self.ovx, self.ovy);
+    }
+
+    fn gotoxy(&mut self, x: i32, y: i32) {
+        self.overwriting = true;
+        self.ovx = max(0, min(x, self.cols as i32 - 1)) as u16;
+        self.ovy = max(0, min(y, self.rows as i32 - 1)) as u16;
+        self.move_to(self.ovx, self.ovy);
+    }
+
+    fn key_waiting(&self) -> bool {
+        self.transport.input_waiting()
+    }
+
+    fn get_input(&mut self, millisec: MintCount) -> InputEvent {
+        self.flush();
+        if self.transport.is_closed() {
+            return InputEvent::Close;
+        }
+
+        let timeout = if millisec < 10 {
+            Duration::ZERO
+        } else {
+            Duration::from_millis(millisec as u64)
+        };
+        match self.read_input(timeout) {
+            Some(Input::Byte(byte)) => match self.read_event(byte) {
+                InputEvent::Key(key) => {
+                    InputEvent::Key(self.key_names.get(&key).cloned().unwrap_or(key))
+                }
+                event => event,
+            },
+            Some(Input::Size(cols, rows)) => {
+                self.set_size(cols, rows);
+                InputEvent::Resize
+            }
+            None => InputEvent::Timeout,
+        }
+    }
+
+    fn input_fd(&self) -> Option<i32> {
+        None
+    }
+
+    fn decode_key(&mut self, code: &MintString, name: &MintString) -> bool {
+        // Keys are decoded here, by name, so any name will do.
+        self.key_names.insert(code.clone(), name.clone());
+        true
+    }
+
+    fn announce(&mut self, left: &MintString, right: &MintString) {
+        let (cols, rows) = (self.cols as usize, self.rows);
+        let n = min(left.len(), cols - 1);
+        let m = min(right.len(), cols.saturating_sub(n + 1));
+
+        self.set_colours(self.fore, self.back);
+        self.move_to(0, rows - 1);
+        self.out.extend(left[..n].iter().map(|&ch| ch as char));
+        let cursor_x = n as u16;
+        self.out.extend(right[..m].iter().map(|&ch| ch as char));
+        if n + m < cols {
+            self.out.push_str("\x1b[K");
+        }
+        self.move_to(cursor_x, rows - 1);
+        self.flush();
+    }
+
+    fn mode_line(&mut self, area: &Rect, line: &ModeLine) {
+        if area.rows > 0 {
+            let text = line.render(area.cols as usize);
+            let (row, col) = (area.row as u16, area.col as u16);
+            self.drawn.retain(|&(_, drawn_row), _| drawn_row != row);
+
+            let saved = self.cursor;
+            self.set_colours(self.mode_fore, self.mode_back);
+            self.move_to(col, row);
+            self.out.push_str(&String::from_utf8_lossy(&text));
+            self.move_to(saved.0, saved.1);
+            self.flush();
+        }
+    }
+
+    fn set_clipboard(&mut self, text: &MintString) {
+        self.out.push_str(&emacs_window::clipboard_sequence(text));
+        self.flush();
+    }
+
+    fn suspend(&mut self) -> bool {
+        // Stopping would leave the other end hanging, with no shell of its
+        // own to come back to.
+        false
+    }
+
+    fn audible_bell(&mut self, _freq: MintCount, _millisec: MintCount) {
+        // The speaker to play a tone on is at the other end.
+        self.out.push('\x07');
+        self.flush();
+    }
+
+    fn visual_bell(&mut self, millisec: MintCount) {
+        // Invert the screen now; it is put back by whichever of input or
+        // redisplay next notices the time is up.
+        self.out.push_str(FLASH_ON);
+        self.flush();
+        self.bell_until = Some(Instant::now() + Duration::from_millis(millisec as u64));
+    }
+
+    fn set_fore_colour(&mut self, colour: i32) {
+        self.fore = colour;
+    }
+
+    fn get_fore_colour(&self) -> i32 {
+        self.fore
+    }
+
+    fn set_back_colour(&mut self, colour: i32) {
+        self.back = colour;
+    }
+
+    fn get_back_colour(&self) -> i32 {
+        self.back
+    }
+
+    fn set_ctrl_fore_colour(&mut self, colour: i32) {
+        self.ctrl_fore = colour;
+    }
+
+    fn get_ctrl_fore_colour(&self) -> i32 {
+        self.ctrl_fore
+    }
+
+    fn set_mode_fore_colour(&mut self, colour: i32) {
+        self.mode_fore = colour;
+    }
+
+    fn get_mode_fore_colour(&self) -> i32 {
+        self.mode_fore
+    }
+
+    fn set_mode_back_colour(&mut self, colour: i32) {
+        self.mode_back = colour;
+    }
+
+    fn get_mode_back_colour(&self) -> i32 {
+        self.mode_back
+    }
+
+    fn set_whitespace_display(&mut self, flag: bool) {
+        self.show_wsp = flag;
+    }
+
+    fn get_whitespace_display(&self) -> bool {
+        self.show_wsp
+    }
+
+    fn set_whitespace_colour(&mut self, colour: i32) {
+        self.wsp_fore = colour;
+    }
+
+    fn get_whitespace_colour(&self) -> i32 {
+        self.wsp_fore
+    }
+
+    fn set_eob_indicator(&mut self, glyph: &MintString) {
+        self.eob_glyph = glyph.clone();
+    }
+
+    fn get_eob_indicator(&self) -> MintString {
+        self.eob_glyph.clone()
+    }
+
+    fn set_eol_indicator(&mut self, glyph: &MintString) {
+        self.eol_glyph = glyph.clone();
+    }
+
+    fn get_eol_indicator(&self) -> MintString {
+        self.eol_glyph.clone()
+    }
+
+    fn get_bot_scroll_percent(&self) -> MintCount {
+        self.bot_scroll_percent
+    }
+
+    fn set_bot_scroll_percent(&mut self, perc: MintCount) {
+        self.bot_scroll_percent = perc;
+    }
+
+    fn get_top_scroll_percent(&self) -> MintCount {
+        self.top_scroll_percent
+    }
+
+    fn set_top_scroll_percent(&mut self, perc: MintCount) {
+        self.top_scroll_percent = perc;
+    }
+}
+
+impl Drop for EmacsWindowStream {
+    fn drop(&mut self) {
+        if self.bell_until.is_some() {
+            self.out.push_str(FLASH_OFF);
+        }
+        self.out.push_str(FINISH);
+        self.flush();
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+// SGR parameter for DOS colour "colour", with "base" 30 for foreground or
+// 40 for background.  Bit 3 selects the bright colours.
+fn sgr_colour(colour: i32, base: i32) -> i32 {
+    // DOS colours go blue, green, red; ANSI ones red, green, blue.
+    const ANSI: [i32; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+    let hue = ANSI[(colour & 0x07) as usize];
+    if colour & 0x08 != 0 {
+        base + 60 + hue
+    } else {
+        base + hue
+    }
+}
+
+// Name of the key that sends the single byte "byte".  Control keys are
+// named as curses names them.
+fn ascii_key(byte: u8) -> MintString {
+    match byte {
+        0x00 => b"C-@".to_vec(),
+        0x08 | 0x7f => b"Back Space".to_vec(),
+        0x09 => b"Tab".to_vec(),
+        0x0a | 0x0d => b"Return".to_vec(),
+        0x1b => b"Escape".to_vec(),
+        0x01..=0x1f => vec![b'C', b'-', byte + b'a' - 1],
+        _ => char_key(byte as char),
+    }
+}
+
+// The key sent as "bytes", so far.
+fn decode_key(bytes: &[u8]) -> Decoded {
+    match bytes {
+        [] | [0x1b] | [0x1b, b'[' | b'O'] => Decoded::More,
+        [0x1b, b'[', rest @ ..] => decode_csi(rest),
+        [0x1b, b'O', final_byte] => Decoded::Key(final_key(*final_byte, 1)),
+        [0x1b, rest @ ..] => match decode_key(rest) {
+            Decoded::Key(key) => Decoded::Key(meta_key(&key)),
+            other => other,
+        },
+        [byte] if *byte < 0x80 => Decoded::Key(ascii_key(*byte)),
+        [lead, ..] => {
+            // A UTF-8 character, or a byte on its own if it isn't one.
+            let len = match lead {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            if bytes.len() < len {
+                return Decoded::More;
+            }
+            match std::str::from_utf8(&bytes[..len]) {
+                Ok(text) if len > 1 => Decoded::Key(char_key(text.chars().next().unwrap())),
+                _ => Decoded::Key(vec![*lead]),
+            }
+        }
+    }
+}
+
+// The key sent as "ESC [" followed by "rest".
+fn decode_csi(rest: &[u8]) -> Decoded {
+    let Some((&final_byte, params)) = rest.split_last() else {
+        return Decoded::More;
+    };
+    if !(0x40..=0x7e).contains(&final_byte) {
+        return if rest.len() < 16 {
+            Decoded::More
+        } else {
+            Decoded::Event(InputEvent::Unknown)
+        };
+    }
+    let params = String::from_utf8_lossy(params);
+
+    // SGR mouse report: "<" button ; column ; row, then M or m.
+    if let Some(mouse) = params.strip_prefix('<') {
+        return Decoded::Event(decode_mouse(mouse, final_byte == b'M'));
+    }
+
+    let mut numbers = params.split(';').map(|n| n.parse::<u32>().unwrap_or(1));
+    let first = numbers.next().unwrap_or(1);
+    let modifiers = numbers.next().unwrap_or(1);
+    let key = if final_byte == b'~' {
+        tilde_key(first, modifiers)
+    } else {
+        final_key(final_byte, modifiers)
+    };
+    Decoded::Key(key)
+}
+
+// Decode the modifiers in an xterm key sequence, 1 plus a bit each for
+// Shift, Alt and Ctrl, and name "key" with them.
+fn with_modifiers(key: &str, modifiers: u32) -> MintString {
+    let bits = modifiers.saturating_sub(1);
+    let name = modified_key(bits & 4 != 0, bits & 1 != 0, key);
+    if bits & 2 != 0 { meta_key(&name) } else { name }
+}
+
+// Keys sent as "ESC [" or "ESC O", optional modifiers, and "final_byte".
+fn final_key(final_byte: u8, modifiers: u32) -> MintString {
+    let key = match final_byte {
+        b'A' => "Up Arrow",
+        b'B' => "Down Arrow",
+        b'C' => "Right Arrow",
+        b'D' => "Left Arrow",
+        b'H' => "Home",
+        b'F' => "End",
+        b'P' => "F1",
+        b'Q' => "F2",
+        b'R' => "F3",
+        b'S' => "F4",
+        b'Z' => return b"Tab".to_vec(),
+        b'M' => return b"Enter".to_vec(),
+        _ => return b"Unknown".to_vec(),
+    };
+    with_modifiers(key, modifiers)
+}
+
+// Keys sent as "ESC [", a number, optional modifiers and "~".
+fn tilde_key(number: u32, modifiers: u32) -> MintString {
+    let key = match number {
+        1 | 7 => "Home",
+        2 => "Ins",
+        3 => "Del",
+        4 | 8 => "End",
+        5 => "Pg Up",
+        6 => "Pg Dn",
+        11..=15 => return with_modifiers(&format!("F{}", number - 10), modifiers),
+        17..=21 => return with_modifiers(&format!("F{}", number - 11), modifiers),
+        23..=26 => return with_modifiers(&format!("F{}", number - 12), modifiers),
+        28 | 29 => return with_modifiers(&format!("F{}", number - 13), modifiers),
+        31..=34 => return with_modifiers(&format!("F{}", number - 14), modifiers),
+        _ => return b"Unknown".to_vec(),
+    };
+    with_modifiers(key, modifiers)
+}
+
+// A mouse report, "button;column;row", pressed or not.
+fn decode_mouse(report: &str, pressed: bool) -> InputEvent {
+    let fields: Vec<MintCount> = report.split(';').map(|n| n.parse().unwrap_or(0)).collect();
+    let [button, col, row] = fields[..] else {
+        return InputEvent::Unknown;
+    };
+    match button {
+        64 => InputEvent::Wheel { up: true },
+        65 => InputEvent::Wheel { up: false },
+        _ => InputEvent::Mouse {
+            action: if button & 32 != 0 {
+                MouseAction::Drag
+            } else if pressed {
+                MouseAction::Down
+            } else {
+                MouseAction::Up
+            },
+            button: (button & 3) as u8 + 1,
+            row: row.saturating_sub(1),
+            col: col.saturating_sub(1),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(bytes: &[u8]) -> String {
+        match decode_key(bytes) {
+            Decoded::Key(key) => String::from_utf8_lossy(&key).into_owned(),
+            Decoded::Event(event) => format!("{:?}", event),
+            Decoded::More => "More".to_string(),
+        }
+    }
+
+    #[test]
+    fn single_bytes_are_keys() {
+        assert_eq!("x", key(b"x"));
+        assert_eq!("Comma", key(b","));
+        assert_eq!("C-a", key(b"\x01"));
+        assert_eq!("C-@", key(b"\x00"));
+        assert_eq!("Return", key(b"\r"));
+        assert_eq!("Back Space", key(b"\x7f"));
+    }
+
+    #[test]
+    fn escape_sequences_are_keys() {
+        assert_eq!("More", key(b"\x1b"));
+        assert_eq!("More", key(b"\x1b["));
+        assert_eq!("More", key(b"\x1b[1;5"));
+        assert_eq!("Up Arrow", key(b"\x1b[A"));
+        assert_eq!("C-Right Arrow", key(b"\x1b[1;5C"));
+        assert_eq!("M-S-Home", key(b"\x1b[1;4H"));
+        assert_eq!("F1", key(b"\x1bOP"));
+        assert_eq!("Pg Dn", key(b"\x1b[6~"));
+        assert_eq!("S-F5", key(b"\x1b[15;2~"));
+        assert_eq!("F12", key(b"\x1b[24~"));
+        assert_eq!("M-x", key(b"\x1bx"));
+        assert_eq!("M-C-x", key(b"\x1b\x18"));
+    }
+
+    #[test]
+    fn utf8_characters_are_keys() {
+        assert_eq!("More", key("é".as_bytes()[..1].as_ref()));
+        assert_eq!(vec![0xe9], ascii_key(0xe9));
+        assert_eq!(Decoded::Key(vec![0xe9]), decode_key("é".as_bytes()));
+        assert_eq!("€", key("€".as_bytes()));
+    }
+
+    #[test]
+    fn mouse_reports_are_events() {
+        let mouse = |action, button, row, col| {
+            Decoded::Event(InputEvent::Mouse {
+                action,
+                button,
+                row,
+                col,
+            })
+        };
+        assert_eq!(
+            mouse(MouseAction::Down, 1, 4, 9),
+            decode_key(b"\x1b[<0;10;5M")
+        );
+        assert_eq!(mouse(MouseAction::Up, 3, 0, 0), decode_key(b"\x1b[<2;1;1m"));
+        assert_eq!(
+            mouse(MouseAction::Drag, 1, 1, 1),
+            decode_key(b"\x1b[<32;2;2M")
+        );
+        assert_eq!(
+            Decoded::Event(InputEvent::Wheel { up: false }),
+            decode_key(b"\x1b[<65;2;2M")
+        );
+    }
+
+    #[test]
+    fn colours_map_to_sgr() {
+        assert_eq!(34, sgr_colour(1, 30));
+        assert_eq!(97, sgr_colour(15, 30));
+        assert_eq!(40, sgr_colour(0, 40));
+        assert_eq!(103, sgr_colour(14, 40));
+    }
+}
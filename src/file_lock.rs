@@ -0,0 +1,203 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Locks on the files being edited, so two editors don't both change one. */
+
+// The lock on "dir/name" is a symbolic link "dir/.#name" to
+// "user@host.pid", as Emacs makes them, so each sees the other's locks.
+// Where there are no symbolic links, a file holding the same is used.  A
+// lock left behind by a process on this host that has gone is stale, and
+// taken over as if there were none.
+
+use crate::mint::Mint;
+use crate::sysprim::{host_name, user_name};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{self, Path, PathBuf};
+use std::process;
+
+fn lock_path(file: &Path) -> Option<PathBuf> {
+    let mut name = OsString::from(".#");
+    name.push(file.file_name()?);
+    Some(file.with_file_name(name))
+}
+
+fn our_owner() -> String {
+    format!("{}@{}.{}", user_name(), host_name(), process::id())
+}
+
+// The owner written in the lock at "path".  Emacs may add ":" and the
+// time the system booted, which is left off.
+fn read_lock(path: &Path) -> Option<String> {
+    let owner = match fs::read_link(path) {
+        Ok(target) => target.to_string_lossy().into_owned(),
+        Err(_) => fs::read_to_string(path).ok()?,
+    };
+    let owner = owner.split(':').next().unwrap_or_default();
+    Some(owner.to_string())
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks that there is such a process to signal.
+    let found = unsafe { libc::kill(pid, 0) } == 0;
+    found || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    true
+}
+
+// Whether "owner" is another editor, rather than this one or one on this
+// host that has finished.
+fn is_other(owner: &str) -> bool {
+    if owner == our_owner() {
+        return false;
+    }
+    let Some((user_host, pid)) = owner.rsplit_once('.') else {
+        return true;
+    };
+    let host = user_host.split_once('@').map_or("", |(_, host)| host);
+    match pid.parse() {
+        Ok(pid) if host == host_name() => process_exists(pid),
+        _ => true,
+    }
+}
+
+#[cfg(unix)]
+fn make_lock(path: &Path, owner: &str) -> io::Result<()> {
+    std::os::unix::fs::symlink(owner, path)
+}
+
+#[cfg(not(unix))]
+fn make_lock(path: &Path, owner: &str) -> io::Result<()> {
+    use std::io::Write;
+    fs::File::create_new(path)?.write_all(owner.as_bytes())
+}
+
+// Remove the lock on "file" if it is this editor's.
+fn remove_lock(file: &Path) {
+    if let Some(path) = lock_path(file)
+        && read_lock(&path).is_some_and(|owner| owner == our_owner())
+    {
+        fs::remove_file(path).ok();
+    }
+}
+
+// The owner of the lock on "file", as "user@host.pid", if another editor
+// holds it.
+pub fn holder(file: &Path) -> Option<String> {
+    read_lock(&lock_path(file)?).filter(|owner| is_other(owner))
+}
+
+// Lock "file" for the editor running "interp".  If another editor holds
+// the lock, it is left with it and its owner returned, unless "steal" is
+// true.
+pub fn lock(interp: &Mint, file: &Path, steal: bool) -> io::Result<Option<String>> {
+    let Some(path) = lock_path(file) else {
+        return Ok(None);
+    };
+    if let Some(owner) = read_lock(&path) {
+        if is_other(&owner) && !steal {
+            return Ok(Some(owner));
+        }
+        fs::remove_file(&path)?;
+    }
+    match make_lock(&path, &our_owner()) {
+        // Someone else was quicker.
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(holder(file)),
+        Err(e) => Err(e),
+        Ok(()) => {
+            let file = path::absolute(file)?;
+            let mut held = interp.context().held_locks.borrow_mut();
+            if !held.contains(&file) {
+                held.push(file);
+            }
+            Ok(None)
+        }
+    }
+}
+
+// Give up the lock on "file", if this editor holds it.
+pub fn unlock(interp: &Mint, file: &Path) {
+    let Ok(file) = path::absolute(file) else {
+        return;
+    };
+    let mut held = interp.context().held_locks.borrow_mut();
+    let count = held.len();
+    held.retain(|f| *f != file);
+    if held.len() != count {
+        remove_lock(&file);
+    }
+}
+
+// Give up every lock this editor holds, as it finishes.
+pub fn unlock_all(interp: &Mint) {
+    for file in interp.context().held_locks.take() {
+        remove_lock(&file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_are_seen_and_stolen() {
+        let interp = Mint::new();
+        let file = std::env::temp_dir().join(format!("freemacs-lock-{}.txt", process::id()));
+        let path = lock_path(&file).unwrap();
+        assert_eq!(None, lock(&interp, &file, false).unwrap());
+        assert_eq!(Some(our_owner()), read_lock(&path));
+        assert_eq!(None, holder(&file));
+
+        unlock(&interp, &file);
+        assert!(read_lock(&path).is_none());
+
+        let other = "someone@elsewhere.example.1:12345";
+        make_lock(&path, other).unwrap();
+        let other = "someone@elsewhere.example.1".to_string();
+        assert_eq!(Some(other.clone()), holder(&file));
+        assert_eq!(Some(other), lock(&interp, &file, false).unwrap());
+        assert_eq!(None, lock(&interp, &file, true).unwrap());
+        assert_eq!(None, holder(&file));
+
+        unlock_all(&interp);
+        assert!(read_lock(&path).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_locks_are_taken_over() {
+        let interp = Mint::new();
+        let file = std::env::temp_dir().join(format!("freemacs-stale-{}.txt", process::id()));
+        let path = lock_path(&file).unwrap();
+        // No process has this id, as neither Linux nor macOS go so high.
+        let owner = format!("{}@{}.{}", user_name(), host_name(), 1 << 23);
+        make_lock(&path, &owner).unwrap();
+        assert_eq!(None, holder(&file));
+        assert_eq!(None, lock(&interp, &file, false).unwrap());
+        unlock(&interp, &file);
+        assert!(read_lock(&path).is_none());
+    }
+}
@@ -288,6 +288,48 @@ impl MintPrim for HkPrim {
     }
 }
 
+// #(fb,X)
+// -------
+// Form backtrace.  List the forms being expanded, innermost first, which
+// are those called as active functions whose text has not all been
+// scanned yet.  A form that ends by calling another is done by then, so
+// isn't listed.
+//
+// Returns: The names of the forms, separated by literal string "X".
+struct FbPrim;
+impl MintPrim for FbPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let list = interp.backtrace().join(args[1].value().as_slice());
+        interp.return_string(is_active, &list);
+    }
+}
+
+// #(ph,X,Y,Z)
+// -----------
+// Primitive hook.  Call the form "Y" before, and the form "Z" after, each
+// time the primitive "X" runs, with the primitive's name as argument.
+// "X" can also name a class of primitives:
+//     change  Those that change the current buffer's text: is, dm, bi,
+//             tr, tb, rf, rv!, ar and sr
+//     file    Those that read or change files: rf, wf, rv!, ar, av, rn,
+//             de, cp and md
+// A null "Y" or "Z" calls nothing then, and both null removes the hook.
+// Primitives used by a hook don't call hooks, so a hook can change the
+// buffer itself.  What a hook returns is thrown away.
+//
+// Returns: null, or "X" if it is neither a primitive nor a class.
+struct PhPrim;
+impl MintPrim for PhPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let name = args[1].value();
+        if interp.set_prim_hook(name, args[2].value(), args[3].value()) {
+            interp.return_null(is_active);
+        } else {
+            interp.return_string(is_active, name);
+        }
+    }
+}
+
 pub fn register_frm_prims(interp: &mut Mint) {
     interp.add_prim(b"ds".to_vec(), Box::new(DsPrim));
     interp.add_prim(b"gs".to_vec(), Box::new(GsPrim));
@@ -300,6 +342,8 @@ pub fn register_frm_prims(interp: &mut Mint) {
     interp.add_prim(b"es".to_vec(), Box::new(EsPrim));
     interp.add_prim(b"mp".to_vec(), Box::new(MpPrim));
     interp.add_prim(b"hk".to_vec(), Box::new(HkPrim));
+    interp.add_prim(b"ph".to_vec(), Box::new(PhPrim));
+    interp.add_prim(b"fb".to_vec(), Box::new(FbPrim));
 }
 
 #[cfg(test)]
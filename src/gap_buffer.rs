@@ -20,6 +20,7 @@ use crate::buffer::Buffer;
 use crate::mint_types::{MintChar, MintCount};
 use regex::bytes::Regex;
 use std::borrow::Cow;
+use std::io::{self, Read};
 use std::ops::Range;
 
 const BLOCK_SIZE: MintCount = 65536;
@@ -134,6 +135,10 @@ impl Buffer for GapBuffer {
         self.allocated() - self.free()
     }
 
+    fn capacity(&self) -> MintCount {
+        self.allocated()
+    }
+
     fn get(&self, offset: MintCount) -> Option<MintChar> {
         if offset >= self.size() {
             return None;
@@ -159,6 +164,10 @@ impl Buffer for GapBuffer {
         }
     }
 
+    fn new_empty(&self) -> Box<dyn Buffer> {
+        Box::new(GapBuffer::with_default_size())
+    }
+
     fn insert(&mut self, offset: MintCount, to_insert: &[MintChar]) -> bool {
         let insert_size = to_insert.len();
         if (self.free() as usize) < insert_size {
@@ -174,6 +183,87 @@ impl Buffer for GapBuffer {
         }
     }
 
+    fn insert_from(
+        &mut self,
+        offset: MintCount,
+        reader: &mut dyn Read,
+        size_hint: MintCount,
+    ) -> io::Result<MintCount> {
+        if offset > self.size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset past end of buffer",
+            ));
+        }
+        // Read straight into the gap, which only needs to move if the
+        // size hint was too small.
+        if self.free() < size_hint {
+            self.expand(size_hint - self.free());
+        }
+        self.move_gap_to(offset);
+        let mut total: MintCount = 0;
+        loop {
+            if self.free() == 0 {
+                self.expand(BLOCK_SIZE);
+                self.move_gap_to(offset + total);
+            }
+            let gap = self.bottop as usize..self.topbot as usize;
+            match reader.read(&mut self.buffer[gap]) {
+                Ok(0) => return Ok(total),
+                Ok(n) => {
+                    self.bottop += n as MintCount;
+                    total += n as MintCount;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.bottop -= total;
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn for_each_chunk(
+        &self,
+        start: MintCount,
+        end: MintCount,
+        reverse: bool,
+        f: &mut dyn FnMut(MintCount, &[MintChar]) -> bool,
+    ) {
+        let end = end.min(self.size());
+        if start >= end {
+            return;
+        }
+        // Text before the gap, then text after it.
+        let before = (start < self.bottop).then(|| (start, end.min(self.bottop)));
+        let after = (end > self.bottop).then(|| (start.max(self.bottop), end));
+        let mut chunks = [before, after];
+        if reverse {
+            chunks.reverse();
+        }
+        for (from, to) in chunks.into_iter().flatten() {
+            let shift = if from >= self.bottop { self.free() } else { 0 };
+            let slice = &self.buffer[(from + shift) as usize..(to + shift) as usize];
+            if !f(from, slice) {
+                return;
+            }
+        }
+    }
+
+    fn compact(&mut self) -> MintCount {
+        // Always leave at least a partial block free for insertions.
+        let new_size = (self.size() / BLOCK_SIZE + 1) * BLOCK_SIZE;
+        let old_size = self.allocated();
+        if new_size >= old_size {
+            return 0;
+        }
+        self.move_gap_to(self.size());
+        self.resize(new_size, 0);
+        self.buffer.shrink_to_fit();
+        self.topbot = new_size;
+        old_size - new_size
+    }
+
     fn find_forward(
         &self,
         regex: &Regex,
@@ -376,6 +466,77 @@ mod tests {
         assert!(!gb.replace(5, 5, &to_ms("ABCDE")));
     }
 
+    #[test]
+    fn gap_buffer_insert_from_reader() {
+        let mut gb = GapBuffer::new(5);
+        assert!(gb.insert(0, &to_ms("0189")));
+        let mut reader = "234567".as_bytes();
+        assert_eq!(6, gb.insert_from(2, &mut reader, 6).unwrap());
+        assert_eq!("0123456789", to_string(&gb));
+    }
+
+    #[test]
+    fn gap_buffer_insert_from_reader_grows() {
+        let mut gb = GapBuffer::new(5);
+        let text = "x".repeat(100_000);
+        let mut reader = text.as_bytes();
+        assert_eq!(100_000, gb.insert_from(0, &mut reader, 0).unwrap());
+        assert_eq!(text, to_string(&gb));
+    }
+
+    #[test]
+    fn gap_buffer_insert_from_reader_off_end_fails() {
+        let mut gb = GapBuffer::with_default_size();
+        let mut reader = "abc".as_bytes();
+        assert!(gb.insert_from(1, &mut reader, 3).is_err());
+        assert_eq!(0, gb.size());
+    }
+
+    #[test]
+    fn gap_buffer_byte_scanning() {
+        let mut gb = GapBuffer::new(5);
+        assert!(gb.insert(0, &to_ms("a\nb\nc\nd")));
+        // Move the gap into the middle of the text.
+        assert!(gb.insert(3, &to_ms("\n")));
+        assert_eq!("a\nb\n\nc\nd", to_string(&gb));
+        assert_eq!(4, gb.count_byte(b'\n', 0, gb.size()));
+        assert_eq!(2, gb.count_byte(b'\n', 2, 5));
+        assert_eq!(Some(3), gb.find_byte_forward(b'\n', 2, gb.size()));
+        assert_eq!(Some(4), gb.find_byte_forward(b'\n', 4, gb.size()));
+        assert_eq!(None, gb.find_byte_forward(b'\n', 7, gb.size()));
+        assert_eq!(Some(6), gb.find_byte_backward(b'\n', 0, gb.size()));
+        assert_eq!(Some(3), gb.find_byte_backward(b'\n', 0, 4));
+        assert_eq!(None, gb.find_byte_backward(b'\n', 0, 1));
+    }
+
+    #[test]
+    fn gap_buffer_read_range() {
+        let mut gb = GapBuffer::new(5);
+        assert!(gb.insert(0, &to_ms("0156789")));
+        assert!(gb.insert(2, &to_ms("234")));
+        let mut out = to_ms(">");
+        gb.read_range(1, 8, &mut out);
+        assert_eq!(to_ms(">1234567"), out);
+        out.clear();
+        gb.read_range(8, 20, &mut out);
+        assert_eq!(to_ms("89"), out);
+    }
+
+    #[test]
+    fn gap_buffer_compact() {
+        let mut gb = GapBuffer::with_default_size();
+        let text = "x".repeat(200_000);
+        assert!(gb.insert(0, text.as_bytes()));
+        assert_eq!(262144, gb.allocated());
+        assert_eq!(0, gb.compact());
+        assert!(gb.erase(10, 199_980));
+        assert_eq!(196608, gb.compact());
+        assert_eq!(65536, gb.allocated());
+        assert_eq!("x".repeat(20), to_string(&gb));
+        assert!(gb.insert(5, &to_ms("0123456789")));
+        assert_eq!("xxxxx0123456789xxxxxxxxxxxxxxx", to_string(&gb));
+    }
+
     #[test]
     fn gap_buffer_find_forward_basic() {
         let mut gb = GapBuffer::with_default_size();
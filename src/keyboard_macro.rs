@@ -0,0 +1,158 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Keyboard macros, recorded and replayed as keys are read. */
+
+// While recording, each key #(it) reads is noted.  A command's keys are
+// only added to the macro once the command is done, which is when the
+// interpreter goes back to the default string, so that the keys of the
+// command that stops recording are left out, as are those of the one that
+// started it.  Replaying hands the keys back to #(it) ahead of anything
+// typed, as fast as they are asked for, and keeps the screen from being
+// redrawn until they have all been read.  Keys queued by #(pu) are read
+// ahead even of those, and never recorded; see emacs_window::push_key.
+
+use std::collections::VecDeque;
+
+use crate::mint::Mint;
+use crate::mint_types::MintString;
+
+#[derive(Default)]
+pub struct KeyboardMacro {
+    recording: bool,
+    // Keys of the commands done since recording started.
+    recorded: Vec<MintString>,
+    // Keys read by the command going on.
+    command: Vec<MintString>,
+    // The macro last recorded or set.
+    last: Vec<MintString>,
+    // Keys still to be replayed, and how many times to go through the
+    // macro again after those.
+    replay: VecDeque<MintString>,
+    repeats: usize,
+}
+
+fn with_macro<F, R>(interp: &Mint, f: F) -> R
+where
+    F: FnOnce(&mut KeyboardMacro) -> R,
+{
+    f(&mut interp.context().keyboard_macro.borrow_mut())
+}
+
+// Note "key" as read, if recording.  Events that aren't keys, such as
+// "Timeout" or "Resize", are never recorded.
+pub fn key_read(interp: &Mint, key: &[u8]) {
+    const NOT_KEYS: &[&[u8]] = &[
+        b"Timeout",
+        b"Process-Output",
+        b"Resize",
+        b"Focus-In",
+        b"Focus-Out",
+        b"Close",
+        b"Unknown",
+    ];
+    with_macro(interp, |m| {
+        if m.recording && !NOT_KEYS.contains(&key) {
+            m.command.push(key.to_vec());
+        }
+    })
+}
+
+// The command going on is done, so its keys belong in the macro.
+pub fn end_command(interp: &Mint) {
+    with_macro(interp, |m| {
+        let keys = std::mem::take(&mut m.command);
+        if m.recording {
+            m.recorded.extend(keys);
+        }
+    })
+}
+
+// Start recording.  Returns false if already recording.
+pub fn start(interp: &Mint) -> bool {
+    with_macro(interp, |m| {
+        if m.recording {
+            return false;
+        }
+        m.recording = true;
+        m.recorded.clear();
+        m.command.clear();
+        true
+    })
+}
+
+// Stop recording, keeping what was recorded as the macro.  Returns false
+// if not recording.
+pub fn stop(interp: &Mint) -> bool {
+    with_macro(interp, |m| {
+        if !m.recording {
+            return false;
+        }
+        m.recording = false;
+        m.command.clear();
+        m.last = std::mem::take(&mut m.recorded);
+        true
+    })
+}
+
+// Replay the macro "times" times, or stop replaying if "times" is 0.
+pub fn replay(interp: &Mint, times: usize) -> Result<(), &'static str> {
+    with_macro(interp, |m| {
+        m.replay.clear();
+        m.repeats = 0;
+        if times == 0 {
+            return Ok(());
+        }
+        if m.recording {
+            return Err("recording keyboard macro");
+        }
+        if m.last.is_empty() {
+            return Err("no keyboard macro");
+        }
+        m.replay.extend(m.last.iter().cloned());
+        m.repeats = times - 1;
+        Ok(())
+    })
+}
+
+// The next key being replayed, if there is one.
+pub fn next_key(interp: &Mint) -> Option<MintString> {
+    with_macro(interp, |m| {
+        if m.replay.is_empty() && m.repeats > 0 {
+            m.repeats -= 1;
+            m.replay.extend(m.last.iter().cloned());
+        }
+        m.replay.pop_front()
+    })
+}
+
+// Whether next_key has a key to give.
+pub fn replaying(interp: &Mint) -> bool {
+    with_macro(interp, |m| {
+        !m.replay.is_empty() || (m.repeats > 0 && !m.last.is_empty())
+    })
+}
+
+// The keys of the macro.
+pub fn keys(interp: &Mint) -> Vec<MintString> {
+    with_macro(interp, |m| m.last.clone())
+}
+
+pub fn set_keys(interp: &Mint, keys: Vec<MintString>) {
+    with_macro(interp, |m| m.last = keys)
+}
@@ -19,22 +19,51 @@
 /* Library entry so integration tests can depend on the crate API. */
 pub mod buffer;
 pub mod bufprim;
+pub mod coding;
+pub mod context;
+pub mod editor;
 pub mod emacs_buffer;
 pub mod emacs_buffers;
 pub mod emacs_window;
+pub mod emacs_window_batch;
+#[cfg(any(feature = "crossterm", target_os = "windows"))]
 pub mod emacs_window_crossterm;
+#[cfg(all(feature = "curses", not(target_os = "windows")))]
 pub mod emacs_window_curses;
 pub mod emacs_window_debug;
+#[cfg(feature = "gui")]
+pub mod emacs_window_gui;
+pub mod emacs_window_scripted;
+pub mod emacs_window_session;
+pub mod emacs_window_stream;
+pub mod file_lock;
 pub mod frmprim;
 pub mod gap_buffer;
+pub mod keyboard_macro;
 pub mod libprim;
 pub mod mint;
 pub mod mint_arg;
 pub mod mint_form;
 pub mod mint_string;
 pub mod mint_types;
+pub mod mmap_buffer;
 pub mod mthprim;
+pub mod piece_list;
+pub mod piece_table_buffer;
+#[cfg(any(unix, windows))]
+pub mod plugin;
+pub mod recovery;
+pub mod rope_buffer;
+pub mod signals;
+pub mod state;
 pub mod strprim;
+pub mod subprocess;
 pub mod sysprim;
+pub mod transport;
 pub mod varprim;
+pub mod wakeup;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod window_layout;
 pub mod winprim;
+pub mod worker;
@@ -18,8 +18,13 @@
 
 use crate::mint::{Mint, MintPrim};
 use crate::mint_arg::MintArgList;
-use std::fs::File;
-use std::io::{Read, Write};
+use crate::mint_types::{MintCount, MintString};
+use crate::sysprim::replace_file;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
 
 // Library file header structure
 #[repr(C)]
@@ -57,6 +62,235 @@ impl LibHdr {
             data_length: u32::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]),
         })
     }
+
+    // The original Freemacs, for 16-bit DOS, wrote the same fields as
+    // 16-bit words.
+    const DOS_SIZE: usize = 10;
+
+    fn from_dos_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::DOS_SIZE {
+            return None;
+        }
+        let word = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]) as u32;
+        Some(Self {
+            total_length: word(0),
+            name_length: word(2),
+            reserved: word(4),
+            form_pos: word(6),
+            data_length: word(8),
+        })
+    }
+
+    fn to_dos_bytes(&self) -> Option<[u8; Self::DOS_SIZE]> {
+        let mut bytes = [0u8; Self::DOS_SIZE];
+        let fields = [
+            self.total_length,
+            self.name_length,
+            self.reserved,
+            self.form_pos,
+            self.data_length,
+        ];
+        for (chunk, field) in bytes.chunks_mut(2).zip(fields) {
+            chunk.copy_from_slice(&u16::try_from(field).ok()?.to_le_bytes());
+        }
+        Some(bytes)
+    }
+}
+
+// A form as found in a library: its name, and where its value is.
+struct LibEntry {
+    name: MintString,
+    data: Range<usize>,
+    pos: MintCount,
+    reserved: u32,
+}
+
+impl LibEntry {
+    fn form(&self, buffer: &[u8]) -> LibForm {
+        LibForm {
+            name: self.name.clone(),
+            value: buffer[self.data.clone()].to_vec(),
+            pos: self.pos,
+            reserved: self.reserved,
+        }
+    }
+}
+
+// A form to write to a library.
+struct LibForm {
+    name: MintString,
+    value: MintString,
+    pos: MintCount,
+    reserved: u32,
+}
+
+impl LibForm {
+    // The form as written with headers of "size" bytes, or None if it is
+    // too long for them.
+    fn to_bytes(&self, size: usize) -> Option<MintString> {
+        let hdr = LibHdr {
+            total_length: (size + self.name.len() + self.value.len()) as u32,
+            name_length: self.name.len() as u32,
+            reserved: self.reserved,
+            form_pos: self.pos,
+            data_length: self.value.len() as u32,
+        };
+        let mut bytes = if size == LibHdr::DOS_SIZE {
+            hdr.to_dos_bytes()?.to_vec()
+        } else {
+            hdr.to_bytes().to_vec()
+        };
+        bytes.extend_from_slice(&self.name);
+        bytes.extend_from_slice(&self.value);
+        Some(bytes)
+    }
+}
+
+// What is wrong with a library, and where.
+struct LibError {
+    offset: usize,
+    name: Option<MintString>,
+    problem: &'static str,
+}
+
+// A library as read.
+struct Library {
+    forms: Vec<LibEntry>,
+    // The size of its headers.
+    size: usize,
+    // The first thing wrong with it, if anything is.
+    error: Option<LibError>,
+}
+
+impl Library {
+    // The error as reported by #(ll) and #(ul), or null if there is none.
+    fn error_message(&self) -> MintString {
+        let Some(error) = &self.error else {
+            return MintString::new();
+        };
+        let form = match &error.name {
+            Some(name) => format!(" in form {}", String::from_utf8_lossy(name)),
+            None => String::new(),
+        };
+        let message = format!(
+            "{}{} at offset {}; {} forms loaded",
+            error.problem,
+            form,
+            error.offset,
+            self.forms.len()
+        );
+        message.into_bytes()
+    }
+}
+
+// The forms in library "buffer", with headers of "size" bytes read by
+// "read_hdr".  A form whose total length does not add up is taken as it
+// is, but is an error, as is anything other than DOS end of file marks
+// left over.  A form cut short ends the library.
+fn parse_library(buffer: &[u8], size: usize, read_hdr: fn(&[u8]) -> Option<LibHdr>) -> Library {
+    let mut forms = Vec::new();
+    let mut error = None;
+    let mut offset = 0;
+    while let Some(hdr) = read_hdr(&buffer[offset..]) {
+        let name_len = hdr.name_length as usize;
+        let data_len = hdr.data_length as usize;
+        let length = size + name_len + data_len;
+        let name_start = offset + size;
+        let data_start = name_start + name_len;
+        let name = buffer.get(name_start..data_start).map(|name| name.to_vec());
+        if offset + length > buffer.len() {
+            let problem = "truncated";
+            error = Some(LibError {
+                offset,
+                name,
+                problem,
+            });
+            break;
+        }
+        if hdr.total_length as usize != length && error.is_none() {
+            let problem = "bad length";
+            error = Some(LibError {
+                offset,
+                name,
+                problem,
+            });
+        }
+        forms.push(LibEntry {
+            name: buffer[name_start..data_start].to_vec(),
+            data: data_start..data_start + data_len,
+            pos: hdr.form_pos,
+            reserved: hdr.reserved,
+        });
+        offset += length;
+    }
+    if error.is_none() && buffer[offset..].iter().any(|&b| b != 0x1a) {
+        let problem = "truncated";
+        error = Some(LibError {
+            offset,
+            name: None,
+            problem,
+        });
+    }
+    Library { forms, size, error }
+}
+
+// Library "buffer", written either by #(sl) or by the original Freemacs,
+// told apart by which way the lengths add up.  If neither does, it is
+// read the way that goes further before going wrong.
+fn read_library(buffer: &[u8]) -> Library {
+    let native = parse_library(buffer, LibHdr::SIZE, LibHdr::from_bytes);
+    let Some(native_error) = &native.error else {
+        return native;
+    };
+    let dos = parse_library(buffer, LibHdr::DOS_SIZE, LibHdr::from_dos_bytes);
+    match &dos.error {
+        Some(dos_error) if dos_error.offset <= native_error.offset => native,
+        _ => dos,
+    }
+}
+
+// Library "buffer" with each of "forms" put in place of the form of the
+// same name, or added at the end if it has none.  The headers stay the
+// size they were.  An error message if the library is damaged, or a form
+// is too long for its headers.
+fn update_library(buffer: &[u8], forms: Vec<LibForm>) -> Result<MintString, MintString> {
+    let library = read_library(buffer);
+    if library.error.is_some() {
+        return Err(library.error_message());
+    }
+    let mut kept: Vec<LibForm> = library.forms.iter().map(|e| e.form(buffer)).collect();
+    for form in forms {
+        match kept.iter_mut().find(|f| f.name == form.name) {
+            Some(old) => {
+                let reserved = old.reserved;
+                *old = LibForm { reserved, ..form };
+            }
+            None => kept.push(form),
+        }
+    }
+    let mut bytes = Vec::new();
+    for form in &kept {
+        let Some(form_bytes) = form.to_bytes(library.size) else {
+            return Err(b"Form too long".to_vec());
+        };
+        bytes.extend(form_bytes);
+    }
+    Ok(bytes)
+}
+
+// Change "file", which holds "old", to hold "new", writing only from
+// where they first differ, and if they are the same length, only up to
+// where they last differ.
+fn write_changes(file: &mut File, old: &[u8], new: &[u8]) -> std::io::Result<()> {
+    let start = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let mut end = new.len();
+    if old.len() == new.len() {
+        let same = old[start..].iter().rev().zip(new[start..].iter().rev());
+        end -= same.take_while(|(a, b)| a == b).count();
+    }
+    file.seek(SeekFrom::Start(start as u64))?;
+    file.write_all(&new[start..end])?;
+    file.set_len(new.len() as u64)
 }
 
 // #(sl,X,Y1,Y2,...,Yn)
@@ -117,6 +351,7 @@ impl MintPrim for SlPrim {
                         interp.return_string(is_active, &error_msg);
                         return;
                     }
+                    interp.forget_form_change(form_name);
                 }
             }
         }
@@ -125,12 +360,17 @@ impl MintPrim for SlPrim {
     }
 }
 
-// #(ll,X)
-// -------
+// #(ll,X,Y,Z,W)
+// -------------
 // Load library.  Load library from file "X".  This library file should be
-// in a form written by #(sl,...).
+// in a form written by #(sl,...), or by the original Freemacs for DOS,
+// whose headers have 16-bit fields.  Only forms whose names start with
+// "Y" are loaded.  Each is defined with "W" put before its name, and if
+// "Z" is "s", forms already defined are skipped rather than replaced.
 //
-// Returns: Error message or null if no error.
+// Returns: Error message or null if no error.  If the library is damaged,
+// the forms before the damage are loaded, and the message says what is
+// wrong, where, and how many forms were loaded.
 struct LlPrim;
 impl MintPrim for LlPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
@@ -155,43 +395,409 @@ impl MintPrim for LlPrim {
             return;
         }
 
-        // Parse the library file
-        let mut offset = 0;
-        while offset + LibHdr::SIZE <= buffer.len() {
-            // Read header
-            let hdr = match LibHdr::from_bytes(&buffer[offset..]) {
-                Some(h) => h,
-                None => break,
-            };
+        let filter = args[2].value();
+        let skip = args[3].value() == b"s";
+        let prefix = args[4].value();
+        let library = read_library(&buffer);
+        // Each form is copied out of the library only when first wanted.
+        let source: Arc<[u8]> = buffer.into();
+        for form in &library.forms {
+            if !form.name.starts_with(filter) {
+                continue;
+            }
+            let name = [&prefix[..], &form.name].concat();
+            if skip && interp.get_form(&name).is_some() {
+                continue;
+            }
+            interp.set_form_source(&name, source.clone(), form.data.clone());
+            interp.set_form_pos(&name, form.pos);
+            interp.forget_form_change(&name);
+        }
+
+        interp.return_string(is_active, &library.error_message());
+    }
+}
+
+// The names of the forms in library "buffer", separated by "separator",
+// each followed by a space and its length.
+fn catalogue(buffer: &[u8], separator: &[u8]) -> MintString {
+    let entries = read_library(buffer).forms.into_iter().map(|form| {
+        let mut entry = form.name;
+        entry.extend(format!(" {}", form.data.len()).into_bytes());
+        entry
+    });
+    entries.collect::<Vec<_>>().join(separator)
+}
 
-            offset += LibHdr::SIZE;
+// #(lL,X,Y)
+// ---------
+// List library.  Lists the forms in library file "X" without defining
+// them, so that some can be chosen to load.
+//
+// Returns: A list, separated by literal string "Y", of the names of the
+// forms in "X", each followed by a space and the length of the form.
+// Null if "X" cannot be read.
+struct LLPrim;
+impl MintPrim for LLPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let file_name = String::from_utf8_lossy(args[1].value());
+        let separator = args[2].value();
+        let buffer = std::fs::read(file_name.as_ref()).unwrap_or_default();
+        interp.return_string(is_active, &catalogue(&buffer, separator));
+    }
+}
 
-            let name_len = hdr.name_length as usize;
-            let data_len = hdr.data_length as usize;
+// #(ul,X,Y1,Y2,...,Yn)
+// --------------------
+// Update library.  Writes forms "Y1", ..., "Yn" into library file "X" as
+// #(sl,...) would, each in place of the form of the same name already
+// there, or at the end if there is none.  Forms before the first one
+// changed are not rewritten.  "X" is made if it does not exist, and keeps
+// 16-bit headers if it has them.
+//
+// Returns: An error message if an error occurs, otherwise null.
+struct UlPrim;
+impl MintPrim for UlPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let file_name = args[1].value();
+        let file_name_str = String::from_utf8_lossy(file_name);
 
-            // Check we have enough data
-            if offset + name_len + data_len > buffer.len() {
-                break;
+        let opened = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(file_name_str.as_ref());
+        let mut file = match opened {
+            Ok(f) => f,
+            Err(e) => {
+                let error_msg = format!("{}", e).into_bytes();
+                interp.return_string(is_active, &error_msg);
+                return;
             }
+        };
+        let mut buffer = Vec::new();
+        if let Err(e) = file.read_to_end(&mut buffer) {
+            let error_msg = format!("{}", e).into_bytes();
+            interp.return_string(is_active, &error_msg);
+            return;
+        }
+
+        let mut forms = Vec::new();
+        if args.len() > 2 {
+            for arg in args.iter().take(args.len() - 1).skip(2) {
+                let form_name = arg.value();
+                if let Some(form) = interp.get_form(form_name) {
+                    forms.push(LibForm {
+                        name: form_name.to_vec(),
+                        value: form.content().to_vec(),
+                        pos: form.get_pos(),
+                        reserved: 0,
+                    });
+                }
+            }
+        }
 
-            // Extract form name and content
-            let form_name = buffer[offset..offset + name_len].to_vec();
-            offset += name_len;
+        let names: Vec<MintString> = forms.iter().map(|form| form.name.clone()).collect();
+        let library = match update_library(&buffer, forms) {
+            Ok(library) => library,
+            Err(error_msg) => {
+                interp.return_string(is_active, &error_msg);
+                return;
+            }
+        };
+        if write_changes(&mut file, &buffer, &library).is_err() {
+            interp.return_string(is_active, &b"Write error".to_vec());
+            return;
+        }
+        for name in names {
+            interp.forget_form_change(&name);
+        }
+        interp.return_null(is_active);
+    }
+}
 
-            let form_value = buffer[offset..offset + data_len].to_vec();
-            offset += data_len;
+// Write forms "names" to library file "path", replacing what it held.
+pub fn save_forms(interp: &Mint, path: &Path, names: &[&MintString]) -> std::io::Result<()> {
+    let mut bytes = Vec::new();
+    for &name in names {
+        if let Some(form) = interp.get_form(name) {
+            let value = form.content().clone();
+            let form = LibForm {
+                name: name.clone(),
+                value,
+                pos: form.get_pos(),
+                reserved: 0,
+            };
+            bytes.extend(form.to_bytes(LibHdr::SIZE).unwrap_or_default());
+        }
+    }
+    replace_file(path, &bytes)
+}
 
-            // Set the form in the interpreter
-            interp.set_form_value(&form_name, &form_value);
-            interp.set_form_pos(&form_name, hdr.form_pos);
+// #(ll-recovery,X)
+// ----------------
+// Load recovery library.  Defines the forms in "X", the recovery library
+// of a session that stopped without exiting, as changed in this session,
+// so that they are kept if it stops the same way.  Then removes "X".
+//
+// Returns: A message saying how many forms were recovered, or an error
+// message.
+struct LlRecoveryPrim;
+impl MintPrim for LlRecoveryPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let file_name = String::from_utf8_lossy(args[1].value()).into_owned();
+        let buffer = match std::fs::read(&file_name) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                let error_msg = format!("{}: {}", file_name, e).into_bytes();
+                interp.return_string(is_active, &error_msg);
+                return;
+            }
+        };
+        let library = read_library(&buffer);
+        let source: Arc<[u8]> = buffer.into();
+        for form in &library.forms {
+            interp.set_form_source(&form.name, source.clone(), form.data.clone());
+            interp.set_form_pos(&form.name, form.pos);
         }
+        let message = if library.error.is_some() {
+            library.error_message()
+        } else {
+            std::fs::remove_file(&file_name).ok();
+            let count = library.forms.len();
+            format!("Recovered {} forms from {}", count, file_name).into_bytes()
+        };
+        interp.return_string(is_active, &message);
+    }
+}
 
-        // Success - return null
+// The library built in, as .MIN source.
+const EMBEDDED_LIBRARY: &[u8] = include_bytes!("../Editor/fallback.min");
+
+const NAME: &[u8] = b"Name:";
+const DELIMITER: &[u8] = b"[*]";
+
+fn find(text: &[u8], s: &[u8]) -> Option<usize> {
+    text.windows(s.len()).position(|w| w == s)
+}
+
+// The strings defined in .MIN source "text".  Each is "Name:" and its
+// name at the start of a line, then its documentation, then its value
+// between "[*]"s.  As when byte-compiling, "SELF" and "arg1" to "arg9" in
+// the value become parameter marks.  Comments are not taken out.
+fn min_strings(text: &[u8]) -> Vec<(MintString, MintString)> {
+    let mut strings = Vec::new();
+    let mut rest = text;
+    loop {
+        let start = if rest.starts_with(NAME) {
+            0
+        } else {
+            match find(rest, b"\nName:") {
+                Some(pos) => pos + 1,
+                None => break,
+            }
+        };
+        rest = &rest[start + NAME.len()..];
+        let name_end = find(rest, b"\n").unwrap_or(rest.len());
+        let name = rest[..name_end].to_vec();
+        let Some(open) = find(rest, DELIMITER) else {
+            break;
+        };
+        rest = &rest[open + DELIMITER.len()..];
+        let Some(close) = find(rest, DELIMITER) else {
+            break;
+        };
+        strings.push((name, mark_args(&rest[..close])));
+        rest = &rest[close + DELIMITER.len()..];
+    }
+    strings
+}
+
+// "value" marked as #(mp,X,#(Farglist)) would mark it.
+fn mark_args(value: &[u8]) -> MintString {
+    let mut value = value.to_vec();
+    let params =
+        std::iter::once(b"SELF".to_vec()).chain((1..=9).map(|n| format!("arg{}", n).into_bytes()));
+    for (mark, param) in (0x80u8..).zip(params) {
+        let mut pos = 0;
+        while pos + param.len() <= value.len() {
+            if value[pos..].starts_with(&param) {
+                value.splice(pos..pos + param.len(), [mark]);
+            }
+            pos += 1;
+        }
+    }
+    value
+}
+
+// #(ll-embedded)
+// --------------
+// Load embedded library.  Defines the strings of the small library built
+// into Freemacs, enough to edit and save a file with when no .ED files
+// can be found.  Calling #(F&setup) afterwards starts it.
+//
+// Returns: null.
+struct LlEmbeddedPrim;
+impl MintPrim for LlEmbeddedPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, _args: &MintArgList) {
+        for (name, value) in min_strings(EMBEDDED_LIBRARY) {
+            interp.set_form_value(&name, &value);
+            interp.forget_form_change(&name);
+        }
         interp.return_null(is_active);
     }
 }
 
 pub fn register_lib_prims(interp: &mut Mint) {
     interp.add_prim(b"ll".to_vec(), Box::new(LlPrim));
+    interp.add_prim(b"lL".to_vec(), Box::new(LLPrim));
+    interp.add_prim(b"ll-embedded".to_vec(), Box::new(LlEmbeddedPrim));
+    interp.add_prim(b"ll-recovery".to_vec(), Box::new(LlRecoveryPrim));
     interp.add_prim(b"sl".to_vec(), Box::new(SlPrim));
+    interp.add_prim(b"ul".to_vec(), Box::new(UlPrim));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dos_form(name: &[u8], value: &[u8], pos: u16) -> Vec<u8> {
+        let total = (LibHdr::DOS_SIZE + name.len() + value.len()) as u16;
+        let mut bytes = Vec::new();
+        for word in [total, name.len() as u16, 0xbeef, pos, value.len() as u16] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    fn names<'a>(buffer: &'a [u8], forms: &'a [LibEntry]) -> Vec<(&'a [u8], &'a [u8], MintCount)> {
+        let forms = forms.iter();
+        forms
+            .map(|f| (&f.name[..], &buffer[f.data.clone()], f.pos))
+            .collect()
+    }
+
+    #[test]
+    fn dos_libraries_are_read() {
+        let mut buffer = dos_form(b"Fone", b"#(ow,\x81)", 2);
+        buffer.extend(dos_form(b"lib-name", b"F", 0));
+        buffer.push(0x1a);
+        let expected: Vec<(&[u8], &[u8], MintCount)> =
+            vec![(b"Fone", b"#(ow,\x81)", 2), (b"lib-name", b"F", 0)];
+        assert_eq!(expected, names(&buffer, &read_library(&buffer).forms));
+    }
+
+    #[test]
+    fn native_libraries_are_read() {
+        let (name, value) = (b"Ftwo", b"value");
+        let hdr = LibHdr {
+            total_length: (LibHdr::SIZE + name.len() + value.len()) as u32,
+            name_length: name.len() as u32,
+            reserved: 0,
+            form_pos: 3,
+            data_length: value.len() as u32,
+        };
+        let mut buffer = hdr.to_bytes().to_vec();
+        buffer.extend_from_slice(name);
+        buffer.extend_from_slice(value);
+        let expected: Vec<(&[u8], &[u8], MintCount)> = vec![(b"Ftwo", b"value", 3)];
+        assert_eq!(expected, names(&buffer, &read_library(&buffer).forms));
+        // A form cut short is left out.
+        buffer.truncate(buffer.len() - 1);
+        let library = read_library(&buffer);
+        assert!(library.forms.is_empty());
+        let message = "truncated in form Ftwo at offset 0; 0 forms loaded";
+        assert_eq!(message.as_bytes(), library.error_message());
+    }
+
+    fn lib_form(name: &[u8], value: &[u8]) -> LibForm {
+        let (name, value) = (name.to_vec(), value.to_vec());
+        LibForm {
+            name,
+            value,
+            pos: 0,
+            reserved: 0,
+        }
+    }
+
+    #[test]
+    fn dos_libraries_are_updated_in_place() {
+        let mut buffer = dos_form(b"Fone", b"1", 0);
+        buffer.extend(dos_form(b"Ftwo", b"2", 0));
+        let updated = update_library(
+            &buffer,
+            vec![lib_form(b"Fone", b"one"), lib_form(b"Fnew", b"")],
+        );
+        let mut expected = dos_form(b"Fone", b"one", 0);
+        expected.extend(dos_form(b"Ftwo", b"2", 0));
+        expected.extend(dos_form(b"Fnew", b"", 0));
+        // Hash links already there are kept.
+        expected[36..38].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(Ok(expected), updated);
+        let long = lib_form(b"Flong", &[b'x'; 70000]);
+        let error = b"Form too long".to_vec();
+        assert_eq!(Err(error), update_library(&buffer, vec![long]));
+    }
+
+    #[test]
+    fn only_changes_are_written() {
+        let path = std::env::temp_dir().join(format!("ul-test-{}", std::process::id()));
+        std::fs::write(&path, b"abcdefgh").unwrap();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        write_changes(&mut file, b"abcdefgh", b"abXYefgh").unwrap();
+        write_changes(&mut file, b"abXYefgh", b"abXY").unwrap();
+        drop(file);
+        assert_eq!(b"abXY".to_vec(), std::fs::read(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damaged_libraries_are_reported() {
+        let mut buffer = dos_form(b"Fone", b"1", 0);
+        buffer.extend(dos_form(b"Ftwo", b"22", 0));
+        buffer.truncate(buffer.len() - 1);
+        let library = read_library(&buffer);
+        assert_eq!(1, library.forms.len());
+        let message = "truncated in form Ftwo at offset 15; 1 forms loaded";
+        assert_eq!(message.as_bytes(), library.error_message());
+        buffer.truncate(17);
+        let message = "truncated at offset 15; 1 forms loaded";
+        assert_eq!(message.as_bytes(), read_library(&buffer).error_message());
+        let error = Err(message.as_bytes().to_vec());
+        assert_eq!(error, update_library(&buffer, Vec::new()));
+    }
+
+    #[test]
+    fn catalogue_lists_names_and_lengths() {
+        let mut buffer = dos_form(b"Fone", b"#(ow)", 0);
+        buffer.extend(dos_form(b"lib-name", b"", 0));
+        assert_eq!(b"Fone 5;lib-name 0".to_vec(), catalogue(&buffer, b";"));
+        assert!(catalogue(b"", b";").is_empty());
+    }
+
+    #[test]
+    fn min_strings_are_marked() {
+        let text = b"Header\n\nName:Fone\nDoc here.\n[*]#(SELF,arg1,arg2)[*]\n\nName:K.C-x C-s\n[*]F:save[*]\n";
+        let strings = min_strings(text);
+        assert_eq!(2, strings.len());
+        assert_eq!(
+            (b"Fone".to_vec(), b"#(\x80,\x81,\x82)".to_vec()),
+            strings[0]
+        );
+        assert_eq!((b"K.C-x C-s".to_vec(), b"F:save".to_vec()), strings[1]);
+    }
+
+    #[test]
+    fn embedded_library_has_setup() {
+        let strings = min_strings(EMBEDDED_LIBRARY);
+        assert!(strings.iter().any(|(name, _)| name == b"F&setup"));
+        assert!(strings.iter().any(|(name, _)| name == b"K.C-x C-s"));
+    }
 }
@@ -16,22 +16,27 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
-use freemacs::buffer;
-use freemacs::emacs_buffers;
+use freemacs::editor::Editor;
 use freemacs::emacs_window;
-use freemacs::gap_buffer;
-use freemacs::mint;
-
-use freemacs::bufprim;
-use freemacs::frmprim;
-use freemacs::libprim;
-use freemacs::mthprim;
-use freemacs::strprim;
+use freemacs::emacs_window_batch::EmacsWindowBatch;
+use freemacs::emacs_window_session::{EmacsWindowPlayback, EmacsWindowRecorder};
+use freemacs::emacs_window_stream::EmacsWindowStream;
+use freemacs::mint_types::MintCount;
+use freemacs::recovery;
+use freemacs::signals;
+use freemacs::state;
 use freemacs::sysprim;
-use freemacs::varprim;
-use freemacs::winprim;
+use freemacs::transport::Transport;
 
+use std::any::Any;
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process;
 
 const INITIAL_STRING: &[u8] = b"#(rd)#(ow,(\n\
 Freemacs, a programmable editor - Version )##(lv,vn)(\n\
@@ -85,56 +90,473 @@ Compiling the .ED files from the .MIN sources...\n\
 \t\t#(ow,(\
 . - Set the environment string EMACS to the subdirectory\n\
 containing the Freemacs .ED files.  For example, EMACS=/emacs/\n\
-Press any key to exit...))\
-\t\t#(it,10000)#(ow,(\n))#(hl,1)\
+Press any key to edit with the built-in library...))\
+\t\t#(it,10000)#(ll-embedded)#(F&setup)\
 \t))\
 ))";
 
-fn new_window() -> Box<dyn emacs_window::EmacsWindow> {
-    #[cfg(feature = "crossterm")]
-    {
-        use freemacs::emacs_window_crossterm;
-        Box::new(emacs_window_crossterm::EmacsWindowCrossterm::new())
+// The terminal backend called "name", if this was built with it.
+fn backend_window(name: &str) -> Option<Box<dyn emacs_window::EmacsWindow>> {
+    match name {
+        // Only says what would be shown.
+        "debug" => {
+            use freemacs::emacs_window_debug;
+            Some(Box::new(emacs_window_debug::EmacsWindowDebug::new(80, 25)))
+        }
+        // Windows always has crossterm, as ncurses doesn't build there.
+        #[cfg(any(feature = "crossterm", target_os = "windows"))]
+        "crossterm" => {
+            use freemacs::emacs_window_crossterm;
+            Some(Box::new(emacs_window_crossterm::EmacsWindowCrossterm::new()))
+        }
+        #[cfg(all(feature = "curses", not(target_os = "windows")))]
+        "curses" => {
+            use freemacs::emacs_window_curses;
+            Some(Box::new(emacs_window_curses::EmacsWindowCurses::new()))
+        }
+        _ => None,
     }
-    #[cfg(not(feature = "crossterm"))]
-    {
-        use freemacs::emacs_window_curses;
-        Box::new(emacs_window_curses::EmacsWindowCurses::new())
+}
+
+// The backend used unless another is asked for: crossterm if built with
+// it, otherwise curses, otherwise the debug window.
+const DEFAULT_BACKEND: &str = if cfg!(any(feature = "crossterm", target_os = "windows")) {
+    "crossterm"
+} else if cfg!(feature = "curses") {
+    "curses"
+} else {
+    "debug"
+};
+
+// With "gui", open a window of our own if we can, rather than use the
+// terminal.
+fn terminal_window(options: &Options) -> Box<dyn emacs_window::EmacsWindow> {
+    #[cfg(feature = "gui")]
+    if options.gui {
+        use freemacs::emacs_window_gui;
+        match emacs_window_gui::EmacsWindowGui::new(80, 25) {
+            Ok(window) => return Box::new(window),
+            Err(e) => eprintln!("Can't open a window: {}", e),
+        }
     }
+    #[cfg(not(feature = "gui"))]
+    if options.gui {
+        eprintln!("Built without the gui feature, so using the terminal");
+    }
+    let name = options.backend.as_deref().unwrap_or(DEFAULT_BACKEND);
+    backend_window(name).unwrap_or_else(|| {
+        eprintln!("Built without the {} backend", name);
+        process::exit(2);
+    })
 }
 
-fn gap_buffer_factory() -> Box<dyn buffer::Buffer> {
-    Box::new(gap_buffer::GapBuffer::with_default_size())
+// Wait for a connection to "address" and run the session over it.  A
+// "unix:" prefix means the path of a Unix socket, to be used with
+// something like socat; anything else is a TCP address for telnet.
+// Whoever connects can run commands as the user, so the socket is made
+// for the user alone, and TCP addresses must be loopback ones unless
+// "remote" is true.
+fn socket_window(address: &str, remote: bool) -> io::Result<EmacsWindowStream> {
+    #[cfg(unix)]
+    if let Some(path) = address.strip_prefix("unix:") {
+        // Made with mode 0600 rather than changed after, when someone
+        // else could already have connected.
+        let mask = unsafe { libc::umask(0o077) };
+        let listener = UnixListener::bind(path);
+        unsafe { libc::umask(mask) };
+        let listener = listener?;
+        eprintln!("Waiting for a connection on {}", path);
+        let (stream, _) = listener.accept()?;
+        fs::remove_file(path).ok();
+        let transport = Transport::new(stream.try_clone()?, Box::new(stream));
+        return Ok(EmacsWindowStream::new(transport, 80, 24));
+    }
+    let addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    if !remote && addrs.iter().any(|addr| !addr.ip().is_loopback()) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not a loopback address; use --listen-remote to allow others to connect",
+        ));
+    }
+    let listener = TcpListener::bind(&addrs[..])?;
+    eprintln!("Waiting for a connection on {}", listener.local_addr()?);
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true).ok();
+    let transport = Transport::telnet(stream.try_clone()?, Box::new(stream));
+    Ok(EmacsWindowStream::new(transport, 80, 24))
 }
 
-fn main() {
-    emacs_buffers::init_buffers(gap_buffer_factory);
-    emacs_window::init_window(new_window());
+// FREEMACS_RECORD names a file to record the session to, and
+// FREEMACS_PLAYBACK one to play a recorded session back from.
+fn new_window(options: &Options) -> Box<dyn emacs_window::EmacsWindow> {
+    // Open these before the terminal is taken over, so that problems with
+    // them can still be seen.
+    let record = env::var_os("FREEMACS_RECORD").and_then(|path| {
+        File::create(&path)
+            .map_err(|e| eprintln!("{}: {}", path.to_string_lossy(), e))
+            .ok()
+    });
+    let playback = env::var_os("FREEMACS_PLAYBACK").and_then(|path| {
+        fs::read_to_string(&path)
+            .map_err(|e| eprintln!("{}: {}", path.to_string_lossy(), e))
+            .ok()
+    });
 
-    let args: Vec<String> = env::args().collect();
-    let envp: Vec<(String, String)> = env::vars().collect();
+    let mut window = match &options.listen {
+        Some(address) => match socket_window(address, options.listen_remote) {
+            Ok(window) => Box::new(window),
+            Err(e) => {
+                eprintln!("{}: {}", address, e);
+                process::exit(1);
+            }
+        },
+        None => terminal_window(options),
+    };
+    if let Some(log) = playback {
+        window = Box::new(EmacsWindowPlayback::new(window, &log));
+    }
+    if let Some(file) = record {
+        window = Box::new(EmacsWindowRecorder::new(
+            window,
+            Box::new(BufWriter::new(file)),
+        ));
+    }
+    window
+}
 
-    let mut interp = mint::Mint::with_initial_string(INITIAL_STRING);
+// An installed copy of the .ED files, which FREEMACS_DATADIR can name
+// when building.
+const DATADIR: Option<&str> = option_env!("FREEMACS_DATADIR");
+
+// The first directory holding emacs.ed of $XDG_DATA_HOME/freemacs,
+// ~/.local/share/freemacs and DATADIR, with a '/' after it.  Given to the
+// macros as env.EMACS, where an EMACS in the environment still wins and
+// the search of the PATH comes after.
+fn library_dir(envp: &[(String, String)]) -> Option<String> {
+    let var = |name: &str| {
+        envp.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .filter(|value| !value.is_empty())
+    };
+    let mut dirs = Vec::new();
+    // Relative paths are to be ignored, says the spec.
+    if let Some(data_home) = var("XDG_DATA_HOME").filter(|dir| dir.starts_with('/')) {
+        dirs.push(format!("{}/freemacs", data_home));
+    }
+    if let Some(home) = var("HOME") {
+        dirs.push(format!("{}/.local/share/freemacs", home));
+    }
+    dirs.extend(DATADIR.map(String::from));
+    dirs.into_iter()
+        .map(|dir| format!("{}/", dir.trim_end_matches('/')))
+        .find(|dir| Path::new(&format!("{}emacs.ed", dir)).is_file())
+}
 
-    bufprim::register_buf_prims(&mut interp);
-    winprim::register_win_prims(&mut interp);
-    mthprim::register_mth_prims(&mut interp);
-    libprim::register_lib_prims(&mut interp);
-    frmprim::register_frm_prims(&mut interp);
-    strprim::register_str_prims(&mut interp);
-    sysprim::register_sys_prims(&mut interp, &args, &envp);
-    varprim::register_var_prims(&mut interp);
+// Read each file into a buffer of its own, leaving the first current.  For
+// the macros to take them on, env.FILES lists the buffer number, file name
+// and line of each, every one followed by a comma.
+fn open_files(editor: &mut Editor, files: &[(String, MintCount)]) {
+    let mut list = String::new();
+    let mut first = None;
+    for (file, line) in files {
+        match editor.visit_file(file) {
+            Ok(bufno) => {
+                editor.goto_line(*line);
+                first.get_or_insert(bufno);
+                list.push_str(&format!("{},{},{},", bufno, file, line));
+            }
+            Err(e) => eprintln!("{}: {}", file, e),
+        }
+    }
+    if let Some(bufno) = first {
+        editor.select_buffer(bufno);
+    }
+    editor
+        .interp()
+        .set_form_value(b"env.FILES", list.as_bytes());
+}
 
-    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        loop {
-            interp.scan();
+// Run "script" with no window: the file it names or, if it starts with
+// "#(", the MINT it is.  #(ow) writes to standard output.  Returns the
+// code #(hl) gave, or 0 if the script ran to its end.
+fn run_batch(script: &str, options: &Options, args: &[String], envp: &[(String, String)]) -> i32 {
+    let text = if script.starts_with("#(") {
+        script.as_bytes().to_vec()
+    } else {
+        match fs::read(script) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("{}: {}", script, e);
+                return 2;
+            }
         }
-    })) {
-        Ok(_) => {}
+    };
+    let window = EmacsWindowBatch::new(80, 25);
+    let mut editor = Editor::new(Box::new(window), &text, args, envp);
+    state::load_forms(editor.interp());
+    editor
+        .interp()
+        .set_form_value(b"env.BATCH", script.as_bytes());
+    if options.quick {
+        editor.interp().set_form_value(b"env.QUICK", b"1");
+    }
+    open_files(&mut editor, &options.files);
+    // With nothing to scan, the first step would run the default string.
+    if !text.is_empty() {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| editor.step()));
+        if let Err(e) = result {
+            report_panic(&*e, editor.interp().take_panic_report());
+            return 1;
+        }
+    }
+    editor.halted().unwrap_or(0)
+}
+
+// Say why the editor panicked, and what the macros were doing if it was
+// in a primitive, once the terminal is back to normal.
+fn report_panic(e: &(dyn Any + Send), report: Option<String>) {
+    let message = e
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| e.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    eprintln!("Exception: {}", message);
+    if let Some(report) = report {
+        eprint!("{}", report);
+    }
+}
+
+const USAGE: &str = "usage: freemacs [FILES...] [+LINE] [-q] [--batch SCRIPT] \
+[--backend curses|crossterm|debug] [--gui] [--listen ADDRESS [--listen-remote]] [--recover] [--version]";
+
+// Options that are ours rather than the macros'.  "-f COMMAND" is left
+// for the macros to read from env.RUNLINE.
+#[derive(Default, Debug, PartialEq)]
+struct Options {
+    // Files to open, each with the line given by a "+LINE" before it, or
+    // 0 if none was.
+    files: Vec<(String, MintCount)>,
+    // --gui: open a window of our own rather than use the terminal.
+    gui: bool,
+    // --listen ADDRESS: run the session over the first connection to it.
+    listen: Option<String>,
+    // --listen-remote: let --listen take a TCP address other hosts can
+    // reach, rather than only a loopback one.
+    listen_remote: bool,
+    // --backend NAME: the terminal library to use, rather than the default.
+    backend: Option<String>,
+    // -q: the macros are to leave out the user's own settings.  Seen by
+    // them as env.QUICK.
+    quick: bool,
+    // --batch SCRIPT: run SCRIPT with no window, rather than the editor.
+    // Seen by the script as env.BATCH.
+    batch: Option<String>,
+    // --recover: load the forms a session that crashed left behind.
+    recover: bool,
+    // --version: say which version this is, and exit.
+    version: bool,
+}
+
+// Take our options out of "args", leaving the rest for the macros.
+// Returns an error for an option missing its argument.
+fn take_options(args: &mut Vec<String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut rest = Vec::new();
+    let mut line = 0;
+    let mut iter = std::mem::take(args).into_iter();
+    rest.extend(iter.next());
+    while let Some(arg) = iter.next() {
+        let mut value = |option: &str| {
+            iter.next()
+                .ok_or_else(|| format!("{} needs an argument", option))
+        };
+        match arg.as_str() {
+            "--gui" => options.gui = true,
+            "--listen" => options.listen = Some(value(&arg)?),
+            "--listen-remote" => options.listen_remote = true,
+            "--backend" => options.backend = Some(value(&arg)?),
+            "--batch" => options.batch = Some(value(&arg)?),
+            "--recover" => options.recover = true,
+            "--version" => options.version = true,
+            "-q" => options.quick = true,
+            // The command is the macros', and mustn't be taken for ours.
+            "-f" => {
+                let command = value(&arg)?;
+                rest.push(arg);
+                rest.push(command);
+            }
+            "--" => {
+                let files = iter.by_ref().map(|file| (file, std::mem::take(&mut line)));
+                options.files.extend(files);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if arg.starts_with('+') => {
+                line = arg[1..]
+                    .parse()
+                    .map_err(|_| format!("bad line number {}", arg))?;
+            }
+            _ if arg.starts_with('-') => rest.push(arg),
+            _ => options.files.push((arg, std::mem::take(&mut line))),
+        }
+    }
+    *args = rest;
+    Ok(options)
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let envp: Vec<(String, String)> = env::vars().collect();
+    let options = take_options(&mut args).unwrap_or_else(|e| {
+        eprintln!("freemacs: {}\n{}", e, USAGE);
+        process::exit(2);
+    });
+    if options.version {
+        println!("freemacs {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+
+    if let Some(script) = &options.batch {
+        process::exit(run_batch(script, &options, &args, &envp));
+    }
+
+    let mut script = INITIAL_STRING.to_vec();
+    if options.recover {
+        match recovery::latest(&sysprim::default_swap_dir()) {
+            Some(path) => {
+                let load = format!("#(an,##(ll-recovery,{}))", path.to_string_lossy());
+                script.extend_from_slice(load.as_bytes());
+            }
+            None => eprintln!("freemacs: no forms to recover"),
+        }
+    }
+    let mut editor = Editor::new(new_window(&options), &script, &args, &envp);
+    state::load_forms(editor.interp());
+    if let Some(dir) = library_dir(&envp) {
+        editor.interp().set_form_value(b"env.EMACS", dir.as_bytes());
+    }
+    if options.quick {
+        editor.interp().set_form_value(b"env.QUICK", b"1");
+    }
+    open_files(&mut editor, &options.files);
+    // After the window, as curses has handlers of its own.
+    signals::install();
+    editor.keep_recovery();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| editor.run()));
+    let recovery_path = recovery::path(editor.interp());
+    let saved = match result {
+        Ok(_) => recovery::remove(editor.interp()).map(|_| None),
+        Err(_) => recovery::save(editor.interp()).map(|_| Some(recovery_path.clone())),
+    };
+    let report = editor.interp().take_panic_report();
+    // Puts the terminal back, and writes out any session being recorded.
+    drop(editor);
+    let code = match result {
+        Ok(code) => code,
         Err(e) => {
-            eprintln!("Exception: {:?}", e);
+            report_panic(&*e, report);
+            1
         }
+    };
+    match saved {
+        Ok(Some(path)) if path.exists() => {
+            eprintln!(
+                "Forms kept in {}; run with --recover to load them",
+                path.display()
+            );
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("{}: {}", recovery_path.display(), e),
+    }
+    process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> (Result<Options, String>, Vec<String>) {
+        let mut args: Vec<String> = line.split(' ').map(String::from).collect();
+        let options = take_options(&mut args);
+        (options, args)
+    }
+
+    #[test]
+    fn options_are_taken_out() {
+        let (options, rest) = parse(
+            "freemacs -q a.c --backend debug +10 b.c --batch x.min --recover \
+             --listen [::]:2323 --listen-remote",
+        );
+        let expected = Options {
+            files: vec![("a.c".to_string(), 0), ("b.c".to_string(), 10)],
+            listen: Some("[::]:2323".to_string()),
+            listen_remote: true,
+            backend: Some("debug".to_string()),
+            quick: true,
+            batch: Some("x.min".to_string()),
+            recover: true,
+            ..Options::default()
+        };
+        assert_eq!(Ok(expected), options);
+        assert_eq!(vec!["freemacs"], rest);
+    }
+
+    #[test]
+    fn macro_options_are_left() {
+        let (options, rest) = parse("freemacs -f --version -x +3 -- --gui");
+        let expected = Options {
+            files: vec![("--gui".to_string(), 3)],
+            ..Options::default()
+        };
+        assert_eq!(Ok(expected), options);
+        assert_eq!(vec!["freemacs", "-f", "--version", "-x"], rest);
+    }
+
+    #[test]
+    fn bad_options_are_errors() {
+        assert_eq!(
+            Err("--batch needs an argument".to_string()),
+            parse("freemacs --batch").0
+        );
+        assert_eq!(
+            Err("unknown option --bogus".to_string()),
+            parse("freemacs --bogus").0
+        );
+        assert_eq!(
+            Err("bad line number +x".to_string()),
+            parse("freemacs +x a").0
+        );
+    }
+
+    #[test]
+    fn listening_is_only_local_unless_asked() {
+        let e = socket_window("0.0.0.0:0", false).err().unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, e.kind());
+    }
+
+    #[test]
+    fn library_is_found_in_data_dirs() {
+        let root = env::temp_dir().join(format!("freemacs-data-{}", process::id()));
+        let data_home = root.join("data");
+        let home = root.join("home");
+        let local = home.join(".local/share/freemacs");
+        fs::create_dir_all(data_home.join("freemacs")).unwrap();
+        fs::create_dir_all(&local).unwrap();
+        fs::write(local.join("emacs.ed"), "").unwrap();
+
+        let mut envp = vec![
+            ("XDG_DATA_HOME".to_string(), data_home.display().to_string()),
+            ("HOME".to_string(), home.display().to_string()),
+        ];
+        let local = format!("{}/", local.display());
+        assert_eq!(Some(local.clone()), library_dir(&envp));
+
+        fs::write(data_home.join("freemacs/emacs.ed"), "").unwrap();
+        let xdg = format!("{}/freemacs/", data_home.display());
+        assert_eq!(Some(xdg), library_dir(&envp));
+
+        envp[0].1 = "data".to_string();
+        assert_eq!(Some(local), library_dir(&envp));
+        fs::remove_dir_all(&root).ok();
     }
-    emacs_window::free_window();
-    emacs_buffers::free_buffers();
 }
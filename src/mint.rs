@@ -16,21 +16,65 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
+use crate::context::Context;
+use crate::keyboard_macro;
 use crate::mint_arg::{ArgType, MintArg, MintArgList};
 use crate::mint_form::MintForm;
 use crate::mint_types::{MintChar, MintCount, MintString};
-use std::collections::{HashMap, VecDeque};
-use std::rc::Rc;
-
-pub trait MintPrim {
+use crate::worker;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Primitives and variables are shared by the interpreters they are added
+// to, which can be on any thread.
+pub trait MintPrim: Send + Sync {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList);
 }
 
-pub trait MintVar {
+// A primitive made from a closure by add_prim_fn.
+struct FnPrim<F>(F);
+
+impl<F> MintPrim for FnPrim<F>
+where
+    F: Fn(&mut Mint, bool, &MintArgList) + Send + Sync,
+{
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        (self.0)(interp, is_active, args)
+    }
+}
+
+pub trait MintVar: Send + Sync {
     fn get_val(&self, interp: &Mint) -> MintString;
     fn set_val(&self, interp: &mut Mint, val: &MintString);
 }
 
+// A variable that just holds its value, as #(sv) makes for names that
+// aren't variables already.
+pub struct StoredVar {
+    value: Mutex<MintString>,
+}
+
+impl StoredVar {
+    pub fn new(val: &[MintChar]) -> Self {
+        Self {
+            value: Mutex::new(val.to_vec()),
+        }
+    }
+}
+
+impl MintVar for StoredVar {
+    fn get_val(&self, _interp: &Mint) -> MintString {
+        self.value.lock().unwrap().clone()
+    }
+
+    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+        *self.value.lock().unwrap() = val.clone();
+    }
+}
+
 struct ActiveString {
     data: VecDeque<MintChar>,
 }
@@ -158,6 +202,20 @@ impl NeutralString {
     }
 }
 
+// A form to be called once the active string is done, after "due".
+struct Timer {
+    form: MintString,
+    due: Instant,
+    interval: Option<Duration>,
+}
+
+// Forms called before and after a primitive runs, null for none.
+#[derive(Clone)]
+struct PrimHook {
+    before: MintString,
+    after: MintString,
+}
+
 pub struct Mint {
     idle_max: i32,
     idle_count: i32,
@@ -167,8 +225,28 @@ pub struct Mint {
     active_string: ActiveString,
     neutral_string: NeutralString,
     forms: HashMap<MintString, MintForm>,
-    vars: HashMap<MintString, Rc<Box<dyn MintVar>>>,
-    prims: HashMap<MintString, Rc<Box<dyn MintPrim>>>,
+    // Forms defined since they were last loaded from or saved to a
+    // library, and how many times forms have been defined or erased.
+    changed_forms: HashSet<MintString>,
+    form_changes: u64,
+    vars: HashMap<MintString, Arc<Box<dyn MintVar>>>,
+    prims: HashMap<MintString, Arc<Box<dyn MintPrim>>>,
+    // Names for groups of primitives, such as "change", that hooks can be
+    // set on all at once, and the hooks set.
+    prim_classes: HashMap<MintString, Vec<MintString>>,
+    prim_hooks: HashMap<MintString, PrimHook>,
+    // Set while a hook runs, so that the primitives it uses don't call it.
+    in_hook: bool,
+    // Forms called as active functions whose text is still being scanned,
+    // innermost last, each with the length the active string had below
+    // its text.
+    expanding: Vec<(MintString, usize)>,
+    // What was going on when a primitive last panicked.
+    panic_report: Option<String>,
+    timers: Vec<Timer>,
+    // Set by #(hl), with the code to exit with.
+    halt_code: Option<i32>,
+    context: Context,
 }
 
 impl Default for Mint {
@@ -193,8 +271,18 @@ impl Mint {
             active_string: ActiveString::new(),
             neutral_string: NeutralString::new(),
             forms: HashMap::new(),
+            changed_forms: HashSet::new(),
+            form_changes: 0,
             vars: HashMap::new(),
             prims: HashMap::new(),
+            prim_classes: HashMap::new(),
+            prim_hooks: HashMap::new(),
+            in_hook: false,
+            expanding: Vec::new(),
+            panic_report: None,
+            timers: Vec::new(),
+            halt_code: None,
+            context: Context::new(),
         };
 
         mint.active_string.push_front(DEFAULT_STRING_NOKEY);
@@ -209,11 +297,54 @@ impl Mint {
     }
 
     pub fn add_var(&mut self, name: MintString, var: Box<dyn MintVar>) {
-        self.vars.insert(name, Rc::new(var));
+        self.vars.insert(name, Arc::new(var));
     }
 
     pub fn add_prim(&mut self, name: MintString, prim: Box<dyn MintPrim>) {
-        self.prims.insert(name, Rc::new(prim));
+        self.prims.insert(name, Arc::new(prim));
+    }
+
+    // Add a primitive that runs "f", for when a struct of its own would be
+    // more than it needs.
+    pub fn add_prim_fn<F>(&mut self, name: MintString, f: F)
+    where
+        F: Fn(&mut Mint, bool, &MintArgList) + Send + Sync + 'static,
+    {
+        self.add_prim(name, Box::new(FnPrim(f)));
+    }
+
+    // Add "prims" to the class "class", making it if need be.
+    pub fn add_prim_class(&mut self, class: &[MintChar], prims: &[&[MintChar]]) {
+        let members = self.prim_classes.entry(class.to_vec()).or_default();
+        members.extend(prims.iter().map(|prim| prim.to_vec()));
+    }
+
+    // Call form "before" before, and form "after" after, the primitive
+    // "name" runs, or each primitive in the class "name".  Both null
+    // removes the hook.  Returns false if "name" is neither.
+    pub fn set_prim_hook(
+        &mut self,
+        name: &[MintChar],
+        before: &[MintChar],
+        after: &[MintChar],
+    ) -> bool {
+        let prims = match self.prim_classes.get(name) {
+            Some(members) => members.clone(),
+            None if self.prims.contains_key(name) => vec![name.to_vec()],
+            None => return false,
+        };
+        for prim in prims {
+            if before.is_empty() && after.is_empty() {
+                self.prim_hooks.remove(&prim);
+            } else {
+                let hook = PrimHook {
+                    before: before.to_vec(),
+                    after: after.to_vec(),
+                };
+                self.prim_hooks.insert(prim, hook);
+            }
+        }
+        true
     }
 
     pub fn get_var(&self, var_name: &MintString) -> MintString {
@@ -227,18 +358,23 @@ impl Mint {
         var.unwrap_or_default()
     }
 
+    // The names of the variables, in order.
+    pub fn var_names(&self) -> Vec<MintString> {
+        let mut names: Vec<MintString> = self.vars.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // Set variable "var_name" to "val", making it if there is none.
     pub fn set_var(&mut self, var_name: &MintString, val: &MintString) {
         if let Some(var) = self.vars.get(var_name).cloned() {
             var.set_val(self, val);
-        } else if cfg!(debug_assertions) {
-            eprintln!(
-                "Can't find variable '{:?}' while writing",
-                String::from_utf8_lossy(var_name)
-            );
+        } else {
+            self.add_var(var_name.clone(), Box::new(StoredVar::new(val)));
         }
     }
 
-    pub fn get_prim(&self, prim_name: &[MintChar]) -> Option<Rc<Box<dyn MintPrim>>> {
+    pub fn get_prim(&self, prim_name: &[MintChar]) -> Option<Arc<Box<dyn MintPrim>>> {
         self.prims.get(prim_name).cloned()
     }
 
@@ -266,6 +402,11 @@ impl Mint {
         }
     }
 
+    // Have "s" scanned next, whatever the current function returns.
+    pub fn push_active(&mut self, s: &[MintChar]) {
+        self.active_string.push_front(s);
+    }
+
     pub fn return_integer(&mut self, is_active: bool, n: i32, base: i32) {
         let mut s = Vec::new();
         crate::mint_string::append_num(&mut s, n, base);
@@ -340,6 +481,74 @@ impl Mint {
         self.idle_max
     }
 
+    // Call "form" after "delay", and every "delay" after that if "repeat".
+    // Any timer already set for "form" is replaced.
+    pub fn add_timer(&mut self, form: &[MintChar], delay: Duration, repeat: bool) {
+        self.cancel_timer(form);
+        self.timers.push(Timer {
+            form: form.to_vec(),
+            due: Instant::now() + delay,
+            interval: repeat.then_some(delay),
+        });
+    }
+
+    // Cancel the timer for "form", or all of them if "form" is empty.
+    pub fn cancel_timer(&mut self, form: &[MintChar]) {
+        self.timers
+            .retain(|timer| !form.is_empty() && timer.form != form);
+    }
+
+    // When the next timer is due, if there is one.
+    pub fn next_timer(&self) -> Option<Instant> {
+        self.timers.iter().map(|timer| timer.due).min()
+    }
+
+    // Calls to the forms whose timers are due, in the order they came due.
+    // Repeating timers are set again from now, so that a slow form can't
+    // leave them running back to back.
+    fn due_timer_calls(&mut self) -> MintString {
+        let now = Instant::now();
+        let mut due: Vec<(Instant, MintString)> = Vec::new();
+        self.timers.retain_mut(|timer| {
+            if timer.due > now {
+                return true;
+            }
+            due.push((timer.due, timer.form.clone()));
+            match timer.interval {
+                Some(interval) => {
+                    timer.due = now + interval;
+                    true
+                }
+                None => false,
+            }
+        });
+        due.sort_by_key(|(when, _)| *when);
+        let mut calls = Vec::new();
+        for (_, form) in due {
+            calls.extend_from_slice(b"#((");
+            calls.extend_from_slice(&form);
+            calls.extend_from_slice(b"))");
+        }
+        calls
+    }
+
+    // Stop scanning for good, leaving whoever runs the interpreter to exit
+    // with "code".
+    pub fn halt(&mut self, code: i32) {
+        self.halt_code = Some(code);
+        self.active_string.clear();
+        self.neutral_string.clear();
+    }
+
+    pub fn halted(&self) -> Option<i32> {
+        self.halt_code
+    }
+
+    // The buffers and window the primitives work on.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
     pub fn set_form_pos(&mut self, form_name: &MintString, n: MintCount) {
         if let Some(form) = self.forms.get_mut(form_name) {
             form.set_pos(n);
@@ -356,21 +565,66 @@ impl Mint {
 
     pub fn del_form(&mut self, form_name: &[MintChar]) {
         self.forms.remove(form_name);
+        self.changed_forms.remove(form_name);
+        self.form_changes += 1;
     }
 
     pub fn set_form_value(&mut self, form_name: &[MintChar], value: &[MintChar]) {
         self.forms
             .insert(form_name.to_vec(), MintForm::from_string(value));
+        self.changed_forms.insert(form_name.to_vec());
+        self.form_changes += 1;
+    }
+
+    // Define form "form_name" as "range" of "source", copied only when it
+    // is first wanted.
+    pub fn set_form_source(
+        &mut self,
+        form_name: &[MintChar],
+        source: Arc<[MintChar]>,
+        range: Range<usize>,
+    ) {
+        self.forms
+            .insert(form_name.to_vec(), MintForm::from_source(source, range));
+        self.changed_forms.insert(form_name.to_vec());
+        self.form_changes += 1;
+    }
+
+    // The forms defined since they were last loaded from or saved to a
+    // library, in order.
+    pub fn changed_forms(&self) -> Vec<&MintString> {
+        let mut names: Vec<&MintString> = self.changed_forms.iter().collect();
+        names.sort();
+        names
+    }
+
+    // Take form "form_name" to be as kept in a library.
+    pub fn forget_form_change(&mut self, form_name: &[MintChar]) {
+        self.changed_forms.remove(form_name);
+    }
+
+    // How many times forms have been defined or erased, to tell whether
+    // any have been since.
+    pub fn form_changes(&self) -> u64 {
+        self.form_changes
     }
 
     pub fn scan(&mut self) {
+        if self.halt_code.is_some() {
+            return;
+        }
         if self.active_string.is_empty() {
             self.neutral_string.clear();
-            if !self.idle_string.is_empty() {
+            let mut calls = self.due_timer_calls();
+            calls.extend(worker::finished_calls(self));
+            if !calls.is_empty() {
+                self.active_string.load(&calls);
+            } else if !self.idle_string.is_empty() {
                 self.active_string.load(&self.idle_string.clone());
                 self.idle_string.clear();
             } else {
-                let default = if key_waiting() {
+                keyboard_macro::end_command(self);
+                let default = if key_waiting(self) {
                     &self.default_string_key
                 } else {
                     &self.default_string_nokey
@@ -378,7 +632,11 @@ impl Mint {
                 self.active_string.load(default);
             }
         }
+        self.scan_active();
+    }
 
+    // Scan what is in the active string until it is all gone.
+    fn scan_active(&mut self) {
         let mut pos = 0;
         while pos < self.active_string.data.len() {
             let ch = self.active_string.data[pos];
@@ -543,18 +801,52 @@ impl Mint {
 
         let is_active = args[0].arg_type() == ArgType::Active;
         let func_name = args[0].value();
+        // Forms whose text has all been scanned are done.
+        let remaining = self.active_string.data.len();
+        while self
+            .expanding
+            .last()
+            .is_some_and(|(_, below)| *below >= remaining)
+        {
+            self.expanding.pop();
+        }
 
         if let Some(prim) = self.get_prim(func_name) {
-            prim.execute(self, is_active, &args);
-        } else if let Some(form) = self.forms.get(func_name) {
-            let pos = form.get_pos();
-            let content = form.content()[pos as usize..].to_vec();
-            self.return_seg_string(is_active, &content, &args);
+            let hook = if self.in_hook {
+                None
+            } else {
+                self.prim_hooks.get(func_name).cloned()
+            };
+            if let Some(hook) = &hook {
+                self.run_hook(&hook.before, func_name);
+            }
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                prim.execute(self, is_active, &args);
+            }));
+            if let Err(payload) = result {
+                // Only the innermost primitive's report is kept.
+                if self.panic_report.is_none() {
+                    self.panic_report = Some(self.report(&args));
+                }
+                panic::resume_unwind(payload);
+            }
+            if let Some(hook) = &hook {
+                self.run_hook(&hook.after, func_name);
+            }
         } else {
-            let default_name: &[MintChar] = if is_active { DFLTA } else { DFLTN };
-            if let Some(form) = self.forms.get(default_name) {
+            let form_name: &[MintChar] = if self.forms.contains_key(func_name) {
+                func_name
+            } else if is_active {
+                DFLTA
+            } else {
+                DFLTN
+            };
+            if let Some(form) = self.forms.get(form_name) {
                 let pos = form.get_pos();
                 let content = form.content()[pos as usize..].to_vec();
+                if is_active {
+                    self.expanding.push((form_name.to_vec(), remaining));
+                }
                 self.return_seg_string(is_active, &content, &args);
             }
         }
@@ -562,6 +854,91 @@ impl Mint {
         true
     }
 
+    // Call "form" with "prim_name" as its argument, there and then, leaving
+    // the scan that is going on as it was.  What it returns is dropped.
+    fn run_hook(&mut self, form: &[MintChar], prim_name: &[MintChar]) {
+        if form.is_empty() {
+            return;
+        }
+        let mut call = b"#((".to_vec();
+        call.extend_from_slice(form);
+        call.extend_from_slice(b"),");
+        call.extend_from_slice(prim_name);
+        call.push(b')');
+        let active = std::mem::replace(&mut self.active_string, ActiveString::new());
+        let neutral = std::mem::replace(&mut self.neutral_string, NeutralString::new());
+        let expanding = std::mem::take(&mut self.expanding);
+        self.active_string.load(&call);
+        self.in_hook = true;
+        self.scan_active();
+        self.in_hook = false;
+        self.active_string = active;
+        self.neutral_string = neutral;
+        self.expanding = expanding;
+    }
+
+    // The forms being expanded, innermost first.  A form is being expanded
+    // from when it is called as an active function until its text has all
+    // been scanned.
+    pub fn backtrace(&self) -> Vec<MintString> {
+        let remaining = self.active_string.data.len();
+        self.expanding
+            .iter()
+            .rev()
+            .filter(|(_, below)| *below < remaining)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    // What was going on when a primitive panicked, if one has: the call,
+    // the forms being expanded, and the start of the active string and end
+    // of the neutral string.
+    pub fn take_panic_report(&mut self) -> Option<String> {
+        self.panic_report.take()
+    }
+
+    fn report(&self, args: &MintArgList) -> String {
+        const SHOWN: usize = 60;
+        let mut call = if args[0].arg_type() == ArgType::Active {
+            b"#(".to_vec()
+        } else {
+            b"##(".to_vec()
+        };
+        for (i, arg) in args.iter().enumerate() {
+            match arg.arg_type() {
+                ArgType::End => break,
+                _ if i > 0 => call.push(b','),
+                _ => {}
+            }
+            call.extend_from_slice(arg.value());
+        }
+        call.push(b')');
+        let mut report = format!("in {}\n", shown(&call, SHOWN, false));
+        for form in self.backtrace() {
+            report.push_str(&format!("  called from {}\n", shown(&form, SHOWN, false)));
+        }
+        let active: MintString = self.active_string.data.iter().copied().collect();
+        report.push_str(&format!(
+            "active string: {}\n",
+            shown(&active, SHOWN, false)
+        ));
+        let mut neutral = Vec::new();
+        for arg in self.neutral_string.args.iter().rev() {
+            match arg.arg_type() {
+                ArgType::Active => neutral.extend_from_slice(b"#("),
+                ArgType::Neutral => neutral.extend_from_slice(b"##("),
+                ArgType::Arg => neutral.push(b','),
+                ArgType::Null | ArgType::End => {}
+            }
+            neutral.extend_from_slice(arg.value());
+        }
+        report.push_str(&format!(
+            "neutral string: {}\n",
+            shown(&neutral, SHOWN, true)
+        ));
+        report
+    }
+
     pub fn return_seg_string(&mut self, is_active: bool, ss: &MintString, args: &MintArgList) {
         if is_active {
             for &ch in ss.iter().rev() {
@@ -585,6 +962,35 @@ impl Mint {
     }
 }
 
-fn key_waiting() -> bool {
-    crate::winprim::key_waiting()
+// At most "max" characters of "s", from its end if "from_end", with what
+// isn't printable escaped.
+fn shown(s: &[MintChar], max: usize, from_end: bool) -> String {
+    let text: Vec<char> = String::from_utf8_lossy(s)
+        .chars()
+        .flat_map(char::escape_debug)
+        .collect();
+    if text.len() <= max {
+        return text.into_iter().collect();
+    }
+    let text: String = if from_end {
+        text[text.len() - max..].iter().collect()
+    } else {
+        text[..max].iter().collect()
+    };
+    if from_end {
+        format!("...{}", text)
+    } else {
+        format!("{}...", text)
+    }
+}
+
+fn key_waiting(interp: &Mint) -> bool {
+    crate::winprim::key_waiting(interp)
 }
+
+// An interpreter can be handed to another thread, or kept behind a mutex
+// that background tasks share.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Mint>();
+};
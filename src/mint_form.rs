@@ -17,52 +17,98 @@
  */
 
 use crate::mint_types::{MintChar, MintCount, MintString};
+use std::cell::OnceCell;
 use std::cmp::min;
+use std::ops::Range;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct MintForm {
-    content: MintString,
+    content: OnceCell<MintString>,
+    // For a form loaded from a library, the library and where in it the
+    // content is, to be copied the first time it is wanted.
+    source: Option<(Arc<[MintChar]>, Range<usize>)>,
     index: MintCount,
 }
 
 impl MintForm {
     pub fn from_string(s: &[MintChar]) -> Self {
         Self {
-            content: s.to_vec(),
+            content: OnceCell::from(s.to_vec()),
+            source: None,
+            index: 0,
+        }
+    }
+
+    // A form whose content is "range" of "source", which is kept until the
+    // form is dropped.
+    pub fn from_source(source: Arc<[MintChar]>, range: Range<usize>) -> Self {
+        Self {
+            content: OnceCell::new(),
+            source: Some((source, range)),
             index: 0,
         }
     }
 
     pub fn set_pos(&mut self, n: MintCount) {
-        self.index = min(n, self.content.len() as MintCount);
+        self.index = min(n, self.len() as MintCount);
     }
 
     pub fn get_pos(&self) -> MintCount {
-        min(self.index, self.content.len() as MintCount)
+        min(self.index, self.len() as MintCount)
     }
 
     pub fn at_end(&self) -> bool {
-        self.index >= self.content.len() as MintCount
+        self.index >= self.len() as MintCount
     }
 
     pub fn get_n(&mut self, n: i32) -> MintString {
-        self.index = min(self.index, self.content.len() as MintCount);
+        self.index = min(self.index, self.len() as MintCount);
         let len = min(
-            (self.content.len() as MintCount) - self.index,
+            (self.len() as MintCount) - self.index,
             n.max(0) as MintCount,
         );
         let start = self.index as usize;
-        let result = self.content[start..start + len as usize].to_vec();
+        let result = self.content()[start..start + len as usize].to_vec();
         self.index += len;
         result
     }
 
     pub fn get(&self) -> MintString {
-        let index = min(self.index, self.content.len() as MintCount);
-        self.content[index as usize..].to_vec()
+        let index = min(self.index, self.len() as MintCount);
+        self.content()[index as usize..].to_vec()
+    }
+
+    // The length of the content, without copying it from a library.
+    fn len(&self) -> usize {
+        match (self.content.get(), &self.source) {
+            (None, Some((_, range))) => range.len(),
+            _ => self.content().len(),
+        }
     }
 
     pub fn content(&self) -> &MintString {
-        &self.content
+        self.content.get_or_init(|| match &self.source {
+            Some((source, range)) => source[range.clone()].to_vec(),
+            None => MintString::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_is_copied_when_wanted() {
+        let source: Arc<[MintChar]> = Arc::from(&b"xxhello worldyy"[..]);
+        let mut form = MintForm::from_source(source, 2..13);
+        form.set_pos(6);
+        assert_eq!(6, form.get_pos());
+        assert!(form.content.get().is_none());
+        assert_eq!(b"world".to_vec(), form.get());
+        assert_eq!(b"hello world".to_vec(), *form.content());
+        assert_eq!(b"world".to_vec(), form.get_n(10));
+        assert!(form.at_end());
     }
 }
@@ -0,0 +1,409 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+use crate::buffer::Buffer;
+use crate::mint_types::{MintChar, MintCount};
+use crate::piece_list::{self, LastPiece};
+use memmap2::Mmap;
+use regex::bytes::Regex;
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Original,
+    Added,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    source: Source,
+    start: usize,
+    len: usize,
+}
+
+impl piece_list::Piece for Piece {
+    fn length(&self) -> usize {
+        self.len
+    }
+
+    fn split_off(&mut self, at: usize) -> Self {
+        let rest = Piece {
+            source: self.source,
+            start: self.start + at,
+            len: self.len - at,
+        };
+        self.len = at;
+        rest
+    }
+}
+
+// Buffer that maps a file read-only and keeps edits as a list of pieces
+// referring either to the mapped file or to an append-only buffer of
+// added text.  Unmodified parts of the file are never copied, until the
+// file is about to be rewritten; see unmap_file.
+#[derive(Debug, Default)]
+pub struct MmapBuffer {
+    original: Option<Mmap>,
+    // Device and inode of the mapped file, to know it again by.
+    #[cfg(unix)]
+    original_id: (u64, u64),
+    added: Vec<MintChar>,
+    pieces: Vec<Piece>,
+    size: MintCount,
+    last_piece: LastPiece,
+}
+
+impl MmapBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn piece_slice(&self, piece: &Piece) -> &[MintChar] {
+        let range = piece.start..piece.start + piece.len;
+        match piece.source {
+            Source::Original => &self.original.as_ref().unwrap()[range],
+            Source::Added => &self.added[range],
+        }
+    }
+
+    fn locate(&self, offset: MintCount) -> (usize, MintCount) {
+        piece_list::locate(&self.pieces, &self.last_piece, offset)
+    }
+
+    fn split_at(&mut self, offset: MintCount) -> usize {
+        piece_list::split_at(&mut self.pieces, &self.last_piece, self.size, offset)
+    }
+
+    fn slice<'a>(&'a self, start: MintCount, end: MintCount) -> Cow<'a, [MintChar]> {
+        if start >= end {
+            return Cow::Borrowed(&[]);
+        }
+        let (mut index, piece_start) = self.locate(start);
+        let offset = (start - piece_start) as usize;
+        let len = (end - start) as usize;
+        let first = self.piece_slice(&self.pieces[index]);
+        if offset + len <= first.len() {
+            return Cow::Borrowed(&first[offset..offset + len]);
+        }
+        let mut v = Vec::with_capacity(len);
+        v.extend_from_slice(&first[offset..]);
+        while v.len() < len {
+            index += 1;
+            let piece = self.piece_slice(&self.pieces[index]);
+            let take = piece.len().min(len - v.len());
+            v.extend_from_slice(&piece[..take]);
+        }
+        Cow::Owned(v)
+    }
+}
+
+impl Buffer for MmapBuffer {
+    fn size(&self) -> MintCount {
+        self.size
+    }
+
+    fn capacity(&self) -> MintCount {
+        self.added.capacity() as MintCount
+    }
+
+    fn get(&self, offset: MintCount) -> Option<MintChar> {
+        if offset >= self.size {
+            return None;
+        }
+        let (index, start) = self.locate(offset);
+        Some(self.piece_slice(&self.pieces[index])[(offset - start) as usize])
+    }
+
+    fn replace(&mut self, offset: MintCount, n: MintCount, replacement: &[MintChar]) -> bool {
+        self.erase(offset, n) && self.insert(offset, replacement)
+    }
+
+    fn erase(&mut self, offset: MintCount, n: MintCount) -> bool {
+        if offset > self.size || self.size - offset < n {
+            return false;
+        }
+        if n > 0 {
+            let first = self.split_at(offset);
+            let last = self.split_at(offset + n);
+            self.pieces.drain(first..last);
+            self.size -= n;
+            self.last_piece.set((0, 0));
+        }
+        true
+    }
+
+    fn new_empty(&self) -> Box<dyn Buffer> {
+        Box::new(MmapBuffer::new())
+    }
+
+    fn insert(&mut self, offset: MintCount, to_insert: &[MintChar]) -> bool {
+        if offset > self.size {
+            return false;
+        }
+        if to_insert.is_empty() {
+            return true;
+        }
+        let index = self.split_at(offset);
+        let start = self.added.len();
+        self.added.extend_from_slice(to_insert);
+        // Typing at the end of the previous insertion just extends it.
+        if index > 0
+            && let Some(prev) = self.pieces.get_mut(index - 1)
+            && prev.source == Source::Added
+            && prev.start + prev.len == start
+        {
+            prev.len += to_insert.len();
+        } else {
+            self.pieces.insert(
+                index,
+                Piece {
+                    source: Source::Added,
+                    start,
+                    len: to_insert.len(),
+                },
+            );
+        }
+        self.size += to_insert.len() as MintCount;
+        self.last_piece.set((0, 0));
+        true
+    }
+
+    fn for_each_chunk(
+        &self,
+        start: MintCount,
+        end: MintCount,
+        reverse: bool,
+        f: &mut dyn FnMut(MintCount, &[MintChar]) -> bool,
+    ) {
+        let end = end.min(self.size);
+        if start >= end {
+            return;
+        }
+        let (mut index, mut piece_start) = self.locate(if reverse { end - 1 } else { start });
+        loop {
+            let piece = &self.pieces[index];
+            let piece_end = piece_start + piece.len as MintCount;
+            let from = start.max(piece_start);
+            let to = end.min(piece_end);
+            let slice = self.piece_slice(piece);
+            let chunk = &slice[(from - piece_start) as usize..(to - piece_start) as usize];
+            if !f(from, chunk) {
+                return;
+            }
+            if reverse {
+                if from == start {
+                    return;
+                }
+                index -= 1;
+                piece_start -= self.pieces[index].len as MintCount;
+            } else {
+                if to == end {
+                    return;
+                }
+                index += 1;
+                piece_start = piece_end;
+            }
+        }
+    }
+
+    fn map_file(&mut self, file: &fs::File) -> io::Result<bool> {
+        if self.size != 0 {
+            return Ok(false);
+        }
+        // Files that say they are empty may not be, such as those in /proc,
+        // so are left to be read.
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+        if len == 0 {
+            return Ok(false);
+        }
+        if len > MintCount::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                "file too large",
+            ));
+        }
+        // SAFETY: The mapping is read-only, and the editor unmaps it before
+        // rewriting the file.  As with any mapped file, contents are
+        // undefined if another process truncates it while mapped.
+        let map = unsafe { Mmap::map(file)? };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            self.original_id = (metadata.dev(), metadata.ino());
+        }
+        self.pieces = vec![Piece {
+            source: Source::Original,
+            start: 0,
+            len: map.len(),
+        }];
+        self.size = map.len() as MintCount;
+        self.original = Some(map);
+        self.last_piece.set((0, 0));
+        Ok(true)
+    }
+
+    #[cfg(unix)]
+    fn maps_file(&self, file: &fs::Metadata) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        self.original.is_some() && self.original_id == (file.dev(), file.ino())
+    }
+
+    // Without a way to tell files apart, any mapping might be of "file".
+    #[cfg(not(unix))]
+    fn maps_file(&self, _file: &fs::Metadata) -> bool {
+        self.original.is_some()
+    }
+
+    fn unmap_file(&mut self) {
+        let Some(original) = self.original.take() else {
+            return;
+        };
+        for piece in self.pieces.iter_mut() {
+            if piece.source == Source::Original {
+                let start = self.added.len();
+                self.added
+                    .extend_from_slice(&original[piece.start..piece.start + piece.len]);
+                piece.source = Source::Added;
+                piece.start = start;
+            }
+        }
+    }
+
+    fn find_forward(
+        &self,
+        regex: &Regex,
+        start: MintCount,
+        end: MintCount,
+    ) -> Option<(MintCount, MintCount)> {
+        let slice = self.slice(start, end);
+        regex.find(&slice).map(|matched| {
+            (
+                start + matched.start() as MintCount,
+                start + matched.end() as MintCount,
+            )
+        })
+    }
+
+    fn find_backward(
+        &self,
+        regex: &Regex,
+        start: MintCount,
+        end: MintCount,
+    ) -> Option<(MintCount, MintCount)> {
+        let slice = self.slice(start, end);
+        regex.find_iter(&slice).last().map(|matched| {
+            (
+                start + matched.start() as MintCount,
+                start + matched.end() as MintCount,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn to_string<T: Buffer>(buf: &T) -> String {
+        let mut s = Vec::new();
+        for i in 0..buf.size() {
+            s.push(buf.get(i).unwrap());
+        }
+        String::from_utf8(s).unwrap()
+    }
+
+    fn mapped(contents: &str) -> MmapBuffer {
+        let file = tempfile_with(contents);
+        let mut mb = MmapBuffer::new();
+        assert!(mb.map_file(&file).unwrap());
+        mb
+    }
+
+    fn tempfile_with(contents: &str) -> fs::File {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "freemacs-mmap-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, contents).unwrap();
+        let file = fs::File::open(&path).unwrap();
+        fs::remove_file(&path).ok();
+        file
+    }
+
+    #[test]
+    fn mmap_buffer_insert_erase() {
+        let mut mb = MmapBuffer::new();
+        assert!(mb.insert(0, b"0189"));
+        assert!(mb.insert(2, b"234567"));
+        assert_eq!("0123456789", to_string(&mb));
+        assert!(mb.erase(1, 8));
+        assert_eq!("09", to_string(&mb));
+        assert!(!mb.erase(1, 2));
+        assert!(!mb.insert(3, b"x"));
+    }
+
+    #[test]
+    fn mmap_buffer_maps_file() {
+        let mut mb = mapped("hello world");
+        assert_eq!(11, mb.size());
+        assert!(mb.replace(0, 5, b"goodbye"));
+        assert_eq!("goodbye world", to_string(&mb));
+        assert_eq!(Source::Original, mb.pieces[1].source);
+        assert!(!mb.map_file(&tempfile_with("x")).unwrap());
+    }
+
+    #[test]
+    fn mmap_buffer_unmaps_file() {
+        let mut mb = mapped("hello world");
+        assert!(mb.replace(0, 5, b"goodbye"));
+        mb.unmap_file();
+        assert!(mb.original.is_none());
+        assert!(mb.pieces.iter().all(|piece| piece.source == Source::Added));
+        assert_eq!("goodbye world", to_string(&mb));
+        assert!(mb.insert(13, b"!"));
+        assert_eq!("goodbye world!", to_string(&mb));
+    }
+
+    #[test]
+    fn mmap_buffer_byte_scanning() {
+        let mut mb = mapped("a\nb\nc");
+        assert!(mb.insert(2, b"x\ny"));
+        assert_eq!("a\nx\nyb\nc", to_string(&mb));
+        assert_eq!(3, mb.count_byte(b'\n', 0, mb.size()));
+        assert_eq!(Some(3), mb.find_byte_forward(b'\n', 2, mb.size()));
+        assert_eq!(Some(6), mb.find_byte_forward(b'\n', 4, mb.size()));
+        assert_eq!(Some(3), mb.find_byte_backward(b'\n', 0, 6));
+        assert_eq!(None, mb.find_byte_backward(b'\n', 2, 3));
+    }
+
+    #[test]
+    fn mmap_buffer_find_across_pieces() {
+        let mut mb = mapped("abcdef");
+        assert!(mb.insert(3, b"XY"));
+        let re = Regex::new("cXYd").unwrap();
+        assert_eq!(Some((2, 6)), mb.find_forward(&re, 0, mb.size()));
+        assert_eq!(Some((2, 6)), mb.find_backward(&re, 0, mb.size()));
+        assert_eq!(None, mb.find_forward(&re, 3, mb.size()));
+    }
+}
@@ -76,7 +76,7 @@ impl MintPrim for BcPrim {
 }
 
 // Binary operation helper trait
-trait BinaryOp {
+trait BinaryOp: Send + Sync {
     fn perform(&self, a1: i32, a2: i32) -> i32;
 }
 
@@ -0,0 +1,105 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Finding and splitting pieces, for the buffers kept as lists of them. */
+
+// PieceTableBuffer and MmapBuffer both describe their text as a list of
+// pieces, each some run of bytes held elsewhere.  What a piece refers to
+// differs, but finding the piece an offset is in, and splitting a piece
+// so that one starts at an offset, don't depend on it.
+
+use crate::mint_types::MintCount;
+use std::cell::Cell;
+
+pub trait Piece: Copy {
+    fn length(&self) -> usize;
+    // Make this the first "at" bytes of the piece, returning a piece for
+    // the rest.
+    fn split_off(&mut self, at: usize) -> Self;
+}
+
+// Index and starting offset of the piece last found by locate, so
+// sequential calls don't have to scan from the start.  Set back to the
+// start whenever the list changes.
+pub type LastPiece = Cell<(usize, MintCount)>;
+
+// Find the piece of "pieces" containing "offset", returning its index and
+// starting offset.  "offset" must be less than the pieces' total length.
+pub fn locate<P: Piece>(pieces: &[P], last: &LastPiece, offset: MintCount) -> (usize, MintCount) {
+    let (mut index, mut start) = last.get();
+    if index >= pieces.len() || offset < start {
+        index = 0;
+        start = 0;
+    }
+    while offset >= start + pieces[index].length() as MintCount {
+        start += pieces[index].length() as MintCount;
+        index += 1;
+    }
+    last.set((index, start));
+    (index, start)
+}
+
+// Make sure a piece of "pieces", "size" bytes in all, starts at "offset",
+// returning its index.
+pub fn split_at<P: Piece>(
+    pieces: &mut Vec<P>,
+    last: &LastPiece,
+    size: MintCount,
+    offset: MintCount,
+) -> usize {
+    if offset >= size {
+        return pieces.len();
+    }
+    let (index, start) = locate(pieces, last, offset);
+    let split = (offset - start) as usize;
+    if split == 0 {
+        return index;
+    }
+    let rest = pieces[index].split_off(split);
+    pieces.insert(index + 1, rest);
+    index + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Piece for usize {
+        fn length(&self) -> usize {
+            *self
+        }
+
+        fn split_off(&mut self, at: usize) -> Self {
+            let rest = *self - at;
+            *self = at;
+            rest
+        }
+    }
+
+    #[test]
+    fn pieces_are_found_and_split() {
+        let mut pieces: Vec<usize> = vec![3, 4];
+        let last = LastPiece::default();
+        assert_eq!((1, 3), locate(&pieces, &last, 5));
+        assert_eq!((0, 0), locate(&pieces, &last, 2));
+        assert_eq!(1, split_at(&mut pieces, &last, 7, 3));
+        assert_eq!(2, split_at(&mut pieces, &last, 7, 5));
+        assert_eq!(vec![3, 2, 2], pieces);
+        assert_eq!(3, split_at(&mut pieces, &last, 7, 7));
+    }
+}
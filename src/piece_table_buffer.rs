@@ -0,0 +1,348 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+use crate::buffer::{Buffer, BufferSnapshot};
+use crate::mint_types::{MintChar, MintCount};
+use crate::piece_list::{self, LastPiece};
+use regex::bytes::Regex;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    start: usize,
+    len: usize,
+}
+
+impl piece_list::Piece for Piece {
+    fn length(&self) -> usize {
+        self.len
+    }
+
+    fn split_off(&mut self, at: usize) -> Self {
+        let rest = Piece {
+            start: self.start + at,
+            len: self.len - at,
+        };
+        self.len = at;
+        rest
+    }
+}
+
+// Buffer holding all text ever inserted in an append-only store, with the
+// current contents described by a list of pieces of that store.  Text in
+// the store is never changed, so a snapshot only needs to share the store
+// and the piece list, making it O(1).  The piece list is copied the first
+// time it is changed after a snapshot.
+#[derive(Debug, Default)]
+pub struct PieceTableBuffer {
+    store: Arc<RwLock<Vec<MintChar>>>,
+    pieces: Arc<Vec<Piece>>,
+    size: MintCount,
+    last_piece: LastPiece,
+}
+
+// Contents of a PieceTableBuffer at some point in time.
+#[derive(Debug, Clone)]
+pub struct PieceTableSnapshot {
+    store: Arc<RwLock<Vec<MintChar>>>,
+    pieces: Arc<Vec<Piece>>,
+    size: MintCount,
+}
+
+impl PieceTableSnapshot {
+    pub fn size(&self) -> MintCount {
+        self.size
+    }
+
+    pub fn to_vec(&self) -> Vec<MintChar> {
+        let store = self.store.read().unwrap();
+        let mut v = Vec::with_capacity(self.size as usize);
+        for piece in self.pieces.iter() {
+            v.extend_from_slice(&store[piece.start..piece.start + piece.len]);
+        }
+        v
+    }
+}
+
+impl PieceTableBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> PieceTableSnapshot {
+        PieceTableSnapshot {
+            store: Arc::clone(&self.store),
+            pieces: Arc::clone(&self.pieces),
+            size: self.size,
+        }
+    }
+
+    // Restore contents from "snapshot", which must have been taken from
+    // this buffer.  Returns false if it was taken from another buffer.
+    pub fn restore(&mut self, snapshot: &PieceTableSnapshot) -> bool {
+        if !Arc::ptr_eq(&self.store, &snapshot.store) {
+            return false;
+        }
+        self.pieces = Arc::clone(&snapshot.pieces);
+        self.size = snapshot.size;
+        self.last_piece.set((0, 0));
+        true
+    }
+
+    fn locate(&self, offset: MintCount) -> (usize, MintCount) {
+        piece_list::locate(&self.pieces, &self.last_piece, offset)
+    }
+
+    fn split_at(&mut self, offset: MintCount) -> usize {
+        let pieces = Arc::make_mut(&mut self.pieces);
+        piece_list::split_at(pieces, &self.last_piece, self.size, offset)
+    }
+}
+
+impl Buffer for PieceTableBuffer {
+    fn size(&self) -> MintCount {
+        self.size
+    }
+
+    // The store is shared with snapshots, and holds deleted text too.
+    fn capacity(&self) -> MintCount {
+        self.store.read().unwrap().capacity() as MintCount
+    }
+
+    fn get(&self, offset: MintCount) -> Option<MintChar> {
+        if offset >= self.size {
+            return None;
+        }
+        let (index, start) = self.locate(offset);
+        let piece = self.pieces[index];
+        Some(self.store.read().unwrap()[piece.start + (offset - start) as usize])
+    }
+
+    fn replace(&mut self, offset: MintCount, n: MintCount, replacement: &[MintChar]) -> bool {
+        self.erase(offset, n) && self.insert(offset, replacement)
+    }
+
+    fn erase(&mut self, offset: MintCount, n: MintCount) -> bool {
+        if offset > self.size || self.size - offset < n {
+            return false;
+        }
+        if n > 0 {
+            let first = self.split_at(offset);
+            let last = self.split_at(offset + n);
+            Arc::make_mut(&mut self.pieces).drain(first..last);
+            self.size -= n;
+            self.last_piece.set((0, 0));
+        }
+        true
+    }
+
+    fn new_empty(&self) -> Box<dyn Buffer> {
+        Box::new(PieceTableBuffer::new())
+    }
+
+    fn insert(&mut self, offset: MintCount, to_insert: &[MintChar]) -> bool {
+        if offset > self.size {
+            return false;
+        }
+        if to_insert.is_empty() {
+            return true;
+        }
+        let index = self.split_at(offset);
+        let start = {
+            let mut store = self.store.write().unwrap();
+            let start = store.len();
+            store.extend_from_slice(to_insert);
+            start
+        };
+        let pieces = Arc::make_mut(&mut self.pieces);
+        // Typing at the end of the previous insertion just extends it.
+        if index > 0
+            && let Some(prev) = pieces.get_mut(index - 1)
+            && prev.start + prev.len == start
+        {
+            prev.len += to_insert.len();
+        } else {
+            pieces.insert(
+                index,
+                Piece {
+                    start,
+                    len: to_insert.len(),
+                },
+            );
+        }
+        self.size += to_insert.len() as MintCount;
+        self.last_piece.set((0, 0));
+        true
+    }
+
+    fn snapshot(&self) -> BufferSnapshot {
+        Box::new(PieceTableBuffer::snapshot(self))
+    }
+
+    fn restore(&mut self, snapshot: &BufferSnapshot) -> bool {
+        match snapshot.downcast_ref::<PieceTableSnapshot>() {
+            Some(snapshot) => PieceTableBuffer::restore(self, snapshot),
+            None => false,
+        }
+    }
+
+    fn for_each_chunk(
+        &self,
+        start: MintCount,
+        end: MintCount,
+        reverse: bool,
+        f: &mut dyn FnMut(MintCount, &[MintChar]) -> bool,
+    ) {
+        let end = end.min(self.size);
+        if start >= end {
+            return;
+        }
+        let store = self.store.read().unwrap();
+        let (mut index, mut piece_start) = self.locate(if reverse { end - 1 } else { start });
+        loop {
+            let piece = self.pieces[index];
+            let piece_end = piece_start + piece.len as MintCount;
+            let from = start.max(piece_start);
+            let to = end.min(piece_end);
+            let store_from = piece.start + (from - piece_start) as usize;
+            let store_to = store_from + (to - from) as usize;
+            if !f(from, &store[store_from..store_to]) {
+                return;
+            }
+            if reverse {
+                if from == start {
+                    return;
+                }
+                index -= 1;
+                piece_start -= self.pieces[index].len as MintCount;
+            } else {
+                if to == end {
+                    return;
+                }
+                index += 1;
+                piece_start = piece_end;
+            }
+        }
+    }
+
+    fn find_forward(
+        &self,
+        regex: &Regex,
+        start: MintCount,
+        end: MintCount,
+    ) -> Option<(MintCount, MintCount)> {
+        let mut slice = Vec::new();
+        self.read_range(start, end, &mut slice);
+        regex.find(&slice).map(|matched| {
+            (
+                start + matched.start() as MintCount,
+                start + matched.end() as MintCount,
+            )
+        })
+    }
+
+    fn find_backward(
+        &self,
+        regex: &Regex,
+        start: MintCount,
+        end: MintCount,
+    ) -> Option<(MintCount, MintCount)> {
+        let mut slice = Vec::new();
+        self.read_range(start, end, &mut slice);
+        regex.find_iter(&slice).last().map(|matched| {
+            (
+                start + matched.start() as MintCount,
+                start + matched.end() as MintCount,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_string<T: Buffer>(buf: &T) -> String {
+        let mut s = Vec::new();
+        for i in 0..buf.size() {
+            s.push(buf.get(i).unwrap());
+        }
+        String::from_utf8(s).unwrap()
+    }
+
+    #[test]
+    fn piece_table_buffer_insert_erase() {
+        let mut pb = PieceTableBuffer::new();
+        assert!(pb.insert(0, b"0189"));
+        assert!(pb.insert(2, b"234567"));
+        assert_eq!("0123456789", to_string(&pb));
+        assert!(pb.erase(1, 8));
+        assert_eq!("09", to_string(&pb));
+        assert!(!pb.erase(1, 2));
+        assert!(!pb.insert(3, b"x"));
+    }
+
+    #[test]
+    fn piece_table_buffer_snapshot_restore() {
+        let mut pb = PieceTableBuffer::new();
+        assert!(pb.insert(0, b"hello world"));
+        let snapshot = pb.snapshot();
+        assert!(pb.replace(0, 5, b"goodbye"));
+        assert!(pb.insert(0, b"oh, "));
+        assert_eq!("oh, goodbye world", to_string(&pb));
+        assert_eq!(b"hello world".to_vec(), snapshot.to_vec());
+        assert!(pb.restore(&snapshot));
+        assert_eq!("hello world", to_string(&pb));
+        assert!(!PieceTableBuffer::new().restore(&snapshot));
+    }
+
+    #[test]
+    fn piece_table_buffer_byte_scanning() {
+        let mut pb = PieceTableBuffer::new();
+        assert!(pb.insert(0, b"a\nb\nc"));
+        assert!(pb.insert(2, b"x\ny"));
+        assert_eq!("a\nx\nyb\nc", to_string(&pb));
+        assert_eq!(3, pb.count_byte(b'\n', 0, pb.size()));
+        assert_eq!(Some(3), pb.find_byte_forward(b'\n', 2, pb.size()));
+        assert_eq!(Some(6), pb.find_byte_forward(b'\n', 4, pb.size()));
+        assert_eq!(Some(3), pb.find_byte_backward(b'\n', 0, 6));
+        assert_eq!(None, pb.find_byte_backward(b'\n', 2, 3));
+    }
+
+    #[test]
+    fn piece_table_buffer_trait_snapshot() {
+        let mut pb = PieceTableBuffer::new();
+        assert!(pb.insert(0, b"hello"));
+        let snapshot = Buffer::snapshot(&pb);
+        assert!(pb.insert(5, b" world"));
+        assert!(Buffer::restore(&mut pb, &snapshot));
+        assert_eq!("hello", to_string(&pb));
+        assert!(!Buffer::restore(&mut pb, &(Box::new(0) as BufferSnapshot)));
+    }
+
+    #[test]
+    fn piece_table_buffer_find_across_pieces() {
+        let mut pb = PieceTableBuffer::new();
+        assert!(pb.insert(0, b"abcdef"));
+        assert!(pb.insert(3, b"XY"));
+        let re = Regex::new("cXYd").unwrap();
+        assert_eq!(Some((2, 6)), pb.find_forward(&re, 0, pb.size()));
+        assert_eq!(Some((2, 6)), pb.find_backward(&re, 0, pb.size()));
+        assert_eq!(None, pb.find_forward(&re, 3, pb.size()));
+    }
+}
@@ -0,0 +1,258 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Primitives loaded from shared libraries at run time; see #(pl). */
+
+// A plugin is a shared library with a C interface, so it can be written in
+// anything that can export C functions.  It exports
+//
+//     uint32_t freemacs_plugin_init(uint32_t version,
+//                                   const PluginRegistrar *registrar);
+//
+// which is called once, as the plugin is loaded, with PLUGIN_API_VERSION.
+// It adds its primitives by calling registrar->add_prim with each one's
+// name, the function to run it, and a pointer handed back to that
+// function each time, and returns 0, or anything else if it can't start,
+// such as for a version it doesn't know.
+//
+// A primitive's function is given its arguments, not counting its name,
+// and gives back what it returns by calling ret->set, as many times as it
+// likes, each call adding to the end.  Strings are bytes and a length,
+// with no terminating 0, and are only good until the call returns.
+// Functions can be called from whichever thread the editor is on, though
+// never from two at once.
+
+use std::ffi::c_void;
+use std::sync::Arc;
+
+use libloading::Library;
+
+use crate::mint::{Mint, MintPrim};
+use crate::mint_arg::{ArgType, MintArgList};
+use crate::mint_types::MintString;
+
+// Changed whenever anything below is, so old plugins can refuse to load.
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+#[repr(C)]
+pub struct PluginStr {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl PluginStr {
+    fn new(s: &[u8]) -> Self {
+        PluginStr {
+            ptr: s.as_ptr(),
+            len: s.len(),
+        }
+    }
+
+    // SAFETY: "ptr" must point to "len" bytes, or "len" be 0.
+    unsafe fn to_vec(&self) -> MintString {
+        if self.len == 0 {
+            return Vec::new();
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }.to_vec()
+    }
+}
+
+#[repr(C)]
+pub struct PluginReturn {
+    pub ctx: *mut c_void,
+    pub set: unsafe extern "C" fn(ctx: *mut c_void, text: PluginStr),
+}
+
+pub type PluginPrimFn = unsafe extern "C" fn(
+    user: *mut c_void,
+    args: *const PluginStr,
+    nargs: usize,
+    ret: *const PluginReturn,
+);
+
+#[repr(C)]
+pub struct PluginRegistrar {
+    pub ctx: *mut c_void,
+    pub add_prim: unsafe extern "C" fn(
+        ctx: *mut c_void,
+        name: PluginStr,
+        prim: PluginPrimFn,
+        user: *mut c_void,
+    ),
+}
+
+pub type PluginInitFn =
+    unsafe extern "C" fn(version: u32, registrar: *const PluginRegistrar) -> u32;
+
+struct PluginPrim {
+    func: PluginPrimFn,
+    user: *mut c_void,
+    // The library "func" is in, kept loaded while the primitive is.
+    _library: Option<Arc<Library>>,
+}
+
+// SAFETY: plugins agree to their functions being called from any thread,
+// and the interpreter never calls a primitive from two at once.
+unsafe impl Send for PluginPrim {}
+unsafe impl Sync for PluginPrim {}
+
+impl MintPrim for PluginPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let args: Vec<PluginStr> = args
+            .iter()
+            .skip(1)
+            .filter(|arg| arg.arg_type() != ArgType::End)
+            .map(|arg| PluginStr::new(arg.value()))
+            .collect();
+        let mut result: MintString = Vec::new();
+        let ret = PluginReturn {
+            ctx: (&mut result as *mut MintString).cast(),
+            set: add_to_result,
+        };
+        // SAFETY: "args" and "ret" outlive the call, as the plugin expects.
+        unsafe { (self.func)(self.user, args.as_ptr(), args.len(), &ret) };
+        interp.return_string(is_active, &result);
+    }
+}
+
+unsafe extern "C" fn add_to_result(ctx: *mut c_void, text: PluginStr) {
+    // SAFETY: "ctx" is the result given in execute, and "text" is good
+    // for the call.
+    unsafe { (*ctx.cast::<MintString>()).extend(text.to_vec()) };
+}
+
+// Primitives added by a plugin's init function, before they go to the
+// interpreter.
+type Added = Vec<(MintString, PluginPrimFn, *mut c_void)>;
+
+unsafe extern "C" fn add_prim(
+    ctx: *mut c_void,
+    name: PluginStr,
+    prim: PluginPrimFn,
+    user: *mut c_void,
+) {
+    // SAFETY: "ctx" is the list given to the init function, and "name" is
+    // good for the call.
+    unsafe { (*ctx.cast::<Added>()).push((name.to_vec(), prim, user)) };
+}
+
+// Load the plugin at "path", adding its primitives, which replace any
+// already of the same name.
+pub fn load(interp: &mut Mint, path: &str) -> Result<(), String> {
+    // SAFETY: loading a library runs its initialisers, and a plugin is
+    // trusted as the editor's own code is.
+    let library = unsafe { Library::new(path) }.map_err(|e| e.to_string())?;
+    let init = unsafe { library.get::<PluginInitFn>(b"freemacs_plugin_init\0") }
+        .map_err(|e| e.to_string())?;
+    let init = *init;
+    // SAFETY: "init" has the type plugins agree to export it with.
+    unsafe { start(interp, init, Some(Arc::new(library))) }
+}
+
+// Call a plugin's "init", and add the primitives it registers.
+//
+// SAFETY: "init" must keep to what plugins agree to, above.
+unsafe fn start(
+    interp: &mut Mint,
+    init: PluginInitFn,
+    library: Option<Arc<Library>>,
+) -> Result<(), String> {
+    let mut added: Added = Vec::new();
+    let registrar = PluginRegistrar {
+        ctx: (&mut added as *mut Added).cast(),
+        add_prim,
+    };
+    let status = unsafe { init(PLUGIN_API_VERSION, &registrar) };
+    if status != 0 {
+        return Err(format!("plugin failed to start: {}", status));
+    }
+    for (name, func, user) in added {
+        let prim = PluginPrim {
+            func,
+            user,
+            _library: library.clone(),
+        };
+        interp.add_prim(name, Box::new(prim));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frmprim;
+
+    // A plugin as one would be written in Rust: #(up,X,Y) returns its
+    // arguments in upper case, one after the other.
+    unsafe extern "C" fn up(
+        _user: *mut c_void,
+        args: *const PluginStr,
+        nargs: usize,
+        ret: *const PluginReturn,
+    ) {
+        let args = unsafe { std::slice::from_raw_parts(args, nargs) };
+        for arg in args {
+            let text = unsafe { arg.to_vec() }.to_ascii_uppercase();
+            unsafe { ((*ret).set)((*ret).ctx, PluginStr::new(&text)) };
+        }
+    }
+
+    unsafe extern "C" fn init(version: u32, registrar: *const PluginRegistrar) -> u32 {
+        if version != PLUGIN_API_VERSION {
+            return 1;
+        }
+        let registrar = unsafe { &*registrar };
+        unsafe {
+            (registrar.add_prim)(
+                registrar.ctx,
+                PluginStr::new(b"up"),
+                up,
+                std::ptr::null_mut(),
+            )
+        };
+        0
+    }
+
+    unsafe extern "C" fn refuse(_version: u32, _registrar: *const PluginRegistrar) -> u32 {
+        7
+    }
+
+    #[test]
+    fn plugin_prims_are_added() {
+        let mut interp = Mint::with_initial_string(b"#(ds,r,##(up,ab,,c))");
+        frmprim::register_frm_prims(&mut interp);
+        assert_eq!(Ok(()), unsafe { start(&mut interp, init, None) });
+        interp.scan();
+        assert_eq!(b"ABC".as_ref(), interp.get_form(b"r").unwrap().content());
+    }
+
+    #[test]
+    fn plugins_can_refuse_to_start() {
+        let mut interp = Mint::new();
+        assert_eq!(Err("plugin failed to start: 7".to_string()), unsafe {
+            start(&mut interp, refuse, None)
+        });
+        assert!(interp.get_prim(b"up").is_none());
+    }
+
+    #[test]
+    fn missing_plugins_are_reported() {
+        let mut interp = Mint::new();
+        assert!(load(&mut interp, "/no/such/plugin.so").is_err());
+    }
+}
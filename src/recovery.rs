@@ -0,0 +1,84 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Keeping the forms defined in a session, should it crash. */
+
+// Every so often, and if the editor panics, the forms defined since they
+// were last loaded from or saved to a library are written to a recovery
+// library in the swap directory, freemacs-USER-PID.ed.  It is removed when
+// the editor exits.  "--recover" loads the newest one left behind.
+
+use crate::libprim;
+use crate::mint::Mint;
+use crate::sysprim::{swap_dir, user_name};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+fn prefix() -> String {
+    format!("freemacs-{}-", user_name())
+}
+
+fn file_name() -> String {
+    format!("{}{}.ed", prefix(), process::id())
+}
+
+// This session's recovery library, in the swap directory of "interp".
+pub fn path(interp: &Mint) -> PathBuf {
+    swap_dir(interp).join(file_name())
+}
+
+// The recovery library another session left behind in "dir" most
+// recently.
+pub fn latest(dir: &Path) -> Option<PathBuf> {
+    let prefix = prefix();
+    let ours = dir.join(file_name());
+    let entries = fs::read_dir(dir).ok()?;
+    entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.starts_with(&prefix) && name.ends_with(".ed") && entry.path() != ours
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max()
+        .map(|(_, path)| path)
+}
+
+// Write the forms changed in "interp" to this session's recovery library,
+// or remove it if none have.  The environment is left out, as it is the
+// next session's own.
+pub fn save(interp: &Mint) -> io::Result<()> {
+    let names: Vec<_> = interp
+        .changed_forms()
+        .into_iter()
+        .filter(|name| !name.starts_with(b"env."))
+        .collect();
+    if names.is_empty() {
+        return remove(interp);
+    }
+    libprim::save_forms(interp, &path(interp), &names)
+}
+
+pub fn remove(interp: &Mint) -> io::Result<()> {
+    match fs::remove_file(path(interp)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,470 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+use crate::buffer::Buffer;
+use crate::mint_types::{MintChar, MintCount};
+use regex::bytes::Regex;
+use std::borrow::Cow;
+
+// Leaves are merged while they fit in this size, and text is split into
+// leaves of at most this size when inserted.
+const MAX_LEAF: usize = 4096;
+
+// Rope node.  Branches are kept height balanced, as in an AVL tree, so that
+// edits anywhere in the text cost O(log n) rather than moving a gap.
+#[derive(Debug)]
+enum Node {
+    Leaf(Vec<MintChar>),
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+        len: usize,
+        height: usize,
+    },
+}
+
+impl Node {
+    fn empty() -> Self {
+        Node::Leaf(Vec::new())
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Node::Leaf(text) => text.len(),
+            Node::Branch { len, .. } => *len,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            Node::Leaf(text) => text.capacity(),
+            Node::Branch { left, right, .. } => left.capacity() + right.capacity(),
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            Node::Leaf(_) => 0,
+            Node::Branch { height, .. } => *height,
+        }
+    }
+
+    fn branch(left: Node, right: Node) -> Self {
+        Node::Branch {
+            len: left.len() + right.len(),
+            height: left.height().max(right.height()) + 1,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    fn from_slice(text: &[MintChar]) -> Self {
+        if text.len() <= MAX_LEAF {
+            Node::Leaf(text.to_vec())
+        } else {
+            let (left, right) = text.split_at(text.len() / 2);
+            Node::branch(Node::from_slice(left), Node::from_slice(right))
+        }
+    }
+
+    fn get(&self, mut offset: usize) -> MintChar {
+        let mut node = self;
+        loop {
+            match node {
+                Node::Leaf(text) => return text[offset],
+                Node::Branch { left, right, .. } => {
+                    if offset < left.len() {
+                        node = left;
+                    } else {
+                        offset -= left.len();
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    // Join two ropes, keeping the result balanced.
+    fn join(left: Node, right: Node) -> Self {
+        if left.len() == 0 {
+            return right;
+        }
+        if right.len() == 0 {
+            return left;
+        }
+        let (hl, hr) = (left.height(), right.height());
+        match (left, right) {
+            (Node::Leaf(mut a), Node::Leaf(b)) if a.len() + b.len() <= MAX_LEAF => {
+                a.extend_from_slice(&b);
+                Node::Leaf(a)
+            }
+            (
+                Node::Branch {
+                    left: ll,
+                    right: lr,
+                    ..
+                },
+                right,
+            ) if hl > hr + 1 => Node::branch(*ll, Node::join(*lr, right)).rebalance(),
+            (
+                left,
+                Node::Branch {
+                    left: rl,
+                    right: rr,
+                    ..
+                },
+            ) if hr > hl + 1 => Node::branch(Node::join(left, *rl), *rr).rebalance(),
+            (left, right) => Node::branch(left, right),
+        }
+    }
+
+    // Restore balance to a branch whose children differ in height by two.
+    fn rebalance(self) -> Self {
+        let Node::Branch { left, right, .. } = self else {
+            return self;
+        };
+        let (hl, hr) = (left.height(), right.height());
+        if hl > hr + 1 {
+            let Node::Branch {
+                left: ll,
+                right: lr,
+                ..
+            } = *left
+            else {
+                unreachable!()
+            };
+            if ll.height() >= lr.height() {
+                Node::branch(*ll, Node::branch(*lr, *right))
+            } else {
+                let Node::Branch {
+                    left: lrl,
+                    right: lrr,
+                    ..
+                } = *lr
+                else {
+                    unreachable!()
+                };
+                Node::branch(Node::branch(*ll, *lrl), Node::branch(*lrr, *right))
+            }
+        } else if hr > hl + 1 {
+            let Node::Branch {
+                left: rl,
+                right: rr,
+                ..
+            } = *right
+            else {
+                unreachable!()
+            };
+            if rr.height() >= rl.height() {
+                Node::branch(Node::branch(*left, *rl), *rr)
+            } else {
+                let Node::Branch {
+                    left: rll,
+                    right: rlr,
+                    ..
+                } = *rl
+                else {
+                    unreachable!()
+                };
+                Node::branch(Node::branch(*left, *rll), Node::branch(*rlr, *rr))
+            }
+        } else {
+            Node::branch(*left, *right)
+        }
+    }
+
+    // Split into the first "at" characters and the remainder.
+    fn split(self, at: usize) -> (Node, Node) {
+        match self {
+            Node::Leaf(mut text) => {
+                let rest = text.split_off(at);
+                (Node::Leaf(text), Node::Leaf(rest))
+            }
+            Node::Branch { left, right, .. } => {
+                let left_len = left.len();
+                if at < left_len {
+                    let (a, b) = left.split(at);
+                    (a, Node::join(b, *right))
+                } else if at > left_len {
+                    let (a, b) = right.split(at - left_len);
+                    (Node::join(*left, a), b)
+                } else {
+                    (*left, *right)
+                }
+            }
+        }
+    }
+
+    // Call "f" with the leaves covering "start" to "end", as for
+    // Buffer::for_each_chunk.  "base" is the offset of this node in the
+    // rope.  Returns false if "f" asked to stop.
+    fn for_each_chunk(
+        &self,
+        base: usize,
+        start: usize,
+        end: usize,
+        reverse: bool,
+        f: &mut dyn FnMut(MintCount, &[MintChar]) -> bool,
+    ) -> bool {
+        match self {
+            Node::Leaf(text) => f((base + start) as MintCount, &text[start..end]),
+            Node::Branch { left, right, .. } => {
+                let left_len = left.len();
+                let mut parts = [
+                    (start < left_len).then(|| (left, base, start, end.min(left_len))),
+                    (end > left_len).then(|| {
+                        let start = start.saturating_sub(left_len);
+                        (right, base + left_len, start, end - left_len)
+                    }),
+                ];
+                if reverse {
+                    parts.reverse();
+                }
+                parts.into_iter().flatten().all(|(node, base, start, end)| {
+                    node.for_each_chunk(base, start, end, reverse, f)
+                })
+            }
+        }
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Cow<'_, [MintChar]> {
+        match self {
+            Node::Leaf(text) => Cow::Borrowed(&text[start..end]),
+            Node::Branch { left, right, .. } => {
+                let left_len = left.len();
+                if end <= left_len {
+                    left.slice(start, end)
+                } else if start >= left_len {
+                    right.slice(start - left_len, end - left_len)
+                } else {
+                    let mut v = Vec::with_capacity(end - start);
+                    v.extend_from_slice(&left.slice(start, left_len));
+                    v.extend_from_slice(&right.slice(0, end - left_len));
+                    Cow::Owned(v)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RopeBuffer {
+    root: Node,
+}
+
+impl Default for RopeBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RopeBuffer {
+    pub fn new() -> Self {
+        Self {
+            root: Node::empty(),
+        }
+    }
+
+    fn take_root(&mut self) -> Node {
+        std::mem::replace(&mut self.root, Node::empty())
+    }
+
+    fn slice(&self, start: MintCount, end: MintCount) -> Cow<'_, [MintChar]> {
+        if start >= end {
+            return Cow::Borrowed(&[]);
+        }
+        self.root.slice(start as usize, end as usize)
+    }
+}
+
+impl Buffer for RopeBuffer {
+    fn size(&self) -> MintCount {
+        self.root.len() as MintCount
+    }
+
+    fn capacity(&self) -> MintCount {
+        self.root.capacity() as MintCount
+    }
+
+    fn get(&self, offset: MintCount) -> Option<MintChar> {
+        if offset >= self.size() {
+            return None;
+        }
+        Some(self.root.get(offset as usize))
+    }
+
+    fn replace(&mut self, offset: MintCount, n: MintCount, replacement: &[MintChar]) -> bool {
+        self.erase(offset, n) && self.insert(offset, replacement)
+    }
+
+    fn erase(&mut self, offset: MintCount, n: MintCount) -> bool {
+        if offset > self.size() || self.size() - offset < n {
+            return false;
+        }
+        if n > 0 {
+            let (before, rest) = self.take_root().split(offset as usize);
+            let (_, after) = rest.split(n as usize);
+            self.root = Node::join(before, after);
+        }
+        true
+    }
+
+    fn new_empty(&self) -> Box<dyn Buffer> {
+        Box::new(RopeBuffer::new())
+    }
+
+    fn insert(&mut self, offset: MintCount, to_insert: &[MintChar]) -> bool {
+        if offset > self.size() {
+            return false;
+        }
+        if !to_insert.is_empty() {
+            let (before, after) = self.take_root().split(offset as usize);
+            let middle = Node::from_slice(to_insert);
+            self.root = Node::join(Node::join(before, middle), after);
+        }
+        true
+    }
+
+    fn for_each_chunk(
+        &self,
+        start: MintCount,
+        end: MintCount,
+        reverse: bool,
+        f: &mut dyn FnMut(MintCount, &[MintChar]) -> bool,
+    ) {
+        let end = end.min(self.size());
+        if start < end {
+            self.root
+                .for_each_chunk(0, start as usize, end as usize, reverse, f);
+        }
+    }
+
+    fn find_forward(
+        &self,
+        regex: &Regex,
+        start: MintCount,
+        end: MintCount,
+    ) -> Option<(MintCount, MintCount)> {
+        let slice = self.slice(start, end);
+        regex.find(&slice).map(|matched| {
+            (
+                start + matched.start() as MintCount,
+                start + matched.end() as MintCount,
+            )
+        })
+    }
+
+    fn find_backward(
+        &self,
+        regex: &Regex,
+        start: MintCount,
+        end: MintCount,
+    ) -> Option<(MintCount, MintCount)> {
+        let slice = self.slice(start, end);
+        regex.find_iter(&slice).last().map(|matched| {
+            (
+                start + matched.start() as MintCount,
+                start + matched.end() as MintCount,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_string<T: Buffer>(buf: &T) -> String {
+        let mut s = Vec::new();
+        for i in 0..buf.size() {
+            s.push(buf.get(i).unwrap());
+        }
+        String::from_utf8(s).unwrap()
+    }
+
+    #[test]
+    fn rope_buffer_insert_erase() {
+        let mut rb = RopeBuffer::new();
+        assert!(rb.insert(0, b"0189"));
+        assert!(rb.insert(2, b"234567"));
+        assert_eq!("0123456789", to_string(&rb));
+        assert!(rb.erase(1, 8));
+        assert_eq!("09", to_string(&rb));
+        assert!(!rb.erase(1, 2));
+        assert!(!rb.insert(3, b"x"));
+    }
+
+    #[test]
+    fn rope_buffer_stays_balanced() {
+        let mut rb = RopeBuffer::new();
+        let mut expected = Vec::new();
+        let chunk = vec![b'x'; MAX_LEAF];
+        for i in 0..1000 {
+            // Alternate between the ends of the text, the worst case for a
+            // gap buffer.
+            let offset = if i % 2 == 0 { 0 } else { rb.size() };
+            assert!(rb.insert(offset, &chunk));
+            expected.splice(offset as usize..offset as usize, chunk.iter().copied());
+        }
+        assert_eq!(expected.len(), rb.size() as usize);
+        assert!(rb.root.height() <= 15);
+        assert!(rb.erase(100, 3_000_000));
+        assert_eq!(expected.len() - 3_000_000, rb.size() as usize);
+        assert!(rb.root.height() <= 15);
+    }
+
+    #[test]
+    fn rope_buffer_byte_scanning() {
+        let mut rb = RopeBuffer::new();
+        let text = [vec![b'a'; MAX_LEAF], b"\n\n".to_vec(), vec![b'b'; MAX_LEAF]].concat();
+        assert!(rb.insert(0, &text));
+        let first = MAX_LEAF as MintCount;
+        assert_eq!(2, rb.count_byte(b'\n', 0, rb.size()));
+        assert_eq!(Some(first), rb.find_byte_forward(b'\n', 0, rb.size()));
+        assert_eq!(
+            Some(first + 1),
+            rb.find_byte_forward(b'\n', first + 1, rb.size())
+        );
+        assert_eq!(Some(first + 1), rb.find_byte_backward(b'\n', 0, rb.size()));
+        assert_eq!(None, rb.find_byte_backward(b'\n', 0, first));
+    }
+
+    #[test]
+    fn rope_buffer_find_across_leaves() {
+        let mut rb = RopeBuffer::new();
+        let text = [
+            vec![b'a'; MAX_LEAF],
+            b"needle".to_vec(),
+            vec![b'b'; MAX_LEAF],
+        ]
+        .concat();
+        assert!(rb.insert(0, &text));
+        let re = Regex::new("a+needleb").unwrap();
+        assert_eq!(
+            Some((0, MAX_LEAF as MintCount + 7)),
+            rb.find_forward(&re, 0, rb.size())
+        );
+        let re = Regex::new("needle").unwrap();
+        let found = Some((MAX_LEAF as MintCount, MAX_LEAF as MintCount + 6));
+        assert_eq!(found, rb.find_backward(&re, 0, rb.size()));
+        assert_eq!(
+            None,
+            rb.find_forward(&re, MAX_LEAF as MintCount + 1, rb.size())
+        );
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Signals that ask the editor to finish, or to stop for a while. */
+
+// SIGTERM and SIGHUP only set a flag, and wake the editor if it is
+// waiting for a key, which is when the flag is looked at.  The editor
+// then calls Fexit-hook, if it is defined, so that modified buffers can
+// be saved, and halts as #(hl) does, putting the terminal back as it
+// found it.  SIGTSTP likewise has the editor give the terminal back
+// before it stops, and take it again once continued.
+
+#[cfg(unix)]
+use crate::wakeup;
+#[cfg(unix)]
+use std::os::fd::IntoRawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static TERMINATION: AtomicI32 = AtomicI32::new(0);
+static SUSPENSION: AtomicBool = AtomicBool::new(false);
+// The pipe the handlers write to, to end a wait for a key; -1 until made.
+static WAKE_READ: AtomicI32 = AtomicI32::new(-1);
+static WAKE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+#[cfg(unix)]
+fn wake() {
+    let fd = WAKE_WRITE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        wakeup::write_byte(fd);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_termination(signal: libc::c_int) {
+    TERMINATION.store(signal, Ordering::SeqCst);
+    wake();
+}
+
+#[cfg(unix)]
+extern "C" fn on_suspension(_signal: libc::c_int) {
+    SUSPENSION.store(true, Ordering::SeqCst);
+    wake();
+}
+
+#[cfg(unix)]
+fn catch(signal: libc::c_int, handler: extern "C" fn(libc::c_int)) {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+}
+
+// Catch SIGTERM, SIGHUP and SIGTSTP from now on.  Where there are no
+// signals, this does nothing.
+#[cfg(unix)]
+pub fn install() {
+    if WAKE_WRITE.load(Ordering::SeqCst) < 0
+        && let Some((read, write)) = wakeup::open_pipe()
+    {
+        WAKE_READ.store(read.into_raw_fd(), Ordering::SeqCst);
+        WAKE_WRITE.store(write.into_raw_fd(), Ordering::SeqCst);
+    }
+    catch(libc::SIGTERM, on_termination);
+    catch(libc::SIGHUP, on_termination);
+    catch(libc::SIGTSTP, on_suspension);
+    INSTALLED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+// Whether signals are being caught, so that waits need to be cut short
+// to notice them.
+pub fn installed() -> bool {
+    INSTALLED.load(Ordering::SeqCst)
+}
+
+// What to wait on to be woken by a signal being caught.
+pub fn wake_fd() -> Option<i32> {
+    Some(WAKE_READ.load(Ordering::SeqCst)).filter(|&fd| fd >= 0)
+}
+
+// Keep the signals the editor waits for from the calling thread, so that
+// they go to the editor's thread and cut its wait short, rather than to
+// one that doesn't care.  Others, such as SIGSEGV or SIGPIPE, are left to
+// the thread they are meant for.  For the threads that read process
+// output and do #(bg) jobs.
+#[cfg(unix)]
+pub fn keep_from_thread() {
+    // SAFETY: the set is emptied before anything is added to it.
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in [
+            libc::SIGTERM,
+            libc::SIGHUP,
+            libc::SIGTSTP,
+            libc::SIGCHLD,
+            libc::SIGWINCH,
+        ] {
+            libc::sigaddset(&mut set, signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn keep_from_thread() {}
+
+// Whether a signal asking the editor to finish, or to stop, has been
+// caught and not yet acted on.
+pub fn pending() -> bool {
+    TERMINATION.load(Ordering::SeqCst) != 0 || SUSPENSION.load(Ordering::SeqCst)
+}
+
+// Whether SIGTSTP has been caught since this was last called.
+pub fn take_suspension() -> bool {
+    SUSPENSION.swap(false, Ordering::SeqCst)
+}
+
+// Whether the editor can stop itself, as job control shells expect.
+pub fn can_stop() -> bool {
+    cfg!(unix)
+}
+
+// Stop until continued, as SIGTSTP would have.
+#[cfg(unix)]
+pub fn stop() {
+    unsafe { libc::raise(libc::SIGSTOP) };
+}
+
+#[cfg(not(unix))]
+pub fn stop() {}
+
+// The signal asking the editor to finish, if one has been caught since
+// this was last called.
+pub fn take_termination() -> Option<i32> {
+    match TERMINATION.swap(0, Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn termination_signals_are_caught() {
+        install();
+        assert!(installed());
+        unsafe { libc::raise(libc::SIGHUP) };
+        assert!(pending());
+        assert_eq!(Some(libc::SIGHUP), take_termination());
+        assert_eq!(None, take_termination());
+        unsafe { libc::raise(libc::SIGTSTP) };
+        assert!(pending());
+        assert!(take_suspension());
+        assert!(!take_suspension());
+        assert!(!pending());
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* What the editor keeps from one session to the next. */
+
+// Recent files, registers, bookmarks and the like are each kept in a
+// file of their own in the state directory, $XDG_STATE_HOME/freemacs or
+// ~/.local/state/freemacs, named for what they hold.  At startup, each is
+// defined as the form "state.NAME" for the macros to use; #(ss) writes
+// them and #(sg) reads them again.
+
+use crate::mint::Mint;
+use crate::sysprim::{home_dir, replace_file};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub fn state_dir() -> Option<PathBuf> {
+    // Relative paths are to be ignored, says the spec.
+    let var = |name| {
+        env::var_os(name)
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+    };
+    if let Some(dir) = var("XDG_STATE_HOME") {
+        return Some(dir.join("freemacs"));
+    }
+    if cfg!(windows)
+        && let Some(dir) = var("LOCALAPPDATA")
+    {
+        return Some(dir.join("freemacs"));
+    }
+    let home = home_dir();
+    (!home.is_empty()).then(|| Path::new(&home).join(".local/state/freemacs"))
+}
+
+// Whether "name" can name some state: letters, digits, '-' and '_' only,
+// which leaves out the files being written.
+fn is_state_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn state_path(name: &str) -> io::Result<PathBuf> {
+    if !is_state_name(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid state name",
+        ));
+    }
+    state_dir()
+        .map(|dir| dir.join(name))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))
+}
+
+// The state "name", as last written.
+pub fn read(name: &str) -> io::Result<Vec<u8>> {
+    fs::read(state_path(name)?)
+}
+
+// Keep "content" as the state "name", or forget it if "content" is empty.
+pub fn write(name: &str, content: &[u8]) -> io::Result<()> {
+    let path = state_path(name)?;
+    if content.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        };
+    }
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(path.parent().unwrap_or(Path::new(".")))?;
+    replace_file(&path, content)
+}
+
+// Define "state.NAME" as each state kept.
+pub fn load_forms(interp: &mut Mint) {
+    let Some(entries) = state_dir().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !is_state_name(&name) {
+            continue;
+        }
+        if let Ok(content) = fs::read(entry.path()) {
+            interp.set_form_value(format!("state.{}", name).as_bytes(), &content);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_is_kept_and_loaded() {
+        let dir = env::temp_dir().join(format!("freemacs-state-{}", std::process::id()));
+        // SAFETY: The other tests only read the environment through std,
+        // which locks it.
+        unsafe { env::set_var("XDG_STATE_HOME", &dir) };
+        assert_eq!(Some(dir.join("freemacs")), state_dir());
+
+        write("recent", b"/tmp/a\n").unwrap();
+        write("registers", b"abc").unwrap();
+        write("registers", b"").unwrap();
+        assert!(write("../escape", b"x").is_err());
+        fs::write(dir.join("freemacs/recent.12345678"), "partly written").unwrap();
+        assert_eq!(b"/tmp/a\n".to_vec(), read("recent").unwrap());
+
+        let mut interp = Mint::new();
+        load_forms(&mut interp);
+        let form = interp
+            .get_form(b"state.recent")
+            .map(|form| form.content().clone());
+        assert_eq!(Some(b"/tmp/a\n".to_vec()), form);
+        assert!(interp.get_form(b"state.registers").is_none());
+        assert!(interp.get_form(b"state.recent.12345678").is_none());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
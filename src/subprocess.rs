@@ -0,0 +1,486 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Processes run on pseudo-terminals, with their output going to buffers. */
+
+// Each buffer can have one process.  What it prints is read on a thread of
+// its own, and inserted at the buffer's process mark whenever the editor
+// waits for a key.  What is typed goes to it only when the macros send it,
+// so that a shell buffer can be edited like any other.  The terminal is a
+// dumb one, and the escape sequences programs send regardless are taken
+// out of the output, as are carriage returns.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::{Child, ExitStatus};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use crate::emacs_buffers::{lock_buffer, with_buffers};
+use crate::mint::Mint;
+use crate::mint_types::{MintCount, MintString};
+use crate::signals;
+use crate::wakeup::Waker;
+
+pub struct Process {
+    child: Child,
+    // The terminal's master side, written to as the keyboard.
+    master: File,
+    output: Receiver<Vec<u8>>,
+    filter: OutputFilter,
+}
+
+// The processes "interp" is running, keyed by buffer number.
+fn with_processes<F, R>(interp: &Mint, f: F) -> R
+where
+    F: FnOnce(&RefCell<HashMap<MintCount, Process>>) -> R,
+{
+    f(&interp.context().processes)
+}
+
+fn no_process() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no process in buffer")
+}
+
+// Run "command" with the shell, or the user's shell by itself if it is
+// empty, on a terminal "cols" by "rows", for buffer "bufno".
+pub fn start(
+    interp: &Mint,
+    bufno: MintCount,
+    command: &str,
+    cols: u16,
+    rows: u16,
+) -> io::Result<()> {
+    if with_processes(interp, |p| p.borrow().contains_key(&bufno)) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "buffer already has a process",
+        ));
+    }
+    let process = Process::spawn(command, cols, rows, interp.context().waker.clone())?;
+    with_processes(interp, |p| p.borrow_mut().insert(bufno, process));
+    Ok(())
+}
+
+// Send "data" to the process for buffer "bufno", as if typed.
+pub fn send(interp: &Mint, bufno: MintCount, data: &[u8]) -> io::Result<()> {
+    with_processes(interp, |p| match p.borrow_mut().get_mut(&bufno) {
+        Some(process) => {
+            process.master.write_all(data)?;
+            process.master.flush()
+        }
+        None => Err(no_process()),
+    })
+}
+
+// Send "signal" to the job in the foreground on the terminal of the
+// process for buffer "bufno", which is the process itself unless it is a
+// shell running something.
+pub fn signal(interp: &Mint, bufno: MintCount, signal: i32) -> io::Result<()> {
+    with_processes(interp, |p| match p.borrow().get(&bufno) {
+        Some(process) => kill(process, signal),
+        None => Err(no_process()),
+    })
+}
+
+pub fn any_processes(interp: &Mint) -> bool {
+    with_processes(interp, |p| !p.borrow().is_empty())
+}
+
+// Insert the output waiting from each process into its buffer.  A process
+// that has finished has that noted in its buffer, and is forgotten, as is
+// one whose buffer has gone.  Returns whether any buffer changed.
+pub fn insert_output(interp: &Mint) -> bool {
+    with_processes(interp, |p| {
+        let mut changed = false;
+        p.borrow_mut().retain(|&bufno, process| {
+            let (output, closed) = process.read_output();
+            let status = if closed {
+                process.child.wait().ok()
+            } else {
+                None
+            };
+            let Some(buffer) = with_buffers(interp, |buffers| buffers.get_buffer(bufno)) else {
+                kill(process, SIGHUP).ok();
+                return false;
+            };
+            let mut buffer = lock_buffer(&buffer);
+            if !output.is_empty() {
+                changed |= buffer.insert_output(&output);
+            }
+            if let Some(status) = status {
+                changed |= buffer.insert_output(&finished_message(status));
+            }
+            !closed
+        });
+        changed
+    })
+}
+
+impl Process {
+    fn spawn(command: &str, cols: u16, rows: u16, waker: Waker) -> io::Result<Self> {
+        let (child, master) = spawn(command, cols, rows)?;
+        let reader = master.try_clone()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            signals::keep_from_thread();
+            read_output(reader, sender, &waker);
+            // The channel has closed by now, so the process is seen to
+            // have finished.
+            waker.wake();
+        });
+        Ok(Process {
+            child,
+            master,
+            output: receiver,
+            filter: OutputFilter::default(),
+        })
+    }
+
+    // Everything read since last time, and whether the terminal has closed.
+    fn read_output(&mut self) -> (MintString, bool) {
+        let mut output = Vec::new();
+        loop {
+            match self.output.try_recv() {
+                Ok(bytes) => output.extend(bytes.iter().filter_map(|&b| self.filter.feed(b))),
+                Err(TryRecvError::Empty) => return (output, false),
+                Err(TryRecvError::Disconnected) => return (output, true),
+            }
+        }
+    }
+}
+
+fn read_output(mut master: File, sender: Sender<Vec<u8>>, waker: &Waker) {
+    let mut buf = [0u8; 4096];
+    loop {
+        // Once every process using the terminal has gone, reading it
+        // fails rather than returning end of file.
+        let len = match master.read(&mut buf) {
+            Ok(0) => return,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        if sender.send(buf[..len].to_vec()).is_err() {
+            return;
+        }
+        waker.wake();
+    }
+}
+
+fn finished_message(status: ExitStatus) -> MintString {
+    let message = match status.code() {
+        Some(0) => "Process finished".to_string(),
+        Some(code) => format!("Process exited with status {}", code),
+        None => "Process killed".to_string(),
+    };
+    format!("\n{}\n", message).into_bytes()
+}
+
+// Signals by name, as #(pk) takes them.
+#[cfg(unix)]
+const SIGNALS: [(&str, i32); 8] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+];
+#[cfg(not(unix))]
+const SIGNALS: [(&str, i32); 0] = [];
+
+// Sent to a process whose buffer has gone, as to one whose terminal has.
+const SIGHUP: i32 = 1;
+
+// The signal named by "name", with or without "SIG" in front, or given
+// by number.
+pub fn signal_number(name: &str) -> Option<i32> {
+    let name = name.trim().to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    match name.parse() {
+        Ok(number) => Some(number),
+        Err(_) => SIGNALS.iter().find(|(n, _)| *n == name).map(|&(_, s)| s),
+    }
+}
+
+// What a terminal sends for the key named "key", or None if it sends
+// nothing anything would understand.
+pub fn key_bytes(key: &[u8]) -> Option<Vec<u8>> {
+    if let Some(rest) = key.strip_prefix(b"M-") {
+        let mut bytes = vec![0x1b];
+        bytes.extend(key_bytes(rest)?);
+        return Some(bytes);
+    }
+    let bytes: &[u8] = match key {
+        b"Return" | b"Enter" | b"KP-Enter" => b"\r",
+        b"Tab" => b"\t",
+        b"Back Space" => b"\x7f",
+        b"Escape" => b"\x1b",
+        b"Comma" => b",",
+        b"LPar" => b"(",
+        b"RPar" => b")",
+        b"Up Arrow" => b"\x1b[A",
+        b"Down Arrow" => b"\x1b[B",
+        b"Right Arrow" => b"\x1b[C",
+        b"Left Arrow" => b"\x1b[D",
+        b"Home" => b"\x1b[H",
+        b"End" => b"\x1b[F",
+        b"Ins" => b"\x1b[2~",
+        b"Del" => b"\x1b[3~",
+        b"Pg Up" => b"\x1b[5~",
+        b"Pg Dn" => b"\x1b[6~",
+        [b'C', b'-', ch] if (b'@'..=b'_').contains(&ch.to_ascii_uppercase()) => {
+            return Some(vec![ch.to_ascii_uppercase() & 0x1f]);
+        }
+        // Characters below 256 are named by their byte, as in Latin-1.
+        [ch] if *ch >= 0x80 => return Some((*ch as char).to_string().into_bytes()),
+        [_] => key,
+        _ => {
+            let text = std::str::from_utf8(key).ok()?;
+            let mut chars = text.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(_), None) => Some(key.to_vec()),
+                _ => None,
+            };
+        }
+    };
+    Some(bytes.to_vec())
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum FilterState {
+    #[default]
+    Text,
+    Escape,
+    // A control sequence, "ESC [", up to its final byte.
+    Control,
+    // An operating system command, "ESC ]", up to BEL or "ESC \".
+    Command,
+}
+
+// Takes escape sequences and carriage returns out of terminal output.
+#[derive(Default)]
+struct OutputFilter {
+    state: FilterState,
+}
+
+impl OutputFilter {
+    fn feed(&mut self, byte: u8) -> Option<u8> {
+        match (self.state, byte) {
+            (FilterState::Text, 0x1b) => {
+                self.state = FilterState::Escape;
+                None
+            }
+            (FilterState::Text, b'\r') => None,
+            (FilterState::Text, _) => Some(byte),
+            (FilterState::Escape, b'[') => {
+                self.state = FilterState::Control;
+                None
+            }
+            (FilterState::Escape, b']') => {
+                self.state = FilterState::Command;
+                None
+            }
+            (FilterState::Escape, _) => {
+                self.state = FilterState::Text;
+                None
+            }
+            (FilterState::Control, 0x40..=0x7e) => {
+                self.state = FilterState::Text;
+                None
+            }
+            (FilterState::Control, _) => None,
+            (FilterState::Command, 0x07) => {
+                self.state = FilterState::Text;
+                None
+            }
+            (FilterState::Command, 0x1b) => {
+                self.state = FilterState::Escape;
+                None
+            }
+            (FilterState::Command, _) => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn spawn(command: &str, cols: u16, rows: u16) -> io::Result<(Child, File)> {
+    use std::env;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    let (master, slave) = open_pty(cols, rows)?;
+    let mut cmd = if command.is_empty() {
+        Command::new(env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string()))
+    } else {
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+    cmd.env("TERM", "dumb")
+        .env("COLUMNS", cols.to_string())
+        .env("LINES", rows.to_string())
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    // The child gets a session of its own, with the terminal as its
+    // controlling terminal, so that job control and ^C work in it.
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = cmd.spawn()?;
+    Ok((child, master))
+}
+
+// A new pseudo-terminal "cols" by "rows", as its master and slave sides.
+#[cfg(unix)]
+fn open_pty(cols: u16, rows: u16) -> io::Result<(File, File)> {
+    use std::ffi::CStr;
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(fd);
+        // Only the slave side is for the child.
+        if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) != 0
+            || libc::grantpt(fd) != 0
+            || libc::unlockpt(fd) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let name = libc::ptsname(fd);
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let name = CStr::from_ptr(name).to_owned();
+        let fd = libc::open(
+            name.as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let slave = File::from_raw_fd(fd);
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+        Ok((master, slave))
+    }
+}
+
+#[cfg(unix)]
+fn kill(process: &Process, signal: i32) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let group = unsafe { libc::tcgetpgrp(process.master.as_raw_fd()) };
+    let target = if group > 0 {
+        -group
+    } else {
+        process.child.id() as libc::pid_t
+    };
+    if unsafe { libc::kill(target, signal) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn spawn(_command: &str, _cols: u16, _rows: u16) -> io::Result<(Child, File)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "processes need a pseudo-terminal",
+    ))
+}
+
+#[cfg(not(unix))]
+fn kill(_process: &Process, _signal: i32) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn filter(bytes: &[u8]) -> Vec<u8> {
+        let mut filter = OutputFilter::default();
+        bytes.iter().filter_map(|&b| filter.feed(b)).collect()
+    }
+
+    #[test]
+    fn escape_sequences_are_removed() {
+        assert_eq!(b"ab\n".to_vec(), filter(b"\x1b[1;31ma\x1b[0mb\r\n"));
+        assert_eq!(b"$ ".to_vec(), filter(b"\x1b]0;title\x07$ "));
+        assert_eq!(b"x".to_vec(), filter(b"\x1b]0;title\x1b\\x"));
+        assert_eq!(b"y".to_vec(), filter(b"\x1b=y"));
+    }
+
+    #[test]
+    fn keys_are_sent_as_a_terminal_would() {
+        assert_eq!(Some(b"\x03".to_vec()), key_bytes(b"C-c"));
+        assert_eq!(Some(b"\r".to_vec()), key_bytes(b"Return"));
+        assert_eq!(Some(b",".to_vec()), key_bytes(b"Comma"));
+        assert_eq!(Some(b"\x1bb".to_vec()), key_bytes(b"M-b"));
+        assert_eq!(Some(b"\x1b[A".to_vec()), key_bytes(b"Up Arrow"));
+        assert_eq!(Some("é".as_bytes().to_vec()), key_bytes(&[0xe9]));
+        assert_eq!(Some("€".as_bytes().to_vec()), key_bytes("€".as_bytes()));
+        assert_eq!(None, key_bytes(b"F1"));
+    }
+
+    #[test]
+    fn signals_by_name_or_number() {
+        assert_eq!(Some(2), signal_number("INT"));
+        assert_eq!(Some(15), signal_number("sigterm"));
+        assert_eq!(Some(9), signal_number("9"));
+        assert_eq!(None, signal_number("BOGUS"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn process_output_is_read() {
+        let mut process = Process::spawn("echo hello", 80, 24, Waker::new()).unwrap();
+        let mut output = Vec::new();
+        for _ in 0..100 {
+            let (bytes, closed) = process.read_output();
+            output.extend(bytes);
+            if closed {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(b"hello\n".to_vec(), output);
+        assert!(process.child.wait().unwrap().success());
+    }
+}
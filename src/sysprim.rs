@@ -16,14 +16,26 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
+use crate::emacs_buffers::{with_buffers, with_current_buffer};
+use crate::emacs_window;
+use crate::file_lock;
 use crate::mint::{Mint, MintPrim, MintVar};
 use crate::mint_arg::MintArgList;
+use crate::mint_string;
 use crate::mint_types::MintString;
+#[cfg(any(unix, windows))]
+use crate::plugin;
+use crate::state;
+use crate::subprocess;
+use crate::worker;
 use std::env;
 use std::fs;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // #(ab,X)
 // -------
@@ -53,27 +65,31 @@ impl MintPrim for AbPrim {
 // #(hl,X)
 // -------
 // Halt.  Exit to operating system with return code "X" interpreted as
-// decimal number.
+// decimal number.  Nothing more is scanned, and the terminal is put back
+// as it was and the buffers freed before the editor exits.
 //
 // Returns: does not return
 struct HlPrim;
 impl MintPrim for HlPrim {
-    fn execute(&self, _interp: &mut Mint, _is_active: bool, args: &MintArgList) {
+    fn execute(&self, interp: &mut Mint, _is_active: bool, args: &MintArgList) {
         let exit_code = if args.len() >= 2 {
             args[1].get_int_value(10)
         } else {
             0
         };
-        process::exit(exit_code);
+        interp.halt(exit_code);
     }
 }
 
-// #(ct,X,Y)
-// ---------
+// #(ct,X,Y,Z)
+// -----------
 // Current time.  If "X" is null, returns system date/time.  If "X" is not
 // null, it is used as a filename.  If "X" is specified, then if "Y" is
 // non-null, binary file attributes and file size are included in the
-// output string.
+// output string.  If "Z" is non-null, dates are formatted by it rather
+// than as below, using strftime's "%" codes: "%Y-%m-%dT%H:%M:%S%z" for
+// ISO 8601, "%s" for seconds since 1970, or "%c" for the locale's format.
+// An invalid format gives a null date.
 //
 // Returns: ("X" null) System date in format "Sun Aug 08 09:01:03 2003".
 //
@@ -90,14 +106,21 @@ impl MintPrim for HlPrim {
 //     Bit 3 - File is a volume label
 //     Bit 4 - File is a directory
 //     Bit 5 - File is ready for archiving (modified since backup)
+// Only the directory and system file bits are ever set; see #(fs) for
+// the full story about a file.
 struct CtPrim;
 impl MintPrim for CtPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
         let file_name = args[1].value();
+        let format = match String::from_utf8_lossy(args[3].value()) {
+            format if format.is_empty() => DEFAULT_TIME_FORMAT.into(),
+            format => format,
+        };
+        let format_time = |time| format_system_time(time, &format);
 
         let result = if file_name.is_empty() {
             // Get current system time
-            format_system_time(SystemTime::now())
+            format_time(SystemTime::now())
         } else {
             // Get file time
             let path_str = String::from_utf8_lossy(file_name);
@@ -122,9 +145,9 @@ impl MintPrim for CtPrim {
                         attrs.push('0'); // Bit 1: hidden (not used)
                         attrs.push('0'); // Bit 0: read-only (not implemented)
 
-                        format!("{}{} {}", attrs, format_system_time(modified), size)
+                        format!("{}{} {}", attrs, format_time(modified), size)
                     } else {
-                        format_system_time(modified)
+                        format_time(modified)
                     }
                 } else {
                     String::new()
@@ -139,35 +162,97 @@ impl MintPrim for CtPrim {
     }
 }
 
-// #(ff,X,Y)
-// ---------
+// #(ff,X,Y,Z)
+// -----------
 // Find file.  "X" is a literal string which may contain globbing
-// characters. "Y" is a separator string used in the return value.
+// characters.  A "**" on its own between slashes matches any number of
+// directories, so "src/**/*.rs" finds files anywhere under "src".  "Y" is
+// a separator string used in the return value.  "Z" is a string of
+// option letters:
+//     f   Only find files, leaving out directories
+//     d   Only find directories
+//     h   Leave out hidden files, whose names start with ".", unless
+//         "X" names them with a literal "."
+//     p   Return paths as matched by "X", rather than just file names
+//     a   Return absolute paths
+//     n   Sort by path
+//     t   Sort by time last modified, most recent first
+// Without "n" or "t", files come in the order the system lists them.
 //
 // Returns: List of matching files, separated by literal string "Y".
 struct FfPrim;
 impl MintPrim for FfPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
-        let pattern = args[1].value();
-        let separator = args[2].value();
-        let pattern_str = String::from_utf8_lossy(pattern);
-
-        let mut results = Vec::new();
-
-        // Use glob pattern matching
-        if let Ok(entries) = glob::glob(&pattern_str) {
-            for entry in entries.flatten() {
-                if let Some(file_name) = entry.file_name() {
-                    results.extend_from_slice(file_name.to_string_lossy().as_bytes());
-                    results.extend_from_slice(separator);
-                }
-            }
-        }
-
+        let results = find_files(args[1].value(), args[2].value(), args[3].value());
         interp.return_string(is_active, &results);
     }
 }
 
+// Files matching "pattern", as #(ff) finds them.
+pub fn find_files(pattern: &[u8], separator: &[u8], options: &[u8]) -> MintString {
+    let pattern_str = String::from_utf8_lossy(pattern);
+
+    let skip_hidden = options.contains(&b'h');
+    let files_only = options.contains(&b'f');
+    let dirs_only = options.contains(&b'd');
+
+    // Use glob pattern matching
+    let mut paths: Vec<PathBuf> = match glob::glob(&pattern_str) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|entry| {
+                let is_dir = entry.is_dir();
+                !((files_only && is_dir)
+                    || (dirs_only && !is_dir)
+                    || (skip_hidden && is_hidden_match(&pattern_str, entry)))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    if options.contains(&b't') {
+        let modified = |path: &PathBuf| fs::metadata(path).and_then(|m| m.modified()).ok();
+        paths.sort_by_cached_key(|path| std::cmp::Reverse(modified(path)));
+    } else if options.contains(&b'n') {
+        paths.sort();
+    }
+
+    let mut results = Vec::new();
+    for path in paths {
+        let name = if options.contains(&b'a') {
+            path.canonicalize().unwrap_or(path).into_os_string()
+        } else if options.contains(&b'p') {
+            path.into_os_string()
+        } else {
+            match path.file_name() {
+                Some(file_name) => file_name.to_os_string(),
+                None => continue,
+            }
+        };
+        results.extend_from_slice(name.to_string_lossy().as_bytes());
+        results.extend_from_slice(separator);
+    }
+    results
+}
+
+// Whether "path", found by "pattern", has a hidden name in it that the
+// pattern doesn't name with a literal ".".
+fn is_hidden_match(pattern: &str, path: &Path) -> bool {
+    let dotted: Vec<glob::Pattern> = Path::new(pattern)
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .filter(|part| part.starts_with('.'))
+        .filter_map(|part| glob::Pattern::new(&part).ok())
+        .collect();
+    path.components().any(|part| {
+        let name = part.as_os_str().to_string_lossy();
+        name.starts_with('.')
+            && name != "."
+            && name != ".."
+            && !dotted.iter().any(|p| p.matches(&name))
+    })
+}
+
 // #(rn,X,Y)
 // ---------
 // Rename file.  Rename file given by literal string "X" to "Y".
@@ -210,6 +295,553 @@ impl MintPrim for DePrim {
     }
 }
 
+// #(cp,X,Y)
+// ---------
+// Copy file.  Copy file given by literal string "X" to "Y", replacing
+// "Y" if it exists.
+//
+// Returns: null if successful, error text otherwise.
+struct CpPrim;
+impl MintPrim for CpPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let from_name = args[1].value();
+        let to_name = args[2].value();
+        let from_str = String::from_utf8_lossy(from_name);
+        let to_str = String::from_utf8_lossy(to_name);
+
+        with_buffers(interp, |buffers| buffers.unmap_file(&to_str));
+        let result = match fs::copy(from_str.as_ref(), to_str.as_ref()) {
+            Ok(_) => Vec::new(),
+            Err(e) => format!("{}", e).into_bytes(),
+        };
+
+        interp.return_string(is_active, &result);
+    }
+}
+
+// #(md,X,Y)
+// ---------
+// File mode.  If "Y" is null, read the permission bits of file "X".  If
+// "Y" is not null, set them to "Y", read as an octal number such as "755".
+// On Windows only whether a file can be written is kept, so its mode
+// reads as "666" or "444", and setting a mode with no write bits makes it
+// read only.
+//
+// Returns: ("Y" null) The permission bits of "X" in octal, or null if no
+// such file.
+//
+// Returns: ("Y" not null) null if successful, error text otherwise.
+struct MdPrim;
+impl MintPrim for MdPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let file_str = String::from_utf8_lossy(args[1].value());
+        let mode = args[2].value();
+
+        let result = if mode.is_empty() {
+            match fs::metadata(file_str.as_ref()) {
+                Ok(metadata) => format!("{:o}", file_mode(&metadata.permissions())).into_bytes(),
+                Err(_) => Vec::new(),
+            }
+        } else {
+            match u32::from_str_radix(&String::from_utf8_lossy(mode), 8) {
+                Ok(mode) => match set_file_mode(file_str.as_ref(), mode) {
+                    Ok(_) => Vec::new(),
+                    Err(e) => format!("{}", e).into_bytes(),
+                },
+                Err(_) => b"invalid mode".to_vec(),
+            }
+        };
+
+        interp.return_string(is_active, &result);
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(permissions: &fs::Permissions) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(permissions: &fs::Permissions) -> u32 {
+    if permissions.readonly() { 0o444 } else { 0o666 }
+}
+
+#[cfg(unix)]
+fn set_file_mode(path: &str, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(path: &str, mode: u32) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+// #(fs,X,Y)
+// ---------
+// File status.  Read what is known about file "X", without following it
+// if it is a symbolic link.  The fields are separated by literal string
+// "Y", or by a space if "Y" is null, and are, in order:
+//     Type        "file", "dir", "link" or "other"
+//     Size        In bytes
+//     Modified    Seconds since 1970-01-01 00:00:00 UTC
+//     Mode        Permission bits in octal, as for #(md)
+//     Owner       User name, or number if it has none; empty on Windows
+//
+// Returns: The fields, for example "file 104323 1060333263 644 martin",
+// or null if no such file.
+struct FsPrim;
+impl MintPrim for FsPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let file_str = String::from_utf8_lossy(args[1].value());
+        let separator = match args[2].value() {
+            sep if sep.is_empty() => b" ".as_slice(),
+            sep => sep,
+        };
+
+        let result = match fs::symlink_metadata(file_str.as_ref()) {
+            Ok(metadata) => {
+                let file_type = metadata.file_type();
+                let kind = if file_type.is_symlink() {
+                    "link"
+                } else if file_type.is_dir() {
+                    "dir"
+                } else if file_type.is_file() {
+                    "file"
+                } else {
+                    "other"
+                };
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |since| since.as_secs());
+                let fields = [
+                    kind.to_string(),
+                    metadata.len().to_string(),
+                    modified.to_string(),
+                    format!("{:o}", file_mode(&metadata.permissions())),
+                    file_owner(&metadata),
+                ];
+                fields
+                    .iter()
+                    .map(|field| field.as_bytes())
+                    .collect::<Vec<_>>()
+                    .join(separator)
+            }
+            Err(_) => Vec::new(),
+        };
+
+        interp.return_string(is_active, &result);
+    }
+}
+
+#[cfg(unix)]
+fn file_owner(metadata: &fs::Metadata) -> String {
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = metadata.uid();
+    match passwd_entry(uid) {
+        Some((name, _)) => name,
+        None => uid.to_string(),
+    }
+}
+
+#[cfg(not(unix))]
+fn file_owner(_metadata: &fs::Metadata) -> String {
+    String::new()
+}
+
+// The name and home directory of the user "uid".
+#[cfg(unix)]
+fn passwd_entry(uid: libc::uid_t) -> Option<(String, String)> {
+    use std::ffi::CStr;
+
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let rc = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if rc == 0 && !found.is_null() {
+        let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+        let dir = unsafe { CStr::from_ptr(pwd.pw_dir) };
+        Some((
+            name.to_string_lossy().into_owned(),
+            dir.to_string_lossy().into_owned(),
+        ))
+    } else {
+        None
+    }
+}
+
+// #(lk,X,Y)
+// ---------
+// Lock file.  Lock file "X" against changes by other editors, with a
+// symbolic link ".#" and its name beside it, as Emacs does.  If another
+// editor holds the lock, it keeps it unless "Y" is non-null, when the
+// lock is stolen from it.  A lock left by an editor that has finished is
+// taken over.  If the lock can't be made, as where the directory can't
+// be written, nothing is done.  Locks are given up by #(uk) or on exit.
+//
+// Returns: The holder of the lock as "user@host.pid" if another editor
+// holds it, otherwise null.
+struct LkPrim;
+impl MintPrim for LkPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let file_str = String::from_utf8_lossy(args[1].value());
+        let steal = !args[2].is_empty();
+        let holder = file_lock::lock(interp, Path::new(file_str.as_ref()), steal);
+        let result = holder.ok().flatten().unwrap_or_default();
+        interp.return_string(is_active, &result.into_bytes());
+    }
+}
+
+// #(lq,X)
+// -------
+// Lock query.  Find who holds the lock on file "X".
+//
+// Returns: The holder of the lock as "user@host.pid" if another editor
+// holds it, otherwise null.
+struct LqPrim;
+impl MintPrim for LqPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let file_str = String::from_utf8_lossy(args[1].value());
+        let holder = file_lock::holder(Path::new(file_str.as_ref()));
+        interp.return_string(is_active, &holder.unwrap_or_default().into_bytes());
+    }
+}
+
+// #(uk,X)
+// -------
+// Unlock file.  Give up the lock on file "X" taken by #(lk).
+//
+// Returns: null.
+struct UkPrim;
+impl MintPrim for UkPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let file_str = String::from_utf8_lossy(args[1].value());
+        file_lock::unlock(interp, Path::new(file_str.as_ref()));
+        interp.return_null(is_active);
+    }
+}
+
+// #(tf,X)
+// -------
+// Temporary file.  Make a new, empty file in the swap directory "sd",
+// named "X" followed by letters and digits that no other file there has,
+// that only the user can read or write.  Unlike a name made up by the
+// macros, no other program can have made it first.  It is for the caller
+// to delete with #(de).
+//
+// Returns: The file's path, or null if it can't be made.
+struct TfPrim;
+impl MintPrim for TfPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let prefix = String::from_utf8_lossy(args[1].value());
+        let path = match temp_file(&swap_dir(interp), &prefix) {
+            Ok((path, _)) => path.to_string_lossy().into_owned(),
+            Err(_) => String::new(),
+        };
+        interp.return_string(is_active, &path.into_bytes());
+    }
+}
+
+// #(sg,X)
+// -------
+// State get.  Read the state "X" kept from earlier sessions by #(ss).
+//
+// Returns: The state, or null if none is kept.
+struct SgPrim;
+impl MintPrim for SgPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let name = String::from_utf8_lossy(args[1].value());
+        let content = state::read(&name).unwrap_or_default();
+        interp.return_string(is_active, &content);
+    }
+}
+
+// #(ss,X,Y)
+// ---------
+// State set.  Keep "Y" as the state "X" for later sessions, which see it
+// as the form "state.X"; the form is set now as well.  If "Y" is null,
+// the state is forgotten.  "X" is made of letters, digits, "-" and "_",
+// and names a file in $XDG_STATE_HOME/freemacs, or else
+// ~/.local/state/freemacs.
+//
+// Returns: null if successful, error text otherwise.
+struct SsPrim;
+impl MintPrim for SsPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let name = String::from_utf8_lossy(args[1].value()).into_owned();
+        let content = args[2].value();
+        let result = match state::write(&name, content) {
+            Ok(()) => {
+                let form_name = format!("state.{}", name).into_bytes();
+                if content.is_empty() {
+                    interp.del_form(&form_name);
+                } else {
+                    interp.set_form_value(&form_name, content);
+                }
+                Vec::new()
+            }
+            Err(e) => e.to_string().into_bytes(),
+        };
+        interp.return_string(is_active, &result);
+    }
+}
+
+// #(pr,X)
+// -------
+// Process run.  Run the shell command "X" on a terminal of its own,
+// attached to the current buffer, or the user's shell if "X" is null.
+// Output from the process is inserted at the buffer's process mark, "&",
+// which moves past it, while #(it) waits for a key.  Point moves with the
+// mark if it is there.  When the process finishes, a line saying so is
+// inserted.  A buffer can only have one process at a time.
+//
+// Returns: null if successful, error text otherwise.
+struct PrPrim;
+impl MintPrim for PrPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let command = String::from_utf8_lossy(args[1].value());
+        let bufno = with_current_buffer(interp, |buf| buf.get_buf_number());
+        let (cols, rows) = emacs_window::with_window(interp, |w| {
+            (w.get_columns() as u16, w.get_screen_lines() as u16)
+        });
+        let result = match subprocess::start(interp, bufno, &command, cols, rows) {
+            Ok(_) => Vec::new(),
+            Err(e) => format!("{}", e).into_bytes(),
+        };
+        interp.return_string(is_active, &result);
+    }
+}
+
+// #(pi,X,Y)
+// ---------
+// Process input.  Send literal string "X" to the current buffer's
+// process, as if typed.  If "Y" is non-null, "X" is instead the name of
+// a key, as #(it) returns, and what a terminal sends for that key is
+// sent, so keys can be passed straight on to the process.
+//
+// Returns: null if successful, error text otherwise.
+struct PiPrim;
+impl MintPrim for PiPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let input = args[1].value();
+        let bufno = with_current_buffer(interp, |buf| buf.get_buf_number());
+        let data = if args[2].value().is_empty() {
+            Some(input.to_vec())
+        } else {
+            subprocess::key_bytes(input)
+        };
+        let result = match data {
+            Some(data) => match subprocess::send(interp, bufno, &data) {
+                Ok(_) => Vec::new(),
+                Err(e) => format!("{}", e).into_bytes(),
+            },
+            None => b"no such key".to_vec(),
+        };
+        interp.return_string(is_active, &result);
+    }
+}
+
+// #(pk,X)
+// -------
+// Process kill.  Send signal "X" to the current buffer's process, or to
+// whatever it is running in the foreground if it is a shell.  "X" is a
+// signal name, such as "INT", "QUIT", "TERM", "KILL", "HUP", "STOP",
+// "TSTP" or "CONT", or a number.  If "X" is null, "INT" is sent, as for
+// a ^C typed at a terminal.
+//
+// Returns: null if successful, error text otherwise.
+struct PkPrim;
+impl MintPrim for PkPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let name = String::from_utf8_lossy(args[1].value());
+        let name = if name.is_empty() { "INT".into() } else { name };
+        let bufno = with_current_buffer(interp, |buf| buf.get_buf_number());
+        let result = match subprocess::signal_number(&name) {
+            Some(signal) => match subprocess::signal(interp, bufno, signal) {
+                Ok(_) => Vec::new(),
+                Err(e) => format!("{}", e).into_bytes(),
+            },
+            None => b"no such signal".to_vec(),
+        };
+        interp.return_string(is_active, &result);
+    }
+}
+
+// #(ti,X,Y,Z)
+// -----------
+// Timer.  Call the form "X" once "Y" milliseconds have passed, and again
+// every "Y" milliseconds after that if "Z" is not null.  Forms are called
+// between commands, when the active string is done, and a wait for a key
+// in #(it) is cut short when a timer comes due.  Setting a timer for a
+// form replaces any timer already set for it.
+//
+// Returns: null
+struct TiPrim;
+impl MintPrim for TiPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let form = args[1].value();
+        if !form.is_empty() {
+            let delay = args[2].get_int_value(10).max(0) as u64;
+            let repeat = !args[3].value().is_empty();
+            interp.add_timer(form, Duration::from_millis(delay), repeat);
+        }
+        interp.return_null(is_active);
+    }
+}
+
+// #(tk,X)
+// -------
+// Timer kill.  Cancel the timer set for the form "X" by #(ti), or all
+// timers if "X" is null.
+//
+// Returns: null
+struct TkPrim;
+impl MintPrim for TkPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        interp.cancel_timer(args[1].value());
+        interp.return_null(is_active);
+    }
+}
+
+// #(bg,X,Y,Z,W)
+// -------------
+// Background.  Start the slow operation "X" on a worker thread, and carry
+// on without waiting for it.  "X" is one of:
+//     rf  Read the file "Y", as it is on disk
+//     wf  Write the current buffer's text, as it is now, to the file "Y",
+//         converted according to the buffer's "en" coding.  The buffer's
+//         visited file and modified flag are left alone.
+//     ff  Find the files matching "Y", as #(ff) does with a newline as
+//         separator and "p" as options
+//     sh  Run the shell command "Y", for what it prints
+// Operations are done one at a time, in the order given.  When one is
+// done, the form "Z" is defined as its result, or the error text if it
+// failed, and the form "W" is called with "A" as its argument if it
+// succeeded, or "B" if not.  That happens between commands, as timers
+// are called, and #(it) stops waiting for a key to let it happen.
+//
+// Returns: null, or error text if "X" is not an operation.
+struct BgPrim;
+impl MintPrim for BgPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let arg = String::from_utf8_lossy(args[2].value()).into_owned();
+        let work: worker::Work = match args[1].value().as_slice() {
+            b"rf" => Box::new(move || fs::read(arg)),
+            b"wf" => {
+                let content = with_current_buffer(interp, |buf| {
+                    buf.get_coding().encode(&buf.read_to_mark_from(b']', 0))
+                });
+                with_buffers(interp, |buffers| buffers.unmap_file(&arg));
+                Box::new(move || fs::write(arg, content).map(|_| Vec::new()))
+            }
+            b"ff" => Box::new(move || Ok(find_files(arg.as_bytes(), b"\n", b"p"))),
+            b"sh" => Box::new(move || command_output(&arg)),
+            _ => {
+                interp.return_string(is_active, &b"no such operation".to_vec());
+                return;
+            }
+        };
+        let job = worker::Job {
+            form: args[3].value().clone(),
+            then: args[4].value().clone(),
+        };
+        worker::submit(interp, job, work);
+        interp.return_null(is_active);
+    }
+}
+
+// What the shell command "command" prints, or what it prints as an error
+// if it fails.
+fn command_output(command: &str) -> io::Result<MintString> {
+    #[cfg(unix)]
+    let output = process::Command::new("/bin/sh")
+        .args(["-c", command])
+        .output()?;
+    #[cfg(not(unix))]
+    let output = process::Command::new("cmd")
+        .args(["/C", command])
+        .output()?;
+    if output.status.success() {
+        return Ok(output.stdout);
+    }
+    let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(io::Error::other(if message.is_empty() {
+        output.status.to_string()
+    } else {
+        message
+    }))
+}
+
+// #(pl,X)
+// -------
+// Plugin load.  Load the shared library "X" as a plugin, adding the
+// primitives it defines, which replace any of the same name.  See
+// plugin.rs for what a plugin must export.
+//
+// Returns: null if successful, error text otherwise.
+struct PlPrim;
+impl MintPrim for PlPrim {
+    #[cfg(any(unix, windows))]
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let path = String::from_utf8_lossy(args[1].value()).into_owned();
+        match plugin::load(interp, &path) {
+            Ok(()) => interp.return_null(is_active),
+            Err(e) => interp.return_string(is_active, &e.into_bytes()),
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn execute(&self, interp: &mut Mint, is_active: bool, _args: &MintArgList) {
+        interp.return_string(is_active, &b"plugins not supported".to_vec());
+    }
+}
+
+// The environment as #(ev) reads it, shared with #(se).
+type Environment = Arc<Mutex<Vec<(String, String)>>>;
+
+// #(se,X,Y)
+// ---------
+// Set environment.  Set the environment variable "X" to "Y", or remove it
+// if "Y" is null.  Processes started after this, and the forms defined
+// by #(ev) after this, see the change.
+//
+// Returns: null if successful, error text otherwise.
+struct SePrim {
+    envp: Environment,
+}
+
+impl MintPrim for SePrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let name = String::from_utf8_lossy(args[1].value()).into_owned();
+        let value = String::from_utf8_lossy(args[2].value()).into_owned();
+        if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+            interp.return_string(is_active, &b"invalid variable".to_vec());
+            return;
+        }
+        // SAFETY: The threads reading input and process output never look
+        // at the environment.
+        unsafe {
+            if value.is_empty() {
+                env::remove_var(&name);
+            } else {
+                env::set_var(&name, &value);
+            }
+        }
+        let mut envp = self.envp.lock().unwrap();
+        envp.retain(|(key, _)| *key != name);
+        if !value.is_empty() {
+            envp.push((name, value));
+        }
+        interp.return_null(is_active);
+    }
+}
+
 // #(ev)
 // -----
 // Read environment.  This reads the operating system environment, and
@@ -219,18 +851,20 @@ impl MintPrim for DePrim {
 //     env.SWITCHAR        The switch character (eg '-')
 //     env.FULLPATH        The full path to the executable
 //     env.SCREEN          The original contents of the screen
+// The original screen can only be read inside tmux or on a Linux console;
+// elsewhere env.SCREEN is empty.
 //
 // Returns: null
 struct EvPrim {
     argv: Vec<String>,
-    envp: Vec<(String, String)>,
+    envp: Environment,
 }
 
 impl EvPrim {
-    fn new(argv: &[String], envp: &[(String, String)]) -> Self {
+    fn new(argv: &[String], envp: Environment) -> Self {
         Self {
             argv: argv.to_vec(),
-            envp: envp.to_vec(),
+            envp,
         }
     }
 }
@@ -246,8 +880,8 @@ impl MintPrim for EvPrim {
         // Set switch character
         interp.set_form_value(ENV_SWITCHAR, SWITCHAR);
 
-        // Set screen (empty - not available)
-        interp.set_form_value(ENV_SCREEN, &Vec::new());
+        // Set screen, as it was before the display started
+        interp.set_form_value(ENV_SCREEN, &emacs_window::original_screen(interp));
 
         // Set full path and run line
         if !self.argv.is_empty() {
@@ -261,7 +895,7 @@ impl MintPrim for EvPrim {
         }
 
         // Set environment variables
-        for (key, value) in &self.envp {
+        for (key, value) in self.envp.lock().unwrap().iter() {
             let mut form_name = b"env.".to_vec();
             form_name.extend_from_slice(key.as_bytes());
             interp.set_form_value(&form_name, value.as_bytes());
@@ -273,19 +907,91 @@ impl MintPrim for EvPrim {
 
 // System variables
 
-// sd - Swap directory
+// The directory for swap and other scratch files: the one "sd" was set
+// to, or else the default.
+pub fn swap_dir(interp: &Mint) -> PathBuf {
+    let dir = interp.context().swap_dir.borrow().clone();
+    dir.unwrap_or_else(default_swap_dir)
+}
+
+// The swap directory before "sd" is set: $EMACSTMP, $TMP or $TEMP, or
+// else the current directory.
+pub fn default_swap_dir() -> PathBuf {
+    env::var_os("EMACSTMP")
+        .or_else(|| env::var_os("TMP"))
+        .or_else(|| env::var_os("TEMP"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+// Whether files can be made in "dir", found by making one.
+fn is_writable_dir(dir: &Path) -> bool {
+    let probe = dir.join(format!(".freemacs-{}", process::id()));
+    match fs::File::create_new(&probe) {
+        Ok(_) => fs::remove_file(&probe).is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Make a new file in "dir", named "prefix" and some letters and digits
+// that no other file there has.  Only the user can read or write it.
+pub fn temp_file(dir: &Path, prefix: &str) -> io::Result<(PathBuf, fs::File)> {
+    // Seeded differently in every process.
+    let state = RandomState::new();
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut attempt = 0u32;
+    loop {
+        let suffix = state.hash_one((process::id(), attempt, Instant::now())) & 0xffff_ffff;
+        let path = dir.join(format!("{}{:08x}", prefix, suffix));
+        match options.open(&path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            result => return result.map(|file| (path, file)),
+        }
+    }
+}
+
+// Write "content" to "path" by way of a new file beside it, renamed over
+// it, so that a link someone else has left at "path" is replaced rather
+// than written through.
+pub fn replace_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (temp, mut file) = temp_file(dir, &format!("{}.", name))?;
+    let result = file
+        .write_all(content)
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        fs::remove_file(&temp).ok();
+    }
+    result
+}
+
+// sd
+// --
+// Swap directory.  Setting it to a directory that doesn't exist or can't
+// be written to leaves it unchanged.  Setting it to null goes back to the
+// default, from the environment.
 struct SdVar;
 impl MintVar for SdVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        env::var("EMACSTMP")
-            .or_else(|_| env::var("TMP"))
-            .or_else(|_| env::var("TEMP"))
-            .unwrap_or_else(|_| ".".to_string())
-            .into_bytes()
+    fn get_val(&self, interp: &Mint) -> MintString {
+        swap_dir(interp).to_string_lossy().into_owned().into_bytes()
     }
 
-    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
-        // Cannot be set
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        if val.is_empty() {
+            interp.context().swap_dir.take();
+            return;
+        }
+        let path = PathBuf::from(String::from_utf8_lossy(val).as_ref());
+        if is_writable_dir(&path) {
+            interp.context().swap_dir.replace(Some(path));
+        }
     }
 }
 
@@ -320,21 +1026,79 @@ impl MintVar for CdVar {
 struct CnVar;
 impl MintVar for CnVar {
     fn get_val(&self, _interp: &Mint) -> MintString {
-        #[cfg(target_os = "windows")]
-        let result = b"Windows".to_vec();
-
-        #[cfg(not(target_os = "windows"))]
-        let result = {
-            use std::process::Command;
-            if let Ok(output) = Command::new("uname").arg("-sr").output() {
-                let s = String::from_utf8_lossy(&output.stdout).to_string();
-                s.trim().as_bytes().to_vec()
-            } else {
-                b"Unknown".to_vec()
-            }
-        };
+        system_name().into_bytes()
+    }
 
-        result
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Cannot be set
+    }
+}
+
+// The system's name and release, as "uname -sr" has them.
+#[cfg(unix)]
+fn system_name() -> String {
+    use std::ffi::CStr;
+
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return "Unknown".to_string();
+    }
+    let sysname = unsafe { CStr::from_ptr(name.sysname.as_ptr()) };
+    let release = unsafe { CStr::from_ptr(name.release.as_ptr()) };
+    format!(
+        "{} {}",
+        sysname.to_string_lossy(),
+        release.to_string_lossy()
+    )
+}
+
+#[cfg(target_os = "windows")]
+fn system_name() -> String {
+    "Windows".to_string()
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+fn system_name() -> String {
+    env::consts::OS.to_string()
+}
+
+// os
+// --
+// Get the operating system, such as "linux", "macos" or "windows".  This
+// value cannot be set.
+struct OsVar;
+impl MintVar for OsVar {
+    fn get_val(&self, _interp: &Mint) -> MintString {
+        env::consts::OS.as_bytes().to_vec()
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Cannot be set
+    }
+}
+
+// ar
+// --
+// Get the processor architecture, such as "x86_64" or "aarch64".  This
+// value cannot be set.
+struct ArVar;
+impl MintVar for ArVar {
+    fn get_val(&self, _interp: &Mint) -> MintString {
+        env::consts::ARCH.as_bytes().to_vec()
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Cannot be set
+    }
+}
+
+// hn
+// --
+// Get the host name.  This value cannot be set.
+struct HnVar;
+impl MintVar for HnVar {
+    fn get_val(&self, _interp: &Mint) -> MintString {
+        host_name().into_bytes()
     }
 
     fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
@@ -342,6 +1106,24 @@ impl MintVar for CnVar {
     }
 }
 
+#[cfg(unix)]
+pub fn host_name() -> String {
+    use std::ffi::CStr;
+
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len() - 1) } != 0 {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(not(unix))]
+pub fn host_name() -> String {
+    env::var("COMPUTERNAME").unwrap_or_default()
+}
+
 // is
 // --
 // Get/set "inhibit snow" flag for IBM CGA.
@@ -357,13 +1139,13 @@ impl MintVar for IsVar {
     }
 }
 
-// bp
+// pd
 // --
-// Set the default bell pitch. If < 0 use visible bell.
-struct BpVar;
-impl MintVar for BpVar {
+// Get the process id of the editor.  This value cannot be set.
+struct PdVar;
+impl MintVar for PdVar {
     fn get_val(&self, _interp: &Mint) -> MintString {
-        b"440".to_vec()
+        process::id().to_string().into_bytes()
     }
 
     fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
@@ -371,19 +1153,100 @@ impl MintVar for BpVar {
     }
 }
 
+// un
+// --
+// Get the name of the user running the editor.  This value cannot be set.
+struct UnVar;
+impl MintVar for UnVar {
+    fn get_val(&self, _interp: &Mint) -> MintString {
+        user_name().into_bytes()
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Cannot be set
+    }
+}
+
+#[cfg(unix)]
+pub fn user_name() -> String {
+    passwd_entry(unsafe { libc::getuid() })
+        .map(|(name, _)| name)
+        .or_else(|| env::var("USER").ok())
+        .unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+pub fn user_name() -> String {
+    env::var("USERNAME").unwrap_or_default()
+}
+
+// hd
+// --
+// Get the user's home directory, with a trailing "/", as for "cd".  This
+// value cannot be set.
+struct HdVar;
+impl MintVar for HdVar {
+    fn get_val(&self, _interp: &Mint) -> MintString {
+        let mut result = home_dir().into_bytes();
+        if !result.is_empty() && !result.ends_with(b"/") {
+            result.push(b'/');
+        }
+        result
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Cannot be set
+    }
+}
+
+#[cfg(unix)]
+pub fn home_dir() -> String {
+    env::var("HOME")
+        .ok()
+        .filter(|home| !home.is_empty())
+        .or_else(|| passwd_entry(unsafe { libc::getuid() }).map(|(_, dir)| dir))
+        .unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+pub fn home_dir() -> String {
+    env::var("USERPROFILE").unwrap_or_default()
+}
+
+// bp
+// --
+// Set the default bell pitch, in Hz, used by #(bl) when its frequency is
+// 0.  If < 0 use visible bell.
+struct BpVar;
+impl MintVar for BpVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        emacs_window::bell_pitch(interp).to_string().into_bytes()
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        emacs_window::set_bell_pitch(interp, mint_string::get_int_value(val, 10));
+    }
+}
+
+// "Day Mon DD HH:MM:SS YYYY", as DOS Freemacs had it.
+const DEFAULT_TIME_FORMAT: &str = "%a %b %d %H:%M:%S %Y";
+
 // Helper function to format system time
-fn format_system_time(time: SystemTime) -> String {
-    use std::time::UNIX_EPOCH;
+fn format_system_time(time: SystemTime, format: &str) -> String {
+    use std::fmt::Write;
 
     if let Ok(duration) = time.duration_since(UNIX_EPOCH) {
         let secs = duration.as_secs();
 
-        // Simple time formatting (similar to strftime)
-        // This is a basic implementation - format: "Day Mon DD HH:MM:SS YYYY"
         use chrono::Local;
         use chrono::TimeZone;
         let dt = Local.timestamp_opt(secs as i64, 0).unwrap();
-        dt.format("%a %b %d %H:%M:%S %Y").to_string()
+        // Formatting fails, rather than panicking, on a bad "%" code.
+        let mut result = String::new();
+        match write!(result, "{}", dt.format(format)) {
+            Ok(_) => result,
+            Err(_) => String::new(),
+        }
     } else {
         String::new()
     }
@@ -396,11 +1259,38 @@ pub fn register_sys_prims(interp: &mut Mint, argv: &[String], envp: &[(String, S
     interp.add_prim(b"ff".to_vec(), Box::new(FfPrim));
     interp.add_prim(b"rn".to_vec(), Box::new(RnPrim));
     interp.add_prim(b"de".to_vec(), Box::new(DePrim));
-    interp.add_prim(b"ev".to_vec(), Box::new(EvPrim::new(argv, envp)));
+    interp.add_prim(b"cp".to_vec(), Box::new(CpPrim));
+    interp.add_prim(b"md".to_vec(), Box::new(MdPrim));
+    interp.add_prim(b"fs".to_vec(), Box::new(FsPrim));
+    interp.add_prim(b"lk".to_vec(), Box::new(LkPrim));
+    interp.add_prim(b"lq".to_vec(), Box::new(LqPrim));
+    interp.add_prim(b"uk".to_vec(), Box::new(UkPrim));
+    interp.add_prim(b"tf".to_vec(), Box::new(TfPrim));
+    interp.add_prim(b"sg".to_vec(), Box::new(SgPrim));
+    interp.add_prim(b"ss".to_vec(), Box::new(SsPrim));
+    let envp: Environment = Arc::new(Mutex::new(envp.to_vec()));
+    interp.add_prim(b"ev".to_vec(), Box::new(EvPrim::new(argv, envp.clone())));
+    interp.add_prim(b"se".to_vec(), Box::new(SePrim { envp }));
+    interp.add_prim(b"pr".to_vec(), Box::new(PrPrim));
+    interp.add_prim(b"pi".to_vec(), Box::new(PiPrim));
+    interp.add_prim(b"pk".to_vec(), Box::new(PkPrim));
+    interp.add_prim(b"ti".to_vec(), Box::new(TiPrim));
+    interp.add_prim(b"tk".to_vec(), Box::new(TkPrim));
+    interp.add_prim(b"bg".to_vec(), Box::new(BgPrim));
+    interp.add_prim(b"pl".to_vec(), Box::new(PlPrim));
 
     interp.add_var(b"bp".to_vec(), Box::new(BpVar));
     interp.add_var(b"cd".to_vec(), Box::new(CdVar));
     interp.add_var(b"cn".to_vec(), Box::new(CnVar));
+    interp.add_var(b"os".to_vec(), Box::new(OsVar));
+    interp.add_var(b"ar".to_vec(), Box::new(ArVar));
+    interp.add_var(b"hn".to_vec(), Box::new(HnVar));
+    interp.add_var(b"pd".to_vec(), Box::new(PdVar));
+    interp.add_var(b"un".to_vec(), Box::new(UnVar));
+    interp.add_var(b"hd".to_vec(), Box::new(HdVar));
     interp.add_var(b"is".to_vec(), Box::new(IsVar));
     interp.add_var(b"sd".to_vec(), Box::new(SdVar));
+
+    // Class for #(ph)
+    interp.add_prim_class(b"file", &[b"rn", b"de", b"cp", b"md"]);
 }
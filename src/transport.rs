@@ -0,0 +1,291 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* A connection to a terminal as a stream of bytes each way. */
+
+// Input is read on a thread of its own, so that reads can time out
+// whatever the stream is.  Telnet connections have their commands taken
+// out of the input, and the window sizes they report passed on.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Byte(u8),
+    // The terminal is now this many columns and rows.
+    Size(u16, u16),
+}
+
+pub struct Transport {
+    input: Receiver<Input>,
+    // Input read while looking for something else, or given back.
+    ahead: RefCell<VecDeque<Input>>,
+    output: Box<dyn Write + Send>,
+    closed: Cell<bool>,
+}
+
+// Telnet commands.
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const NAWS: u8 = 31;
+
+impl Transport {
+    pub fn new<R: Read + Send + 'static>(input: R, output: Box<dyn Write + Send>) -> Self {
+        Self::with_filter(input, output, None)
+    }
+
+    // A telnet client, asked to leave echoing to us, send keys as they
+    // are typed and say how big its window is.
+    pub fn telnet<R: Read + Send + 'static>(input: R, mut output: Box<dyn Write + Send>) -> Self {
+        let request = [IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD, IAC, DO, NAWS];
+        output.write_all(&request).ok();
+        output.flush().ok();
+        Self::with_filter(input, output, Some(TelnetFilter::default()))
+    }
+
+    fn with_filter<R: Read + Send + 'static>(
+        input: R,
+        output: Box<dyn Write + Send>,
+        filter: Option<TelnetFilter>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || read_input(input, sender, filter));
+        Self {
+            input: receiver,
+            ahead: RefCell::new(VecDeque::new()),
+            output,
+            closed: Cell::new(false),
+        }
+    }
+
+    // The next input, waiting up to "timeout" for it.
+    pub fn read(&mut self, timeout: Duration) -> Option<Input> {
+        if let Some(input) = self.ahead.get_mut().pop_front() {
+            return Some(input);
+        }
+        match self.input.recv_timeout(timeout) {
+            Ok(input) => Some(input),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                self.closed.set(true);
+                None
+            }
+        }
+    }
+
+    // Have "input" read again before anything else.
+    pub fn unread(&mut self, input: Input) {
+        self.ahead.get_mut().push_front(input);
+    }
+
+    pub fn input_waiting(&self) -> bool {
+        if !self.ahead.borrow().is_empty() {
+            return true;
+        }
+        match self.input.try_recv() {
+            Ok(input) => {
+                self.ahead.borrow_mut().push_back(input);
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => {
+                self.closed.set(true);
+                false
+            }
+        }
+    }
+
+    // Whether the other end has gone, and everything it sent been read.
+    pub fn is_closed(&self) -> bool {
+        self.closed.get() && self.ahead.borrow().is_empty()
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+fn read_input<R: Read>(mut input: R, sender: Sender<Input>, mut filter: Option<TelnetFilter>) {
+    let mut buf = [0u8; 1024];
+    loop {
+        let len = match input.read(&mut buf) {
+            Ok(0) => return,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        for &byte in &buf[..len] {
+            let input = match filter.as_mut() {
+                Some(filter) => filter.feed(byte),
+                None => Some(Input::Byte(byte)),
+            };
+            if let Some(input) = input
+                && sender.send(input).is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum TelnetState {
+    #[default]
+    Data,
+    // After a carriage return, which clients follow with a null or a
+    // line feed that isn't a key.
+    Return,
+    Command,
+    Option,
+    Sub,
+    SubCommand,
+}
+
+// Takes telnet commands out of the input.
+#[derive(Default)]
+struct TelnetFilter {
+    state: TelnetState,
+    sub: Vec<u8>,
+}
+
+impl TelnetFilter {
+    fn feed(&mut self, byte: u8) -> Option<Input> {
+        match (self.state, byte) {
+            (TelnetState::Data | TelnetState::Return, IAC) => {
+                self.state = TelnetState::Command;
+                None
+            }
+            (TelnetState::Return, 0 | b'\n') => {
+                self.state = TelnetState::Data;
+                None
+            }
+            (TelnetState::Data | TelnetState::Return, _) => {
+                self.state = if byte == b'\r' {
+                    TelnetState::Return
+                } else {
+                    TelnetState::Data
+                };
+                Some(Input::Byte(byte))
+            }
+            (TelnetState::Command, IAC) => {
+                self.state = TelnetState::Data;
+                Some(Input::Byte(IAC))
+            }
+            (TelnetState::Command, DO | DONT | WILL | WONT) => {
+                self.state = TelnetState::Option;
+                None
+            }
+            (TelnetState::Command, SB) => {
+                self.state = TelnetState::Sub;
+                self.sub.clear();
+                None
+            }
+            (TelnetState::Command | TelnetState::Option, _) => {
+                self.state = TelnetState::Data;
+                None
+            }
+            (TelnetState::Sub, IAC) => {
+                self.state = TelnetState::SubCommand;
+                None
+            }
+            (TelnetState::Sub, _) => {
+                self.sub.push(byte);
+                None
+            }
+            (TelnetState::SubCommand, SE) => {
+                self.state = TelnetState::Data;
+                match self.sub[..] {
+                    [NAWS, w1, w2, h1, h2] => Some(Input::Size(
+                        u16::from_be_bytes([w1, w2]),
+                        u16::from_be_bytes([h1, h2]),
+                    )),
+                    _ => None,
+                }
+            }
+            (TelnetState::SubCommand, _) => {
+                // A doubled IAC in the subnegotiation stands for itself.
+                self.state = TelnetState::Sub;
+                self.sub.push(byte);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(bytes: &[u8]) -> Vec<Input> {
+        let mut filter = TelnetFilter::default();
+        bytes.iter().filter_map(|&b| filter.feed(b)).collect()
+    }
+
+    #[test]
+    fn telnet_commands_are_removed() {
+        let bytes = [b'a', IAC, WILL, NAWS, b'b', IAC, IAC, IAC, 241, b'c'];
+        let expected = [b'a', b'b', IAC, b'c'].map(Input::Byte);
+        assert_eq!(expected.to_vec(), filter(&bytes));
+    }
+
+    #[test]
+    fn telnet_window_size_is_passed_on() {
+        let bytes = [IAC, SB, NAWS, 0, 132, 0, 43, IAC, SE, b'x'];
+        assert_eq!(
+            vec![Input::Size(132, 43), Input::Byte(b'x')],
+            filter(&bytes)
+        );
+    }
+
+    #[test]
+    fn telnet_return_is_one_key() {
+        let bytes = [b'\r', 0, b'\r', b'\n', b'\n'];
+        let expected = [b'\r', b'\r', b'\n'].map(Input::Byte);
+        assert_eq!(expected.to_vec(), filter(&bytes));
+    }
+
+    #[test]
+    fn transport_reads_bytes_then_closes() {
+        let mut transport = Transport::new(&b"hi"[..], Box::new(io::sink()));
+        let timeout = Duration::from_secs(5);
+        assert_eq!(Some(Input::Byte(b'h')), transport.read(timeout));
+        transport.unread(Input::Byte(b'H'));
+        assert_eq!(Some(Input::Byte(b'H')), transport.read(timeout));
+        assert_eq!(Some(Input::Byte(b'i')), transport.read(timeout));
+        assert_eq!(None, transport.read(timeout));
+        assert!(transport.is_closed());
+    }
+}
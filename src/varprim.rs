@@ -16,6 +16,7 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
+use crate::emacs_window;
 use crate::mint::{Mint, MintPrim, MintVar};
 use crate::mint_arg::MintArgList;
 use crate::mint_string::{self, get_int_value};
@@ -38,6 +39,8 @@ impl MintPrim for LvPrim {
 // #(sv,X,Y)
 // ---------
 // Set variable.  Set variable given by literal string "X" to value "Y".
+// If there is no variable "X", one is made, which #(lv,X) then reads.
+// Variables are apart from forms, so "X" can name a form as well.
 //
 // Returns: null
 struct SvPrim;
@@ -50,6 +53,33 @@ impl MintPrim for SvPrim {
     }
 }
 
+// #(lV,X,Y)
+// ---------
+// List variables.
+//
+// Returns: A list, separated by literal string "X", of the variables
+// whose names start with "Y", each name followed by a space and the
+// variable's value.
+struct LVPrim;
+impl MintPrim for LVPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let separator = args[1].value();
+        let prefix = args[2].value();
+        let entries: Vec<MintString> = interp
+            .var_names()
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|mut entry| {
+                let value = interp.get_var(&entry);
+                entry.push(b' ');
+                entry.extend(value);
+                entry
+            })
+            .collect();
+        interp.return_string(is_active, &entries.join(&separator[..]));
+    }
+}
+
 // vn
 // --
 // Get version number.  This variable cannot be set.
@@ -64,6 +94,80 @@ impl MintVar for VnVar {
     }
 }
 
+// vc
+// --
+// Get the git commit the editor was built from, or null if unknown.  This
+// variable cannot be set.
+struct VcVar;
+impl MintVar for VcVar {
+    fn get_val(&self, _interp: &Mint) -> MintString {
+        env!("FREEMACS_COMMIT").as_bytes().to_vec()
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Read-only
+    }
+}
+
+// vd
+// --
+// Get the date the editor was built, as "YYYY-MM-DD".  This variable
+// cannot be set.
+struct VdVar;
+impl MintVar for VdVar {
+    fn get_val(&self, _interp: &Mint) -> MintString {
+        env!("FREEMACS_BUILD_DATE").as_bytes().to_vec()
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Read-only
+    }
+}
+
+// vf
+// --
+// Get the optional features the editor was built with, separated by
+// spaces: any of "curses", "crossterm", "gui" and "wasm".  This variable
+// cannot be set.
+struct VfVar;
+impl MintVar for VfVar {
+    fn get_val(&self, _interp: &Mint) -> MintString {
+        let features = [
+            ("curses", cfg!(feature = "curses")),
+            ("crossterm", cfg!(feature = "crossterm")),
+            ("gui", cfg!(feature = "gui")),
+            ("wasm", cfg!(feature = "wasm")),
+        ];
+        let enabled: Vec<&str> = features
+            .iter()
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| *name)
+            .collect();
+        enabled.join(" ").into_bytes()
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Read-only
+    }
+}
+
+// wb
+// --
+// Get the name of what is driving the screen: "curses", "crossterm",
+// "gui", "stream" for a terminal at the other end of a socket, or "batch",
+// "debug" or "scripted" when there is no terminal.  This variable cannot
+// be set.
+struct WbVar;
+impl MintVar for WbVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        emacs_window::with_window(interp, |w| w.backend().as_bytes().to_vec())
+    }
+
+    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
+        // Read-only
+    }
+}
+
 // as
 // --
 // Auto save limit.  After this many characters have been entered, the
@@ -87,9 +191,14 @@ impl MintVar for AsVar {
 pub fn register_var_prims(interp: &mut Mint) {
     // Primitives
     interp.add_prim(b"lv".to_vec(), Box::new(LvPrim));
+    interp.add_prim(b"lV".to_vec(), Box::new(LVPrim));
     interp.add_prim(b"sv".to_vec(), Box::new(SvPrim));
 
     // Variables
     interp.add_var(b"vn".to_vec(), Box::new(VnVar));
+    interp.add_var(b"vc".to_vec(), Box::new(VcVar));
+    interp.add_var(b"vd".to_vec(), Box::new(VdVar));
+    interp.add_var(b"vf".to_vec(), Box::new(VfVar));
+    interp.add_var(b"wb".to_vec(), Box::new(WbVar));
     interp.add_var(b"as".to_vec(), Box::new(AsVar));
 }
@@ -0,0 +1,186 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Waiting for a key and for everything else at once. */
+
+// While the editor waits for a key, output can arrive from processes, jobs
+// given to #(bg) can finish, and signals can ask it to finish.  Rather
+// than look for those every so often, the threads reading process output
+// and doing jobs write a byte to a pipe when they have something, as the
+// signal handlers do to a pipe of their own, and the editor sleeps in
+// poll() on both pipes and the terminal together.  Timers are dealt with
+// by not sleeping past the next one.  Displays that have no descriptor to
+// wait on, and systems without poll(), still look every so often.
+
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::mint::Mint;
+#[cfg(unix)]
+use crate::signals;
+
+// The pipe a Waker writes to, and the editor waits on.
+#[cfg(unix)]
+struct Pipe {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+// Wakes the editor from a wait.  Each thread with something to report
+// has a clone.
+#[derive(Clone)]
+pub struct Waker {
+    #[cfg(unix)]
+    pipe: Option<Arc<Pipe>>,
+}
+
+impl Default for Waker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(unix)]
+impl Waker {
+    // Without a pipe, which is only if the system won't give us one, the
+    // editor can't be woken, so looks every so often as it did before.
+    pub fn new() -> Self {
+        Waker {
+            pipe: open_pipe().map(|(read, write)| Arc::new(Pipe { read, write })),
+        }
+    }
+
+    pub fn wake(&self) {
+        if let Some(pipe) = &self.pipe {
+            write_byte(pipe.write.as_raw_fd());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Waker {
+    pub fn new() -> Self {
+        Waker {}
+    }
+
+    pub fn wake(&self) {}
+}
+
+// Whether wait can be used, rather than looking every so often.
+#[cfg(unix)]
+pub fn can_wait(interp: &Mint) -> bool {
+    interp.context().waker.pipe.is_some()
+}
+
+#[cfg(not(unix))]
+pub fn can_wait(_interp: &Mint) -> bool {
+    false
+}
+
+// Sleep until there is input on "input_fd", something wakes the editor,
+// a signal is caught, or "timeout" has passed.  Whatever woke it is for
+// the caller to look for.
+#[cfg(unix)]
+pub fn wait(interp: &Mint, input_fd: i32, timeout: Duration) {
+    let mut fds = vec![input_fd];
+    let pipe = interp.context().waker.pipe.clone();
+    fds.extend(pipe.iter().map(|pipe| pipe.read.as_raw_fd()));
+    fds.extend(signals::wake_fd());
+    let mut polls: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let millis = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+    // SAFETY: "polls" is a live array of "polls.len()" pollfds.  Being
+    // interrupted by a signal is the same as being woken.
+    unsafe { libc::poll(polls.as_mut_ptr(), polls.len() as libc::nfds_t, millis) };
+    // Emptied before the caller looks, so nothing written after that is
+    // missed by the next wait.
+    for poll in &polls[1..] {
+        if poll.revents != 0 {
+            drain(poll.fd);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn wait(_interp: &Mint, _input_fd: i32, _timeout: Duration) {}
+
+// A pipe whose ends don't block, and aren't passed on to processes.
+#[cfg(unix)]
+pub fn open_pipe() -> Option<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe() fills in "fds" with two descriptors only we own.
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return None;
+        }
+        let ends = (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]));
+        for fd in fds {
+            libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK);
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        Some(ends)
+    }
+}
+
+// Write a byte to "fd", which is safe from a signal handler.  A full pipe
+// already has a wait to end, so that failing doesn't matter.
+#[cfg(unix)]
+pub fn write_byte(fd: RawFd) {
+    // SAFETY: writes one byte from a live buffer.
+    unsafe { libc::write(fd, b"!".as_ptr().cast(), 1) };
+}
+
+// Read all there is from "fd".
+#[cfg(unix)]
+fn drain(fd: RawFd) {
+    let mut buf = [0u8; 64];
+    // SAFETY: reads into a live buffer of the length given; "fd" doesn't
+    // block, so this stops once it is empty.
+    while unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn waking_ends_a_wait() {
+        let interp = Mint::new();
+        let (input, _keys) = open_pipe().unwrap();
+        let waker = interp.context().waker.clone();
+        let waking = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            waker.wake();
+        });
+        let start = Instant::now();
+        wait(&interp, input.as_raw_fd(), Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        waking.join().unwrap();
+    }
+}
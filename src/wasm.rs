@@ -0,0 +1,154 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Entry points for running in a browser, drawing on an xterm.js terminal. */
+
+// The page owns the terminal, so the editor draws on a scripted window
+// and each call returns the escape sequences that bring the terminal up
+// to date with it.  Strings are passed through a shared input area:
+// JavaScript asks freemacs_input for room, copies the bytes in, and then
+// makes the call with their length.  Output is read the same way, from
+// freemacs_output and freemacs_output_len.  See web/freemacs.js.
+//
+// A browser can't wait for input, so keys are fed in one at a time as
+// they are typed, and a macro that reads more keys than it has been given
+// sees "Timeout".
+
+use std::cell::RefCell;
+
+use crate::editor::Editor;
+use crate::emacs_window_scripted::{EmacsWindowScripted, SharedScreen};
+
+struct Session {
+    editor: Editor,
+    screen: SharedScreen,
+    // Rows as the terminal last showed them.
+    shown: Vec<String>,
+}
+
+thread_local! {
+    static SESSION: RefCell<Option<Session>> = const { RefCell::new(None) };
+    static INPUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static OUTPUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// Escape sequences to redraw the rows of "lines" that differ from "shown",
+// and put the cursor at "cursor".
+fn screen_update(lines: &[String], cursor: (usize, usize), shown: &mut Vec<String>) -> String {
+    let mut out = String::new();
+    if shown.len() != lines.len() {
+        out.push_str("\x1b[2J");
+        *shown = vec![String::new(); lines.len()];
+    }
+    for (row, (line, old)) in lines.iter().zip(shown.iter_mut()).enumerate() {
+        if line != old {
+            out.push_str(&format!("\x1b[{};1H{}\x1b[K", row + 1, line));
+            *old = line.clone();
+        }
+    }
+    out.push_str(&format!("\x1b[{};{}H", cursor.0 + 1, cursor.1 + 1));
+    out
+}
+
+fn with_input<R>(len: usize, f: impl FnOnce(&[u8]) -> R) -> R {
+    INPUT.with(|input| {
+        let input = input.borrow();
+        f(&input[..len.min(input.len())])
+    })
+}
+
+fn refresh(session: &mut Session) {
+    let screen = session.screen.lock().unwrap();
+    let update = screen_update(&screen.lines(), screen.cursor(), &mut session.shown);
+    OUTPUT.with(|output| *output.borrow_mut() = update.into_bytes());
+}
+
+// Room for "len" bytes of input.  The area moves, so ask again each time.
+#[unsafe(no_mangle)]
+pub extern "C" fn freemacs_input(len: usize) -> *mut u8 {
+    INPUT.with(|input| {
+        let mut input = input.borrow_mut();
+        input.resize(len, 0);
+        input.as_mut_ptr()
+    })
+}
+
+// Start the editor on a terminal "cols" by "rows", running the first
+// "len" bytes of input as its starting script.
+#[unsafe(no_mangle)]
+pub extern "C" fn freemacs_start(cols: u32, rows: u32, len: usize) {
+    // Only one editor can be alive at a time, so finish any old one first.
+    SESSION.with(|session| *session.borrow_mut() = None);
+
+    let window = EmacsWindowScripted::new(cols, rows);
+    let screen = window.screen();
+    let editor = with_input(len, |script| {
+        Editor::new(Box::new(window), script, &[], &[])
+    });
+    let mut session = Session {
+        editor,
+        screen,
+        shown: Vec::new(),
+    };
+    session.editor.step();
+    refresh(&mut session);
+    SESSION.with(|s| *s.borrow_mut() = Some(session));
+}
+
+// Type the key named by the first "len" bytes of input.
+#[unsafe(no_mangle)]
+pub extern "C" fn freemacs_key(len: usize) {
+    let key = with_input(len, |key| String::from_utf8_lossy(key).into_owned());
+    SESSION.with(|session| {
+        if let Some(session) = session.borrow_mut().as_mut() {
+            session.editor.feed_key(&key);
+            refresh(session);
+        }
+    });
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn freemacs_output() -> *const u8 {
+    OUTPUT.with(|output| output.borrow().as_ptr())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn freemacs_output_len() -> usize {
+    OUTPUT.with(|output| output.borrow().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_update_redraws_changed_rows() {
+        let mut shown = Vec::new();
+        let lines = vec!["one".to_string(), String::new()];
+        assert_eq!(
+            "\x1b[2J\x1b[1;1Hone\x1b[K\x1b[1;4H",
+            screen_update(&lines, (0, 3), &mut shown)
+        );
+
+        let lines = vec!["one".to_string(), "two".to_string()];
+        assert_eq!(
+            "\x1b[2;1Htwo\x1b[K\x1b[2;4H",
+            screen_update(&lines, (1, 3), &mut shown)
+        );
+    }
+}
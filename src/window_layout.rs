@@ -0,0 +1,626 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+use std::cmp::{max, min};
+
+use crate::emacs_window::ModeLine;
+use crate::mint::Mint;
+use crate::mint_types::MintCount;
+
+pub type PaneId = MintCount;
+
+// Smallest pane is one line of text plus its mode line.
+const MIN_ROWS: MintCount = 2;
+const MIN_COLS: MintCount = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub row: MintCount,
+    pub col: MintCount,
+    pub rows: MintCount,
+    pub cols: MintCount,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    // Panes stacked one above the other
+    Below,
+    // Panes side by side
+    Right,
+}
+
+impl Direction {
+    fn extent(self, rect: &Rect) -> MintCount {
+        match self {
+            Direction::Below => rect.rows,
+            Direction::Right => rect.cols,
+        }
+    }
+
+    fn min_extent(self) -> MintCount {
+        match self {
+            Direction::Below => MIN_ROWS,
+            Direction::Right => MIN_COLS,
+        }
+    }
+}
+
+// A pane as placed on the screen by Layout::arrange.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaneView {
+    pub id: PaneId,
+    pub bufno: MintCount,
+    pub current: bool,
+    pub text: Rect,
+    pub mode_area: Rect,
+    pub mode_line: ModeLine,
+}
+
+struct Pane {
+    id: PaneId,
+    bufno: MintCount,
+    rect: Rect,
+    mode_line: ModeLine,
+}
+
+impl Pane {
+    fn new(id: PaneId, bufno: MintCount) -> Self {
+        Self {
+            id,
+            bufno,
+            rect: Rect::default(),
+            mode_line: ModeLine::default(),
+        }
+    }
+}
+
+enum Node {
+    Pane(PaneId),
+    Split(Direction, Vec<Child>),
+}
+
+// Children remember their size from the last arrangement, so that
+// resizing the screen keeps their proportions.
+struct Child {
+    node: Node,
+    size: MintCount,
+}
+
+impl Node {
+    fn contains(&self, target: PaneId) -> bool {
+        match self {
+            Node::Pane(id) => *id == target,
+            Node::Split(_, children) => children.iter().any(|c| c.node.contains(target)),
+        }
+    }
+
+    fn first_pane(&self) -> PaneId {
+        match self {
+            Node::Pane(id) => *id,
+            Node::Split(_, children) => children[0].node.first_pane(),
+        }
+    }
+
+    fn collect_panes(&self, ids: &mut Vec<PaneId>) {
+        match self {
+            Node::Pane(id) => ids.push(*id),
+            Node::Split(_, children) => children.iter().for_each(|c| c.node.collect_panes(ids)),
+        }
+    }
+
+    // Smallest extent in direction "dir" that fits every pane.
+    fn min_extent(&self, dir: Direction) -> MintCount {
+        match self {
+            Node::Pane(_) => dir.min_extent(),
+            Node::Split(d, children) => {
+                let extents = children.iter().map(|c| c.node.min_extent(dir));
+                if *d == dir {
+                    extents.sum()
+                } else {
+                    extents.max().unwrap_or(0)
+                }
+            }
+        }
+    }
+
+    fn arrange(&mut self, area: Rect, placed: &mut Vec<(PaneId, Rect)>) {
+        match self {
+            Node::Pane(id) => placed.push((*id, area)),
+            Node::Split(dir, children) => {
+                let dir = *dir;
+                let sizes = fit_sizes(dir, children, dir.extent(&area));
+                let mut offset = 0;
+                for (child, size) in children.iter_mut().zip(sizes) {
+                    child.size = size;
+                    let child_area = match dir {
+                        Direction::Below => Rect {
+                            row: area.row + offset,
+                            rows: size,
+                            ..area
+                        },
+                        Direction::Right => Rect {
+                            col: area.col + offset,
+                            cols: size,
+                            ..area
+                        },
+                    };
+                    child.node.arrange(child_area, placed);
+                    offset += size;
+                }
+            }
+        }
+    }
+
+    fn split(
+        &mut self,
+        target: PaneId,
+        new_id: PaneId,
+        dir: Direction,
+        sizes: (MintCount, MintCount),
+    ) -> bool {
+        match self {
+            Node::Pane(id) => {
+                if *id != target {
+                    return false;
+                }
+                *self = Node::Split(
+                    dir,
+                    vec![
+                        Child {
+                            node: Node::Pane(target),
+                            size: sizes.0,
+                        },
+                        Child {
+                            node: Node::Pane(new_id),
+                            size: sizes.1,
+                        },
+                    ],
+                );
+                true
+            }
+            Node::Split(d, children) => {
+                // Splitting in the same direction as the parent just adds a sibling
+                if *d == dir
+                    && let Some(i) = children
+                        .iter()
+                        .position(|c| matches!(c.node, Node::Pane(id) if id == target))
+                {
+                    children[i].size = sizes.0;
+                    let new_child = Child {
+                        node: Node::Pane(new_id),
+                        size: sizes.1,
+                    };
+                    children.insert(i + 1, new_child);
+                    return true;
+                }
+                children
+                    .iter_mut()
+                    .any(|c| c.node.split(target, new_id, dir, sizes))
+            }
+        }
+    }
+
+    // Remove pane "target", giving its space to a neighbour.  Returns a
+    // pane from the neighbour, or None if "target" isn't below this node.
+    fn remove(&mut self, target: PaneId) -> Option<PaneId> {
+        let Node::Split(_, children) = self else {
+            return None;
+        };
+        let Some(i) = children
+            .iter()
+            .position(|c| matches!(c.node, Node::Pane(id) if id == target))
+        else {
+            return children.iter_mut().find_map(|c| c.node.remove(target));
+        };
+
+        let removed = children.remove(i);
+        let heir = i.saturating_sub(1);
+        children[heir].size += removed.size;
+        let heir_pane = children[heir].node.first_pane();
+        if children.len() == 1
+            && let Some(only) = children.pop()
+        {
+            *self = only.node;
+        }
+        Some(heir_pane)
+    }
+
+    // Move space between pane "target" and its neighbour in the innermost
+    // split in direction "dir" that can spare it.  Returns None if
+    // "target" isn't below this node, otherwise whether anything changed.
+    fn resize(&mut self, target: PaneId, dir: Direction, delta: i32) -> Option<bool> {
+        match self {
+            Node::Pane(id) => (*id == target).then_some(false),
+            Node::Split(d, children) => {
+                let i = children.iter().position(|c| c.node.contains(target))?;
+                if children[i].node.resize(target, dir, delta) == Some(true) {
+                    return Some(true);
+                }
+                if *d != dir || children.len() < 2 {
+                    return Some(false);
+                }
+                let j = if i + 1 < children.len() { i + 1 } else { i - 1 };
+                let (grow, shrink) = if delta >= 0 { (i, j) } else { (j, i) };
+                let spare = children[shrink]
+                    .size
+                    .saturating_sub(children[shrink].node.min_extent(dir));
+                let n = min(delta.unsigned_abs(), spare);
+                children[grow].size += n;
+                children[shrink].size -= n;
+                Some(n > 0)
+            }
+        }
+    }
+
+    fn balance(&mut self) {
+        if let Node::Split(_, children) = self {
+            let total: MintCount = children.iter().map(|c| c.size).sum();
+            let n = children.len() as MintCount;
+            for (i, child) in children.iter_mut().enumerate() {
+                child.size = total / n + if (i as MintCount) < total % n { 1 } else { 0 };
+                child.node.balance();
+            }
+        }
+    }
+}
+
+// Share "total" between "children" in proportion to their previous sizes,
+// keeping each at least its minimum size where there is room.
+fn fit_sizes(dir: Direction, children: &[Child], total: MintCount) -> Vec<MintCount> {
+    let sum: u64 = children.iter().map(|c| c.size as u64).sum();
+    let n = children.len() as u64;
+    let mins: Vec<MintCount> = children.iter().map(|c| c.node.min_extent(dir)).collect();
+    let mut sizes: Vec<MintCount> = children
+        .iter()
+        .zip(&mins)
+        .map(|(c, &min_size)| {
+            // Share equally if the children have never been arranged
+            let size = (c.size as u64 * total as u64)
+                .checked_div(sum)
+                .unwrap_or(total as u64 / n);
+            max(size as MintCount, min_size)
+        })
+        .collect();
+
+    let mut used: MintCount = sizes.iter().sum();
+    while used > total {
+        let largest = (0..sizes.len())
+            .filter(|&i| sizes[i] > mins[i])
+            .max_by_key(|&i| sizes[i]);
+        match largest {
+            Some(i) => sizes[i] -= 1,
+            None => break,
+        }
+        used -= 1;
+    }
+    if used > total {
+        // Not enough room for everything, so later panes lose out
+        let mut room = total;
+        for size in sizes.iter_mut() {
+            *size = min(*size, room);
+            room -= *size;
+        }
+    } else if let Some(last) = sizes.last_mut() {
+        *last += total - used;
+    }
+    sizes
+}
+
+// Tiles the screen with any number of panes, each showing a buffer with a
+// mode line beneath it.  Panes are numbered from 1, and one of them is
+// always current.
+pub struct Layout {
+    root: Node,
+    panes: Vec<Pane>,
+    current: PaneId,
+    next_id: PaneId,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Layout {
+    pub fn new() -> Self {
+        Self {
+            root: Node::Pane(1),
+            panes: vec![Pane::new(1, 0)],
+            current: 1,
+            next_id: 2,
+        }
+    }
+
+    fn pane(&self, id: PaneId) -> Option<&Pane> {
+        self.panes.iter().find(|p| p.id == id)
+    }
+
+    fn pane_mut(&mut self, id: PaneId) -> Option<&mut Pane> {
+        self.panes.iter_mut().find(|p| p.id == id)
+    }
+
+    fn view(&self, pane: &Pane) -> PaneView {
+        let text_rows = pane.rect.rows.saturating_sub(1);
+        PaneView {
+            id: pane.id,
+            bufno: pane.bufno,
+            current: pane.id == self.current,
+            text: Rect {
+                rows: text_rows,
+                ..pane.rect
+            },
+            mode_area: Rect {
+                row: pane.rect.row + text_rows,
+                rows: min(1, pane.rect.rows),
+                ..pane.rect
+            },
+            mode_line: pane.mode_line.clone(),
+        }
+    }
+
+    pub fn get_current(&self) -> PaneId {
+        self.current
+    }
+
+    pub fn pane_count(&self) -> MintCount {
+        self.panes.len() as MintCount
+    }
+
+    // Place every pane within "area", in top to bottom, left to right order.
+    pub fn arrange(&mut self, area: Rect) -> Vec<PaneView> {
+        let mut placed = Vec::new();
+        self.root.arrange(area, &mut placed);
+        placed
+            .into_iter()
+            .filter_map(|(id, rect)| {
+                let pane = self.pane_mut(id)?;
+                pane.rect = rect;
+                self.pane(id).map(|pane| self.view(pane))
+            })
+            .collect()
+    }
+
+    // Split the current pane in two, with the new pane showing the same
+    // buffer.  Returns None if the pane is too small to split.
+    pub fn split(&mut self, dir: Direction) -> Option<PaneId> {
+        let pane = self.pane(self.current)?;
+        let extent = dir.extent(&pane.rect);
+        if extent < 2 * dir.min_extent() {
+            return None;
+        }
+        let bufno = pane.bufno;
+        let id = self.next_id;
+        let sizes = (extent - extent / 2, extent / 2);
+        if !self.root.split(self.current, id, dir, sizes) {
+            return None;
+        }
+        self.next_id += 1;
+        self.panes.push(Pane::new(id, bufno));
+        Some(id)
+    }
+
+    // Remove pane "id".  If it was current, a neighbouring pane becomes
+    // current.  The last pane can't be removed.
+    pub fn delete(&mut self, id: PaneId) -> bool {
+        if self.panes.len() <= 1 {
+            return false;
+        }
+        match self.root.remove(id) {
+            Some(heir) => {
+                self.panes.retain(|p| p.id != id);
+                if self.current == id {
+                    self.current = heir;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn select(&mut self, id: PaneId) -> bool {
+        if self.pane(id).is_some() {
+            self.current = id;
+            true
+        } else {
+            false
+        }
+    }
+
+    // The pane after the current one, wrapping around to the first.
+    pub fn next_pane(&self) -> PaneId {
+        let mut ids = Vec::new();
+        self.root.collect_panes(&mut ids);
+        let i = ids.iter().position(|&id| id == self.current).unwrap_or(0);
+        ids[(i + 1) % ids.len()]
+    }
+
+    // Grow the current pane by "delta" lines or columns, shrinking a
+    // neighbour.  Takes effect when the panes are next arranged.
+    pub fn resize(&mut self, dir: Direction, delta: i32) -> bool {
+        self.root.resize(self.current, dir, delta) == Some(true)
+    }
+
+    // Share space equally between the panes in each split.
+    pub fn balance(&mut self) {
+        self.root.balance();
+    }
+
+    pub fn get_buffer(&self, id: PaneId) -> Option<MintCount> {
+        self.pane(id).map(|p| p.bufno)
+    }
+
+    pub fn set_buffer(&mut self, id: PaneId, bufno: MintCount) {
+        if let Some(pane) = self.pane_mut(id) {
+            pane.bufno = bufno;
+        }
+    }
+
+    // Remember the current pane's mode line, so that it can be redrawn
+    // when the pane is no longer current.
+    pub fn set_mode_line(&mut self, line: ModeLine) {
+        if let Some(pane) = self.pane_mut(self.current) {
+            pane.mode_line = line;
+        }
+    }
+}
+
+pub fn reset_layout(interp: &Mint) {
+    *interp.context().layout.borrow_mut() = Layout::new();
+}
+
+pub fn with_layout<F, R>(interp: &Mint, f: F) -> R
+where
+    F: FnOnce(&mut Layout) -> R,
+{
+    f(&mut interp.context().layout.borrow_mut())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Rect = Rect {
+        row: 0,
+        col: 0,
+        rows: 24,
+        cols: 80,
+    };
+
+    fn rects(layout: &mut Layout, area: Rect) -> Vec<(PaneId, Rect)> {
+        layout
+            .arrange(area)
+            .into_iter()
+            .map(|v| {
+                (
+                    v.id,
+                    Rect {
+                        rows: v.text.rows + 1,
+                        ..v.text
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn rect(row: MintCount, col: MintCount, rows: MintCount, cols: MintCount) -> Rect {
+        Rect {
+            row,
+            col,
+            rows,
+            cols,
+        }
+    }
+
+    #[test]
+    fn layout_single_pane() {
+        let mut layout = Layout::new();
+        let views = layout.arrange(SCREEN);
+        assert_eq!(1, views.len());
+        assert!(views[0].current);
+        assert_eq!(rect(0, 0, 23, 80), views[0].text);
+        assert_eq!(rect(23, 0, 1, 80), views[0].mode_area);
+    }
+
+    #[test]
+    fn layout_split_below_and_right() {
+        let mut layout = Layout::new();
+        layout.arrange(SCREEN);
+        assert_eq!(Some(2), layout.split(Direction::Below));
+        layout.arrange(SCREEN);
+        assert_eq!(Some(3), layout.split(Direction::Right));
+        assert_eq!(Some(4), layout.split(Direction::Below));
+        assert_eq!(
+            vec![
+                (1, rect(0, 0, 6, 40)),
+                (4, rect(6, 0, 6, 40)),
+                (3, rect(0, 40, 12, 40)),
+                (2, rect(12, 0, 12, 80)),
+            ],
+            rects(&mut layout, SCREEN)
+        );
+        assert_eq!(4, layout.next_pane());
+        assert!(layout.select(2));
+        assert_eq!(1, layout.next_pane());
+    }
+
+    #[test]
+    fn layout_split_too_small() {
+        let mut layout = Layout::new();
+        layout.arrange(rect(0, 0, 3, 80));
+        assert_eq!(None, layout.split(Direction::Below));
+        assert_eq!(Some(2), layout.split(Direction::Right));
+    }
+
+    #[test]
+    fn layout_delete_collapses_split() {
+        let mut layout = Layout::new();
+        layout.arrange(SCREEN);
+        layout.split(Direction::Right);
+        layout.arrange(SCREEN);
+        layout.split(Direction::Below);
+        assert!(layout.delete(1));
+        assert_eq!(3, layout.get_current());
+        assert_eq!(
+            vec![(3, rect(0, 0, 24, 40)), (2, rect(0, 40, 24, 40))],
+            rects(&mut layout, SCREEN)
+        );
+        assert!(layout.delete(2));
+        assert!(!layout.delete(3));
+        assert_eq!(vec![(3, SCREEN)], rects(&mut layout, SCREEN));
+    }
+
+    #[test]
+    fn layout_resize_and_balance() {
+        let mut layout = Layout::new();
+        layout.arrange(SCREEN);
+        layout.split(Direction::Below);
+        assert!(layout.resize(Direction::Below, 4));
+        assert!(!layout.resize(Direction::Right, 4));
+        assert_eq!(
+            vec![(1, rect(0, 0, 16, 80)), (2, rect(16, 0, 8, 80))],
+            rects(&mut layout, SCREEN)
+        );
+        assert!(layout.resize(Direction::Below, 100));
+        assert_eq!(
+            vec![(1, rect(0, 0, 22, 80)), (2, rect(22, 0, 2, 80))],
+            rects(&mut layout, SCREEN)
+        );
+        layout.balance();
+        assert_eq!(
+            vec![(1, rect(0, 0, 12, 80)), (2, rect(12, 0, 12, 80))],
+            rects(&mut layout, SCREEN)
+        );
+    }
+
+    #[test]
+    fn layout_keeps_proportions() {
+        let mut layout = Layout::new();
+        layout.arrange(SCREEN);
+        layout.split(Direction::Right);
+        layout.resize(Direction::Right, 20);
+        assert_eq!(
+            vec![(1, rect(0, 0, 12, 60)), (2, rect(0, 60, 12, 20))],
+            rects(&mut layout, rect(0, 0, 12, 80))
+        );
+        assert_eq!(
+            vec![(1, rect(0, 0, 12, 30)), (2, rect(0, 30, 12, 10))],
+            rects(&mut layout, rect(0, 0, 12, 40))
+        );
+    }
+}
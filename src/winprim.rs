@@ -16,29 +16,279 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
-use crate::emacs_buffers::with_current_buffer;
-use crate::emacs_window;
+use crate::emacs_buffers::{lock_buffer, with_buffers, with_current_buffer};
+use crate::emacs_window::{self, InputEvent, ModeLine, MouseAction};
+use crate::keyboard_macro;
 use crate::mint::{Mint, MintPrim, MintVar};
 use crate::mint_arg::{ArgType, MintArgList};
 use crate::mint_string;
-use crate::mint_types::MintString;
+use crate::mint_types::{MintCount, MintString};
+use crate::signals;
+use crate::subprocess;
+use crate::wakeup;
+use crate::window_layout::{Direction, PaneId, PaneView, Rect, with_layout};
+use crate::worker;
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+// How often to look for output from processes, and for signals, while
+// waiting for a key, where we can't sleep until they come.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Lay the panes out on the screen above the message line.
+fn arrange_panes(interp: &Mint) -> Vec<PaneView> {
+    let area = emacs_window::with_window(interp, |w| Rect {
+        row: 0,
+        col: 0,
+        rows: w.get_edit_lines(),
+        cols: w.get_columns(),
+    });
+    with_layout(interp, |layout| layout.arrange(area))
+}
+
+fn current_pane(interp: &Mint) -> Option<PaneView> {
+    arrange_panes(interp).into_iter().find(|view| view.current)
+}
+
+// Keep "line" as the current pane's mode line, and draw it.
+fn set_mode_line(interp: &Mint, line: ModeLine) {
+    if let Some(pane) = current_pane(interp) {
+        emacs_window::with_window(interp, |w| w.mode_line(&pane.mode_area, &line));
+        with_layout(interp, |layout| layout.set_mode_line(line));
+    }
+}
+
+// Make pane "id" current, remembering the buffer shown in the pane being
+// left, and selecting the buffer shown in the new one.
+fn select_pane(interp: &Mint, id: PaneId) -> bool {
+    let bufno = with_current_buffer(interp, |buf| buf.get_buf_number());
+    let selected = with_layout(interp, |layout| {
+        let old = layout.get_current();
+        layout.set_buffer(old, bufno);
+        layout.select(id)
+    });
+    if selected && let Some(bufno) = with_layout(interp, |layout| layout.get_buffer(id)) {
+        with_buffers(interp, |buffers| buffers.select_buffer(bufno));
+    }
+    selected
+}
+
+// The keys typed to enter "text".  Line ends are Return, whichever kind
+// they are, and other control characters are dropped.
+fn pasted_keys(text: &[u8]) -> Vec<MintString> {
+    let text = String::from_utf8_lossy(text).replace("\r\n", "\n");
+    text.chars()
+        .filter_map(|ch| match ch {
+            '\n' | '\r' => Some(b"Return".to_vec()),
+            '\t' => Some(b"Tab".to_vec()),
+            ch if ch.is_control() => None,
+            ch => Some(emacs_window::char_key(ch)),
+        })
+        .collect()
+}
+
+// The name #(it) reports "event" by, noted if a keyboard macro is being
+// recorded.  Pasted text is typed, so the keys after the first are queued
+// to be read next, and noted now, as keys read from the queue aren't.
+fn key_name(interp: &Mint, event: InputEvent) -> MintString {
+    let name = match event {
+        InputEvent::Key(name) => name,
+        InputEvent::Resize => b"Resize".to_vec(),
+        InputEvent::Mouse {
+            action,
+            button,
+            row,
+            col,
+        } => {
+            let action = match action {
+                MouseAction::Down => "Down",
+                MouseAction::Up => "Up",
+                MouseAction::Drag => "Drag",
+            };
+            format!("Mouse-{}-{} {} {}", action, button, row, col).into_bytes()
+        }
+        InputEvent::Wheel { up: true } => b"Wheel-Up".to_vec(),
+        InputEvent::Wheel { up: false } => b"Wheel-Down".to_vec(),
+        InputEvent::Paste(text) => {
+            let mut keys = pasted_keys(&text).into_iter();
+            let first = keys.next().unwrap_or_else(|| b"Timeout".to_vec());
+            keyboard_macro::key_read(interp, &first);
+            for key in keys {
+                keyboard_macro::key_read(interp, &key);
+                emacs_window::push_key(interp, &key);
+            }
+            return first;
+        }
+        InputEvent::Focus(true) => b"Focus-In".to_vec(),
+        InputEvent::Focus(false) => b"Focus-Out".to_vec(),
+        InputEvent::Close => b"Close".to_vec(),
+        InputEvent::Timeout => b"Timeout".to_vec(),
+        InputEvent::Unknown => b"Unknown".to_vec(),
+    };
+    keyboard_macro::key_read(interp, &name);
+    name
+}
+
+// Wait up to "timeout" milliseconds for input, meanwhile inserting any
+// output from processes.  Output arriving is reported as a key, so that
+// it can be shown.  A signal to finish or stop, or a #(bg) job coming
+// back, ends the wait as if timed out.  Keys queued by #(pu) or a paste,
+// then those being replayed from a keyboard macro, come first, without
+// waiting, and keys read otherwise are recorded if a macro is being
+// recorded.
+fn read_input(interp: &Mint, timeout: MintCount) -> MintString {
+    if let Some(key) = emacs_window::pop_key(interp) {
+        return key;
+    }
+    if let Some(key) = keyboard_macro::next_key(interp) {
+        return key;
+    }
+    wait_for_input(interp, timeout)
+}
+
+// Auto-save if it is due.  Nothing else would tell the user it failed,
+// so that is said on the message line.
+fn auto_save_if_due(interp: &Mint) {
+    let (_, failed) = with_buffers(interp, |buffers| buffers.auto_save_if_due());
+    if let Some(message) = failed {
+        emacs_window::with_window(interp, |w| {
+            w.announce(&message.into_bytes(), &MintString::new())
+        });
+    }
+}
+
+// Where the display lets us, this sleeps until there is something to
+// return; see wakeup.  Waiting for input is as idle as we get, so this
+// auto-saves when that is due, however long the wait.
+fn wait_for_input(interp: &Mint, timeout: MintCount) -> MintString {
+    let deadline = Instant::now() + Duration::from_millis(timeout as u64);
+    loop {
+        auto_save_if_due(interp);
+        if subprocess::insert_output(interp) {
+            return b"Process-Output".to_vec();
+        }
+        if signals::pending() || worker::any_finished(interp) {
+            return b"Timeout".to_vec();
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        let auto_save = with_buffers(interp, |buffers| buffers.auto_save_wait());
+        let input_fd = emacs_window::with_window(interp, |w| w.input_fd());
+        if let Some(fd) = input_fd.filter(|_| wakeup::can_wait(interp)) {
+            // Keys the display has already read in come first.
+            let event = emacs_window::get_input(interp, 0);
+            if event != InputEvent::Timeout || left.is_zero() {
+                return key_name(interp, event);
+            }
+            wakeup::wait(interp, fd, auto_save.map_or(left, |wait| min(left, wait)));
+            continue;
+        }
+        if auto_save.is_none()
+            && !subprocess::any_processes(interp)
+            && !worker::busy(interp)
+            && !signals::installed()
+        {
+            return key_name(
+                interp,
+                emacs_window::get_input(interp, left.as_millis() as MintCount),
+            );
+        }
+        let event =
+            emacs_window::get_input(interp, min(left, POLL_INTERVAL).as_millis() as MintCount);
+        if event != InputEvent::Timeout || left <= POLL_INTERVAL {
+            return key_name(interp, event);
+        }
+    }
+}
+
+// Calls to Fexit-hook, if it is defined, then to halt as killed by
+// "signal".
+fn exit_calls(signal: i32) -> MintString {
+    format!("#(n?,Fexit-hook,(#(Fexit-hook)))#(hl,{})", 128 + signal).into_bytes()
+}
+
+// The display going away is taken as a hangup, SIGHUP.
+const HANGUP: i32 = 1;
 
 // #(it,X)
 // -------
 // Input timed.  Reads a character from the keyboard, waiting for "X"
 // hundredths of a second, or 0 if "X" is null.
-// Note: Key names are defined elsewhere.
+// Note: Key names are defined elsewhere.  A change in the size of the
+// terminal is reported as the key "Resize"; the windows are laid out
+// again to fit, and the next redisplay repaints the whole screen.  Mouse
+// buttons are reported as "Mouse-Down-N ROW COL" and "Mouse-Up-N ROW COL",
+// with "N" being 1 for the left button, 2 for the middle and 3 for the
+// right, dragging with a button held as "Mouse-Drag-N ROW COL", and the
+// wheel as "Wheel-Up" and "Wheel-Down".  Text pasted in one go is read as
+// the keys that would type it.  Displays that report the focus report it
+// as "Focus-In" and "Focus-Out", and a window being closed, or the
+// terminal going away, is reported as "Close"; read a second time, it is
+// taken as SIGHUP, below.  Keys pressed with Alt held are prefixed "M-",
+// as in "M-x" or "M-Left Arrow"; an Escape followed very quickly by
+// another key is taken to be the same thing.
+// Cursor and function keys held with Ctrl or Shift are prefixed "C-" or
+// "S-", as in "C-Left Arrow" or "S-F3", and keypad keys that can be told
+// from the main keys are prefixed "KP-", as in "KP-Enter".
+//
+// While a buffer has a process, its output is inserted as it arrives,
+// and reported as "Process-Output"; see #(pr).
+//
+// A timer that comes due while waiting ends the wait with "Timeout", so
+// that its form is called once the active string is done; see #(ti).  So
+// does a job given to #(bg) finishing.
+//
+// When the editor is asked to finish by SIGTERM or SIGHUP, the wait ends
+// with "Timeout", and the form "Fexit-hook" is called, if it is defined,
+// to save what needs saving before the editor halts.  SIGTSTP suspends
+// the editor as #(sz) does, and the wait ends with "Timeout" once it is
+// continued.
 //
 // Returns: The name of the key pressed, or "Timeout" if no key pressed.
 struct ItPrim;
 impl MintPrim for ItPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
-        let timeout = args[1].get_int_value(10) * 10; // Hundredths to millis
-        let key = emacs_window::with_window(|w| w.get_input(timeout as u32));
+        let mut timeout = args[1].get_int_value(10) * 10; // Hundredths to millis
+        // Stop waiting when a timer comes due, so that its form is called
+        // on time.  One already overdue is left for the wait to finish, or
+        // loops waiting for a key would never wait at all.
+        let now = Instant::now();
+        if let Some(due) = interp.next_timer().filter(|&due| due > now) {
+            let left = i32::try_from((due - now).as_millis()).unwrap_or(i32::MAX);
+            timeout = min(timeout, left.saturating_add(1));
+        }
+        let key = read_input(interp, timeout as MintCount);
+        if signals::take_suspension() {
+            emacs_window::with_window(interp, |w| w.suspend());
+        }
+        if let Some(signal) = signals::take_termination() {
+            interp.push_active(&exit_calls(signal));
+        } else if key == b"Close" && interp.context().closed.replace(true) {
+            interp.push_active(&exit_calls(HANGUP));
+        }
         interp.return_string(is_active, &key);
     }
 }
 
+// #(sz)
+// -----
+// Suspend.  Put the terminal back as it was, and stop, so that the shell
+// the editor was started from can be used.  Once the editor is continued,
+// the terminal is taken again and the whole screen repainted by the next
+// redisplay.
+//
+// Returns: null if the editor was suspended, or "cannot suspend" if the
+// display has no terminal to give back.
+struct SzPrim;
+impl MintPrim for SzPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, _args: &MintArgList) {
+        if emacs_window::with_window(interp, |w| w.suspend()) {
+            interp.return_null(is_active);
+        } else {
+            interp.return_string(is_active, &b"cannot suspend".to_vec());
+        }
+    }
+}
+
 // #(ow,X)
 // -------
 // Overwrite screen.  Write literal string "X" on screen at the current
@@ -51,7 +301,7 @@ impl MintPrim for OwPrim {
         // Skip first arg (function name) and last arg (END marker)
         for arg in args.iter().skip(1) {
             if arg.arg_type() != ArgType::End {
-                emacs_window::with_window(|w| w.overwrite(arg.value()));
+                emacs_window::with_window(interp, |w| w.overwrite(arg.value()));
             }
         }
         interp.return_null(is_active);
@@ -61,9 +311,9 @@ impl MintPrim for OwPrim {
 // #(an,X,Y,Z)
 // -----------
 // Announce.  Write on the console after the current window.  If "Y" is not
-// null, "X" is displayed after the top window, otherwise "X" and "Z"
-// are displayed at the bottom of the screen, with the cursor placed after
-// "X".
+// null, the current window's mode line is set to "X" and "Z", as for
+// #(ml,X,,Z), otherwise "X" and "Z" are displayed at the bottom of the
+// screen, with the cursor placed after "X".
 //
 // Returns: null
 struct AnPrim;
@@ -73,14 +323,43 @@ impl MintPrim for AnPrim {
         let flag = args[2].value();
         let right = args[3].value();
 
-        emacs_window::with_window(|w| {
-            if flag.is_empty() {
-                w.announce(left, right);
-            } else {
-                w.announce_win(left, right);
-            }
-        });
+        if flag.is_empty() {
+            emacs_window::with_window(interp, |w| w.announce(left, right));
+        } else {
+            set_mode_line(
+                interp,
+                ModeLine {
+                    left: left.clone(),
+                    centre: MintString::new(),
+                    right: right.clone(),
+                },
+            );
+        }
+
+        interp.return_null(is_active);
+    }
+}
 
+// #(ml,X,Y,Z)
+// -----------
+// Mode line.  Sets the mode line of the current window to "X" on the left,
+// "Y" centred and "Z" on the right, and displays it in the mode line
+// colours.  Fields that don't fit are truncated, with "X" kept in
+// preference to "Z", and "Z" in preference to "Y".  The mode line is kept
+// and redrawn with the window.
+//
+// Returns: null
+struct MlPrim;
+impl MintPrim for MlPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        set_mode_line(
+            interp,
+            ModeLine {
+                left: args[1].value().clone(),
+                centre: args[2].value().clone(),
+                right: args[3].value().clone(),
+            },
+        );
         interp.return_null(is_active);
     }
 }
@@ -97,7 +376,7 @@ impl MintPrim for XyPrim {
         let x = args[1].get_int_value(10);
         let y = args[2].get_int_value(10);
 
-        emacs_window::with_window(|w| w.gotoxy(x, y));
+        emacs_window::with_window(interp, |w| w.gotoxy(x, y));
         interp.return_null(is_active);
     }
 }
@@ -112,10 +391,13 @@ impl MintPrim for XyPrim {
 struct BlPrim;
 impl MintPrim for BlPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
-        let freq = args[1].get_int_value(10);
+        let freq = match args[1].get_int_value(10) {
+            0 => emacs_window::bell_pitch(interp),
+            freq => freq,
+        };
         let millis = args[2].get_int_value(10) * 56; // 18ths of second to millis
 
-        emacs_window::with_window(|w| {
+        emacs_window::with_window(interp, |w| {
             if freq < 0 {
                 w.visual_bell(millis as u32);
             } else {
@@ -129,110 +411,553 @@ impl MintPrim for BlPrim {
 
 // #(rd,X)
 // -------
-// Redisplay the screen.  If "X" is non-null, the screen is completely
-// repainted.
+// Redisplay the screen.  Every window is redrawn with its buffer and its
+// last mode line.  If "X" is non-null, the screen is completely repainted.
+// While a keyboard macro is being replayed, nothing is redrawn.
 //
 // Returns: null
 struct RdPrim;
 impl MintPrim for RdPrim {
     fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
-        let force = !args[1].is_empty();
+        if keyboard_macro::replaying(interp) {
+            interp.return_null(is_active);
+            return;
+        }
+        let mut force = !args[1].is_empty();
+
+        // The current pane goes last, so the cursor is left at its point.
+        let (current, others): (Vec<_>, Vec<_>) = arrange_panes(interp)
+            .into_iter()
+            .partition(|view| view.current);
+        emacs_window::with_window(interp, |w| w.begin_update());
+        for view in others.iter().chain(&current) {
+            let buf_rc = with_buffers(interp, |buffers| {
+                let cur_buf = buffers.get_cur_buffer();
+                if view.current {
+                    cur_buf
+                } else {
+                    buffers.get_buffer(view.bufno).unwrap_or(cur_buf)
+                }
+            });
+            let mut buf = lock_buffer(&buf_rc);
+            emacs_window::with_window(interp, |w| {
+                w.redisplay(&mut buf, &view.text, force);
+                w.mode_line(&view.mode_area, &view.mode_line);
+            });
+            force = false;
+        }
+        emacs_window::with_window(interp, |w| w.end_update());
+
+        interp.return_null(is_active);
+    }
+}
+
+// #(sw,X)
+// -------
+// Split window.  The current window is split in two, both showing the
+// current buffer.  If "X" is null, the new window is below the current
+// one, otherwise it is to the right.  The current window stays current.
+//
+// Returns: The number of the new window, or null if there is no room.
+struct SwPrim;
+impl MintPrim for SwPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let dir = if args[1].is_empty() {
+            Direction::Below
+        } else {
+            Direction::Right
+        };
+        arrange_panes(interp);
+        let bufno = with_current_buffer(interp, |buf| buf.get_buf_number());
+        let new_pane = with_layout(interp, |layout| {
+            let current = layout.get_current();
+            layout.set_buffer(current, bufno);
+            layout.split(dir)
+        });
+        match new_pane {
+            Some(id) => interp.return_integer(is_active, id as i32, 10),
+            None => interp.return_null(is_active),
+        }
+    }
+}
+
+// #(dw,X)
+// -------
+// Delete window.  Window number "X", or the current window if "X" is null,
+// is removed and its space given to a neighbouring window.  If it was the
+// current window, the neighbour becomes current and its buffer is
+// selected.  The last window can't be deleted.
+//
+// Returns: null
+struct DwPrim;
+impl MintPrim for DwPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let bufno = with_current_buffer(interp, |buf| buf.get_buf_number());
+        let new_current = with_layout(interp, |layout| {
+            let current = layout.get_current();
+            layout.set_buffer(current, bufno);
+            let id = if args[1].is_empty() {
+                current
+            } else {
+                args[1].get_int_value(10) as PaneId
+            };
+            if layout.delete(id) && layout.get_current() != current {
+                layout.get_buffer(layout.get_current())
+            } else {
+                None
+            }
+        });
+        if let Some(bufno) = new_current {
+            with_buffers(interp, |buffers| buffers.select_buffer(bufno));
+        }
+        interp.return_null(is_active);
+    }
+}
+
+// #(gw,X)
+// -------
+// Go to window.  Window number "X", or the next window if "X" is null,
+// becomes the current window, and the buffer it shows is selected.
+// Windows are ordered top to bottom, then left to right.
+//
+// Returns: The number of the current window.
+struct GwPrim;
+impl MintPrim for GwPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let id = if args[1].is_empty() {
+            with_layout(interp, |layout| layout.next_pane())
+        } else {
+            args[1].get_int_value(10) as PaneId
+        };
+        select_pane(interp, id);
+        let current = with_layout(interp, |layout| layout.get_current());
+        interp.return_integer(is_active, current as i32, 10);
+    }
+}
+
+// #(rw,X,Y)
+// ---------
+// Resize window.  The current window grows by "X" lines, or shrinks if "X"
+// is negative, taking the space from or giving it to a neighbouring
+// window.  If "Y" is non-null, the window is resized by "X" columns
+// instead.  Windows never shrink below one line of text or four columns.
+//
+// Returns: null
+struct RwPrim;
+impl MintPrim for RwPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let dir = if args[2].is_empty() {
+            Direction::Below
+        } else {
+            Direction::Right
+        };
+        let delta = args[1].get_int_value(10);
+        arrange_panes(interp);
+        with_layout(interp, |layout| layout.resize(dir, delta));
+        interp.return_null(is_active);
+    }
+}
+
+// #(bw)
+// -----
+// Balance windows.  Windows side by side or one above the other are given
+// equal shares of the space they occupy.
+//
+// Returns: null
+struct BwPrim;
+impl MintPrim for BwPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, _args: &MintArgList) {
+        with_layout(interp, |layout| layout.balance());
+        interp.return_null(is_active);
+    }
+}
+
+// #(ps,X,Y)
+// ---------
+// Point from screen.  Point is moved to the character shown at screen row
+// "X", column "Y", and the window showing it becomes current.  Positions
+// past the end of a line move point to the end of the line.
+//
+// Returns: The number of the window, or null if the position is not in
+// the text of any window.
+struct PsPrim;
+impl MintPrim for PsPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let row = args[1].get_int_value(10).max(0) as MintCount;
+        let col = args[2].get_int_value(10).max(0) as MintCount;
+
+        let pane = arrange_panes(interp).into_iter().find(|view| {
+            let text = &view.text;
+            (text.row..text.row + text.rows).contains(&row)
+                && (text.col..text.col + text.cols).contains(&col)
+        });
+        match pane {
+            Some(view) => {
+                select_pane(interp, view.id);
+                with_current_buffer(interp, |buf| {
+                    buf.set_point_to_screen(row - view.text.row, col - view.text.col)
+                });
+                interp.return_integer(is_active, view.id as i32, 10);
+            }
+            None => interp.return_null(is_active),
+        }
+    }
+}
+
+// #(kd,X,Y)
+// ---------
+// Key decode.  The key read as "X" is reported as "Y" from now on.  "X" is
+// the name the key is reported as now, or for the curses display, the
+// decimal number curses reads for the key.
+//
+// Returns: null, or "X" if the display doesn't know the key.
+struct KdPrim;
+impl MintPrim for KdPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let code = args[1].value();
+        if emacs_window::with_window(interp, |w| w.decode_key(code, args[2].value())) {
+            interp.return_null(is_active);
+        } else {
+            interp.return_string(is_active, code);
+        }
+    }
+}
+
+// #(kr)
+// -----
+// Keyboard macro record.  Start recording the keys #(it) reads as a
+// keyboard macro.  A command's keys are added once it is done, when the
+// default string is next used, so the keys of the commands that start
+// and end recording are left out.
+//
+// Returns: null, or "already recording" if a macro is being recorded.
+struct KrPrim;
+impl MintPrim for KrPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, _args: &MintArgList) {
+        if keyboard_macro::start(interp) {
+            interp.return_null(is_active);
+        } else {
+            interp.return_string(is_active, &b"already recording".to_vec());
+        }
+    }
+}
+
+// #(ke)
+// -----
+// Keyboard macro end.  Stop recording, and keep the keys recorded as the
+// keyboard macro.
+//
+// Returns: null, or "not recording" if no macro is being recorded.
+struct KePrim;
+impl MintPrim for KePrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, _args: &MintArgList) {
+        if keyboard_macro::stop(interp) {
+            interp.return_null(is_active);
+        } else {
+            interp.return_string(is_active, &b"not recording".to_vec());
+        }
+    }
+}
+
+// #(kx,X)
+// -------
+// Keyboard macro execute.  Replay the keyboard macro "X" times, or once if
+// "X" is null.  #(it) reads the macro's keys ahead of anything typed,
+// without waiting, and the screen isn't redrawn until they have all been
+// read.  If "X" is 0, a replay still going is stopped.
+//
+// Returns: null, or error text if a macro is being recorded or there is
+// no macro.
+struct KxPrim;
+impl MintPrim for KxPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let times = if args[1].is_empty() {
+            1
+        } else {
+            args[1].get_int_value(10).max(0) as usize
+        };
+        match keyboard_macro::replay(interp, times) {
+            Ok(()) => interp.return_null(is_active),
+            Err(e) => interp.return_string(is_active, &e.as_bytes().to_vec()),
+        }
+    }
+}
+
+// #(kl,X)
+// -------
+// Keyboard macro list.  The keys of the keyboard macro, named as #(it)
+// returns them.
+//
+// Returns: The keys, separated by literal string "X".
+struct KlPrim;
+impl MintPrim for KlPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let keys = keyboard_macro::keys(interp);
+        interp.return_string(is_active, &keys.join(args[1].value().as_slice()));
+    }
+}
+
+// #(ks,X,Y)
+// ---------
+// Keyboard macro set.  The keyboard macro becomes the keys in "Y",
+// separated by literal string "X", as #(kl) returns them.  A null "Y"
+// leaves no macro.
+//
+// Returns: null
+struct KsPrim;
+impl MintPrim for KsPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let sep = args[1].value();
+        let list = args[2].value();
+        let keys = if sep.is_empty() {
+            vec![list.clone()]
+        } else {
+            let mut keys = Vec::new();
+            let mut start = 0;
+            for at in memchr::memmem::find_iter(list, sep) {
+                keys.push(list[start..at].to_vec());
+                start = at + sep.len();
+            }
+            keys.push(list[start..].to_vec());
+            keys
+        };
+        let keys = keys.into_iter().filter(|key| !key.is_empty()).collect();
+        keyboard_macro::set_keys(interp, keys);
+        interp.return_null(is_active);
+    }
+}
+
+// #(pu,X,...)
+// -----------
+// Push keys.  The keys "X", and any after it, named as #(it) returns them,
+// are queued to be read by #(it) in order, ahead of anything typed and of
+// a keyboard macro being replayed.  Commands can use this to type on the
+// user's behalf.  Keys pushed are never recorded in a keyboard macro, as
+// replaying the command that pushed them pushes them again.
+//
+// Returns: null
+struct PuPrim;
+impl MintPrim for PuPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        for arg in args.iter().skip(1).filter(|arg| !arg.is_empty()) {
+            emacs_window::push_key(interp, arg.value());
+        }
+        interp.return_null(is_active);
+    }
+}
 
-        with_current_buffer(|buf| {
-            emacs_window::with_window(|w| w.redisplay(buf, force));
+// #(da,X,Y)
+// ---------
+// Display attribute.  Sets display attribute "X" of the current buffer to
+// "Y", to be used rather than the window's when the buffer is shown.
+// "X" is one of "fc", "bc", "cc" and "wc", for the foreground,
+// background, control character and whitespace colours, or "ws" for
+// whether whitespace is shown.  A null "Y" goes back to the window's.
+// With no "Y", the attribute is left as it is.
+//
+// Returns: What the attribute was, or null if the buffer had none of its
+// own.
+struct DaPrim;
+impl MintPrim for DaPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let value = args[2].value();
+        let number = || (!value.is_empty()).then(|| mint_string::get_int_value(value, 10));
+        let set = args.len() > 3;
+        let old = with_current_buffer(interp, |buf| {
+            let own = buf.display_mut();
+            let colour = match &args[1].value()[..] {
+                b"fc" => &mut own.fore,
+                b"bc" => &mut own.back,
+                b"cc" => &mut own.ctrl_fore,
+                b"wc" => &mut own.wsp_fore,
+                b"ws" => {
+                    let old = own.show_wsp.map(i32::from);
+                    if set {
+                        own.show_wsp = number().map(|n| n != 0);
+                    }
+                    return old;
+                }
+                _ => return None,
+            };
+            let old = *colour;
+            if set {
+                *colour = number();
+            }
+            old
         });
+        let mut result = Vec::new();
+        if let Some(old) = old {
+            mint_string::append_num(&mut result, old, 10);
+        }
+        interp.return_string(is_active, &result);
+    }
+}
 
+// #(cl,X)
+// -------
+// Clipboard.  "X" is put on the system clipboard using the OSC 52 escape
+// sequence, which works over SSH as long as the terminal allows it.
+// Terminals that don't understand it ignore it.
+//
+// Returns: null
+struct ClPrim;
+impl MintPrim for ClPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        emacs_window::with_window(interp, |w| w.set_clipboard(args[1].value()));
         interp.return_null(is_active);
     }
 }
 
+// #(rp,X)
+// -------
+// Render performance.  Counts of the work done by redisplay since the
+// counts were last reset.  "X" selects the count: "r" for redisplays,
+// "l" for lines drawn, "c" for character cells written, and "f" for the
+// time spent sending output to the terminal, in microseconds.  If "X" is
+// null the counts are reset.
+//
+// Returns: The count, or null if "X" is null or not one of the above.
+struct RpPrim;
+impl MintPrim for RpPrim {
+    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
+        let stats = emacs_window::with_window(interp, |w| w.get_render_stats());
+        let count = match args[1].get_first_char() {
+            Some(b'r') => Some(stats.redisplays),
+            Some(b'l') => Some(stats.lines),
+            Some(b'c') => Some(stats.cells),
+            Some(b'f') => Some(stats.flush_time.as_micros() as u64),
+            Some(_) => None,
+            None => {
+                emacs_window::with_window(interp, |w| w.reset_render_stats());
+                None
+            }
+        };
+        match count {
+            Some(n) => interp.return_string(is_active, &n.to_string().into_bytes()),
+            None => interp.return_null(is_active),
+        }
+    }
+}
+
 // Variables
 
 // bs - Bottom scroll percent
 struct BsVar;
 impl MintVar for BsVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        let val = emacs_window::with_window(|w| w.get_bot_scroll_percent());
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_bot_scroll_percent());
         let mut s = Vec::new();
         mint_string::append_num(&mut s, val as i32, 10);
         s
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let n = mint_string::get_int_value(val, 10);
-        emacs_window::with_window(|w| w.set_bot_scroll_percent(n as u32));
+        emacs_window::with_window(interp, |w| w.set_bot_scroll_percent(n as u32));
     }
 }
 
 // ts - Top scroll percent
 struct TsVar;
 impl MintVar for TsVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        let val = emacs_window::with_window(|w| w.get_top_scroll_percent());
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_top_scroll_percent());
         let mut s = Vec::new();
         mint_string::append_num(&mut s, val as i32, 10);
         s
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let n = mint_string::get_int_value(val, 10);
-        emacs_window::with_window(|w| w.set_top_scroll_percent(n as u32));
+        emacs_window::with_window(interp, |w| w.set_top_scroll_percent(n as u32));
     }
 }
 
 // bc - Background colour
 struct BcVar;
 impl MintVar for BcVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        let val = emacs_window::with_window(|w| w.get_back_colour());
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_back_colour());
         let mut s = Vec::new();
         mint_string::append_num(&mut s, val, 10);
         s
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let n = mint_string::get_int_value(val, 10);
-        emacs_window::with_window(|w| w.set_back_colour(n));
+        emacs_window::with_window(interp, |w| w.set_back_colour(n));
     }
 }
 
 // fc - Foreground colour
 struct FcVar;
 impl MintVar for FcVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        let val = emacs_window::with_window(|w| w.get_fore_colour());
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_fore_colour());
         let mut s = Vec::new();
         mint_string::append_num(&mut s, val, 10);
         s
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let n = mint_string::get_int_value(val, 10);
-        emacs_window::with_window(|w| w.set_fore_colour(n));
+        emacs_window::with_window(interp, |w| w.set_fore_colour(n));
     }
 }
 
 // cc - Control foreground colour
 struct CcVar;
 impl MintVar for CcVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        let val = emacs_window::with_window(|w| w.get_ctrl_fore_colour());
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_ctrl_fore_colour());
+        let mut s = Vec::new();
+        mint_string::append_num(&mut s, val, 10);
+        s
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        let n = mint_string::get_int_value(val, 10);
+        emacs_window::with_window(interp, |w| w.set_ctrl_fore_colour(n));
+    }
+}
+
+// mf - Mode line foreground colour
+struct MfVar;
+impl MintVar for MfVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_mode_fore_colour());
+        let mut s = Vec::new();
+        mint_string::append_num(&mut s, val, 10);
+        s
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        let n = mint_string::get_int_value(val, 10);
+        emacs_window::with_window(interp, |w| w.set_mode_fore_colour(n));
+    }
+}
+
+// mk - Mode line background colour
+struct MkVar;
+impl MintVar for MkVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_mode_back_colour());
         let mut s = Vec::new();
         mint_string::append_num(&mut s, val, 10);
         s
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let n = mint_string::get_int_value(val, 10);
-        emacs_window::with_window(|w| w.set_ctrl_fore_colour(n));
+        emacs_window::with_window(interp, |w| w.set_mode_back_colour(n));
     }
 }
 
 // rc - Read columns
 struct RcVar;
 impl MintVar for RcVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        let val = emacs_window::with_window(|w| w.get_columns());
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_columns());
         let mut s = Vec::new();
         mint_string::append_num(&mut s, val as i32, 10);
         s
@@ -243,18 +968,28 @@ impl MintVar for RcVar {
     }
 }
 
-// bl - Buffer lines
+// bl - Bottom line of the current window
 struct BlVar;
 impl MintVar for BlVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        let val = emacs_window::with_window(|w| w.get_lines());
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = current_pane(interp)
+            .map(|pane| (pane.text.row + pane.text.rows).saturating_sub(1))
+            .unwrap_or(0);
         let mut s = Vec::new();
         mint_string::append_num(&mut s, val as i32, 10);
         s
     }
 
-    fn set_val(&self, _interp: &mut Mint, _val: &MintString) {
-        // Could adjust window size
+    // Setting it moves the bottom of the panes so that the last text line
+    // of the lowest pane is the one given, with its mode line below.  A
+    // null value gives the panes the whole screen again.
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        let lines = if val.is_empty() {
+            0
+        } else {
+            mint_string::get_int_value(val, 10).max(0) as MintCount + 2
+        };
+        emacs_window::with_window(interp, |w| w.set_edit_lines(lines));
     }
 }
 
@@ -274,48 +1009,94 @@ impl MintVar for TlVar {
 // wc - Whitespace colour
 struct WcVar;
 impl MintVar for WcVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        let val = emacs_window::with_window(|w| w.get_whitespace_colour());
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_whitespace_colour());
         let mut s = Vec::new();
         mint_string::append_num(&mut s, val, 10);
         s
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let n = mint_string::get_int_value(val, 10);
-        emacs_window::with_window(|w| w.set_whitespace_colour(n));
+        emacs_window::with_window(interp, |w| w.set_whitespace_colour(n));
     }
 }
 
 // ws - Whitespace display
 struct WsVar;
 impl MintVar for WsVar {
-    fn get_val(&self, _interp: &Mint) -> MintString {
-        let val = emacs_window::with_window(|w| w.get_whitespace_display());
+    fn get_val(&self, interp: &Mint) -> MintString {
+        let val = emacs_window::with_window(interp, |w| w.get_whitespace_display());
         if val { b"1".to_vec() } else { b"0".to_vec() }
     }
 
-    fn set_val(&self, _interp: &mut Mint, val: &MintString) {
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
         let n = mint_string::get_int_value(val, 10);
-        emacs_window::with_window(|w| w.set_whitespace_display(n != 0));
+        emacs_window::with_window(interp, |w| w.set_whitespace_display(n != 0));
+    }
+}
+
+// eb - End of buffer indicator, shown on lines past the end of the buffer
+struct EbVar;
+impl MintVar for EbVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        emacs_window::with_window(interp, |w| w.get_eob_indicator())
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        emacs_window::with_window(interp, |w| w.set_eob_indicator(val));
+    }
+}
+
+// el - End of line indicator, shown after the last character of each line
+struct ElVar;
+impl MintVar for ElVar {
+    fn get_val(&self, interp: &Mint) -> MintString {
+        emacs_window::with_window(interp, |w| w.get_eol_indicator())
+    }
+
+    fn set_val(&self, interp: &mut Mint, val: &MintString) {
+        emacs_window::with_window(interp, |w| w.set_eol_indicator(val));
     }
 }
 
 pub fn register_win_prims(interp: &mut Mint) {
     // Primitives
     interp.add_prim(b"it".to_vec(), Box::new(ItPrim));
+    interp.add_prim(b"sz".to_vec(), Box::new(SzPrim));
     interp.add_prim(b"ow".to_vec(), Box::new(OwPrim));
     interp.add_prim(b"an".to_vec(), Box::new(AnPrim));
+    interp.add_prim(b"ml".to_vec(), Box::new(MlPrim));
     interp.add_prim(b"xy".to_vec(), Box::new(XyPrim));
     interp.add_prim(b"bl".to_vec(), Box::new(BlPrim));
     interp.add_prim(b"rd".to_vec(), Box::new(RdPrim));
+    interp.add_prim(b"sw".to_vec(), Box::new(SwPrim));
+    interp.add_prim(b"dw".to_vec(), Box::new(DwPrim));
+    interp.add_prim(b"gw".to_vec(), Box::new(GwPrim));
+    interp.add_prim(b"rw".to_vec(), Box::new(RwPrim));
+    interp.add_prim(b"bw".to_vec(), Box::new(BwPrim));
+    interp.add_prim(b"ps".to_vec(), Box::new(PsPrim));
+    interp.add_prim(b"kd".to_vec(), Box::new(KdPrim));
+    interp.add_prim(b"kr".to_vec(), Box::new(KrPrim));
+    interp.add_prim(b"ke".to_vec(), Box::new(KePrim));
+    interp.add_prim(b"kx".to_vec(), Box::new(KxPrim));
+    interp.add_prim(b"kl".to_vec(), Box::new(KlPrim));
+    interp.add_prim(b"ks".to_vec(), Box::new(KsPrim));
+    interp.add_prim(b"pu".to_vec(), Box::new(PuPrim));
+    interp.add_prim(b"cl".to_vec(), Box::new(ClPrim));
+    interp.add_prim(b"da".to_vec(), Box::new(DaPrim));
+    interp.add_prim(b"rp".to_vec(), Box::new(RpPrim));
 
     // Variables
     interp.add_var(b"bc".to_vec(), Box::new(BcVar));
     interp.add_var(b"bl".to_vec(), Box::new(BlVar));
     interp.add_var(b"bs".to_vec(), Box::new(BsVar));
     interp.add_var(b"cc".to_vec(), Box::new(CcVar));
+    interp.add_var(b"eb".to_vec(), Box::new(EbVar));
+    interp.add_var(b"el".to_vec(), Box::new(ElVar));
     interp.add_var(b"fc".to_vec(), Box::new(FcVar));
+    interp.add_var(b"mf".to_vec(), Box::new(MfVar));
+    interp.add_var(b"mk".to_vec(), Box::new(MkVar));
     interp.add_var(b"rc".to_vec(), Box::new(RcVar));
     interp.add_var(b"tl".to_vec(), Box::new(TlVar));
     interp.add_var(b"ts".to_vec(), Box::new(TsVar));
@@ -323,6 +1104,6 @@ pub fn register_win_prims(interp: &mut Mint) {
     interp.add_var(b"ws".to_vec(), Box::new(WsVar));
 }
 
-pub fn key_waiting() -> bool {
-    emacs_window::key_waiting()
+pub fn key_waiting(interp: &Mint) -> bool {
+    emacs_window::key_waiting(interp)
 }
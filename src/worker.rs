@@ -0,0 +1,163 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+/* Slow work done on a thread of its own while the editor carries on. */
+
+// Jobs are run one after another, in the order given, on a worker thread
+// started for the first of them.  Each result comes back over a channel
+// that is looked at between commands, as timers are, and while #(it)
+// waits for a key.  The result is then stored as a form, and a form named
+// with the job is called, so the macros see it much as they would have
+// had they waited.  See #(bg).
+
+use std::cell::RefCell;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::mint::Mint;
+use crate::mint_types::MintString;
+use crate::signals;
+use crate::wakeup::Waker;
+
+// The work itself, done on the worker thread.
+pub type Work = Box<dyn FnOnce() -> io::Result<MintString> + Send>;
+
+// What to do with the result once it is back.
+pub struct Job {
+    // Form to define as the result, or the error text.
+    pub form: MintString,
+    // Form to call once that is done.
+    pub then: MintString,
+}
+
+type Finished = (Job, io::Result<MintString>);
+
+pub struct Worker {
+    jobs: Sender<(Job, Work)>,
+    results: Receiver<Finished>,
+    finished: Vec<Finished>,
+    // Jobs given and not yet back.
+    running: usize,
+}
+
+impl Worker {
+    fn start(waker: Waker) -> Self {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (result_sender, results) = mpsc::channel();
+        thread::spawn(move || run(job_receiver, result_sender, waker));
+        Worker {
+            jobs,
+            results,
+            finished: Vec::new(),
+            running: 0,
+        }
+    }
+
+    // Move whatever has come back to "finished".
+    fn collect(&mut self) {
+        while let Ok(done) = self.results.try_recv() {
+            self.running -= 1;
+            self.finished.push(done);
+        }
+    }
+}
+
+// The worker thread, which finishes once the editor drops its Worker.
+fn run(jobs: Receiver<(Job, Work)>, results: Sender<Finished>, waker: Waker) {
+    signals::keep_from_thread();
+    for (job, work) in jobs {
+        if results.send((job, work())).is_err() {
+            return;
+        }
+        waker.wake();
+    }
+}
+
+fn with_worker<F, R>(interp: &Mint, f: F) -> R
+where
+    F: FnOnce(&RefCell<Option<Worker>>) -> R,
+{
+    f(&interp.context().worker)
+}
+
+// Give "work" to the worker thread, starting it if need be.
+pub fn submit(interp: &Mint, job: Job, work: Work) {
+    let waker = interp.context().waker.clone();
+    with_worker(interp, |w| {
+        let mut worker = w.borrow_mut();
+        let worker = worker.get_or_insert_with(|| Worker::start(waker));
+        worker.running += 1;
+        if let Err(mpsc::SendError((job, _))) = worker.jobs.send((job, work)) {
+            // Only if the thread has died, and then the job never will run.
+            worker.running -= 1;
+            worker
+                .finished
+                .push((job, Err(io::Error::other("worker has gone"))));
+        }
+    })
+}
+
+// Whether any jobs are still to come back.
+pub fn busy(interp: &Mint) -> bool {
+    with_worker(interp, |w| {
+        w.borrow().as_ref().is_some_and(|w| w.running > 0)
+    })
+}
+
+// Whether any jobs have come back, and not yet been dealt with.
+pub fn any_finished(interp: &Mint) -> bool {
+    with_worker(interp, |w| match w.borrow_mut().as_mut() {
+        Some(worker) => {
+            worker.collect();
+            !worker.finished.is_empty()
+        }
+        None => false,
+    })
+}
+
+// Define the form for each job that has come back, and return calls to
+// the forms to call after, in the order the jobs were given.  Those are
+// called with "A" if the job succeeded, and "B" if it failed.
+pub fn finished_calls(interp: &mut Mint) -> MintString {
+    let finished = with_worker(interp, |w| match w.borrow_mut().as_mut() {
+        Some(worker) => {
+            worker.collect();
+            std::mem::take(&mut worker.finished)
+        }
+        None => Vec::new(),
+    });
+    let mut calls = Vec::new();
+    for (job, result) in finished {
+        let (value, outcome) = match result {
+            Ok(value) => (value, b"A"),
+            Err(e) => (e.to_string().into_bytes(), b"B"),
+        };
+        if !job.form.is_empty() {
+            interp.set_form_value(&job.form, &value);
+        }
+        if !job.then.is_empty() {
+            calls.extend_from_slice(b"#((");
+            calls.extend_from_slice(&job.then);
+            calls.extend_from_slice(b"),");
+            calls.extend_from_slice(outcome);
+            calls.extend_from_slice(b")");
+        }
+    }
+    calls
+}
@@ -0,0 +1,58 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+use std::process::{Command, Output};
+
+fn freemacs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_freemacs"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn batch_runs_expression() {
+    let output = freemacs(&["--batch", "#(ow,hello (world))#(an,note)#(hl,3)#(ow,never)"]);
+    assert_eq!(Some(3), output.status.code());
+    assert_eq!("hello world", String::from_utf8_lossy(&output.stdout));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("note\n"));
+}
+
+#[test]
+fn batch_runs_file_on_files() {
+    let dir = std::env::temp_dir();
+    let script = dir.join(format!("freemacs-batch-{}.min", std::process::id()));
+    let file = dir.join(format!("freemacs-batch-{}.txt", std::process::id()));
+    std::fs::write(&script, "#(ow,##(env.FILES)/#(lv,cl)/)#(ow,##(rm,$))").unwrap();
+    std::fs::write(&file, "one\ntwo\n").unwrap();
+
+    let output = freemacs(&[
+        "--batch",
+        script.to_str().unwrap(),
+        "+2",
+        file.to_str().unwrap(),
+    ]);
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.ends_with(",2,/2/two"), "{}", stdout);
+
+    let output = freemacs(&["--batch", "/nonexistent/freemacs.min"]);
+    assert_eq!(Some(2), output.status.code());
+    std::fs::remove_file(&script).ok();
+    std::fs::remove_file(&file).ok();
+}
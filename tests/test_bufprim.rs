@@ -33,3 +33,387 @@ fn ba_prim() {
         TestMint::new("#(ow,#(ba)x#(ba,1)x#(ba,-1))").result()
     );
 }
+
+// A directory for a test's files, removed with them once the test is
+// done with it.
+struct TempDir(std::path::PathBuf);
+
+impl TempDir {
+    fn new(test: &str) -> Self {
+        let name = format!("freemacs-{}-{}", test, std::process::id());
+        let dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+#[test]
+fn av_prim() {
+    let tmp = TempDir::new("av_prim");
+    let file = tmp.path("av_prim.txt");
+    let save = tmp.path("#av_prim.txt#");
+    std::fs::write(&file, "hello").unwrap();
+    std::fs::remove_file(&save).ok();
+
+    // Unmodified buffers are not auto-saved
+    let script = format!("#(rf,{})#(ow,#(av))", file);
+    assert_eq!("0", TestMint::new(&script).result());
+    assert!(!std::path::Path::new(&save).exists());
+
+    let script = format!("#(rf,{})#(sp,])#(is,( world))#(ow,#(av)#(av))", file);
+    assert_eq!("10", TestMint::new(&script).result());
+    assert_eq!("hello world", std::fs::read_to_string(&save).unwrap());
+
+    // Writing the file removes the auto-save file
+    let script = format!("#(rf,{})#(is,X)#(av)#(wf,{})", file, file);
+    TestMint::new(&script).result();
+    assert!(!std::path::Path::new(&save).exists());
+}
+
+#[test]
+fn av_prim_saves_while_waiting() {
+    let tmp = TempDir::new("av_prim_saves_while_waiting");
+    let file = tmp.path("waiting.txt");
+    let save = tmp.path("#waiting.txt#");
+    std::fs::write(&file, "hello").unwrap();
+
+    // Within the one wait, rather than only as it starts.
+    let script = format!("#(rf,{})#(is,X)#(av,1)#(it,120)", file);
+    TestMint::new(&script).result();
+    assert_eq!("helloX", std::fs::read_to_string(&save).unwrap());
+
+    let missing = tmp.path("missing");
+    let script = format!("#(rf,{})#(is,X)#(av,1,{})#(it,120)", file, missing);
+    let mut test = TestMint::new_with_screen(&script, 80, 5);
+    test.result();
+    assert!(test.screen().contains("Auto-save to "), "{}", test.screen());
+}
+
+#[test]
+fn ar_prim() {
+    let tmp = TempDir::new("ar_prim");
+    let file = tmp.path("ar_prim.txt");
+    std::fs::write(tmp.path("#ar_prim.txt#"), "recovered").unwrap();
+    let script = format!("#(ar,{})#(sp,[)#(ow,#(rm,])#(lv,mb))", file);
+    assert_eq!("recovered1", TestMint::new(&script).result());
+    assert_ne!("", TestMint::new("#(ow,#(ar,/nonexistent/file))").result());
+}
+
+#[test]
+fn dk_prim() {
+    let tmp = TempDir::new("dk_prim");
+    let file = tmp.path("dk_prim.txt");
+    std::fs::write(&file, "one").unwrap();
+    let mut test = TestMint::new(&format!(
+        "#(rf,{})#(ow,#(d?,Changed,Same))#(de,{})#(ow,#(d?,Changed,Same))",
+        file, file
+    ));
+    assert_eq!("SameChanged", test.result());
+    assert_eq!("Same", TestMint::new("#(ow,#(d?,Changed,Same))").result());
+}
+
+#[test]
+fn en_var() {
+    let tmp = TempDir::new("en_var");
+    let file = tmp.path("en_var.txt");
+    let copy = tmp.path("en_var_copy.txt");
+    std::fs::write(&file, b"caf\xe9").unwrap();
+    assert_eq!("raw", TestMint::new("#(ow,#(lv,en))").result());
+    let script = format!(
+        "#(sv,en,auto)#(rf,{})#(ow,#(lv,en))#(sp,[)#(ow,#(rc,]))#(wf,{})",
+        file, copy
+    );
+    assert_eq!("latin-15", TestMint::new(&script).result());
+    assert_eq!(b"caf\xe9".to_vec(), std::fs::read(&copy).unwrap());
+    assert_eq!(
+        "raw",
+        TestMint::new("#(sv,en,ebcdic)#(ow,#(lv,en))").result()
+    );
+}
+
+#[test]
+fn en_var_resolved_on_every_read() {
+    let tmp = TempDir::new("en_var_resolved_on_every_read");
+    let file = tmp.path("en_var_every.txt");
+    let copy = tmp.path("en_var_every_copy.txt");
+    std::fs::write(&file, b"caf\xe9").unwrap();
+    // Into a buffer that already has text, so not visiting the file.
+    let script = format!(
+        "#(is,x)#(sv,en,auto)#(rf,{})#(ow,#(lv,en))#(wf,{})",
+        file, copy
+    );
+    assert_eq!("latin-1", TestMint::new(&script).result());
+    assert_eq!(b"xcaf\xe9".to_vec(), std::fs::read(&copy).unwrap());
+
+    let script = format!("#(sv,en,utf-8)#(rf,{})#(ow,#(lv,en))#(wf,{})", file, copy);
+    assert_eq!("raw", TestMint::new(&script).result());
+    assert_eq!(b"caf\xe9".to_vec(), std::fs::read(&copy).unwrap());
+}
+
+#[test]
+fn en_var_changes_column() {
+    // "\u{e9}" is two bytes, each shown as "\xNN" unless read as UTF-8.
+    let script = "#(is,a\u{e9})#(ow,##(lv,cs)/)#(sv,en,utf-8)#(ow,##(lv,cs))";
+    assert_eq!("10/3", TestMint::new(script).result());
+}
+
+#[test]
+fn rf_prim_partial() {
+    let tmp = TempDir::new("rf_prim_partial");
+    let file = tmp.path("rf_prim_partial.txt");
+    std::fs::write(&file, "0123456789").unwrap();
+    let read = |args: &str| {
+        let script = format!("#(rf,{}{})#(sp,[)#(ow,#(rm,]))", file, args);
+        TestMint::new(&script).result()
+    };
+    assert_eq!("0123456789", read(""));
+    assert_eq!("234", read(",2,3"));
+    assert_eq!("56789", read(",5"));
+    assert_eq!("789", read(",-3"));
+    assert_eq!("01", read(",,2"));
+    assert_eq!("0123456789", read(",-100"));
+    assert_eq!("", read(",20,5"));
+}
+
+// Files in /proc say they are empty, and must be read to the end anyway.
+#[cfg(target_os = "linux")]
+#[test]
+fn rf_prim_reads_files_of_unknown_size() {
+    let contents = std::fs::read_to_string("/proc/version").unwrap();
+    for buffer_type in ["gap", "mmap"] {
+        let script = format!(
+            "#(sv,bt,{})#(ba,0)#(rf,/proc/version)#(sp,[)#(ow,##(rm,]))",
+            buffer_type
+        );
+        assert_eq!(contents, TestMint::new(&script).result());
+    }
+}
+
+#[test]
+fn rf_prim_large() {
+    let tmp = TempDir::new("rf_prim_large");
+    let file = tmp.path("rf_prim_large.txt");
+    let contents = "0123456789abcdef\n".repeat(20_000);
+    std::fs::write(&file, &contents).unwrap();
+    let script = format!("#(rf,{})#(sp,[)#(ow,##(rm,]))", file);
+    assert_eq!(contents, TestMint::new(&script).result());
+}
+
+#[test]
+fn wf_prim_rewrites_mapped_file() {
+    let tmp = TempDir::new("wf_prim_rewrites_mapped_file");
+    let file = tmp.path("wf_prim_mapped.txt");
+    std::fs::write(&file, "one\ntwo\n").unwrap();
+    // Buffers 2 and 3 both map the file, and 3 writes over it.
+    let script = format!(
+        "#(sv,bt,mmap)#(ba,0)#(rf,{0})#(ba,0)#(rf,{0})#(sp,[)#(is,new )\
+         #(ow,#(wf,{0}))#(sp,[)#(ow,##(rm,]):)#(ba,2)#(sp,[)#(ow,##(rm,]))",
+        file
+    );
+    assert_eq!("new one\ntwo\n:one\ntwo\n", TestMint::new(&script).result());
+    assert_eq!("new one\ntwo\n", std::fs::read_to_string(&file).unwrap());
+}
+
+#[test]
+fn bt_var() {
+    let tmp = TempDir::new("bt_var");
+    let file = tmp.path("bt_var.txt");
+    std::fs::write(&file, "one\ntwo\n").unwrap();
+    assert_eq!("", TestMint::new("#(ow,#(lv,bt))").result());
+    let script = format!(
+        "#(sv,bt,mmap)#(sv,bt,splay)#(ow,#(lv,bt):)#(ba,0)#(rf,{})#(ow,#(lv,nl):)\
+         #(sp,[>>>>)#(is,2)#(sp,[)#(ow,##(rm,]))",
+        file
+    );
+    assert_eq!("mmap:3:one\n2two\n", TestMint::new(&script).result());
+    assert_eq!("one\ntwo\n", std::fs::read_to_string(&file).unwrap());
+    let script = format!(
+        "#(sv,bt,rope)#(ba,0)#(rf,{})#(sp,]<)#(is,!)#(sp,[)#(ow,##(rm,]))",
+        file
+    );
+    assert_eq!("one\ntwo!\n", TestMint::new(&script).result());
+    let script = format!(
+        "#(sv,bt,piece)#(ba,0)#(rf,{})#(sp,[>)#(dm,>)#(sp,[)#(ow,##(rm,]))",
+        file
+    );
+    assert_eq!("oe\ntwo\n", TestMint::new(&script).result());
+}
+
+#[test]
+fn cb_prim() {
+    let script = "#(ow,#(cb):)#(is,#(gs,big))#(sp,[>>)#(dm,])#(ow,#(cb):)#(sp,[)#(dm,])#(ow,#(cb))";
+    let big = "x".repeat(200_000);
+    assert_eq!(
+        "0:196608:0",
+        TestMint::new(&format!("#(ds,big,{}){}", big, script)).result()
+    );
+}
+
+#[test]
+fn pm_prim_deep() {
+    // Stack well past the old limit of 50 marks, then unwind.
+    let push = "#(pm,9)#(is,x)".repeat(100);
+    let pop = "#(pm,0,E)".repeat(100);
+    let script = format!("{}#(sp,0)#(ow,#(rc,[)){}#(ow,#(pm,0,E))", push, pop);
+    assert_eq!("99E", TestMint::new(&script).result());
+}
+
+#[test]
+fn pm_prim_too_many_marks() {
+    // Only 0 to 9 can be named, so asking for more is an error rather
+    // than room for billions of marks.
+    let script = "#(ow,#(pm,2000000000,E))#(ow,#(pm,10,E))#(ow,#(pm,0,E))#(ow,#(pm,0,E))";
+    assert_eq!("EE", TestMint::new(script).result());
+}
+
+#[test]
+fn cs_var_long_line() {
+    let tmp = TempDir::new("cs_var_long_line");
+    let file = tmp.path("cs_var_long_line.txt");
+    std::fs::write(&file, "ab\tc".repeat(1000)).unwrap();
+    let script = format!(
+        "#(rf,{})#(sv,cs,4001)#(ow,#(lv,cs):)#(sv,cs,9)#(ow,#(lv,cs):)#(sp,>)\
+         #(ow,#(lv,cs):)#(sv,tc,4)#(ow,#(lv,cs):)#(sp,<)#(is,xyz)#(ow,#(lv,cs):)\
+         #(sp,[)#(ow,#(lv,cs))",
+        file
+    );
+    assert_eq!("4001:9:10:6:8:1", TestMint::new(&script).result());
+}
+
+#[test]
+fn rf_wf_binary_round_trip() {
+    let tmp = TempDir::new("rf_wf_binary_round_trip");
+    let file = tmp.path("rf_wf_binary.bin");
+    let copy = tmp.path("rf_wf_binary_copy.bin");
+    let bytes: Vec<u8> = (0..=255).collect();
+    std::fs::write(&file, &bytes).unwrap();
+    // Read, duplicate the text through #(rm) and #(is), then write it back.
+    let script = format!("#(rf,{})#(sp,[)#(is,##(rm,]))#(sp,[)#(wf,{})", file, copy);
+    TestMint::new(&script).result();
+    assert_eq!(
+        [bytes.clone(), bytes].concat(),
+        std::fs::read(&copy).unwrap()
+    );
+}
+
+#[test]
+fn sn_sr_prims() {
+    for bt in ["gap", "piece"] {
+        let script = format!(
+            "#(sv,bt,{})#(ba,0)#(ow,#(sr,none:))#(is,(one two))#(sn)#(sp,[)#(dm,])\
+             #(is,three)#(ow,#(sr,none:))#(sp,[)#(ow,##(rm,]):)#(sp,])#(is,!)#(sr)\
+             #(sp,[)#(ow,##(rm,]))",
+            bt
+        );
+        assert_eq!("none:one two:one two", TestMint::new(&script).result());
+    }
+}
+
+#[test]
+fn tr_prim() {
+    let tr = |args: &str| {
+        let script = format!(
+            "#(is,(Hello, World-1))#(tr,[,{})#(sp,[)#(ow,##(rm,]))",
+            args
+        );
+        TestMint::new(&script).result()
+    };
+    assert_eq!("HELLO, WOrLd-1", tr("eloELO"));
+    assert_eq!("HELLO, WORLD-1", tr("a-z,A-Z"));
+    assert_eq!("Uryyb, Jbeyq-1", tr("a-zA-Z,n-za-mN-ZA-M"));
+    assert_eq!("Hxxxx, Wxxxx-1", tr("a-z,x"));
+    assert_eq!("Hello, World+9", tr("-1,+9"));
+}
+
+#[test]
+fn rv_prim() {
+    let tmp = TempDir::new("rv_prim");
+    let file = tmp.path("rv_prim.txt");
+    std::fs::write(&file, "one\ntwo\nthree\n").unwrap();
+    let script = format!(
+        "#(rf,{})#(pm,1)#(sp,[>>>>>)#(sm,0)#(sp,>)#(is,XX)#(rv!)#(ow,#(lv,mb):##(rm,0):##(rm,[):)\
+         #(sp,])#(is,four)#(rv!,{})#(ow,#(nc,##(rm,[))#(rv!,{}.none))",
+        file, file, file
+    );
+    assert_eq!(
+        "0:wo\n:one\ntwo\n:14Error reading file: No such file or directory os error 2",
+        TestMint::new(&script).result()
+    );
+}
+
+#[test]
+fn lc_var() {
+    assert_eq!(
+        "0:12:0",
+        TestMint::new("#(ow,#(lv,lc):)#(sv,lc,12)#(ow,#(lv,lc):)#(sv,lc,-3)#(ow,#(lv,lc))")
+            .result()
+    );
+}
+
+#[test]
+fn hx_var() {
+    assert_eq!(
+        "0:1:0",
+        TestMint::new("#(ow,#(lv,hx):)#(sv,hx,1)#(ow,#(lv,hx):)#(sv,hx,0)#(ow,#(lv,hx))").result()
+    );
+}
+
+#[test]
+fn tb_prim() {
+    let tmp = TempDir::new("tb_prim");
+    let file = tmp.path("tb_prim.txt");
+    std::fs::write(&file, "\tone\n  two\n").unwrap();
+    let script = format!(
+        "#(rf,{})#(sp,[)#(ow,#(tb,],u):)#(sv,tc,2)#(ow,#(tb,]):)#(ow,#(tb,]):)#(ow,#(nc,##(rm,])))",
+        file
+    );
+    assert_eq!("1:2:0:13", TestMint::new(&script).result());
+}
+
+#[test]
+fn wf_prim_append() {
+    let tmp = TempDir::new("wf_prim_append");
+    let file = tmp.path("wf_prim_append.txt");
+    std::fs::write(&file, "log:").unwrap();
+    let script = format!(
+        "#(is,(one two))#(sp,[>>>)#(wf,{},[,a)#(sp,])#(wf,{},-,a)#(ow,#(lv,mb))",
+        file, file
+    );
+    assert_eq!("1", TestMint::new(&script).result());
+    assert_eq!("log:one two", std::fs::read_to_string(&file).unwrap());
+
+    let script = format!("#(is,(one two))#(wf,{},[)#(ow,#(lv,mb))", file);
+    assert_eq!("0", TestMint::new(&script).result());
+    assert_eq!("one two", std::fs::read_to_string(&file).unwrap());
+}
+
+#[test]
+fn po_var_moves_point_by_offset() {
+    let script = "#(is,(one\ntwo))#(ow,##(lv,po)/##(lv,bz)/)#(sv,po,5)\
+                  #(ow,##(lv,cl)/##(lv,cs)/##(rm,]))#(sv,po,99)#(ow,/##(lv,po))";
+    assert_eq!("7/7/2/2/wo/7", TestMint::new(script).result());
+}
+
+#[test]
+fn bm_var_reports_allocation() {
+    let script = "#(is,abc)#(ow,##(lv,bz)/##(lv,bm)/)\
+                  #(sv,bt,rope)#(ba,0)#(ow,##(lv,bm)/)#(is,abc)#(ow,##(g?,##(lv,bm),2,yes,no))";
+    assert_eq!("3/65536/0/yes", TestMint::new(script).result());
+}
+
+#[test]
+fn am_prim_lists_modified_buffers() {
+    let script = "#(ow,##(am,/):)#(is,abc)#(ba,0)#(ow,##(am,/):)#(is,def)#(ow,##(am,/):)\
+                  #(sv,mb,0)#(ow,##(am,/))";
+    assert_eq!(":1:1/2:1", TestMint::new(script).result());
+}
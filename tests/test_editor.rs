@@ -0,0 +1,233 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+use std::env;
+use std::fs;
+
+use freemacs::editor::Editor;
+use freemacs::emacs_buffers;
+use freemacs::emacs_window;
+use freemacs::emacs_window_scripted::EmacsWindowScripted;
+use freemacs::recovery;
+
+// Insert each key typed, as the default string runs #(d,#(g)).
+const SCRIPT: &[u8] = b"#(ds,k)#(ds,g,(##(it,0)))#(ds,d,(#(is,KEY)))#(mp,d,SELF,KEY)";
+
+fn editor() -> Editor {
+    let window = EmacsWindowScripted::new(80, 25);
+    let mut editor = Editor::new(Box::new(window), SCRIPT, &[], &[]);
+    editor.step();
+    editor
+}
+
+#[test]
+fn feed_key_runs_default_string() {
+    let mut editor = editor();
+    editor.feed_key("a");
+    editor.feed_key("b");
+    assert_eq!(b"ab".to_vec(), editor.buffer_text());
+}
+
+#[test]
+fn editors_on_one_thread_are_independent() {
+    let mut first = editor();
+    let mut second = editor();
+    first.feed_key("a");
+    second.feed_key("b");
+    first.feed_key("c");
+    assert_eq!(b"ac".to_vec(), first.buffer_text());
+    assert_eq!(b"b".to_vec(), second.buffer_text());
+    drop(first);
+    second.feed_key("d");
+    assert_eq!(b"bd".to_vec(), second.buffer_text());
+}
+
+#[test]
+fn editors_keep_their_own_locks_and_swap_directory() {
+    let dir = env::temp_dir().join(format!("freemacs-own-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("locked.txt");
+    let script = format!("#(lk,{})#(sv,sd,{})", file.display(), dir.display());
+    let window = EmacsWindowScripted::new(80, 25);
+    let mut first = Editor::new(Box::new(window), script.as_bytes(), &[], &[]);
+    first.step();
+    let mut second = editor();
+    assert!(recovery::path(first.interp()).starts_with(&dir));
+    assert!(!recovery::path(second.interp()).starts_with(&dir));
+
+    let lock = dir.join(".#locked.txt");
+    drop(second);
+    assert!(fs::symlink_metadata(&lock).is_ok());
+    drop(first);
+    assert!(fs::symlink_metadata(&lock).is_err());
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn editor_moves_between_threads() {
+    let mut editor = editor();
+    editor.feed_key("a");
+    let mut editor = std::thread::spawn(move || {
+        editor.feed_key("b");
+        editor
+    })
+    .join()
+    .unwrap();
+    editor.feed_key("c");
+    assert_eq!(b"abc".to_vec(), editor.buffer_text());
+}
+
+#[test]
+fn hl_prim_halts_the_editor() {
+    let window = EmacsWindowScripted::new(80, 25);
+    let mut editor = Editor::new(Box::new(window), b"#(is,a)#(hl,3)#(is,b)", &[], &[]);
+    assert_eq!(3, editor.run());
+    assert_eq!(Some(3), editor.halted());
+    assert_eq!(b"a".to_vec(), editor.buffer_text());
+    editor.feed_key("c");
+    assert_eq!(b"a".to_vec(), editor.buffer_text());
+}
+
+#[test]
+fn open_file_reads_new_buffer() {
+    let path = env::temp_dir().join(format!("freemacs-editor-{}.txt", std::process::id()));
+    fs::write(&path, "hello\n").unwrap();
+
+    let mut editor = editor();
+    editor.feed_key("a");
+    let bufno = editor.open_file(path.to_str().unwrap()).unwrap();
+    assert!(bufno > 0);
+    assert_eq!(b"hello\n".to_vec(), editor.buffer_text());
+
+    assert!(editor.open_file("/nonexistent/freemacs").is_err());
+    fs::remove_file(&path).ok();
+}
+
+#[test]
+fn visit_file_goes_to_line() {
+    let path = env::temp_dir().join(format!("freemacs-visit-{}.txt", std::process::id()));
+    fs::write(&path, "one\ntwo\nthree\n").unwrap();
+
+    let mut editor = editor();
+    let first = editor.visit_file(path.to_str().unwrap()).unwrap();
+    editor.goto_line(2);
+    editor.feed_key("x");
+    assert_eq!(b"one\nxtwo\nthree\n".to_vec(), editor.buffer_text());
+
+    let second = editor.visit_file("/nonexistent/freemacs").unwrap();
+    assert_ne!(first, second);
+    assert_eq!(b"".to_vec(), editor.buffer_text());
+    assert!(editor.select_buffer(first));
+    assert_eq!(b"one\nxtwo\nthree\n".to_vec(), editor.buffer_text());
+    fs::remove_file(&path).ok();
+}
+
+#[test]
+fn embedded_library_edits_and_saves() {
+    let path = env::temp_dir().join(format!("freemacs-embedded-{}.txt", std::process::id()));
+    fs::write(&path, "one\n").unwrap();
+
+    let window = EmacsWindowScripted::new(80, 25);
+    let mut editor = Editor::new(Box::new(window), b"#(ll-embedded)#(F&setup)", &[], &[]);
+    let bufno = editor.visit_file(path.to_str().unwrap()).unwrap();
+    editor.goto_line(1);
+    let files = format!("{},{},0,", bufno, path.display());
+    editor
+        .interp()
+        .set_form_value(b"env.FILES", files.as_bytes());
+    editor.step();
+    let keys = [
+        "a",
+        "b",
+        "Left Arrow",
+        "x",
+        "C-e",
+        "Return",
+        "Back Space",
+        "C-a",
+        "C-d",
+    ];
+    for key in keys {
+        editor.feed_key(key);
+    }
+    assert_eq!(b"xbone\n".to_vec(), editor.buffer_text());
+
+    emacs_window::push_key(editor.interp(), b"C-x");
+    emacs_window::push_key(editor.interp(), b"C-s");
+    editor.step();
+    assert_eq!("xbone\n", fs::read_to_string(&path).unwrap());
+
+    emacs_window::push_key(editor.interp(), b"C-x");
+    emacs_window::push_key(editor.interp(), b"C-c");
+    editor.step();
+    assert_eq!(Some(0), editor.halted());
+    fs::remove_file(&path).ok();
+}
+
+#[test]
+fn recovery_library_keeps_changed_forms() {
+    let dir = env::temp_dir().join(format!("freemacs-recovery-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dir_name = dir.to_string_lossy().into_owned();
+    let script = format!(
+        "#(sv,sd,{0})#(ds,Fmine,mine)#(ds,Fsaved,saved)#(sl,{0}/lib.ed,Fsaved)#(ds,env.X,y)",
+        dir_name
+    );
+    let window = EmacsWindowScripted::new(80, 25);
+    let mut editor = Editor::new(Box::new(window), script.as_bytes(), &[], &[]);
+    editor.step();
+    recovery::save(editor.interp()).unwrap();
+    let saved = recovery::path(editor.interp());
+    assert!(saved.starts_with(&dir));
+    // As another session would have left it.
+    let name = saved.file_name().unwrap().to_string_lossy();
+    let left = dir.join(name.replace(&std::process::id().to_string(), "1"));
+    fs::rename(&saved, &left).unwrap();
+    assert_eq!(Some(left.clone()), recovery::latest(&dir));
+    drop(editor);
+
+    let script = format!(
+        "#(sv,sd,{})#(ds,message,##(ll-recovery,{}))",
+        dir_name,
+        left.display()
+    );
+    let window = EmacsWindowScripted::new(80, 25);
+    let mut editor = Editor::new(Box::new(window), script.as_bytes(), &[], &[]);
+    editor.step();
+    let interp = editor.interp();
+    let form = |name: &[u8]| interp.get_form(name).map(|form| form.content().clone());
+    let message = format!("Recovered 1 forms from {}", left.display());
+    assert_eq!(Some(message.into_bytes()), form(b"message"));
+    assert_eq!(Some(b"mine".to_vec()), form(b"Fmine"));
+    assert_eq!(None, form(b"Fsaved"));
+    assert_eq!(None, form(b"env.X"));
+    assert!(interp.changed_forms().contains(&&b"Fmine".to_vec()));
+    assert!(!left.exists());
+    fs::remove_dir_all(&dir).ok();
+}
+
+// A buffer locked twice on one thread would wait for itself for ever.
+#[test]
+#[should_panic(expected = "buffer already in use")]
+fn buffer_locked_twice_panics() {
+    let mut editor = editor();
+    let interp = editor.interp();
+    let buf = emacs_buffers::with_buffers(interp, |buffers| buffers.get_cur_buffer());
+    let _held = emacs_buffers::lock_buffer(&buf);
+    emacs_buffers::with_current_buffer(interp, |_| ());
+}
@@ -16,6 +16,11 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
+use std::panic::{self, AssertUnwindSafe};
+
+use freemacs::frmprim;
+use freemacs::mint::Mint;
+
 mod test_mint;
 use test_mint::TestMint;
 
@@ -222,3 +227,50 @@ fn hk_prim() {
     let input = concat!("#(ow,", "#(ds,z1,OK)", "##(hk,aa,bb,cc,dd,z1)", ")");
     assert_eq!(OK, TestMint::new(input).result());
 }
+
+#[test]
+fn ph_prim_hooks_primitives() {
+    let mut test = TestMint::new(
+        "#(ds,log,([NAME]))#(mp,log,,NAME)#(ds,show,(#(ow,#(log,NAME))))#(mp,show,,NAME)\
+         #(ds,fix,(#(is,!)#(ow,+NAME)))#(mp,fix,,NAME)\
+         #(ph,change,show,fix)#(ph,sp,,show)#(is,ab)#(sp,[)#(ow,##(rm,]))\
+         #(ph,change)#(ph,sp)#(is,c)#(sp,[)#(ow,/#(ph,zz)/)",
+    );
+    assert_eq!("[is]+is[sp]ab!/zz/", test.result());
+}
+
+#[test]
+fn ph_prim_change_class_has_snapshot_restore() {
+    let mut test = TestMint::new(
+        "#(ds,show,(#(ow,[NAME])))#(mp,show,,NAME)\
+         #(sn)#(ph,change,show)#(sr)",
+    );
+    assert_eq!("[sr]", test.result());
+}
+
+#[test]
+fn fb_prim_lists_forms_being_expanded() {
+    let mut test = TestMint::new(
+        "#(ds,inner,(#(ow,#(fb,/)!)x))#(ds,outer,(#(inner)y))#(ds,last,(#(inner)))\
+         #(outer)#(last)",
+    );
+    assert_eq!("inner/outer!inner!", test.result());
+}
+
+#[test]
+fn panics_report_the_forms_being_expanded() {
+    let mut interp =
+        Mint::with_initial_string(b"#(ds,f,(#(ds,r,x##(zz,a,b))c))#(ds,g,(#(f)d))#(g)");
+    frmprim::register_frm_prims(&mut interp);
+    interp.add_prim_fn(b"zz".to_vec(), |_, _, _| panic!("zz failed"));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| interp.scan()));
+    assert!(result.is_err());
+    assert_eq!(
+        Some(
+            "in ##(zz,a,b)\n  called from f\n  called from g\n\
+             active string: )cd\nneutral string: #(ds,r,x\n"
+                .to_string()
+        ),
+        interp.take_panic_report()
+    );
+}
@@ -0,0 +1,65 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+mod test_mint;
+use test_mint::TestMint;
+
+//
+// Primitives from libprim.rs
+//
+
+fn temp_path(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("freemacs-lib-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name).to_string_lossy().to_string()
+}
+
+#[test]
+fn ul_and_ll_prims() {
+    let lib = temp_path("ul.ed");
+    let script = format!(
+        "#(ds,Fa,one)#(ds,Fb,two)#(sl,{0},Fa,Fb)\
+         #(ds,Fa,uno)#(ds,Fc,three)#(ow,#(ul,{0},Fa,Fc))\
+         #(es,Fa,Fb,Fc)#(ow,#(ll,{0})##(Fa)/##(Fb)/##(Fc)/##(lL,{0},;))",
+        lib
+    );
+    assert_eq!(
+        "uno/two/three/Fa 3;Fb 3;Fc 5",
+        TestMint::new(&script).result()
+    );
+
+    let missing = temp_path("missing.ed");
+    let mut test = TestMint::new(&format!("#(ow,#(lL,{},;))", missing));
+    assert_eq!("", test.result());
+}
+
+#[test]
+fn ll_prim_filters_and_keeps_forms() {
+    let lib = temp_path("ll.ed");
+    let script = format!(
+        "#(ds,Fa,one)#(ds,Fb,two)#(ds,Ga,other)#(sl,{0},Fa,Fb,Ga)\
+         #(ds,Fa,mine)#(es,Fb,Ga)#(ll,{0},F,s)\
+         #(ow,##(Fa)/##(Fb)/#(n?,Ga,yes,no)/)\
+         #(ll,{0},,,old.)#(ow,##(old.Fa)/##(old.Ga)/##(Fa))",
+        lib
+    );
+    assert_eq!(
+        "mine/two/no/one/other/mine",
+        TestMint::new(&script).result()
+    );
+}
@@ -16,37 +16,18 @@
  * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
  */
 
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
-use freemacs::mint::{Mint, MintPrim};
-use freemacs::mint_arg::MintArgList;
-use freemacs::{buffer, emacs_buffers, gap_buffer};
-
-struct OwPrim {
-    output: Rc<RefCell<String>>,
-}
-
-impl OwPrim {
-    fn new(output: Rc<RefCell<String>>) -> Self {
-        OwPrim { output }
-    }
-}
-
-impl MintPrim for OwPrim {
-    fn execute(&self, interp: &mut Mint, is_active: bool, args: &MintArgList) {
-        let mut output = self.output.borrow_mut();
-        for arg in args.iter().skip(1) {
-            print!("{}", String::from_utf8(arg.value().clone()).unwrap());
-            output.extend(String::from_utf8(arg.value().clone()).unwrap().chars());
-        }
-        interp.return_null(is_active);
-    }
-}
+use freemacs::emacs_window::InputEvent;
+use freemacs::emacs_window_scripted::{EmacsWindowScripted, ScriptLog, SharedScreen};
+use freemacs::mint::Mint;
+use freemacs::{buffer, emacs_buffers, emacs_window, gap_buffer};
 
 pub struct TestMint {
     interp: Mint,
-    output: Rc<RefCell<String>>,
+    output: Arc<Mutex<String>>,
+    window_log: ScriptLog,
+    screen: SharedScreen,
 }
 
 fn gap_buffer_factory() -> Box<dyn buffer::Buffer> {
@@ -55,12 +36,22 @@ fn gap_buffer_factory() -> Box<dyn buffer::Buffer> {
 
 impl TestMint {
     pub fn new_with_env(script: &str, args: &[String], envp: &[(String, String)]) -> Self {
+        TestMint::new_with_window(script, args, envp, EmacsWindowScripted::new(80, 25))
+    }
+
+    fn new_with_window(
+        script: &str,
+        args: &[String],
+        envp: &[(String, String)],
+        window: EmacsWindowScripted,
+    ) -> Self {
         let mut interp = Mint::with_initial_string(script.as_bytes());
-        let output = Rc::new(RefCell::new(String::new()));
-        let ow_prim = OwPrim::new(output.clone());
-        interp.add_prim(b"ow".to_vec(), Box::new(ow_prim));
+        let output = Arc::new(Mutex::new(String::new()));
 
-        emacs_buffers::init_buffers(gap_buffer_factory);
+        emacs_buffers::init_buffers(&interp, gap_buffer_factory);
+        let window_log = window.log();
+        let screen = window.screen();
+        emacs_window::init_window(&interp, Box::new(window));
 
         freemacs::bufprim::register_buf_prims(&mut interp);
         freemacs::frmprim::register_frm_prims(&mut interp);
@@ -69,24 +60,80 @@ impl TestMint {
         freemacs::strprim::register_str_prims(&mut interp);
         freemacs::sysprim::register_sys_prims(&mut interp, args, envp);
         freemacs::varprim::register_var_prims(&mut interp);
-        // FIXME: Work out how to make this work without full windowing.
-        // freemacs::winprim::register_win_prims(&mut interp);
+        freemacs::winprim::register_win_prims(&mut interp);
+
+        // Replaces winprim's #(ow) so tests can see what was written.
+        let written = output.clone();
+        interp.add_prim_fn(b"ow".to_vec(), move |interp, is_active, args| {
+            let mut output = written.lock().unwrap();
+            for arg in args.iter().skip(1) {
+                print!("{}", String::from_utf8(arg.value().clone()).unwrap());
+                output.extend(String::from_utf8(arg.value().clone()).unwrap().chars());
+            }
+            interp.return_null(is_active);
+        });
 
-        TestMint { interp, output }
+        TestMint {
+            interp,
+            output,
+            window_log,
+            screen,
+        }
     }
 
     pub fn new(script: &str) -> Self {
         TestMint::new_with_env(script, &[], &[])
     }
 
+    // Run with the keys that #(it) will read, in order.
+    #[allow(dead_code)]
+    pub fn new_with_keys(script: &str, keys: &[&str]) -> Self {
+        let window = EmacsWindowScripted::new(80, 25).with_keys(keys);
+        TestMint::new_with_window(script, &[], &[], window)
+    }
+
+    // Run with the input events that #(it) will read, in order.
+    #[allow(dead_code)]
+    pub fn new_with_events(script: &str, events: Vec<InputEvent>) -> Self {
+        let window = EmacsWindowScripted::new(80, 25).with_events(events);
+        TestMint::new_with_window(script, &[], &[], window)
+    }
+
+    // Run on a screen "cols" by "lines", to compare with screen().
+    #[allow(dead_code)]
+    pub fn new_with_screen(script: &str, cols: u32, lines: u32) -> Self {
+        let window = EmacsWindowScripted::new(cols, lines);
+        TestMint::new_with_window(script, &[], &[], window)
+    }
+
+    // Run on a screen as new_with_screen, with keys as new_with_keys.
+    #[allow(dead_code)]
+    pub fn new_with_screen_and_keys(script: &str, cols: u32, lines: u32, keys: &[&str]) -> Self {
+        let window = EmacsWindowScripted::new(cols, lines).with_keys(keys);
+        TestMint::new_with_window(script, &[], &[], window)
+    }
+
+    // What is on the screen, and where the cursor is.
+    #[allow(dead_code)]
+    pub fn screen(&self) -> String {
+        self.screen.lock().unwrap().snapshot()
+    }
+
+    // Everything the window was asked to show.
+    #[allow(dead_code)]
+    pub fn window_log(&self) -> Vec<String> {
+        self.window_log.lock().unwrap().clone()
+    }
+
     pub fn result(&mut self) -> String {
         self.interp.scan();
-        self.output.borrow().clone()
+        self.output.lock().unwrap().clone()
     }
 }
 
 impl Drop for TestMint {
     fn drop(&mut self) {
-        emacs_buffers::free_buffers();
+        emacs_window::free_window(&self.interp);
+        emacs_buffers::free_buffers(&self.interp);
     }
 }
@@ -0,0 +1,381 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+mod test_mint;
+use test_mint::TestMint;
+
+//
+// Primitives from sysprim.rs
+//
+
+fn temp_path(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("freemacs-sys-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name).to_string_lossy().to_string()
+}
+
+#[test]
+fn cp_prim() {
+    let from = temp_path("cp_from.txt");
+    let to = temp_path("cp_to.txt");
+    std::fs::write(&from, "copied\n").unwrap();
+    let mut test = TestMint::new(&format!("#(ow,#(cp,{},{}))", from, to));
+    assert_eq!("", test.result());
+    assert_eq!("copied\n", std::fs::read_to_string(&to).unwrap());
+
+    let missing = temp_path("cp_missing.txt");
+    let mut test = TestMint::new(&format!("#(ow,#(cp,{},{}))", missing, to));
+    assert!(!test.result().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn md_prim() {
+    let file = temp_path("md.sh");
+    std::fs::write(&file, "#!/bin/sh\n").unwrap();
+    let script = format!("#(ow,#(md,{0},600)#(md,{0})/#(md,{0},755)#(md,{0}))", file);
+    let mut test = TestMint::new(&script);
+    assert_eq!("600/755", test.result());
+
+    let missing = temp_path("md_missing");
+    let mut test = TestMint::new(&format!("#(ow,#(md,{0})/#(md,{0},9))", missing));
+    assert_eq!("/invalid mode", test.result());
+}
+
+#[cfg(unix)]
+#[test]
+fn fs_prim() {
+    let file = temp_path("fs.txt");
+    std::fs::write(&file, "12345").unwrap();
+    let script = format!("#(md,{0},640)#(ow,#(fs,{0},|))", file);
+    let mut test = TestMint::new(&script);
+    let result = test.result();
+    let fields: Vec<&str> = result.split('|').collect();
+    assert_eq!(5, fields.len(), "{}", result);
+    assert_eq!(["file", "5"], fields[..2]);
+    assert!(fields[2].parse::<u64>().unwrap() > 1_000_000_000);
+    assert_eq!("640", fields[3]);
+    assert!(!fields[4].is_empty());
+
+    let dir = std::env::temp_dir();
+    let mut test = TestMint::new(&format!("#(ow,#(fs,{}))", dir.display()));
+    assert!(test.result().starts_with("dir "));
+    let mut test = TestMint::new(&format!("#(ow,#(fs,{}))", temp_path("fs_missing")));
+    assert_eq!("", test.result());
+}
+
+// A directory tree for #(ff) to look through.
+fn ff_tree(name: &str) -> String {
+    let root = temp_path(name);
+    for dir in ["sub/deep", ".hidden"] {
+        std::fs::create_dir_all(format!("{}/{}", root, dir)).unwrap();
+    }
+    for file in [
+        "a.txt",
+        "sub/b.txt",
+        "sub/deep/c.txt",
+        ".hidden/d.txt",
+        ".e.txt",
+    ] {
+        std::fs::write(format!("{}/{}", root, file), "").unwrap();
+    }
+    root
+}
+
+fn sorted(list: String) -> Vec<String> {
+    let mut names: Vec<String> = list.split(';').map(str::to_string).collect();
+    names.retain(|name| !name.is_empty());
+    names.sort();
+    names
+}
+
+#[test]
+fn ff_prim_options() {
+    let root = ff_tree("ff_options");
+    let find = |pattern: &str, options: &str| {
+        let script = format!("#(ow,#(ff,{}/{},;,{}))", root, pattern, options);
+        sorted(TestMint::new(&script).result())
+    };
+    assert_eq!(
+        vec![".e.txt", "a.txt", "b.txt", "c.txt", "d.txt"],
+        find("**/*.txt", "")
+    );
+    assert_eq!(vec!["a.txt", "b.txt", "c.txt"], find("**/*.txt", "h"));
+    assert_eq!(vec![".hidden", "sub"], find("*", "d"));
+    assert_eq!(vec![".e.txt", "a.txt"], find("*", "f"));
+    assert_eq!(vec![".e.txt"], find(".*", "fh"));
+}
+
+#[test]
+fn ff_prim_paths_and_order() {
+    let root = ff_tree("ff_paths");
+    let now = std::time::SystemTime::now();
+    for (file, age) in [("a.txt", 30), ("sub/b.txt", 60)] {
+        let file = std::fs::File::options()
+            .write(true)
+            .open(format!("{}/{}", root, file))
+            .unwrap();
+        file.set_modified(now - std::time::Duration::from_secs(age))
+            .unwrap();
+    }
+    let find = |pattern: &str, options: &str| {
+        let script = format!("#(ow,#(ff,{}/{},;,{}))", root, pattern, options);
+        TestMint::new(&script).result()
+    };
+    assert_eq!(
+        format!("{0}/a.txt;{0}/sub/b.txt;{0}/sub/deep/c.txt;", root),
+        find("**/*.txt", "hpn")
+    );
+    assert_eq!("c.txt;a.txt;b.txt;", find("**/*.txt", "ht"));
+    let absolute = std::fs::canonicalize(format!("{}/a.txt", root)).unwrap();
+    assert_eq!(format!("{};", absolute.display()), find("a.*", "a"));
+}
+
+#[test]
+fn ct_prim_formats() {
+    let file = temp_path("ct.txt");
+    std::fs::write(&file, "").unwrap();
+    let file_time = std::fs::File::options().write(true).open(&file).unwrap();
+    let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    file_time.set_modified(time).unwrap();
+
+    let script = format!("#(ow,#(ct,{0},,%s)/#(ct,{0},,%Q))", file);
+    assert_eq!("1000000000/", TestMint::new(&script).result());
+    let script = format!("#(ow,#(ct,{0},x,%s))", file);
+    assert_eq!("0000001000000000 0", TestMint::new(&script).result());
+    let now = TestMint::new("#(ow,#(ct,,,%Y-%m-%d))").result();
+    assert_eq!(10, now.len(), "{}", now);
+    assert_eq!(Some(4), now.find('-'));
+}
+
+#[test]
+fn system_vars() {
+    let mut test = TestMint::new("#(ow,#(lv,os)/#(lv,ar)/#(lv,cn)/#(lv,hn))");
+    let result = test.result();
+    let vars: Vec<&str> = result.split('/').collect();
+    assert_eq!(std::env::consts::OS, vars[0]);
+    assert_eq!(std::env::consts::ARCH, vars[1]);
+    assert!(!vars[2].is_empty());
+    #[cfg(unix)]
+    {
+        let uname = std::process::Command::new("uname")
+            .args(["-sr"])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&uname.stdout).trim(), vars[2]);
+        let host = std::process::Command::new("hostname").output();
+        if let Ok(host) = host {
+            assert_eq!(String::from_utf8_lossy(&host.stdout).trim(), vars[3]);
+        }
+    }
+}
+
+#[test]
+fn process_and_user_vars() {
+    let mut test = TestMint::new("#(ow,#(lv,pd)/#(lv,un)/#(lv,hd))");
+    let result = test.result();
+    let vars: Vec<&str> = result.split('/').collect();
+    assert_eq!(std::process::id().to_string(), vars[0]);
+    assert!(!vars[1].is_empty());
+    #[cfg(unix)]
+    if let Ok(home) = std::env::var("HOME") {
+        assert_eq!(
+            format!("{}/", home.trim_end_matches('/')),
+            vars[2..].join("/")
+        );
+    }
+}
+
+#[test]
+fn sd_var_sets_swap_directory() {
+    let dir = temp_path("sd");
+    std::fs::create_dir_all(&dir).unwrap();
+    let default = TestMint::new("#(ow,#(lv,sd))").result();
+    let script = format!(
+        "#(sv,sd,{0})#(ow,#(lv,sd)/)#(sv,sd,{0}/missing)#(ow,#(lv,sd)/)#(sv,sd)#(ow,#(lv,sd))",
+        dir
+    );
+    assert_eq!(
+        format!("{0}/{0}/{1}", dir, default),
+        TestMint::new(&script).result()
+    );
+    assert_eq!(0, std::fs::read_dir(&dir).unwrap().count());
+}
+
+#[test]
+fn tf_prim_makes_temp_files() {
+    let dir = temp_path("tf");
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = format!("#(sv,sd,{})#(ow,#(tf,pipe)|#(tf,pipe))", dir);
+    let result = TestMint::new(&script).result();
+    let (first, second) = result.split_once('|').unwrap();
+    assert_ne!(first, second);
+    for file in [first, second] {
+        assert!(file.starts_with(&format!("{}/pipe", dir)), "{}", file);
+        let metadata = std::fs::metadata(file).unwrap();
+        assert_eq!(0, metadata.len());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+        }
+    }
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn state_prims() {
+    let dir = temp_path("state");
+    // SAFETY: The other tests only read the environment through std, which
+    // locks it.
+    unsafe { std::env::set_var("XDG_STATE_HOME", &dir) };
+    let script = "#(ow,#(ss,recent,(a,b))##(state.recent)/##(sg,recent)/)\
+                  #(ss,recent)#(ow,#(sg,recent)/#(n?,state.recent,yes,no)/#(ss,../x,y))";
+    assert_eq!(
+        "a,b/a,b//no/invalid state name",
+        TestMint::new(script).result()
+    );
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn se_prim_sets_environment() {
+    let envp = [("FREEMACS_SE_GONE".to_string(), "old".to_string())];
+    let script = "#(se,FREEMACS_SE_TEST,hello)#(se,FREEMACS_SE_GONE)#(ev)\
+                  #(ow,##(env.FREEMACS_SE_TEST)/##(env.FREEMACS_SE_GONE)/#(se,A=B,x))";
+    let mut test = TestMint::new_with_env(script, &[], &envp);
+    assert_eq!("hello//invalid variable", test.result());
+    assert_eq!(Ok("hello".to_string()), std::env::var("FREEMACS_SE_TEST"));
+}
+
+#[test]
+fn lock_prims() {
+    let file = temp_path("locked.txt");
+    let lock = temp_path(".#locked.txt");
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("other@elsewhere.example.7", &lock).unwrap();
+    #[cfg(not(unix))]
+    std::fs::write(&lock, "other@elsewhere.example.7").unwrap();
+    let script = format!(
+        "#(ow,#(lq,{f})/#(lk,{f})/#(lk,{f},y)/#(lq,{f})/)#(uk,{f})#(ow,##(ff,{l}))",
+        f = file,
+        l = lock
+    );
+    let mut test = TestMint::new(&script);
+    let other = "other@elsewhere.example.7";
+    assert_eq!(format!("{o}/{o}///", o = other), test.result());
+}
+
+#[test]
+fn timers_call_forms_when_idle() {
+    let script = "#(ds,tick,(#(ow,/tick)))#(ti,tick,0)\
+                  #(ds,tock,(#(ow,/tock)#(==,##(n),x,(#(tk,tock)),(#(ds,n,x)))))\
+                  #(ti,tock,0,r)#(ti,gone,0)#(tk,gone)#(ow,start)";
+    let mut test = TestMint::new(script);
+    assert_eq!("start", test.result());
+    assert_eq!("start/tick/tock", test.result());
+    assert_eq!("start/tick/tock/tock", test.result());
+}
+
+// Scan until the output ends with "end", which a #(bg) job finishing
+// should bring about.
+fn result_ending(test: &mut TestMint, end: &str) -> String {
+    for _ in 0..500 {
+        let result = test.result();
+        if result.ends_with(end) {
+            return result;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    panic!("never got {:?}, only {:?}", end, test.result());
+}
+
+#[test]
+fn bg_prim_reads_and_writes_files() {
+    let from = temp_path("bg_from.txt");
+    let to = temp_path("bg_to.txt");
+    std::fs::write(&from, "in the background").unwrap();
+    let script = format!(
+        "#(ds,read,(#(ow,/read OK ##(got))#(is,##(got))#(bg,wf,{to},,wrote)))#(mp,read,,OK)\
+         #(ds,wrote,(#(ow,/wrote OK)#(bg,rf,{to}.missing,got,failed)))#(mp,wrote,,OK)\
+         #(ds,failed,(#(ow,/failed OK)))#(mp,failed,,OK)\
+         #(ow,#(bg,rf,{from},got,read)start#(bg,xx,y,z,w))",
+        from = from,
+        to = to
+    );
+    let mut test = TestMint::new(&script);
+    let result = result_ending(&mut test, "/failed B");
+    assert!(result.starts_with("startno such operation/read A in the background/wrote A/failed B"));
+    assert_eq!("in the background", std::fs::read_to_string(&to).unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn bg_prim_runs_commands_and_finds_files() {
+    let dir = temp_path("bg_find");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(format!("{}/one.bg", dir), "").unwrap();
+    let script = format!(
+        "#(ds,ran,(#(ow,/ran OK ##(out))#(bg,ff,{dir}/*.bg,found,done)))#(mp,ran,,OK)\
+         #(ds,done,(#(ow,/##(found))))\
+         #(bg,sh,echo hi,out,ran)",
+        dir = dir
+    );
+    let mut test = TestMint::new(&script);
+    let expected = format!("/ran A hi\n/{}/one.bg\n", dir);
+    assert_eq!(expected, result_ending(&mut test, "one.bg\n"));
+}
+
+// Reads keys until one times out, so that a process's output is all in
+// and, once it has finished, the line saying so.
+const WAIT: &str = "#(ds,wait,(#(==,#(it,100),Timeout,,(#(wait)))))";
+
+#[cfg(unix)]
+#[test]
+fn pr_prim_inserts_output() {
+    let script = format!("{}#(ow,#(pr,echo hi))#(wait)#(sp,[)#(ow,##(rm,]))", WAIT);
+    let mut test = TestMint::new(&script);
+    assert_eq!("hi\n\nProcess finished\n", test.result());
+}
+
+#[cfg(unix)]
+#[test]
+fn pi_and_pk_prims_talk_to_the_process() {
+    let script = format!(
+        "{}#(pr,cat)#(ow,#(pi,hello)#(pi,Return,key))#(wait)#(ow,#(pk))#(wait)\
+         #(sp,[)#(ow,##(rm,]))",
+        WAIT
+    );
+    let mut test = TestMint::new(&script);
+    assert_eq!("hello\nhello\n\nProcess killed\n", test.result());
+}
+
+#[test]
+fn process_prims_need_a_process() {
+    let mut test = TestMint::new("#(ow,#(pi,x)/#(pk,INT)/#(pk,BOGUS))");
+    assert_eq!(
+        "no process in buffer/no process in buffer/no such signal",
+        test.result()
+    );
+}
+
+#[test]
+fn pl_prim_reports_missing_plugins() {
+    let mut test = TestMint::new("#(ow,#(==,#(pl,/no/such/plugin.so),,loaded,failed))");
+    assert_eq!("failed", test.result());
+}
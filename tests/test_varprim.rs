@@ -0,0 +1,58 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+mod test_mint;
+use test_mint::TestMint;
+
+//
+// Primitives from varprim.rs
+//
+
+#[test]
+fn sv_prim_makes_new_variables() {
+    let script = "#(ow,#(lv,mine)/)#(sv,mine,one)#(ds,mine,form)\
+                  #(ow,#(lv,mine)/)#(sv,mine,two)#(ow,#(lv,mine)/##(mine))";
+    assert_eq!("/one/two/form", TestMint::new(script).result());
+}
+
+#[test]
+fn sv_prim_sets_existing_variables() {
+    let script = "#(sv,as,42)#(ow,#(lv,as)/#(lv,vn))#(sv,vn,9.9)#(ow,/#(lv,vn))";
+    assert_eq!("42/2.0a/2.0a", TestMint::new(script).result());
+}
+
+#[test]
+fn lv_prim_lists_variables() {
+    let script = "#(sv,my.b,two)#(sv,my.a,one)#(ow,#(lV,;,my.)/#(lV,;,vn))";
+    assert_eq!("my.a one;my.b two/vn 2.0a", TestMint::new(script).result());
+    let all = TestMint::new("#(ow,#(lV,;))").result();
+    assert!(all.split(';').any(|entry| entry == "as 0"));
+}
+
+#[test]
+fn build_variables() {
+    let date = TestMint::new("#(ow,#(lv,vd))").result();
+    assert_eq!(10, date.len());
+    assert_eq!(Some(4), date.find('-'));
+    let features = TestMint::new("#(ow,#(lv,vf))").result();
+    assert_eq!(
+        cfg!(feature = "curses"),
+        features.split(' ').any(|f| f == "curses")
+    );
+    assert_eq!("scripted", TestMint::new("#(ow,#(lv,wb))").result());
+}
@@ -0,0 +1,268 @@
+/*
+ * Copyright 2026 Martin Sandiford
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or (at
+ * your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to: Free Software Foundation
+ * Inc., 51 Franklin St, Fifth Floor, Boston, MA 02110-1301 USA
+ */
+
+mod test_mint;
+use freemacs::emacs_window::{InputEvent, MouseAction};
+use test_mint::TestMint;
+
+#[test]
+fn it_prim_reads_scripted_keys() {
+    let mut test = TestMint::new_with_keys("#(ow,#(it,0)/#(it,0)/#(it,0))", &["a", "C-x"]);
+    assert_eq!("a/C-x/Timeout", test.result());
+}
+
+#[test]
+fn it_prim_joins_escape_to_the_next_key() {
+    let script = "#(is,##(it,0)/##(it,0)/##(it,0))#(rd)";
+    let keys = ["Escape", "x", "M-Left Arrow", "Escape"];
+    let mut test = TestMint::new_with_screen_and_keys(script, 30, 3, &keys);
+    test.result();
+    assert_eq!("M-x/M-Left Arrow/Escape\n\n\ncursor 0 23", test.screen());
+}
+
+#[test]
+fn kd_prim_renames_keys() {
+    let script = "#(kd,Escape,Cancel)#(kd,M-x,Execute)\
+                  #(is,##(it,0)/##(it,0)/##(it,0))#(rd)";
+    let keys = ["Escape", "x", "a", "Escape"];
+    let mut test = TestMint::new_with_screen_and_keys(script, 30, 3, &keys);
+    test.result();
+    assert_eq!("Execute/a/Cancel\n\n\ncursor 0 16", test.screen());
+}
+
+#[test]
+fn it_prim_reads_extended_keys() {
+    // Keypad Enter can be told from Return, unless it is asked not to be.
+    let script = "#(is,##(it,0)/##(it,0)/##(it,0)/)#(kd,KP-Enter,Return)#(is,##(it,0))#(rd)";
+    let keys = ["C-Left Arrow", "Escape", "S-F13", "KP-Enter", "KP-Enter"];
+    let mut test = TestMint::new_with_screen_and_keys(script, 40, 3, &keys);
+    test.result();
+    assert_eq!(
+        "C-Left Arrow/M-S-F13/KP-Enter/Return\n\n\ncursor 0 36",
+        test.screen()
+    );
+}
+
+#[test]
+fn it_prim_names_input_events() {
+    let events = vec![
+        InputEvent::Resize,
+        InputEvent::Mouse {
+            action: MouseAction::Drag,
+            button: 1,
+            row: 4,
+            col: 10,
+        },
+        InputEvent::Wheel { up: true },
+        InputEvent::Focus(false),
+        InputEvent::Close,
+    ];
+    let mut test =
+        TestMint::new_with_events("#(ow,#(it,0)/#(it,0)/#(it,0)/#(it,0)/#(it,0))", events);
+    assert_eq!(
+        "Resize/Mouse-Drag-1 4 10/Wheel-Up/Focus-Out/Close",
+        test.result()
+    );
+}
+
+#[test]
+fn it_prim_halts_once_closed() {
+    let script = "#(ds,Fexit-hook,(#(ow,hook/)))#(ow,#(it,0)/)#(ow,#(it,0)/)#(ow,not reached)";
+    let mut test = TestMint::new_with_events(script, vec![InputEvent::Close, InputEvent::Close]);
+    assert_eq!("Close/hook/", test.result());
+}
+
+#[test]
+fn it_prim_types_pasted_text() {
+    let events = vec![InputEvent::Paste(b"a,\r\nb\x07".to_vec())];
+    let mut test =
+        TestMint::new_with_events("#(ow,#(it,0)/#(it,0)/#(it,0)/#(it,0)/#(it,0))", events);
+    assert_eq!("a/Comma/Return/b/Timeout", test.result());
+}
+
+#[test]
+fn sz_prim_suspends() {
+    let mut test = TestMint::new("#(ow,#(sz)/)");
+    assert_eq!("/", test.result());
+    assert_eq!(vec!["suspend"], test.window_log());
+}
+
+#[test]
+fn an_prim_announces() {
+    let mut test = TestMint::new("#(an,Left,,Right)");
+    assert_eq!("", test.result());
+    assert_eq!(vec!["announce Left|Right"], test.window_log());
+}
+
+#[test]
+fn bl_prim_uses_bell_pitch() {
+    let mut test = TestMint::new("#(bl,0,2)#(bl,-1,1)");
+    test.result();
+    assert_eq!(vec!["bell 440 112", "flash 56"], test.window_log());
+}
+
+#[test]
+fn bl_prim_flashes_for_as_long_as_asked() {
+    // Keys are read while the screen flashes, and #(it,10) waits 100ms.
+    let script = "#(is,one)#(rd)#(bl,-1,2)#(ow,#(it,0)/#(it,10)/)";
+    let mut test = TestMint::new_with_screen_and_keys(script, 10, 3, &["a"]);
+    assert_eq!("a/Timeout/", test.result());
+    assert_eq!("one\n\n\ncursor 0 3\nflashing", test.screen());
+
+    let script = "#(is,one)#(rd)#(bl,-1,2)#(ow,#(it,0)/#(it,10)/#(it,10))";
+    let mut test = TestMint::new_with_screen_and_keys(script, 10, 3, &["a"]);
+    assert_eq!("a/Timeout/Timeout", test.result());
+    assert_eq!("one\n\n\ncursor 0 3", test.screen());
+}
+
+#[test]
+fn bp_var_sets_bell_pitch() {
+    let mut test =
+        TestMint::new("#(sv,bp,880)#(bl,0,1)#(bl,220,1)#(sv,bp,-1)#(bl,,2)#(ow,#(lv,bp))");
+    assert_eq!("-1", test.result());
+    assert_eq!(
+        vec!["bell 880 56", "bell 220 56", "flash 112"],
+        test.window_log()
+    );
+}
+
+#[test]
+fn rd_prim_screen() {
+    let mut test =
+        TestMint::new_with_screen("#(is,(one\n\ttwo\nthree))#(ml,Left,,Right)#(rd)", 20, 6);
+    test.result();
+    assert_eq!(
+        "one\n        two\nthree\n\nLeft           Right\n\ncursor 2 5",
+        test.screen()
+    );
+}
+
+#[test]
+fn rd_prim_draws_in_one_update() {
+    let mut test = TestMint::new_with_screen("#(is,one)#(ml,Mode)#(rd)", 10, 3);
+    test.result();
+    assert_eq!("one\nMode\n\ncursor 0 3", test.screen());
+    assert_eq!(
+        vec![
+            "mode 1 Mode      ",
+            "begin update",
+            "redisplay 0,0 10x1 force=false",
+            "mode 1 Mode      ",
+            "end update"
+        ],
+        test.window_log()
+    );
+}
+
+#[test]
+fn da_prim_sets_buffer_display() {
+    let script = "#(is,(a  \nb))#(ow,#(da,ws,1)/#(da,ws)/#(da,fc,3)/#(da,fc)/#(da,fc,)/#(da,fc))\
+                  #(rd)";
+    let mut test = TestMint::new_with_screen(script, 10, 4);
+    assert_eq!("/1//3/3/", test.result());
+    assert!(test.screen().starts_with("a··\nb\n"));
+}
+
+#[test]
+fn rd_prim_scrolls_to_point() {
+    let mut test = TestMint::new_with_screen("#(is,(1\n2\n3\n4\n5\n6))#(rd)#(an,Note)", 10, 5);
+    test.result();
+    assert_eq!("4\n5\n6\n\nNote\ncursor 4 4", test.screen());
+}
+
+#[test]
+fn bl_var_sets_edit_lines() {
+    let script = "#(is,(one\ntwo\nthree))#(ml,Left,,Right)\
+                  #(ow,##(lv,bl)/)#(sv,bl,1)#(ow,##(lv,bl)/)#(rd)";
+    let mut test = TestMint::new_with_screen(script, 20, 6);
+    assert_eq!("3/1/", test.result());
+    assert_eq!(
+        "two\nthree\nLeft           Right\n\n\n\ncursor 1 5",
+        test.screen()
+    );
+}
+
+#[test]
+fn eb_and_el_vars_set_indicators() {
+    let script = "#(is,(one\ntwo))#(sv,eb,~)#(sv,el,$)#(ow,##(lv,eb)/##(lv,el))#(rd)";
+    let mut test = TestMint::new_with_screen(script, 10, 6);
+    assert_eq!("~/$", test.result());
+    assert_eq!("one$\ntwo$\n~\n~\n\n\ncursor 1 3", test.screen());
+}
+
+#[test]
+fn hx_var_shows_control_characters_in_hex() {
+    // The column of point is cached, and must change with the display.
+    let script = "#(is,(a\x1bb))#(ow,##(lv,cs)/)#(sv,hx,1)#(ow,##(lv,cs))#(rd)";
+    let mut test = TestMint::new_with_screen(script, 10, 3);
+    assert_eq!("5/7", test.result());
+    assert_eq!("a\\x1Bb\n\n\ncursor 0 6", test.screen());
+}
+
+#[test]
+fn keyboard_macros_record_and_replay() {
+    // Each key is a command: "s" and "t" start and stop recording, "e"
+    // replays twice, "l" lists the macro, and other keys are written out.
+    let script = "#(ds,k)#(ds,g,(##(it,0)))\
+                  #(ds,d,(#(n?,on.KEY,(#(on.KEY)),(#(ow,KEY)))))#(mp,d,SELF,KEY)\
+                  #(ds,on.s,(#(kr)))#(ds,on.t,(#(ke)))#(ds,on.e,(#(kx,2)))\
+                  #(ds,on.l,(#(ow,/#(kl,+)/)))#(ds,on.Timeout)\
+                  #(ow,#(ke)/#(kx)/)";
+    let mut test = TestMint::new_with_keys(script, &["s", "a", "b", "t", "l", "e", "x"]);
+    let mut result = String::new();
+    for _ in 0..20 {
+        result = test.result();
+    }
+    assert_eq!("not recording/no keyboard macro/ab/a+b/ababx", result);
+}
+
+#[test]
+fn keyboard_macros_record_pasted_keys_but_not_pushed_ones() {
+    // As above, with "p" pushing the key "q".
+    let script = "#(ds,k)#(ds,g,(##(it,0)))\
+                  #(ds,d,(#(n?,on.KEY,(#(on.KEY)),(#(ow,KEY)))))#(mp,d,SELF,KEY)\
+                  #(ds,on.s,(#(kr)))#(ds,on.t,(#(ke)))#(ds,on.p,(#(pu,q)))\
+                  #(ds,on.l,(#(ow,/#(kl,+)/)))#(ds,on.Timeout)";
+    let key = |name: &str| InputEvent::Key(name.as_bytes().to_vec());
+    let events = vec![
+        key("s"),
+        InputEvent::Paste(b"ab".to_vec()),
+        key("p"),
+        key("t"),
+        key("l"),
+    ];
+    let mut test = TestMint::new_with_events(script, events);
+    let mut result = String::new();
+    for _ in 0..20 {
+        result = test.result();
+    }
+    assert_eq!("abq/a+b+p/", result);
+}
+
+#[test]
+fn ks_prim_sets_keyboard_macro() {
+    let mut test = TestMint::new("#(ks,+,a+Return++b)#(ow,#(kl,/))");
+    assert_eq!("a/Return/b", test.result());
+}
+
+#[test]
+fn pu_prim_pushes_keys() {
+    let script = "#(pu,a,Return)#(pu,,b)#(ow,#(it)/#(it)/#(it)/#(it,0)/#(it,0))";
+    let mut test = TestMint::new_with_keys(script, &["z"]);
+    assert_eq!("a/Return/b/z/Timeout", test.result());
+}
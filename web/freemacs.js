@@ -0,0 +1,73 @@
+// Glue between xterm.js and the Freemacs wasm module (src/wasm.rs).
+
+// Browser key names that Freemacs knows by another name.
+const KEY_NAMES = {
+  ",": "Comma",
+  "(": "LPar",
+  ")": "RPar",
+  Backspace: "Back Space",
+  Tab: "Tab",
+  Enter: "Return",
+  Escape: "Escape",
+  Delete: "Del",
+  Insert: "Ins",
+  ArrowUp: "Up Arrow",
+  ArrowDown: "Down Arrow",
+  ArrowLeft: "Left Arrow",
+  ArrowRight: "Right Arrow",
+  Home: "Home",
+  End: "End",
+  PageUp: "Pg Up",
+  PageDown: "Pg Dn",
+};
+
+// The Freemacs name for a keydown event, or null for keys it doesn't see.
+function keyName(ev) {
+  let name;
+  if (ev.key.length === 1) {
+    name = ev.ctrlKey ? "C-" + ev.key.toLowerCase() : KEY_NAMES[ev.key] || ev.key;
+  } else if (KEY_NAMES[ev.key] || /^F\d+$/.test(ev.key)) {
+    const mods = (ev.ctrlKey ? "C-" : "") + (ev.shiftKey ? "S-" : "");
+    name = mods + (KEY_NAMES[ev.key] || ev.key);
+  } else {
+    return null;
+  }
+  return ev.altKey ? "M-" + name : name;
+}
+
+async function startFreemacs(element, wasmUrl, bootUrl) {
+  const term = new Terminal({ cols: 80, rows: 25 });
+  term.open(element);
+
+  const { instance } = await WebAssembly.instantiateStreaming(fetch(wasmUrl));
+  const wasm = instance.exports;
+  const encoder = new TextEncoder();
+
+  // Copy "bytes" into the module's input area, returning their length.
+  const input = (bytes) => {
+    const ptr = wasm.freemacs_input(bytes.length);
+    new Uint8Array(wasm.memory.buffer, ptr, bytes.length).set(bytes);
+    return bytes.length;
+  };
+  const output = () => {
+    const ptr = wasm.freemacs_output();
+    term.write(new Uint8Array(wasm.memory.buffer, ptr, wasm.freemacs_output_len()));
+  };
+
+  const boot = new Uint8Array(await (await fetch(bootUrl)).arrayBuffer());
+  wasm.freemacs_start(term.cols, term.rows, input(boot));
+  output();
+
+  term.attachCustomKeyEventHandler((ev) => {
+    if (ev.type !== "keydown") {
+      return false;
+    }
+    const name = keyName(ev);
+    if (name !== null) {
+      wasm.freemacs_key(input(encoder.encode(name)));
+      output();
+    }
+    ev.preventDefault();
+    return false;
+  });
+}